@@ -0,0 +1,42 @@
+/*
+ * C interface of saha, available when built with the `capi` feature, e.g.
+ *
+ *     cargo rustc --release --features capi --crate-type staticlib
+ *
+ * Keys are copied into the map. Values are opaque pointers owned by the
+ * caller, which the map never dereferences nor frees.
+ */
+#ifndef SAHA_H
+#define SAHA_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SahaMap SahaMap;
+
+/* Called for each entry by saha_map_for_each; returning false stops it. */
+typedef bool (*SahaVisitor)(void *ctx, const uint8_t *key, size_t key_len, void *value);
+
+SahaMap *saha_map_new(void);
+void saha_map_free(SahaMap *map);
+size_t saha_map_len(const SahaMap *map);
+
+/* Returns whether the key was present, writing its previous value to `old`. */
+bool saha_map_insert(SahaMap *map, const uint8_t *key, size_t key_len, void *value, void **old);
+bool saha_map_get(const SahaMap *map, const uint8_t *key, size_t key_len, void **out);
+bool saha_map_remove(SahaMap *map, const uint8_t *key, size_t key_len, void **out);
+
+/* Returns whether all the entries were visited. The visitor must not modify
+ * the map, which debug builds may catch and abort on, but not reliably. */
+bool saha_map_for_each(const SahaMap *map, SahaVisitor visitor, void *ctx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
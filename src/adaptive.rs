@@ -1,9 +1,16 @@
 use std::collections::hash_map::RandomState;
+use std::fmt;
 use std::hash::{BuildHasher, Hash};
+use std::iter::FusedIterator;
 
 use bumpalo::Bump;
 
-use crate::{LargeStringMap, SmallStringMap};
+#[cfg(feature = "metrics")]
+use crate::common::Metrics;
+use crate::common::{self, Bucket, ShrinkPolicy, SlotAccess};
+use crate::error::{Error, OccupiedError};
+use crate::large;
+use crate::{LargeSlotData, LargeStringMap, SmallSlotData, SmallStringMap};
 
 #[derive(Debug, Clone, Copy)]
 pub enum KeyRef<'a> {
@@ -15,13 +22,45 @@ pub enum KeyRef<'a> {
     Large(&'a [u8]),
 }
 
+/// The tier a key is routed to according to its length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyLen {
+    None,
+    S8,
+    S16,
+    S24,
+    Large,
+}
+
+impl KeyLen {
+    pub fn of(len: usize) -> Self {
+        match len {
+            0 => KeyLen::None,
+            len if len <= 8 => KeyLen::S8,
+            len if len <= 16 => KeyLen::S16,
+            len if len <= 24 => KeyLen::S24,
+            _ => KeyLen::Large,
+        }
+    }
+}
+
 impl<'a> KeyRef<'a> {
-    pub fn key(&self) -> &[u8] {
+    pub fn key(&self) -> &'a [u8] {
         match self {
             KeyRef::None => &[],
             KeyRef::S8(key) | KeyRef::S16(key) | KeyRef::S24(key) | KeyRef::Large(key) => key,
         }
     }
+
+    pub fn key_len(&self) -> KeyLen {
+        match self {
+            KeyRef::None => KeyLen::None,
+            KeyRef::S8(_) => KeyLen::S8,
+            KeyRef::S16(_) => KeyLen::S16,
+            KeyRef::S24(_) => KeyLen::S24,
+            KeyRef::Large(_) => KeyLen::Large,
+        }
+    }
 }
 
 impl<'a> From<&'a [u8]> for KeyRef<'a> {
@@ -60,6 +99,102 @@ impl<'a> Hash for KeyRef<'a> {
     }
 }
 
+/// A type that can be used to look up keys in the map without constructing a
+/// [`KeyRef`] first, similar to hashbrown's `Equivalent`.
+///
+/// Lookups hash and compare the [`KeyRef`] view of the type, so the view must
+/// expose the same bytes as the key it is equivalent to.
+pub trait Equivalent {
+    fn as_key_ref(&self) -> KeyRef<'_>;
+}
+
+impl<'a> Equivalent for KeyRef<'a> {
+    fn as_key_ref(&self) -> KeyRef<'_> {
+        *self
+    }
+}
+
+impl<K: AsKeyBytes + ?Sized> Equivalent for K {
+    fn as_key_ref(&self) -> KeyRef<'_> {
+        KeyRef::from(self.as_key_bytes())
+    }
+}
+
+/// A key type that can be viewed as the bytes it is stored as.
+///
+/// Keys are routed to tiers by the length of their bytes, so e.g. integer keys
+/// land in the 8-byte tier without callers building a [`KeyRef`].
+pub trait AsKeyBytes {
+    fn as_key_bytes(&self) -> &[u8];
+}
+
+impl AsKeyBytes for [u8] {
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> AsKeyBytes for [u8; N] {
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsKeyBytes for str {
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsKeyBytes for Vec<u8> {
+    fn as_key_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsKeyBytes for String {
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<K: AsKeyBytes + ?Sized> AsKeyBytes for &K {
+    fn as_key_bytes(&self) -> &[u8] {
+        (**self).as_key_bytes()
+    }
+}
+
+impl<K: AsKeyBytes + ?Sized> AsKeyBytes for Box<K> {
+    fn as_key_bytes(&self) -> &[u8] {
+        (**self).as_key_bytes()
+    }
+}
+
+/// Integers are keyed by their native-endian bytes, as by `to_ne_bytes`.
+macro_rules! impl_as_key_bytes_int {
+    ($($ty:ty),*) => {
+        $(impl AsKeyBytes for $ty {
+            fn as_key_bytes(&self) -> &[u8] {
+                // SAFETY: integers have no padding and any byte is initialized.
+                unsafe {
+                    std::slice::from_raw_parts(self as *const $ty as *const u8, size_of::<$ty>())
+                }
+            }
+        })*
+    };
+}
+impl_as_key_bytes_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+#[cfg(feature = "uuid")]
+impl AsKeyBytes for uuid::Uuid {
+    fn as_key_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Cloning the map is shallow regarding the keys: the clone shares the same key
+/// arena, and only bucket metadata and values are copied.
+#[derive(Clone)]
 pub struct StringMap<'a, T, S> {
     none_key: Option<T>,
     small8: SmallStringMap<'a, T, 8>,
@@ -67,6 +202,15 @@ pub struct StringMap<'a, T, S> {
     small24: SmallStringMap<'a, T, 24>,
     large: LargeStringMap<'a, T>,
     hasher: S,
+    key_alloc: &'a Bump,
+    /// Reseeds the hasher when probe sequences get pathologically long, if
+    /// enabled by [`set_auto_reseed`](Self::set_auto_reseed).
+    reseed: Option<fn(&mut S)>,
+    reseeds: u32,
+    /// Rehashes the tiers when their keys look clustered, if enabled by
+    /// [`set_max_mean_probe`](Self::set_max_mean_probe).
+    max_mean_probe: Option<f64>,
+    rehashes: u32,
 }
 
 impl<'a, T, S> StringMap<'a, T, S> {
@@ -78,6 +222,11 @@ impl<'a, T, S> StringMap<'a, T, S> {
             small24: SmallStringMap::new(key_alloc),
             large: LargeStringMap::new(key_alloc),
             hasher,
+            key_alloc,
+            reseed: None,
+            reseeds: 0,
+            max_mean_probe: None,
+            rehashes: 0,
         }
     }
 
@@ -85,6 +234,10 @@ impl<'a, T, S> StringMap<'a, T, S> {
         &self.hasher
     }
 
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
     pub fn len(&self) -> usize {
         (self.none_key.is_some() as usize)
             + self.small8.len()
@@ -93,6 +246,18 @@ impl<'a, T, S> StringMap<'a, T, S> {
             + self.large.len()
     }
 
+    /// Returns the number of keys routed to the tier `tier`, in constant
+    /// time.
+    pub fn len_for(&self, tier: KeyLen) -> usize {
+        match tier {
+            KeyLen::None => self.none_key.is_some() as usize,
+            KeyLen::S8 => self.small8.len(),
+            KeyLen::S16 => self.small16.len(),
+            KeyLen::S24 => self.small24.len(),
+            KeyLen::Large => self.large.len(),
+        }
+    }
+
     #[must_use]
     pub fn is_empty(&self) -> bool {
         self.none_key.is_none()
@@ -101,267 +266,2984 @@ impl<'a, T, S> StringMap<'a, T, S> {
             && self.small24.is_empty()
             && self.large.is_empty()
     }
+
+    /// Returns the number of bytes allocated for the slots of all the tiers.
+    pub fn bucket_bytes(&self) -> usize {
+        self.small8.bucket_bytes()
+            + self.small16.bucket_bytes()
+            + self.small24.bucket_bytes()
+            + self.large.bucket_bytes()
+    }
 }
 
-impl<'a, T> StringMap<'a, T, RandomState> {
-    pub fn new(key_alloc: &'a Bump) -> Self {
-        Self::with_hasher(RandomState::new(), key_alloc)
+/// The counters of every tier of a map. The number of probes of each tier
+/// tells how many key operations were routed to it.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TierMetrics {
+    pub small8: Metrics,
+    pub small16: Metrics,
+    pub small24: Metrics,
+    pub large: Metrics,
+}
+
+#[cfg(feature = "metrics")]
+impl TierMetrics {
+    pub fn total(&self) -> Metrics {
+        let mut total = self.small8.clone();
+        total.merge(&self.small16);
+        total.merge(&self.small24);
+        total.merge(&self.large);
+        total
     }
 }
 
-impl<'a, T: Hash, S: BuildHasher> StringMap<'a, T, S> {
-    pub fn get_hashed(&self, key: KeyRef, hash: u64) -> Option<&T> {
-        match key {
-            KeyRef::None => self.none_key.as_ref(),
-            KeyRef::S8(key) => self.small8.get(key, hash),
-            KeyRef::S16(key) => self.small16.get(key, hash),
-            KeyRef::S24(key) => self.small24.get(key, hash),
-            KeyRef::Large(key) => self.large.get(key, hash),
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Drops all the entries, keeping the buckets of every tier allocated.
+    pub fn clear(&mut self) {
+        self.none_key = None;
+        self.small8.clear();
+        self.small16.clear();
+        self.small24.clear();
+        self.large.clear();
+    }
+
+    /// Clears the map and detaches its buckets from the key arena, so that the
+    /// arena can be reset and the buckets reused without allocator traffic.
+    pub fn into_recycled(self) -> Recycled<T, S> {
+        Recycled {
+            small8: self.small8.into_bucket(),
+            small16: self.small16.into_bucket(),
+            small24: self.small24.into_bucket(),
+            large: large::erase_bucket(self.large.into_bucket()),
+            hasher: self.hasher,
         }
     }
 
-    pub fn get(&self, key: KeyRef) -> Option<&T> {
-        self.get_hashed(key, self.hasher.hash_one(key))
+    /// Returns the number of bytes the keys of the map take in the arena,
+    /// i.e. those of the large tier, as the others are stored inline.
+    pub fn key_bytes_live(&self) -> usize {
+        self.large.iter().map(|(key, _)| key.len()).sum()
     }
 
-    pub fn get_mut_hashed(&mut self, key: KeyRef, hash: u64) -> Option<&mut T> {
-        match key {
-            KeyRef::None => self.none_key.as_mut(),
-            KeyRef::S8(key) => self.small8.get_mut(key, hash),
-            KeyRef::S16(key) => self.small16.get_mut(key, hash),
-            KeyRef::S24(key) => self.small24.get_mut(key, hash),
-            KeyRef::Large(key) => self.large.get_mut(key, hash),
-        }
+    /// Returns the number of bytes used in the arena besides the live keys,
+    /// e.g. the keys of removed entries, the copies handed out by
+    /// [`remove_entry`](Self::remove_entry), and the chunk ends left behind
+    /// when a key didn't fit.
+    ///
+    /// This assumes the arena only holds the keys of the map: anything else
+    /// allocated in it is counted as dead too.
+    pub fn key_bytes_dead(&self) -> usize {
+        let used = self.key_alloc.allocated_bytes() - self.key_alloc.chunk_capacity();
+        used.saturating_sub(self.key_bytes_live())
     }
 
-    pub fn get_mut(&mut self, key: KeyRef) -> Option<&mut T> {
-        self.get_mut_hashed(key, self.hasher.hash_one(key))
+    /// Moves the map to the fresh arena `key_alloc`, copying only the live
+    /// keys, so that the current arena can then be dropped or reset.
+    ///
+    /// The tables are moved as is, without rehashing. Keys handed out before,
+    /// e.g. to [`insert_with_key`](Self::insert_with_key), still point into
+    /// the current arena.
+    pub fn compact_into<'b>(self, key_alloc: &'b Bump) -> StringMap<'b, T, S>
+    where
+        T: 'b,
+    {
+        StringMap {
+            none_key: self.none_key,
+            small8: self.small8.map_data(key_alloc, |data| data),
+            small16: self.small16.map_data(key_alloc, |data| data),
+            small24: self.small24.map_data(key_alloc, |data| data),
+            large: (self.large).map_data(key_alloc, |data| data.move_key(key_alloc)),
+            hasher: self.hasher,
+            key_alloc,
+            reseed: self.reseed,
+            reseeds: self.reseeds,
+            max_mean_probe: self.max_mean_probe,
+            rehashes: self.rehashes,
+        }
     }
+}
 
-    pub fn insert_hashed(&mut self, key: KeyRef, hash: u64, value: T) -> Option<T> {
-        match key {
-            KeyRef::None => self.none_key.replace(value),
-            KeyRef::S8(key) => self.small8.insert(key, hash, value, &self.hasher),
-            KeyRef::S16(key) => self.small16.insert(key, hash, value, &self.hasher),
-            KeyRef::S24(key) => self.small24.insert(key, hash, value, &self.hasher),
-            KeyRef::Large(key) => self.large.insert(key, hash, value, &self.hasher),
+/// The emptied buckets of a map, detached from any key arena.
+pub struct Recycled<T, S> {
+    small8: Bucket<SmallSlotData<T, 8>>,
+    small16: Bucket<SmallSlotData<T, 16>>,
+    small24: Bucket<SmallSlotData<T, 24>>,
+    large: Bucket<LargeSlotData<'static, T>>,
+    hasher: S,
+}
+
+impl<T, S> Recycled<T, S> {
+    /// Builds an empty map upon the recycled buckets.
+    pub fn attach(self, key_alloc: &Bump) -> StringMap<'_, T, S> {
+        StringMap {
+            none_key: None,
+            small8: SmallStringMap::from_bucket(self.small8, key_alloc),
+            small16: SmallStringMap::from_bucket(self.small16, key_alloc),
+            small24: SmallStringMap::from_bucket(self.small24, key_alloc),
+            large: LargeStringMap::from_bucket(self.large, key_alloc),
+            hasher: self.hasher,
+            key_alloc,
+            reseed: None,
+            reseeds: 0,
+            max_mean_probe: None,
+            rehashes: 0,
         }
     }
 
-    pub fn insert(&mut self, key: KeyRef, value: T) -> Option<T> {
-        self.insert_hashed(key, self.hasher.hash_one(key), value)
+    /// Resets `key_alloc` and builds an empty map upon the recycled buckets.
+    pub fn reset(self, key_alloc: &mut Bump) -> StringMap<'_, T, S> {
+        key_alloc.reset();
+        self.attach(key_alloc)
     }
+}
 
-    pub fn try_insert_hashed(&mut self, key: KeyRef, hash: u64, value: T) -> Option<(&mut T, T)> {
-        match key {
-            KeyRef::None => match &mut self.none_key {
-                Some(s) => Some((s, value)),
-                slot @ None => {
-                    *slot = Some(value);
-                    None
-                }
-            },
-            KeyRef::S8(key) => self.small8.try_insert(key, hash, value, &self.hasher),
-            KeyRef::S16(key) => self.small16.try_insert(key, hash, value, &self.hasher),
-            KeyRef::S24(key) => self.small24.try_insert(key, hash, value, &self.hasher),
-            KeyRef::Large(key) => self.large.try_insert(key, hash, value, &self.hasher),
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Rebuilds all the tiers under `hasher`, e.g. to switch to a seeded
+    /// hasher after detecting adversarial input.
+    pub fn rehash_with<S2: BuildHasher>(self, hasher: S2) -> StringMap<'a, T, S2> {
+        let StringMap {
+            none_key,
+            mut small8,
+            mut small16,
+            mut small24,
+            mut large,
+            key_alloc,
+            ..
+        } = self;
+
+        small8.rehash(&hasher);
+        small16.rehash(&hasher);
+        small24.rehash(&hasher);
+        large.rehash(&hasher);
+
+        StringMap {
+            none_key,
+            small8,
+            small16,
+            small24,
+            large,
+            hasher,
+            key_alloc,
+            reseed: None,
+            reseeds: 0,
+            max_mean_probe: None,
+            rehashes: 0,
         }
     }
+}
 
-    pub fn try_insert(&mut self, key: KeyRef, value: T) -> Option<(&mut T, T)> {
-        self.try_insert_hashed(key, self.hasher.hash_one(key), value)
+/// The number of high hash bits used as control tags, which the partition
+/// bits are taken below, so that the entries of a partition don't all share
+/// the same tags.
+pub(crate) const TAG_BITS: u32 = 7;
+
+impl<'a, T, S: BuildHasher + Clone> StringMap<'a, T, S> {
+    /// Splits the map into `2^bits` maps by the high bits of the hashes of
+    /// their keys, e.g. to hand a build result to parallel mergers, or to
+    /// spill it partition by partition.
+    ///
+    /// Entries are moved as is, so keys are not copied and the hashes stored
+    /// by the large tier are not recomputed.
+    pub fn repartition(self, bits: u32) -> Vec<StringMap<'a, T, S>> {
+        assert!(bits <= 16, "Too many partitions");
+        let part = |hash: u64| match bits {
+            0 => 0,
+            _ => ((hash << TAG_BITS) >> (64 - bits)) as usize,
+        };
+        let parts = 1 << bits;
+
+        let StringMap {
+            none_key,
+            small8,
+            small16,
+            small24,
+            large,
+            hasher,
+            key_alloc,
+            reseed,
+            reseeds,
+            max_mean_probe,
+            rehashes,
+        } = self;
+
+        let mut small8 = small8.split(parts, part, &hasher).into_iter();
+        let mut small16 = small16.split(parts, part, &hasher).into_iter();
+        let mut small24 = small24.split(parts, part, &hasher).into_iter();
+        let mut large = large.split(parts, part, &hasher).into_iter();
+        let mut maps = (0..parts)
+            .map(|_| StringMap {
+                none_key: None,
+                small8: small8.next().unwrap(),
+                small16: small16.next().unwrap(),
+                small24: small24.next().unwrap(),
+                large: large.next().unwrap(),
+                hasher: hasher.clone(),
+                key_alloc,
+                reseed,
+                reseeds,
+                max_mean_probe,
+                rehashes,
+            })
+            .collect::<Vec<_>>();
+        maps[part(hasher.hash_one(KeyRef::None))].none_key = none_key;
+        maps
     }
+}
 
-    pub fn remove_hashed(&mut self, key: KeyRef, hash: u64) -> Option<T> {
-        match key {
-            KeyRef::None => self.none_key.take(),
-            KeyRef::S8(key) => self.small8.remove(key, hash, &self.hasher),
-            KeyRef::S16(key) => self.small16.remove(key, hash, &self.hasher),
-            KeyRef::S24(key) => self.small24.remove(key, hash, &self.hasher),
-            KeyRef::Large(key) => self.large.remove(key, hash, &self.hasher),
+impl<'a, T, S: BuildHasher + Clone> StringMap<'a, T, S> {
+    /// Moves the entries of `other` into the map, calling `combine` on the
+    /// values of the keys present in both.
+    ///
+    /// Unlike inserting the entries of `other` one by one, keys are not
+    /// copied into the arena of the map and the hashes stored by the large
+    /// tier are reused, unless `other` hashes differently, in which case it is
+    /// rehashed first.
+    pub fn merge(&mut self, other: StringMap<'a, T, S>, mut combine: impl FnMut(&mut T, T)) {
+        let other = match self.hashes_like(&other.hasher) {
+            true => other,
+            false => other.rehash_with(self.hasher.clone()),
+        };
+        match (&mut self.none_key, other.none_key) {
+            (Some(value), Some(other)) => combine(value, other),
+            (slot @ None, other) => *slot = other,
+            (Some(_), None) => {}
         }
+        let hasher = &self.hasher;
+        self.small8.merge(other.small8, &mut combine, hasher);
+        self.small16.merge(other.small16, &mut combine, hasher);
+        self.small24.merge(other.small24, &mut combine, hasher);
+        self.large.merge(other.large, &mut combine, hasher);
     }
 
-    pub fn remove(&mut self, key: KeyRef) -> Option<T> {
-        self.remove_hashed(key, self.hasher.hash_one(key))
+    /// Returns whether `hasher` most likely hashes like the hasher of the map,
+    /// e.g. because it is a clone of it.
+    pub(crate) fn hashes_like(&self, hasher: &S) -> bool {
+        self.hasher.hash_one(KeyRef::None) == hasher.hash_one(KeyRef::None)
+    }
+}
+
+impl<'a, T> StringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+
+    /// Creates a map sized for `ndv` distinct keys of `avg_key_len` bytes on
+    /// average, so that building it does not resize, as long as the lengths
+    /// spread as [`reserve_estimated`](Self::reserve_estimated) expects.
+    ///
+    /// The arena is not grown here; create it with
+    /// [`Bump::with_capacity`] and [`estimated_arena_bytes`] to also avoid
+    /// allocating key chunks mid-build.
+    ///
+    /// [`estimated_arena_bytes`]: Self::estimated_arena_bytes
+    pub fn with_estimated_cardinality(
+        ndv: impl CardinalityEstimate,
+        avg_key_len: usize,
+        key_alloc: &'a Bump,
+    ) -> Self {
+        let mut map = Self::new(key_alloc);
+        map.reserve_estimated(ndv, avg_key_len);
+        map
     }
 }
 
 impl<'a, T, S> StringMap<'a, T, S> {
-    pub fn iter(&self) -> impl Iterator<Item = (KeyRef, &T)> {
-        { self.none_key.iter().map(|value| (KeyRef::None, value)) }
-            .chain(
-                self.small8
-                    .iter()
-                    .map(|(key, value)| (KeyRef::S8(key), value)),
-            )
-            .chain(
-                self.small16
-                    .iter()
-                    .map(|(key, value)| (KeyRef::S16(key), value)),
-            )
-            .chain(
-                self.small24
-                    .iter()
-                    .map(|(key, value)| (KeyRef::S24(key), value)),
-            )
-            .chain(
-                self.large
-                    .iter()
-                    .map(|(key, value)| (KeyRef::Large(key), value)),
-            )
-    }
-
-    pub fn iter_mut(&'a mut self) -> impl Iterator<Item = (KeyRef, &mut T)> + 'a {
-        { self.none_key.iter_mut().map(|value| (KeyRef::None, value)) }
-            .chain(
-                self.small8
-                    .iter_mut()
-                    .map(|(key, value)| (KeyRef::S8(key), value)),
-            )
-            .chain(
-                self.small16
-                    .iter_mut()
-                    .map(|(key, value)| (KeyRef::S16(key), value)),
-            )
-            .chain(
-                self.small24
-                    .iter_mut()
-                    .map(|(key, value)| (KeyRef::S24(key), value)),
-            )
-            .chain(
-                self.large
-                    .iter_mut()
-                    .map(|(key, value)| (KeyRef::Large(key), value)),
-            )
+    /// Returns the number of arena bytes taken by `ndv` distinct keys of
+    /// `avg_key_len` bytes on average, spread as
+    /// [`reserve_estimated`](Self::reserve_estimated) expects. Only keys
+    /// longer than 24 bytes are stored in the arena.
+    pub fn estimated_arena_bytes(ndv: impl CardinalityEstimate, avg_key_len: usize) -> usize {
+        estimated_lengths(ndv.estimate(), avg_key_len)
+            .filter(|&(len, _)| KeyLen::of(len) == KeyLen::Large)
+            .map(|(len, count)| len * count)
+            .sum()
     }
 }
 
-impl<'a, T: 'a, S> IntoIterator for StringMap<'a, T, S> {
-    type Item = (&'a [u8], T);
+/// Spreads `ndv` keys of `avg_key_len` bytes on average evenly over the
+/// lengths from half to one and a half times the average, as an estimate of
+/// their distribution, yielding `(key length, count)` pairs.
+pub(crate) fn estimated_lengths(
+    ndv: usize,
+    avg_key_len: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let lengths = avg_key_len / 2..=avg_key_len + avg_key_len / 2;
+    let n = lengths.clone().count();
+    lengths
+        .enumerate()
+        .map(move |(i, len)| (len, ndv * (i + 1) / n - ndv * i / n))
+}
 
-    type IntoIter = impl Iterator<Item = (&'a [u8], T)> + 'a;
+/// An estimate of the number of distinct keys, such as the one of a
+/// HyperLogLog sketch maintained by the caller.
+pub trait CardinalityEstimate {
+    fn estimate(&self) -> usize;
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        { self.none_key.into_iter().map(|value| (&[] as _, value)) }
-            .chain(self.small8.into_iter())
-            .chain(self.small16.into_iter())
-            .chain(self.small24.into_iter())
-            .chain(self.large.into_iter())
+impl CardinalityEstimate for usize {
+    fn estimate(&self) -> usize {
+        *self
     }
 }
 
-impl<'a, T, S> IntoIterator for &'a StringMap<'a, T, S> {
-    type Item = (KeyRef<'a>, &'a T);
+impl<E: CardinalityEstimate + ?Sized> CardinalityEstimate for &E {
+    fn estimate(&self) -> usize {
+        (**self).estimate()
+    }
+}
 
-    type IntoIter = impl Iterator<Item = (KeyRef<'a>, &'a T)>;
+/// A [`BuildHasher`] that can switch to a fresh random seed, so that a map
+/// flooded with colliding keys can be rebuilt under hashes an attacker can't
+/// predict.
+pub trait Reseed: BuildHasher {
+    fn reseed(&mut self);
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter()
+impl Reseed for RandomState {
+    fn reseed(&mut self) {
+        *self = RandomState::new();
     }
 }
 
-impl<'a, T, S> IntoIterator for &'a mut StringMap<'a, T, S> {
-    type Item = (KeyRef<'a>, &'a mut T);
+/// Probe sequences longer than this are never deemed pathological.
+const MIN_PROBE_LIMIT: usize = 128;
+/// The tolerated probe length per bit of the capacity of a tier.
+const PROBE_LIMIT_PER_BIT: usize = 16;
+/// The fewest entries a tier must hold for its mean probe length to be deemed
+/// a sign of clustering.
+const MIN_CLUSTER_SAMPLE: usize = 64;
+
+impl<'a, T, S: Reseed> StringMap<'a, T, S> {
+    /// Rebuilds all the tiers under a freshly seeded hasher.
+    ///
+    /// Hashes computed before are invalidated.
+    pub fn reseed(&mut self) {
+        self.reseed_with(S::reseed);
+    }
 
-    type IntoIter = impl Iterator<Item = (KeyRef<'a>, &'a mut T)>;
+    /// Enables or disables reseeding the hasher when the probe sequence of a
+    /// tier exceeds a limit logarithmic in its capacity, which is a sign of
+    /// collision flooding.
+    ///
+    /// The check runs before methods hashing keys themselves, so hashes passed
+    /// to `_hashed` methods stay valid until the next such call. The limit
+    /// doubles after every reseed, so a hasher that doesn't depend on its seed
+    /// can't make the map rebuild repeatedly.
+    pub fn set_auto_reseed(&mut self, enabled: bool) {
+        self.reseed = enabled.then_some(S::reseed as fn(&mut S));
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.iter_mut()
+impl<'a, T, S> StringMap<'a, T, S> {
+    pub(crate) fn set_reseed(&mut self, reseed: Option<fn(&mut S)>) {
+        self.reseed = reseed;
+    }
+
+    pub fn max_mean_probe(&self) -> Option<f64> {
+        self.max_mean_probe
+    }
+
+    /// Enables or disables rehashing the tiers at the same capacity when the
+    /// [mean probe length](common::StringMap::mean_probe) of one of them
+    /// exceeds `limit`, recovering from a seed that interacts badly with the
+    /// keys. The hasher is reseeded first if auto reseeding is enabled.
+    ///
+    /// The check runs along with that of auto reseeding, and the limit
+    /// doubles after every such rehash, so that keys clustering under any
+    /// seed can't make the map rebuild repeatedly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is below 1, the shortest possible mean.
+    pub fn set_max_mean_probe(&mut self, limit: Option<f64>) {
+        assert!(
+            limit.is_none_or(|limit| limit >= 1.),
+            "Mean probe length limit {limit:?} is below 1"
+        );
+        self.max_mean_probe = limit;
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Returns the number of times the hasher was reseeded.
+    pub fn reseeds(&self) -> u32 {
+        self.reseeds
+    }
 
-    use super::*;
+    /// Returns the number of times the tiers were rehashed because their keys
+    /// looked clustered.
+    pub fn rehashes(&self) -> u32 {
+        self.rehashes
+    }
 
-    #[test]
-    fn test_hash_map() {
-        let bump = Bump::new();
-        let mut map = StringMap::new(&bump);
-        let mut cmp = HashMap::new();
+    /// Returns the length of the longest probe sequence reaching an entry
+    /// placed since its tier was last rebuilt.
+    pub fn max_probe(&self) -> usize {
+        self.small8
+            .max_probe()
+            .max(self.small16.max_probe())
+            .max(self.small24.max_probe())
+            .max(self.large.max_probe())
+    }
 
-        for _ in 0..50000 {
-            let value = rand::random::<[u8; 10]>();
+    /// Reseeds the hasher if auto reseeding is enabled and some tier looks
+    /// flooded.
+    #[inline]
+    pub(crate) fn guard_probes(&mut self) {
+        if let Some(reseed) = self.reseed {
+            let limit = |capacity: usize| {
+                let limit = PROBE_LIMIT_PER_BIT * capacity.max(1).ilog2() as usize;
+                limit.max(MIN_PROBE_LIMIT) << self.reseeds.min(16)
+            };
+            if self.small8.max_probe() > limit(self.small8.capacity())
+                || self.small16.max_probe() > limit(self.small16.capacity())
+                || self.small24.max_probe() > limit(self.small24.capacity())
+                || self.large.max_probe() > limit(self.large.capacity())
+            {
+                self.reseed_with(reseed);
+            }
+        }
+        if let Some(limit) = self.max_mean_probe {
+            let limit = limit * (1u32 << self.rehashes.min(16)) as f64;
+            let clustered = |len: usize, mean: Option<f64>| {
+                len >= MIN_CLUSTER_SAMPLE && mean.is_some_and(|mean| mean > limit)
+            };
+            if clustered(self.small8.len(), self.small8.mean_probe())
+                || clustered(self.small16.len(), self.small16.mean_probe())
+                || clustered(self.small24.len(), self.small24.mean_probe())
+                || clustered(self.large.len(), self.large.mean_probe())
+            {
+                self.rehash_clustered();
+            }
+        }
+    }
 
-            map.insert(KeyRef::from(&value), Vec::from(value));
-            cmp.insert(value.into_iter().collect::<Vec<_>>(), Vec::from(value));
+    #[cold]
+    fn rehash_clustered(&mut self) {
+        self.rehashes += 1;
+        match self.reseed {
+            Some(reseed) => self.reseed_with(reseed),
+            None => {
+                self.small8.rehash(&self.hasher);
+                self.small16.rehash(&self.hasher);
+                self.small24.rehash(&self.hasher);
+                self.large.rehash(&self.hasher);
+            }
         }
+    }
 
-        // Test conflict conditions
-        for _ in 0..100000 {
-            let value = rand::random::<[u8; 4]>();
+    #[cold]
+    fn reseed_with(&mut self, reseed: fn(&mut S)) {
+        reseed(&mut self.hasher);
+        self.reseeds += 1;
+        self.small8.rehash(&self.hasher);
+        self.small16.rehash(&self.hasher);
+        self.small24.rehash(&self.hasher);
+        self.large.rehash(&self.hasher);
+    }
 
-            map.insert(KeyRef::from(&value), Vec::from(value));
-            cmp.insert(value.into_iter().collect::<Vec<_>>(), Vec::from(value));
+    /// Walks every tier and panics if any internal invariant is broken, e.g.
+    /// to check custom slot data or to chase bugs in unsafe code.
+    ///
+    /// Besides the checks of every tier (see
+    /// [`SmallStringMap::validate`](crate::SmallStringMap)), keys must be
+    /// routed to the tier of their length.
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    pub fn validate(&self) {
+        self.small8.validate(&self.hasher);
+        self.small16.validate(&self.hasher);
+        self.small24.validate(&self.hasher);
+        self.large.validate(&self.hasher);
+        for (key, _) in self.iter() {
+            assert_eq!(
+                KeyLen::of(key.key().len()),
+                key.key_len(),
+                "Key of {} bytes in the wrong tier",
+                key.key().len()
+            );
         }
+    }
 
-        for (k, v) in map.iter() {
-            let value = cmp.remove(k.key());
-            assert_eq!(value.as_ref(), Some(v));
+    /// Returns the counters collected by every tier since the last call,
+    /// resetting them.
+    #[cfg(feature = "metrics")]
+    pub fn take_metrics(&mut self) -> TierMetrics {
+        TierMetrics {
+            small8: self.small8.take_metrics(),
+            small16: self.small16.take_metrics(),
+            small24: self.small24.take_metrics(),
+            large: self.large.take_metrics(),
         }
-        assert!(cmp.is_empty());
     }
 
-    fn gen_record<'a, S: BuildHasher>(hasher: &S, bump: &'a Bump) -> (&'a [u8], u64) {
-        let len = rand::random::<usize>() % 1000;
-        let key = bump.alloc_slice_fill_with(len, |_| rand::random());
+    /// Collects the keys in one pass over the slots of every tier, as slices of
+    /// the arena, e.g. to emit the result of a DISTINCT.
+    ///
+    /// The keys of the large tier are already there, and those of the inline
+    /// tiers are copied into it.
+    pub fn unique_keys(&self) -> Vec<&'a [u8]> {
+        let mut keys = Vec::with_capacity(self.len());
+        keys.extend(self.none_key.as_ref().map(|_| &[] as &[u8]));
+        let small = { self.small8.iter() }
+            .chain(self.small16.iter())
+            .chain(self.small24.iter());
+        keys.extend(small.map(|(key, _)| &*self.key_alloc.alloc_slice_copy(key)));
+        keys.extend(self.large.iter_data().map(LargeSlotData::arena_key));
+        keys
+    }
 
-        let hash = hasher.hash_one(KeyRef::from(&*key));
+    /// Returns the copy in the arena of `key`, if it is in the large tier.
+    pub(crate) fn large_key(&self, key: &[u8], hash: u64) -> Option<&'a [u8]> {
+        Some(self.large.get_data(key, hash)?.arena_key())
+    }
 
-        (key, hash)
+    /// Returns the number of bytes inserting `key` would allocate if it is
+    /// absent: the growth of the slots of its tier, plus the copy of a large
+    /// key in the arena.
+    ///
+    /// The arena itself grows by whole chunks, so a large key that doesn't fit
+    /// in the [current one](Bump::chunk_capacity) makes it allocate more.
+    pub fn insertion_bytes<Q: Equivalent>(&self, key: Q) -> usize {
+        let key = key.as_key_ref();
+        let copy = match key {
+            KeyRef::Large(key) => key.len(),
+            _ => 0,
+        };
+        self.slot_growth_bytes(key) + copy
     }
 
-    fn gen_group_data<'a, S: BuildHasher>(hasher: &S, bump: &'a Bump) -> Vec<(&'a [u8], u64)> {
-        let mut data = vec![];
-        for _ in 0..10000 {
-            data.push(gen_record(hasher, bump))
+    /// Returns the number of bytes the slots of the tier of `key` grow by if
+    /// it is inserted.
+    pub(crate) fn slot_growth_bytes(&self, key: KeyRef) -> usize {
+        match key {
+            KeyRef::None => 0,
+            KeyRef::S8(_) => self.small8.growth_bytes(),
+            KeyRef::S16(_) => self.small16.growth_bytes(),
+            KeyRef::S24(_) => self.small24.growth_bytes(),
+            KeyRef::Large(_) => self.large.growth_bytes(),
         }
-        data
     }
 
-    #[bench]
-    fn bench_group(bencher: &mut test::Bencher) {
-        bencher.iter(|| {
-            let bump = Bump::new();
-            let mut map = StringMap::new(&bump);
+    /// Returns the length of the probe sequence inserting `key` would take if
+    /// it is absent, or `None` if its tier is full.
+    pub(crate) fn insertion_probe(&self, key: KeyRef, hash: u64) -> Option<usize> {
+        match key {
+            KeyRef::None => Some(0),
+            KeyRef::S8(_) => self.small8.insertion_probe(hash),
+            KeyRef::S16(_) => self.small16.insertion_probe(hash),
+            KeyRef::S24(_) => self.small24.insertion_probe(hash),
+            KeyRef::Large(_) => self.large.insertion_probe(hash),
+        }
+    }
 
-            let data = gen_group_data(map.hasher(), &bump);
+    pub fn capacity_for(&self, tier: KeyLen) -> usize {
+        match tier {
+            KeyLen::None => 1,
+            KeyLen::S8 => self.small8.capacity(),
+            KeyLen::S16 => self.small16.capacity(),
+            KeyLen::S24 => self.small24.capacity(),
+            KeyLen::Large => self.large.capacity(),
+        }
+    }
 
-            data.into_iter().for_each(|(key, hash)| {
-                if let Some((count, _)) = map.try_insert_hashed(KeyRef::from(key), hash, 1) {
-                    *count += 1;
-                }
-            });
+    /// Reserves room for `additional` more keys in the tier `tier` only.
+    pub fn reserve_for(&mut self, tier: KeyLen, additional: usize) {
+        match tier {
+            KeyLen::None => {}
+            KeyLen::S8 => self.small8.reserve(additional, &self.hasher),
+            KeyLen::S16 => self.small16.reserve(additional, &self.hasher),
+            KeyLen::S24 => self.small24.reserve(additional, &self.hasher),
+            KeyLen::Large => self.large.reserve(additional, &self.hasher),
+        }
+    }
 
-            for (key, value) in map {
-                let _ = key;
-                let _ = value;
+    /// Reserves room like [`reserve_for`](Self::reserve_for), but returns an
+    /// error instead of aborting if the slots can't be allocated.
+    pub fn try_reserve_for(&mut self, tier: KeyLen, additional: usize) -> Result<(), Error> {
+        match tier {
+            KeyLen::None => Ok(()),
+            KeyLen::S8 => self.small8.try_reserve(additional, &self.hasher),
+            KeyLen::S16 => self.small16.try_reserve(additional, &self.hasher),
+            KeyLen::S24 => self.small24.try_reserve(additional, &self.hasher),
+            KeyLen::Large => self.large.try_reserve(additional, &self.hasher),
+        }
+    }
+
+    /// Reserves room in every tier according to a histogram of expected
+    /// `(key length, count)` pairs.
+    pub fn reserve_hint(&mut self, histogram: impl IntoIterator<Item = (usize, usize)>) {
+        let mut counts = [0; 5];
+        for (len, count) in histogram {
+            counts[KeyLen::of(len) as usize] += count;
+        }
+        for (tier, count) in [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+            .into_iter()
+            .zip(&counts[1..])
+        {
+            if *count > 0 {
+                self.reserve_for(tier, *count);
             }
-        })
+        }
     }
 
-    #[bench]
-    fn bench_group_cmp(bencher: &mut test::Bencher) {
-        bencher.iter(|| {
-            let bump = Bump::new();
-            let mut map = HashMap::new();
+    /// Reserves room for `ndv` distinct keys of `avg_key_len` bytes on
+    /// average, e.g. once an estimate becomes available mid-build.
+    ///
+    /// The keys are expected to spread evenly over the lengths from half to
+    /// one and a half times the average, and each tier gets room for its
+    /// share. Use [`reserve_hint`](Self::reserve_hint) if the distribution
+    /// is known, e.g. for keys of a fixed length.
+    pub fn reserve_estimated(&mut self, ndv: impl CardinalityEstimate, avg_key_len: usize) {
+        self.reserve_hint(estimated_lengths(ndv.estimate(), avg_key_len));
+    }
 
-            let data = gen_group_data(map.hasher(), &bump);
+    /// Returns the load from which the tiers grow, in percent of their
+    /// capacity.
+    pub fn max_load_percent(&self) -> u8 {
+        self.small8.max_load_percent()
+    }
 
-            data.into_iter().for_each(|(key, _)| {
-                if let Err(mut err) = map.try_insert(key, 1) {
-                    *err.entry.get_mut() += 1;
-                }
-            });
+    /// Sets the load from which every tier grows, e.g. to 90% for a map
+    /// bounded by memory rather than by lookup latency.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percent` is under two thirds or above
+    /// [`LargeStringMap::MAX_LOAD_PERCENT`](crate::LargeStringMap).
+    pub fn set_max_load_percent(&mut self, percent: u8) {
+        self.small8.set_max_load_percent(percent);
+        self.small16.set_max_load_percent(percent);
+        self.small24.set_max_load_percent(percent);
+        self.large.set_max_load_percent(percent);
+    }
 
-            for (key, value) in map {
-                let _ = key;
-                let _ = value;
-            }
-        })
+    /// Returns the shrink policy of the tiers.
+    pub fn shrink_policy(&self) -> ShrinkPolicy {
+        self.small8.shrink_policy()
+    }
+
+    /// Sets the shrink policy of every tier, e.g. to keep the slots of a map
+    /// that is repeatedly filled and drained.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an automatic policy shrinks at more than
+    /// [`ShrinkPolicy::MAX_MIN_LOAD_PERCENT`].
+    pub fn set_shrink_policy(&mut self, policy: ShrinkPolicy) {
+        self.small8.set_shrink_policy(policy);
+        self.small16.set_shrink_policy(policy);
+        self.small24.set_shrink_policy(policy);
+        self.large.set_shrink_policy(policy);
+    }
+
+    /// Shrinks every tier to the smallest capacity holding its entries, unless
+    /// the policy is [`ShrinkPolicy::Never`].
+    pub fn shrink_to_fit(&mut self) {
+        self.small8.shrink_to_fit(&self.hasher);
+        self.small16.shrink_to_fit(&self.hasher);
+        self.small24.shrink_to_fit(&self.hasher);
+        self.large.shrink_to_fit(&self.hasher);
+    }
+
+    /// Hints the CPU to fetch the first slot probed for `key` into the cache.
+    #[inline]
+    pub fn prefetch_hashed<Q: Equivalent>(&self, key: Q, hash: u64) {
+        match key.as_key_ref() {
+            KeyRef::None => {}
+            KeyRef::S8(_) => self.small8.prefetch(hash),
+            KeyRef::S16(_) => self.small16.prefetch(hash),
+            KeyRef::S24(_) => self.small24.prefetch(hash),
+            KeyRef::Large(_) => self.large.prefetch(hash),
+        }
+    }
+
+    pub fn get_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> Option<&T> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.as_ref(),
+            KeyRef::S8(key) => self.small8.get(key, hash),
+            KeyRef::S16(key) => self.small16.get(key, hash),
+            KeyRef::S24(key) => self.small24.get(key, hash),
+            KeyRef::Large(key) => self.large.get(key, hash),
+        }
+    }
+
+    /// Gets the value of `key` skipping the defensive checks of
+    /// [`get_hashed`](Self::get_hashed), for verified hot loops. Callers
+    /// already knowing the tier of their keys can also construct the `KeyRef`
+    /// variant directly to skip the length dispatch.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be present in the map, and `hash` must be its hash.
+    pub unsafe fn get_unchecked_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> &T {
+        match key.as_key_ref() {
+            KeyRef::None => unsafe { self.none_key.as_ref().unwrap_unchecked() },
+            KeyRef::S8(key) => unsafe { self.small8.get_unchecked(key, hash) },
+            KeyRef::S16(key) => unsafe { self.small16.get_unchecked(key, hash) },
+            KeyRef::S24(key) => unsafe { self.small24.get_unchecked(key, hash) },
+            KeyRef::Large(key) => unsafe { self.large.get_unchecked(key, hash) },
+        }
+    }
+
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        let key = key.as_key_ref();
+        self.get_hashed(key, self.hasher.hash_one(key))
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get_mut_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> Option<&mut T> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.as_mut(),
+            KeyRef::S8(key) => self.small8.get_mut(key, hash),
+            KeyRef::S16(key) => self.small16.get_mut(key, hash),
+            KeyRef::S24(key) => self.small24.get_mut(key, hash),
+            KeyRef::Large(key) => self.large.get_mut(key, hash),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// See [`get_unchecked_hashed`](Self::get_unchecked_hashed).
+    pub unsafe fn get_unchecked_mut_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> &mut T {
+        match key.as_key_ref() {
+            KeyRef::None => unsafe { self.none_key.as_mut().unwrap_unchecked() },
+            KeyRef::S8(key) => unsafe { self.small8.get_unchecked_mut(key, hash) },
+            KeyRef::S16(key) => unsafe { self.small16.get_unchecked_mut(key, hash) },
+            KeyRef::S24(key) => unsafe { self.small24.get_unchecked_mut(key, hash) },
+            KeyRef::Large(key) => unsafe { self.large.get_unchecked_mut(key, hash) },
+        }
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        let key = key.as_key_ref();
+        self.get_mut_hashed(key, self.hasher.hash_one(key))
+    }
+
+    pub fn insert_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64, value: T) -> Option<T> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.replace(value),
+            KeyRef::S8(key) => self.small8.insert(key, hash, value, &self.hasher),
+            KeyRef::S16(key) => self.small16.insert(key, hash, value, &self.hasher),
+            KeyRef::S24(key) => self.small24.insert(key, hash, value, &self.hasher),
+            KeyRef::Large(key) => self.large.insert(key, hash, value, &self.hasher),
+        }
+    }
+
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<T> {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.insert_hashed(key, self.hasher.hash_one(key), value)
+    }
+
+    /// Inserts `key` without comparing it against the keys already present.
+    ///
+    /// # Safety
+    ///
+    /// `key` must not be in the map, e.g. because the input is known to be
+    /// distinct.
+    pub unsafe fn insert_unique_unchecked_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) {
+        let hasher = &self.hasher;
+        match key.as_key_ref() {
+            KeyRef::None => {
+                debug_assert!(self.none_key.is_none(), "Key already present");
+                self.none_key = Some(value)
+            }
+            KeyRef::S8(key) => unsafe {
+                self.small8
+                    .insert_unique_unchecked(key, hash, value, hasher)
+            },
+            KeyRef::S16(key) => unsafe {
+                self.small16
+                    .insert_unique_unchecked(key, hash, value, hasher)
+            },
+            KeyRef::S24(key) => unsafe {
+                self.small24
+                    .insert_unique_unchecked(key, hash, value, hasher)
+            },
+            KeyRef::Large(key) => unsafe {
+                self.large.insert_unique_unchecked(key, hash, value, hasher)
+            },
+        }
+    }
+
+    /// # Safety
+    ///
+    /// See [`insert_unique_unchecked_hashed`](Self::insert_unique_unchecked_hashed).
+    pub unsafe fn insert_unique_unchecked<Q: Equivalent>(&mut self, key: Q, value: T) {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        unsafe { self.insert_unique_unchecked_hashed(key, self.hasher.hash_one(key), value) }
+    }
+
+    /// Inserts `pairs` without comparing their keys against each other or
+    /// against the keys already present, e.g. to merge the results of
+    /// partitions aggregated on disjoint keys.
+    ///
+    /// # Safety
+    ///
+    /// The keys of `pairs` must be distinct from each other and absent from
+    /// the map. Debug builds check it.
+    pub unsafe fn extend_unique<Q: Equivalent>(&mut self, pairs: impl IntoIterator<Item = (Q, T)>) {
+        for (key, value) in pairs {
+            unsafe { self.insert_unique_unchecked(key, value) }
+        }
+    }
+
+    /// Inserts `key` if it is absent and returns its new value, or returns
+    /// the present value along with `value` otherwise, like
+    /// [`HashMap::try_insert`](std::collections::HashMap::try_insert).
+    pub fn try_insert_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) -> Result<&mut T, OccupiedError<'_, T>> {
+        let mut value = Some(value);
+        match self.get_or_insert_with_hashed(key, hash, || value.take().unwrap()) {
+            (entry, true) => Ok(entry),
+            (entry, false) => Err(OccupiedError {
+                entry,
+                value: value.unwrap(),
+            }),
+        }
+    }
+
+    pub fn try_insert<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+    ) -> Result<&mut T, OccupiedError<'_, T>> {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.try_insert_hashed(key, self.hasher.hash_one(key), value)
+    }
+
+    /// Inserts `key` like [`insert_hashed`](Self::insert_hashed), but returns
+    /// an error instead of aborting if the slots or the copy of a large key
+    /// can't be allocated, leaving the map unchanged.
+    pub fn try_insert_alloc_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) -> Result<Option<T>, Error> {
+        let key = key.as_key_ref();
+        if let Some(old) = self.get_mut_hashed(key, hash) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+        self.try_reserve_for(key.key_len(), 1)?;
+        match key {
+            KeyRef::Large(key) => {
+                let key = self
+                    .key_alloc
+                    .try_alloc_slice_copy(key)
+                    .map_err(|_| Error::AllocFailed { bytes: key.len() })?;
+                let data = LargeSlotData::from_arena(key, hash, value);
+                self.large.insert_data(data, hash, &self.hasher);
+            }
+            key => {
+                self.insert_hashed(key, hash, value);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn try_insert_alloc<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+    ) -> Result<Option<T>, Error> {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.try_insert_alloc_hashed(key, self.hasher.hash_one(key), value)
+    }
+
+    pub fn get_or_insert_with_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        match key.as_key_ref() {
+            KeyRef::None => {
+                let inserted = self.none_key.is_none();
+                (self.none_key.get_or_insert_with(value), inserted)
+            }
+            KeyRef::S8(key) => self
+                .small8
+                .get_or_insert_with(key, hash, value, &self.hasher),
+            KeyRef::S16(key) => self
+                .small16
+                .get_or_insert_with(key, hash, value, &self.hasher),
+            KeyRef::S24(key) => self
+                .small24
+                .get_or_insert_with(key, hash, value, &self.hasher),
+            KeyRef::Large(key) => self
+                .large
+                .get_or_insert_with(key, hash, value, &self.hasher),
+        }
+    }
+
+    pub fn get_or_insert_with<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.get_or_insert_with_hashed(key, self.hasher.hash_one(key), value)
+    }
+
+    /// Inserts `key`, storing the slice itself in the large tier instead of
+    /// copying it into the arena, e.g. for keys already in an input arena that
+    /// outlives the map. Shorter keys are stored inline as usual.
+    pub fn insert_borrowed_hashed(&mut self, key: &'a [u8], hash: u64, value: T) -> Option<T> {
+        match KeyRef::from(key) {
+            KeyRef::Large(key) => self.large.insert_borrowed(key, hash, value, &self.hasher),
+            key => self.insert_hashed(key, hash, value),
+        }
+    }
+
+    pub fn insert_borrowed(&mut self, key: &'a [u8], value: T) -> Option<T> {
+        self.guard_probes();
+        self.insert_borrowed_hashed(key, self.hasher.hash_one(KeyRef::from(key)), value)
+    }
+
+    /// Inserts `key` with the value `value` builds from the key stored in the
+    /// arena, e.g. to keep it inside the value without looking it up again,
+    /// and returns the old value.
+    ///
+    /// A large key already present is reused. Other keys are copied into the
+    /// arena, including those of the inline tiers, which never are otherwise.
+    /// As they have no canonical copy, every call with a key of at most 24
+    /// bytes gets a fresh one, so that repeated upserts of such keys grow the
+    /// arena.
+    pub fn insert_with_key_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: impl FnOnce(&'a [u8]) -> T,
+    ) -> Option<T> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.replace(value(&[])),
+            KeyRef::Large(key) => match self.large.get_data(key, hash) {
+                Some(data) => {
+                    let value = value(data.arena_key());
+                    let old = self.large.get_mut(key, hash).unwrap();
+                    Some(std::mem::replace(old, value))
+                }
+                None => {
+                    let key = self.key_alloc.alloc_slice_copy(key);
+                    let data = LargeSlotData::from_arena(key, hash, value(key));
+                    self.large.insert_data(data, hash, &self.hasher);
+                    None
+                }
+            },
+            key => {
+                let stored = self.key_alloc.alloc_slice_copy(key.key());
+                self.insert_hashed(key, hash, value(stored))
+            }
+        }
+    }
+
+    pub fn insert_with_key<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce(&'a [u8]) -> T,
+    ) -> Option<T> {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.insert_with_key_hashed(key, self.hasher.hash_one(key), value)
+    }
+
+    pub fn remove_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> Option<T> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.take(),
+            KeyRef::S8(key) => self.small8.remove(key, hash, &self.hasher),
+            KeyRef::S16(key) => self.small16.remove(key, hash, &self.hasher),
+            KeyRef::S24(key) => self.small24.remove(key, hash, &self.hasher),
+            KeyRef::Large(key) => self.large.remove(key, hash, &self.hasher),
+        }
+    }
+
+    /// Same as [`remove_hashed`](Self::remove_hashed), but never shrinks the
+    /// tiers, for batches calling [`shrink_underloaded`](Self::shrink_underloaded)
+    /// once at the end.
+    pub(crate) fn take_hashed(&mut self, key: KeyRef, hash: u64) -> Option<T> {
+        match key {
+            KeyRef::None => self.none_key.take(),
+            KeyRef::S8(key) => self
+                .small8
+                .take_data(key, hash)
+                .map(common::SlotData::into_value),
+            KeyRef::S16(key) => self
+                .small16
+                .take_data(key, hash)
+                .map(common::SlotData::into_value),
+            KeyRef::S24(key) => self
+                .small24
+                .take_data(key, hash)
+                .map(common::SlotData::into_value),
+            KeyRef::Large(key) => self
+                .large
+                .take_data(key, hash)
+                .map(common::SlotData::into_value),
+        }
+    }
+
+    pub(crate) fn shrink_underloaded(&mut self) {
+        self.small8.shrink_underloaded(&self.hasher);
+        self.small16.shrink_underloaded(&self.hasher);
+        self.small24.shrink_underloaded(&self.hasher);
+        self.large.shrink_underloaded(&self.hasher);
+    }
+
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<T> {
+        let key = key.as_key_ref();
+        self.remove_hashed(key, self.hasher.hash_one(key))
+    }
+
+    /// Removes `key` from the map, returning the arena-resident key stored
+    /// along with its value.
+    pub fn remove_entry_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+    ) -> Option<(&'a [u8], T)> {
+        match key.as_key_ref() {
+            KeyRef::None => self.none_key.take().map(|value| (&[] as _, value)),
+            KeyRef::S8(key) => self.small8.remove_entry(key, hash, &self.hasher),
+            KeyRef::S16(key) => self.small16.remove_entry(key, hash, &self.hasher),
+            KeyRef::S24(key) => self.small24.remove_entry(key, hash, &self.hasher),
+            KeyRef::Large(key) => self.large.remove_entry(key, hash, &self.hasher),
+        }
+    }
+
+    pub fn remove_entry<Q: Equivalent>(&mut self, key: Q) -> Option<(&'a [u8], T)> {
+        let key = key.as_key_ref();
+        self.remove_entry_hashed(key, self.hasher.hash_one(key))
+    }
+}
+
+/// An opaque handle to an entry, for revisiting it without probing.
+///
+/// A handle stays valid until the tier of its entry resizes or is cleared, or
+/// the entry is removed, after which it is detected as stale. A handle also
+/// holds the hash of its key, so that a slot reused by another entry isn't
+/// mistaken for its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotHandle {
+    tier: KeyLen,
+    slot: common::RawSlot,
+    generation: u64,
+    hash: u64,
+}
+
+/// Inserts into `map` through the raw API to learn the slot of the entry.
+///
+/// If the key is present, its value is replaced if `replace` is set, and the
+/// old value or the rejected `value` is returned respectively.
+fn insert_in_tier<'a, D: common::SlotData<'a>, S: BuildHasher>(
+    map: &mut common::StringMap<'a, D>,
+    key: &[u8],
+    hash: u64,
+    value: D::Value,
+    replace: bool,
+    hasher: &S,
+) -> (common::RawSlot, u64, Option<D::Value>) {
+    match map.raw_find_or_free(hash, |data| data.matches(key, hash), hasher) {
+        Ok(slot) => {
+            let (_, old) = map.raw_get_mut(slot).unwrap().kv_mut();
+            let value = match replace {
+                true => std::mem::replace(old, value),
+                false => value,
+            };
+            (slot, map.generation(), Some(value))
+        }
+        Err(slot) => {
+            map.raw_insert(slot, key, hash, value);
+            (slot, map.generation(), None)
+        }
+    }
+}
+
+/// Returns whether `data`, found in the slot of `handle`, is its entry. Without
+/// `hasher`, the entry is trusted not to have been removed, e.g. while an
+/// [`OccupiedEntry`] borrows the map.
+fn holds_entry<'a, D: common::SlotData<'a>, S: BuildHasher>(
+    data: &D,
+    handle: SlotHandle,
+    hasher: Option<&S>,
+) -> bool {
+    hasher.is_none_or(|hasher| {
+        // Small slots don't store their hashes.
+        let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+        hash == handle.hash
+    })
+}
+
+fn get_in_tier<'m, 'a, D: common::SlotData<'a>, S: BuildHasher>(
+    map: &'m common::StringMap<'a, D>,
+    handle: SlotHandle,
+    hasher: Option<&S>,
+) -> Option<&'m D::Value> {
+    (map.generation() == handle.generation)
+        .then(|| map.raw_get(handle.slot))?
+        .filter(|data| holds_entry(*data, handle, hasher))
+        .map(|data| data.value())
+}
+
+fn get_mut_in_tier<'m, 'a, D: common::SlotData<'a>, S: BuildHasher>(
+    map: &'m mut common::StringMap<'a, D>,
+    handle: SlotHandle,
+    hasher: Option<&S>,
+) -> Option<&'m mut D::Value> {
+    if map.generation() != handle.generation {
+        return None;
+    }
+    (map.raw_get_mut(handle.slot))
+        .filter(|data| holds_entry(*data, handle, hasher))
+        .map(|data| data.kv_mut().1)
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    fn insert_handle_hashed(
+        &mut self,
+        key: KeyRef,
+        hash: u64,
+        value: T,
+        replace: bool,
+    ) -> (SlotHandle, Option<T>) {
+        let hasher = &self.hasher;
+        let (slot, generation, old) = match key {
+            KeyRef::None => {
+                let old = match &mut self.none_key {
+                    Some(old) if replace => Some(std::mem::replace(old, value)),
+                    Some(_) => Some(value),
+                    slot @ None => {
+                        *slot = Some(value);
+                        None
+                    }
+                };
+                (common::RawSlot::default(), 0, old)
+            }
+            KeyRef::S8(key) => insert_in_tier(&mut self.small8, key, hash, value, replace, hasher),
+            KeyRef::S16(key) => {
+                insert_in_tier(&mut self.small16, key, hash, value, replace, hasher)
+            }
+            KeyRef::S24(key) => {
+                insert_in_tier(&mut self.small24, key, hash, value, replace, hasher)
+            }
+            KeyRef::Large(key) => {
+                insert_in_tier(&mut self.large, key, hash, value, replace, hasher)
+            }
+        };
+        let handle = SlotHandle {
+            tier: key.key_len(),
+            slot,
+            generation,
+            hash,
+        };
+        (handle, old)
+    }
+
+    /// Same as [`insert_hashed`](Self::insert_hashed), but also returns a
+    /// handle to the entry.
+    pub fn insert_with_handle_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) -> (SlotHandle, Option<T>) {
+        self.insert_handle_hashed(key.as_key_ref(), hash, value, true)
+    }
+
+    pub fn insert_with_handle<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+    ) -> (SlotHandle, Option<T>) {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.insert_handle_hashed(key, self.hasher.hash_one(key), value, true)
+    }
+
+    /// Same as [`try_insert_hashed`](Self::try_insert_hashed), but also
+    /// returns a handle to the entry, new or existing. The rejected `value` is
+    /// given back if the key is present.
+    pub fn try_insert_with_handle_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) -> (SlotHandle, Option<T>) {
+        self.insert_handle_hashed(key.as_key_ref(), hash, value, false)
+    }
+
+    pub fn try_insert_with_handle<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+    ) -> (SlotHandle, Option<T>) {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        self.insert_handle_hashed(key, self.hasher.hash_one(key), value, false)
+    }
+
+    /// Returns a handle to the entry of `key`, if present.
+    pub fn handle_of<Q: Equivalent>(&self, key: Q) -> Option<SlotHandle> {
+        let key = key.as_key_ref();
+        let hash = self.hasher.hash_one(key);
+        let (slot, generation) = match key {
+            KeyRef::None => (self.none_key.is_some().then(common::RawSlot::default)?, 0),
+            KeyRef::S8(key) => (
+                self.small8.raw_find(hash, |data| data.matches(key, hash))?,
+                self.small8.generation(),
+            ),
+            KeyRef::S16(key) => (
+                self.small16
+                    .raw_find(hash, |data| data.matches(key, hash))?,
+                self.small16.generation(),
+            ),
+            KeyRef::S24(key) => (
+                self.small24
+                    .raw_find(hash, |data| data.matches(key, hash))?,
+                self.small24.generation(),
+            ),
+            KeyRef::Large(key) => (
+                self.large.raw_find(hash, |data| data.matches(key, hash))?,
+                self.large.generation(),
+            ),
+        };
+        Some(SlotHandle {
+            tier: key.key_len(),
+            slot,
+            generation,
+            hash,
+        })
+    }
+
+    /// Returns the value of the entry of `handle`, checking that it is still
+    /// there unless `trusted`.
+    fn value_by_handle(&self, handle: SlotHandle, trusted: bool) -> Option<&T> {
+        let hasher = (!trusted).then_some(&self.hasher);
+        match handle.tier {
+            KeyLen::None => self.none_key.as_ref(),
+            KeyLen::S8 => get_in_tier(&self.small8, handle, hasher),
+            KeyLen::S16 => get_in_tier(&self.small16, handle, hasher),
+            KeyLen::S24 => get_in_tier(&self.small24, handle, hasher),
+            KeyLen::Large => get_in_tier(&self.large, handle, hasher),
+        }
+    }
+
+    fn value_mut_by_handle(&mut self, handle: SlotHandle, trusted: bool) -> Option<&mut T> {
+        let hasher = (!trusted).then_some(&self.hasher);
+        match handle.tier {
+            KeyLen::None => self.none_key.as_mut(),
+            KeyLen::S8 => get_mut_in_tier(&mut self.small8, handle, hasher),
+            KeyLen::S16 => get_mut_in_tier(&mut self.small16, handle, hasher),
+            KeyLen::S24 => get_mut_in_tier(&mut self.small24, handle, hasher),
+            KeyLen::Large => get_mut_in_tier(&mut self.large, handle, hasher),
+        }
+    }
+
+    /// Returns the value of the entry of `handle` in O(1), or `None` if the
+    /// handle is detected as stale.
+    ///
+    /// The keys of the small tiers are hashed again to tell whether their
+    /// slots still hold the entry of the handle.
+    pub fn get_by_handle(&self, handle: SlotHandle) -> Option<&T> {
+        self.value_by_handle(handle, false)
+    }
+
+    pub fn get_mut_by_handle(&mut self, handle: SlotHandle) -> Option<&mut T> {
+        self.value_mut_by_handle(handle, false)
+    }
+}
+
+/// A view into a single entry of a [`StringMap`], as returned by
+/// [`StringMap::entry`].
+pub enum Entry<'m, 'a, 'k, T, S = RandomState> {
+    Occupied(OccupiedEntry<'m, 'a, 'k, T, S>),
+    Vacant(VacantEntry<'m, 'a, 'k, T, S>),
+}
+
+pub struct OccupiedEntry<'m, 'a, 'k, T, S = RandomState> {
+    map: &'m mut StringMap<'a, T, S>,
+    key: KeyRef<'k>,
+    hash: u64,
+    handle: SlotHandle,
+}
+
+/// An absent key, along with the free slot found for it.
+///
+/// Nothing is allocated until the entry is filled: a large key is only copied
+/// into the arena by [`insert`](Self::insert), so that dropping the entry
+/// leaves the arena untouched.
+pub struct VacantEntry<'m, 'a, 'k, T, S = RandomState> {
+    map: &'m mut StringMap<'a, T, S>,
+    key: KeyRef<'k>,
+    hash: u64,
+    slot: common::RawSlot,
+}
+
+/// Finds the slot of `key` in `map`, or a free slot to insert it into, along
+/// with the generation of the tier after growing it if needed.
+fn entry_in_tier<'a, D: common::SlotData<'a>, S: BuildHasher>(
+    map: &mut common::StringMap<'a, D>,
+    key: &[u8],
+    hash: u64,
+    hasher: &S,
+) -> (Result<common::RawSlot, common::RawSlot>, u64) {
+    let slot = map.raw_find_or_free(hash, |data| data.matches(key, hash), hasher);
+    (slot, map.generation())
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Returns the entry of `key`, for inserting or updating it in place.
+    ///
+    /// A vacant entry may grow the tier of `key` to make room for it, but
+    /// only copies a large key into the arena once it is filled.
+    pub fn entry_hashed<'k>(&mut self, key: KeyRef<'k>, hash: u64) -> Entry<'_, 'a, 'k, T, S> {
+        let hasher = &self.hasher;
+        let (slot, generation) = match key {
+            KeyRef::None => match self.none_key {
+                Some(_) => (Ok(common::RawSlot::default()), 0),
+                None => (Err(common::RawSlot::default()), 0),
+            },
+            KeyRef::S8(key) => entry_in_tier(&mut self.small8, key, hash, hasher),
+            KeyRef::S16(key) => entry_in_tier(&mut self.small16, key, hash, hasher),
+            KeyRef::S24(key) => entry_in_tier(&mut self.small24, key, hash, hasher),
+            KeyRef::Large(key) => entry_in_tier(&mut self.large, key, hash, hasher),
+        };
+        match slot {
+            Ok(slot) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                key,
+                hash,
+                handle: SlotHandle {
+                    tier: key.key_len(),
+                    slot,
+                    generation,
+                    hash,
+                },
+            }),
+            Err(slot) => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                hash,
+                slot,
+            }),
+        }
+    }
+
+    pub fn entry<'k>(&mut self, key: KeyRef<'k>) -> Entry<'_, 'a, 'k, T, S> {
+        self.guard_probes();
+        self.entry_hashed(key, self.hasher.hash_one(key))
+    }
+}
+
+impl<'m, 'a, 'k, T, S: BuildHasher> Entry<'m, 'a, 'k, T, S> {
+    pub fn key(&self) -> KeyRef<'k> {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: T) -> &'m mut T {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(self, default: impl FnOnce() -> T) -> &'m mut T {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'m mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    pub fn and_modify(mut self, f: impl FnOnce(&mut T)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'m, 'a, 'k, T, S: BuildHasher> OccupiedEntry<'m, 'a, 'k, T, S> {
+    /// Returns the key used to look up the entry, which equals the stored one.
+    pub fn key(&self) -> KeyRef<'k> {
+        self.key
+    }
+
+    pub fn get(&self) -> &T {
+        self.map.value_by_handle(self.handle, true).unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.map.value_mut_by_handle(self.handle, true).unwrap()
+    }
+
+    pub fn into_mut(self) -> &'m mut T {
+        self.map.value_mut_by_handle(self.handle, true).unwrap()
+    }
+
+    pub fn insert(&mut self, value: T) -> T {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> T {
+        self.map.remove_hashed(self.key, self.hash).unwrap()
+    }
+}
+
+impl<'m, 'a, 'k, T, S> VacantEntry<'m, 'a, 'k, T, S> {
+    pub fn key(&self) -> KeyRef<'k> {
+        self.key
+    }
+
+    pub fn into_key(self) -> KeyRef<'k> {
+        self.key
+    }
+
+    /// Fills the entry with `value`, copying a large key into the arena.
+    pub fn insert(self, value: T) -> &'m mut T {
+        let VacantEntry {
+            map,
+            key,
+            hash,
+            slot,
+        } = self;
+        match key {
+            KeyRef::None => map.none_key.insert(value),
+            KeyRef::S8(key) => map.small8.raw_insert(slot, key, hash, value).kv_mut().1,
+            KeyRef::S16(key) => map.small16.raw_insert(slot, key, hash, value).kv_mut().1,
+            KeyRef::S24(key) => map.small24.raw_insert(slot, key, hash, value).kv_mut().1,
+            KeyRef::Large(key) => map.large.raw_insert(slot, key, hash, value).kv_mut().1,
+        }
+    }
+
+    /// Fills the entry with `value`, keeping `key` itself for a large key
+    /// instead of copying it into the arena, like
+    /// [`StringMap::insert_borrowed`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` differs from the key of the entry.
+    pub fn insert_borrowed(self, key: &'a [u8], value: T) -> &'m mut T {
+        assert_eq!(key, self.key.key(), "Key differs from the entry's");
+        match self.key {
+            KeyRef::Large(_) => {
+                let VacantEntry {
+                    map, hash, slot, ..
+                } = self;
+                let data = LargeSlotData::from_arena(key, hash, value);
+                map.large.raw_insert_data(slot, data, hash).kv_mut().1
+            }
+            _ => self.insert(value),
+        }
+    }
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    pub fn iter_small8(&self) -> common::Iter<'_, SmallSlotData<T, 8>> {
+        self.small8.iter()
+    }
+
+    pub fn iter_small8_mut(&mut self) -> common::IterMut<'_, SmallSlotData<T, 8>> {
+        self.small8.iter_mut()
+    }
+
+    pub fn iter_small16(&self) -> common::Iter<'_, SmallSlotData<T, 16>> {
+        self.small16.iter()
+    }
+
+    pub fn iter_small16_mut(&mut self) -> common::IterMut<'_, SmallSlotData<T, 16>> {
+        self.small16.iter_mut()
+    }
+
+    pub fn iter_small24(&self) -> common::Iter<'_, SmallSlotData<T, 24>> {
+        self.small24.iter()
+    }
+
+    pub fn iter_small24_mut(&mut self) -> common::IterMut<'_, SmallSlotData<T, 24>> {
+        self.small24.iter_mut()
+    }
+
+    pub fn iter_large(&self) -> common::Iter<'_, LargeSlotData<'a, T>> {
+        self.large.iter()
+    }
+
+    pub fn iter_large_mut(&mut self) -> common::IterMut<'_, LargeSlotData<'a, T>> {
+        self.large.iter_mut()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        Iter {
+            none_key: self.none_key.as_ref(),
+            small8: self.small8.iter(),
+            small16: self.small16.iter(),
+            small24: self.small24.iter(),
+            large: self.large.iter(),
+        }
+    }
+
+    /// Calls `f` on every entry, in the order of [`iter`](Self::iter), with a
+    /// tight loop over each tier instead of an iterator's state, e.g. for
+    /// finalization passes over the whole map.
+    pub fn for_each(&self, mut f: impl FnMut(KeyRef<'_>, &T)) {
+        if let Some(value) = &self.none_key {
+            f(KeyRef::None, value);
+        }
+        self.small8.for_each(|key, value| f(KeyRef::S8(key), value));
+        self.small16
+            .for_each(|key, value| f(KeyRef::S16(key), value));
+        self.small24
+            .for_each(|key, value| f(KeyRef::S24(key), value));
+        self.large
+            .for_each(|key, value| f(KeyRef::Large(key), value));
+    }
+
+    /// Calls `f` on every entry like [`for_each`](Self::for_each), with the
+    /// values borrowed mutably.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(KeyRef<'_>, &mut T)) {
+        if let Some(value) = &mut self.none_key {
+            f(KeyRef::None, value);
+        }
+        (self.small8).for_each_mut(|key, value| f(KeyRef::S8(key), value));
+        (self.small16).for_each_mut(|key, value| f(KeyRef::S16(key), value));
+        (self.small24).for_each_mut(|key, value| f(KeyRef::S24(key), value));
+        (self.large).for_each_mut(|key, value| f(KeyRef::Large(key), value));
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, T> {
+        IterMut {
+            none_key: self.none_key.as_mut(),
+            small8: self.small8.iter_mut(),
+            small16: self.small16.iter_mut(),
+            small24: self.small24.iter_mut(),
+            large: self.large.iter_mut(),
+        }
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, without shrinking
+    /// the tiers.
+    pub fn retain(&mut self, mut f: impl FnMut(&[u8], &mut T) -> bool) {
+        if let Some(value) = &mut self.none_key {
+            if !f(&[], value) {
+                self.none_key = None;
+            }
+        }
+        self.small8.retain(&mut f);
+        self.small16.retain(&mut f);
+        self.small24.retain(&mut f);
+        self.large.retain(&mut f);
+    }
+}
+
+impl<'a, T, S> IntoIterator for StringMap<'a, T, S> {
+    type Item = (&'a [u8], T);
+
+    type IntoIter = IntoIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            none_key: self.none_key,
+            small8: self.small8.into_iter(),
+            small16: self.small16.into_iter(),
+            small24: self.small24.into_iter(),
+            large: self.large.into_iter(),
+        }
+    }
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Consumes the map, yielding every entry along with the hash of its key,
+    /// e.g. to insert it into another map with the same hasher through
+    /// [`insert_hashed`](Self::insert_hashed).
+    ///
+    /// The hashes stored by the large tier are reused, and the keys of the
+    /// other tiers are hashed once here.
+    pub fn into_iter_hashed(self) -> IntoIterHashed<'a, T, S> {
+        IntoIterHashed {
+            inner: IntoIter {
+                none_key: self.none_key,
+                small8: self.small8.into_iter(),
+                small16: self.small16.into_iter(),
+                small24: self.small24.into_iter(),
+                large: self.large.into_iter(),
+            },
+            hasher: self.hasher,
+        }
+    }
+}
+
+impl<'b, 'a, T, S> IntoIterator for &'b StringMap<'a, T, S> {
+    type Item = (KeyRef<'b>, &'b T);
+
+    type IntoIter = Iter<'b, 'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'b, 'a, T, S> IntoIterator for &'b mut StringMap<'a, T, S> {
+    type Item = (KeyRef<'b>, &'b mut T);
+
+    type IntoIter = IterMut<'b, 'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+pub struct Iter<'b, 'a, T> {
+    none_key: Option<&'b T>,
+    small8: common::Iter<'b, SmallSlotData<T, 8>>,
+    small16: common::Iter<'b, SmallSlotData<T, 16>>,
+    small24: common::Iter<'b, SmallSlotData<T, 24>>,
+    large: common::Iter<'b, LargeSlotData<'a, T>>,
+}
+
+impl<'b, 'a, T> Iterator for Iter<'b, 'a, T> {
+    type Item = (KeyRef<'b>, &'b T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.none_key.take() {
+            return Some((KeyRef::None, value));
+        }
+        {
+            self.small8
+                .next()
+                .map(|(key, value)| (KeyRef::S8(key), value))
+        }
+        .or_else(|| {
+            self.small16
+                .next()
+                .map(|(key, value)| (KeyRef::S16(key), value))
+        })
+        .or_else(|| {
+            self.small24
+                .next()
+                .map(|(key, value)| (KeyRef::S24(key), value))
+        })
+        .or_else(|| {
+            self.large
+                .next()
+                .map(|(key, value)| (KeyRef::Large(key), value))
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.none_key.is_some() as usize
+            + self.small8.len()
+            + self.small16.len()
+            + self.small24.len()
+            + self.large.len();
+        (len, Some(len))
+    }
+}
+
+impl<'b, 'a, T> Clone for Iter<'b, 'a, T> {
+    fn clone(&self) -> Self {
+        Iter {
+            none_key: self.none_key,
+            small8: self.small8.clone(),
+            small16: self.small16.clone(),
+            small24: self.small24.clone(),
+            large: self.large.clone(),
+        }
+    }
+}
+
+impl<'b, 'a, T: fmt::Debug> fmt::Debug for Iter<'b, 'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.clone()).finish()
+    }
+}
+
+impl<'b, 'a, T> ExactSizeIterator for Iter<'b, 'a, T> {}
+impl<'b, 'a, T> FusedIterator for Iter<'b, 'a, T> {}
+
+pub struct IterMut<'b, 'a, T> {
+    none_key: Option<&'b mut T>,
+    small8: common::IterMut<'b, SmallSlotData<T, 8>>,
+    small16: common::IterMut<'b, SmallSlotData<T, 16>>,
+    small24: common::IterMut<'b, SmallSlotData<T, 24>>,
+    large: common::IterMut<'b, LargeSlotData<'a, T>>,
+}
+
+impl<'b, 'a, T> Iterator for IterMut<'b, 'a, T> {
+    type Item = (KeyRef<'b>, &'b mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.none_key.take() {
+            return Some((KeyRef::None, value));
+        }
+        {
+            self.small8
+                .next()
+                .map(|(key, value)| (KeyRef::S8(key), value))
+        }
+        .or_else(|| {
+            self.small16
+                .next()
+                .map(|(key, value)| (KeyRef::S16(key), value))
+        })
+        .or_else(|| {
+            self.small24
+                .next()
+                .map(|(key, value)| (KeyRef::S24(key), value))
+        })
+        .or_else(|| {
+            self.large
+                .next()
+                .map(|(key, value)| (KeyRef::Large(key), value))
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.none_key.is_some() as usize
+            + self.small8.len()
+            + self.small16.len()
+            + self.small24.len()
+            + self.large.len();
+        (len, Some(len))
+    }
+}
+
+impl<'b, 'a, T> ExactSizeIterator for IterMut<'b, 'a, T> {}
+impl<'b, 'a, T> FusedIterator for IterMut<'b, 'a, T> {}
+
+pub struct IntoIter<'a, T> {
+    none_key: Option<T>,
+    small8: common::IntoIter<'a, SmallSlotData<T, 8>>,
+    small16: common::IntoIter<'a, SmallSlotData<T, 16>>,
+    small24: common::IntoIter<'a, SmallSlotData<T, 24>>,
+    large: common::IntoIter<'a, LargeSlotData<'a, T>>,
+}
+
+impl<'a, T> Iterator for IntoIter<'a, T> {
+    type Item = (&'a [u8], T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.none_key.take() {
+            return Some((&[], value));
+        }
+        { self.small8.next() }
+            .or_else(|| self.small16.next())
+            .or_else(|| self.small24.next())
+            .or_else(|| self.large.next())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.none_key.is_some() as usize
+            + self.small8.len()
+            + self.small16.len()
+            + self.small24.len()
+            + self.large.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for IntoIter<'a, T> {}
+impl<'a, T> FusedIterator for IntoIter<'a, T> {}
+
+/// The iterator of [`StringMap::into_iter_hashed`].
+pub struct IntoIterHashed<'a, T, S> {
+    inner: IntoIter<'a, T>,
+    hasher: S,
+}
+
+impl<'a, T, S> IntoIterHashed<'a, T, S> {
+    /// Returns the hasher of the map, once every entry has been consumed.
+    pub(crate) fn into_hasher(self) -> S {
+        self.hasher
+    }
+}
+
+impl<'a, T, S: BuildHasher> Iterator for IntoIterHashed<'a, T, S> {
+    type Item = (&'a [u8], u64, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let IntoIter {
+            none_key,
+            small8,
+            small16,
+            small24,
+            large,
+        } = &mut self.inner;
+        let hasher = &self.hasher;
+        if let Some(value) = none_key.take() {
+            return Some((&[], hasher.hash_one(KeyRef::None), value));
+        }
+        { small8.next_hashed(hasher) }
+            .or_else(|| small16.next_hashed(hasher))
+            .or_else(|| small24.next_hashed(hasher))
+            .or_else(|| large.next_hashed(hasher))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, T, S: BuildHasher> ExactSizeIterator for IntoIterHashed<'a, T, S> {}
+impl<'a, T, S: BuildHasher> FusedIterator for IntoIterHashed<'a, T, S> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::testing::WeakState;
+
+    #[test]
+    fn test_hash_map() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..50000 {
+            let value = rand::random::<[u8; 10]>();
+
+            map.insert(KeyRef::from(&value), Vec::from(value));
+            cmp.insert(value.into_iter().collect::<Vec<_>>(), Vec::from(value));
+        }
+
+        // Test conflict conditions
+        for _ in 0..100000 {
+            let value = rand::random::<[u8; 4]>();
+
+            map.insert(KeyRef::from(&value), Vec::from(value));
+            cmp.insert(value.into_iter().collect::<Vec<_>>(), Vec::from(value));
+        }
+
+        for (k, v) in map.iter() {
+            let value = cmp.remove(k.key());
+            assert_eq!(value.as_ref(), Some(v));
+        }
+        assert!(cmp.is_empty());
+    }
+
+    #[test]
+    fn test_rehash_with() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut keys = vec![];
+
+        for i in 0..10000usize {
+            let key = bump.alloc_slice_fill_with(i % 40, |_| rand::random::<u8>());
+            map.insert(KeyRef::from(&*key), i);
+            keys.push(&*key);
+        }
+        let len = map.len();
+
+        let hasher = RandomState::new();
+        let map = map.rehash_with(hasher.clone());
+        assert_eq!(map.len(), len);
+
+        for key in keys {
+            let hash = hasher.hash_one(KeyRef::from(key));
+            assert_eq!(
+                map.get_hashed(KeyRef::from(key), hash),
+                map.get(KeyRef::from(key))
+            );
+            assert!(map.get(KeyRef::from(key)).is_some());
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    fn test_validate() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..20000usize {
+            let key = (rand::random::<u16>() % 1000).to_string().repeat(i % 9);
+            match rand::random::<u8>() % 3 {
+                0 => drop(map.remove(KeyRef::from(key.as_bytes()))),
+                _ => drop(map.insert(KeyRef::from(key.as_bytes()), i)),
+            }
+            if i % 1000 == 0 {
+                map.validate();
+            }
+        }
+        map.validate();
+        map.rehash_with(RandomState::new()).validate();
+    }
+
+    #[test]
+    fn test_remove_entry() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let keys: [&[u8]; 5] = [
+            b"",
+            b"short",
+            b"sixteen bytes!!!",
+            b"twenty-four bytes long!!",
+            &[7; 100],
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(KeyRef::from(*key), i);
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            let (stored, value) = map.remove_entry(KeyRef::from(*key)).unwrap();
+            assert_eq!(stored, *key);
+            assert_eq!(value, i);
+            assert_eq!(map.remove_entry(KeyRef::from(*key)), None);
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let keys = ["", "short", "sixteen bytes!!!", "twenty-four bytes long!!"]
+            .map(String::from)
+            .into_iter()
+            .chain([String::from_utf8(vec![b'7'; 100]).unwrap()]);
+        for (i, key) in keys.enumerate() {
+            map.insert(KeyRef::from(key.as_bytes()), i);
+
+            assert_eq!(map.get(&key), Some(&i));
+            assert_eq!(map.get(key.as_str()), Some(&i));
+            assert_eq!(map.get(key.as_bytes()), Some(&i));
+            assert_eq!(map.get(key.clone().into_bytes()), Some(&i));
+            assert!(map.contains_key(KeyRef::from(key.as_bytes())));
+            *map.get_mut(&*key).unwrap() += 1;
+            assert_eq!(map.remove(key), Some(i + 1));
+        }
+        assert_eq!(map.get(b"short"), None);
+        assert!(map.is_empty());
+
+        map.insert(42u64, 42);
+        assert_eq!(map.get(42u64.to_ne_bytes()), Some(&42));
+        assert_eq!(map.iter_small8().count(), 1);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        map.reserve_hint([(4, 500), (12, 300), (20, 0), (100, 1000)]);
+        let caps = [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+            .map(|tier| map.capacity_for(tier));
+        assert!(caps[0] >= 750 && caps[1] >= 450 && caps[3] >= 1500);
+        assert_eq!(
+            caps[2],
+            StringMap::<(), RandomState>::new(&bump).capacity_for(KeyLen::S24)
+        );
+
+        for i in 0..500u32 {
+            map.insert(KeyRef::from(&i.to_ne_bytes()), i);
+        }
+        assert_eq!(map.capacity_for(KeyLen::S8), caps[0]);
+    }
+
+    #[test]
+    fn test_insert_unique_unchecked() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let keys = (0..20000u32)
+            .map(|i| i.to_string().repeat(i as usize % 7))
+            .collect::<std::collections::HashSet<_>>();
+        for key in &keys {
+            unsafe { map.insert_unique_unchecked(KeyRef::from(key.as_bytes()), key.len()) };
+        }
+        assert_eq!(map.len(), keys.len());
+        for key in &keys {
+            assert_eq!(map.get(KeyRef::from(key.as_bytes())), Some(&key.len()));
+        }
+    }
+
+    #[test]
+    fn test_extend_unique() {
+        let bump = Bump::new();
+        let partitions = (0..4)
+            .map(|p| {
+                let mut map = StringMap::new(&bump);
+                for i in (p..20000u32).step_by(4) {
+                    let key = i.to_string().repeat(i as usize % 7 + 1);
+                    *map.get_or_insert_with(key.as_bytes(), || 0).0 += i;
+                }
+                map
+            })
+            .collect::<Vec<_>>();
+
+        let mut map = StringMap::new(&bump);
+        for partition in &partitions {
+            unsafe { map.extend_unique(partition.iter().map(|(key, &value)| (key, value))) };
+        }
+        assert_eq!(map.len(), partitions.iter().map(StringMap::len).sum());
+        for (key, value) in partitions.iter().flatten() {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_slot_handle() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let keys = (0..1000u32)
+            .map(|i| i.to_string().repeat(i as usize % 7))
+            .collect::<std::collections::HashSet<_>>();
+        map.reserve_hint(keys.iter().map(|key| (key.len(), 1)));
+
+        let handles = keys
+            .iter()
+            .map(|key| {
+                let (handle, old) = map.insert_with_handle(key, key.len());
+                assert_eq!(old, None);
+                assert_eq!(map.handle_of(key), Some(handle));
+                (key, handle)
+            })
+            .collect::<Vec<_>>();
+        for &(key, handle) in &handles {
+            *map.get_mut_by_handle(handle).unwrap() += 1;
+            assert_eq!(map.get_by_handle(handle), Some(&(key.len() + 1)));
+
+            let (again, rejected) = map.try_insert_with_handle(key, 0);
+            assert_eq!((again, rejected), (handle, Some(0)));
+        }
+
+        for i in 1000..5000u32 {
+            map.insert(i.to_string().repeat(i as usize % 7), 0);
+        }
+        let stale = handles
+            .iter()
+            .filter(|(key, _)| !key.is_empty())
+            .all(|&(_, handle)| map.get_by_handle(handle).is_none());
+        assert!(stale);
+    }
+
+    #[test]
+    fn test_slot_handle_reused() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        map.set_shrink_policy(ShrinkPolicy::Never);
+        let key = |i: u32| i.to_string().repeat(i as usize % 7 + 1);
+
+        map.reserve_hint((0..2000).map(|i| (key(i).len(), 1)));
+        let old = (0..1000)
+            .map(|i| map.insert_with_handle(key(i), i).0)
+            .collect::<Vec<_>>();
+        for i in 0..1000 {
+            assert_eq!(map.remove(key(i)), Some(i));
+        }
+        let new = (1000..2000)
+            .map(|i| map.insert_with_handle(key(i), i).0)
+            .collect::<Vec<_>>();
+
+        // Some slots of the removed entries are taken by new ones, without
+        // their tiers being rebuilt.
+        let slot = |handle: &SlotHandle| (handle.tier, handle.slot, handle.generation);
+        assert!(old
+            .iter()
+            .any(|old| new.iter().any(|new| slot(new) == slot(old))));
+        for handle in old {
+            assert_eq!(map.get_by_handle(handle), None);
+            assert_eq!(map.get_mut_by_handle(handle), None);
+        }
+        for (i, handle) in (1000..2000).zip(new) {
+            assert_eq!(map.get_by_handle(handle), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_estimated_cardinality() {
+        for len in [6, 12, 20, 40] {
+            let bump = Bump::with_capacity(StringMap::<(), RandomState>::estimated_arena_bytes(
+                5000, len,
+            ));
+            let mut map = StringMap::with_estimated_cardinality(5000, len, &bump);
+            let tiers = [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large];
+            let capacities = tiers.map(|tier| map.capacity_for(tier));
+            let allocated = bump.allocated_bytes();
+
+            let lengths = len / 2..=len + len / 2;
+            for (i, key_len) in (0..5000u32).zip(lengths.cycle()) {
+                let key = format!("{i:0key_len$}");
+                map.insert(key.as_bytes(), i);
+            }
+            assert_eq!(map.len(), 5000);
+            assert_eq!(tiers.map(|tier| map.capacity_for(tier)), capacities);
+            assert_eq!(bump.allocated_bytes(), allocated);
+        }
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..1000usize {
+            map.insert(KeyRef::from(i.to_string().repeat(i % 10).as_bytes()), i);
+        }
+        for (_, value) in map.iter_mut() {
+            *value += 1;
+        }
+        // Every yielded reference stays valid while the others are used.
+        let mut values = (&mut map)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        for value in values.iter_mut().rev() {
+            **value *= 2;
+        }
+        // The map is still usable after the mutable borrows end.
+        map.insert(KeyRef::None, 0);
+
+        for (key, value) in &map {
+            if let KeyRef::None = key {
+                continue;
+            }
+            let i = *value / 2 - 1;
+            assert_eq!(key.key(), i.to_string().repeat(i % 10).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_iter_sparse() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..5000usize {
+            let key = i.to_string().repeat(i % 12);
+            map.insert(KeyRef::from(key.as_bytes()), i);
+            cmp.insert(key, i);
+        }
+        for i in (0..5000usize).filter(|i| i % 37 != 0) {
+            let key = i.to_string().repeat(i % 12);
+            assert_eq!(map.remove(KeyRef::from(key.as_bytes())), cmp.remove(&key));
+        }
+
+        for (_, value) in map.iter_mut() {
+            *value += 1;
+        }
+        assert_eq!(map.iter().len(), cmp.len());
+        for (key, value) in &map {
+            assert_eq!(cmp[std::str::from_utf8(key.key()).unwrap()] + 1, *value);
+        }
+        let mut values = map
+            .into_iter()
+            .map(|(_, value)| value - 1)
+            .collect::<Vec<_>>();
+        let mut expected = cmp.into_values().collect::<Vec<_>>();
+        values.sort();
+        expected.sort();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_repartition() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+        for i in 0..10000 {
+            let key = (0..rand::random::<usize>() % 40)
+                .map(|_| rand::random::<u8>())
+                .collect::<Vec<_>>();
+            map.insert(KeyRef::from(&*key), i);
+            cmp.insert(key, i);
+        }
+
+        let parts = map.repartition(3);
+        assert_eq!(parts.len(), 8);
+        assert_eq!(
+            parts.iter().map(|part| part.len()).sum::<usize>(),
+            cmp.len()
+        );
+        for (key, value) in &cmp {
+            let owners = parts
+                .iter()
+                .filter(|part| part.get(KeyRef::from(&**key)) == Some(value))
+                .count();
+            assert_eq!(owners, 1);
+        }
+        assert!(parts.iter().all(|part| part.len() < cmp.len() / 4));
+
+        let mut parts = StringMap::<u32, _>::new(&bump).repartition(0);
+        assert_eq!(parts.len(), 1);
+        assert!(parts.pop().unwrap().is_empty());
+
+        let mut map = StringMap::new(&bump);
+        let policy = ShrinkPolicy::Automatic {
+            min_load_percent: 10,
+        };
+        map.set_max_load_percent(90);
+        map.set_shrink_policy(policy);
+        for i in 0..1000u32 {
+            map.insert(i.to_string().repeat(i as usize % 5), i);
+        }
+        for part in map.repartition(2) {
+            assert_eq!(part.max_load_percent(), 90);
+            assert_eq!(part.shrink_policy(), policy);
+            assert_eq!(part.small24.max_load_percent(), 90);
+            assert_eq!(part.small24.shrink_policy(), policy);
+            assert_eq!(part.large.max_load_percent(), 90);
+            assert_eq!(part.large.shrink_policy(), policy);
+        }
+    }
+
+    #[test]
+    fn test_entry() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..10000 {
+            let value = rand::random::<u16>() as u64 % 500;
+            let key = b"k".repeat(value as usize % 40);
+            match map.entry(KeyRef::from(&key[..])) {
+                Entry::Occupied(entry) if value.is_multiple_of(3) => {
+                    assert_eq!(Some(entry.remove()), cmp.remove(&key));
+                }
+                entry => {
+                    entry.and_modify(|v| *v += 1).or_insert(value);
+                    cmp.entry(key).and_modify(|v| *v += 1).or_insert(value);
+                }
+            }
+        }
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(&key[..])), Some(value));
+        }
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+
+        // Vacant entries that are dropped don't copy their keys.
+        let used = bump.allocated_bytes() - bump.chunk_capacity();
+        for i in 0..100u8 {
+            let key = [i; 64];
+            assert!(matches!(map.entry(KeyRef::from(&key)), Entry::Vacant(_)));
+        }
+        assert_eq!(bump.allocated_bytes() - bump.chunk_capacity(), used);
+        let key = [b'v'; 64];
+        let Entry::Vacant(entry) = map.entry(KeyRef::from(&key)) else {
+            panic!("Key present");
+        };
+        *entry.insert(1) += 1;
+        assert_eq!(map.get(KeyRef::from(&key)), Some(&2));
+        assert_eq!(map.len(), cmp.len() + 1);
+
+        // Occupied entries never grow their tier, even when it is full.
+        let mut map = StringMap::new(&bump);
+        for i in 0..1000u32 {
+            let key = i.to_string();
+            map.insert(KeyRef::from(key.as_bytes()), i);
+            let capacity = map.capacity_for(KeyLen::S8);
+            assert!(matches!(
+                map.entry(KeyRef::from(key.as_bytes())),
+                Entry::Occupied(_)
+            ));
+            assert_eq!(map.capacity_for(KeyLen::S8), capacity);
+        }
+    }
+
+    #[test]
+    fn test_try_insert() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let keys = [&b""[..], b"abc", &[b'x'; 12], &[b'y'; 20], &[b'z'; 40]];
+
+        for (i, key) in keys.into_iter().enumerate() {
+            let key = KeyRef::from(key);
+            assert_eq!(*map.try_insert(key, i).unwrap(), i);
+            let err = map.try_insert(key, i + 10).unwrap_err();
+            assert_eq!((*err.entry, err.value), (i, i + 10));
+            *err.entry += 100;
+            assert_eq!(map.get(key), Some(&(i + 100)));
+        }
+        let err = map.try_insert(KeyRef::from(&b"abc"[..]), 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to insert 0, key already exists with value 101"
+        );
+        assert_eq!(map.len(), keys.len());
+    }
+
+    #[test]
+    fn test_try_insert_alloc() {
+        let bump = Bump::new();
+        bump.set_allocation_limit(Some(1 << 16));
+        let mut map = StringMap::new(&bump);
+
+        assert_eq!(
+            map.try_reserve_for(KeyLen::S8, usize::MAX / 2),
+            Err(Error::CapacityExhausted)
+        );
+        for i in 0..100 {
+            let key = format!("{i:08}");
+            let key = KeyRef::from(key.as_bytes());
+            assert_eq!(map.try_insert_alloc(key, i), Ok(None));
+            assert_eq!(map.try_insert_alloc(key, i + 1), Ok(Some(i)));
+        }
+
+        let key = vec![b'x'; 1 << 17];
+        assert_eq!(
+            map.try_insert_alloc(KeyRef::from(&key[..]), 0),
+            Err(Error::AllocFailed { bytes: 1 << 17 })
+        );
+        assert_eq!(map.len(), 100);
+        let key = [b'x'; 100];
+        assert_eq!(map.try_insert_alloc(KeyRef::from(&key[..]), 0), Ok(None));
+        assert_eq!(map.get(KeyRef::from(&key[..])), Some(&0));
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+    }
+
+    #[test]
+    fn test_high_load() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        map.set_max_load_percent(90);
+        let mut cmp = HashMap::new();
+        let mut max_load = 0f64;
+
+        for i in 0..50000u32 {
+            let key = format!("{:08}", rand::random::<u16>() % 30000);
+            let k = KeyRef::from(key.as_bytes());
+            match rand::random::<u8>() % 8 {
+                0 => assert_eq!(map.remove(k), cmp.remove(&key)),
+                1 => assert_eq!(map.get(k), cmp.get(&key)),
+                _ => assert_eq!(map.insert(k, i), cmp.insert(key, i)),
+            }
+            let load = map.len() as f64 / map.capacity_for(KeyLen::S8) as f64;
+            max_load = max_load.max(load);
+        }
+        assert!(max_load > 0.85, "{max_load}");
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+
+        assert_eq!(map.len(), cmp.len());
+        assert_eq!(map.iter().count(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(key.as_bytes())), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_shrink_policy() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let fill = |map: &mut StringMap<_, _>| {
+            for i in 0..1000 {
+                map.insert(KeyRef::from(format!("{i:08}").as_bytes()), i);
+            }
+        };
+        let drain = |map: &mut StringMap<_, _>| {
+            for i in 0..990 {
+                assert_eq!(
+                    map.remove(KeyRef::from(format!("{i:08}").as_bytes())),
+                    Some(i)
+                );
+            }
+        };
+
+        fill(&mut map);
+        let full = map.capacity_for(KeyLen::S8);
+        drain(&mut map);
+        assert!(map.capacity_for(KeyLen::S8) < full);
+
+        for policy in [ShrinkPolicy::Never, ShrinkPolicy::OnExplicitCall] {
+            map.set_shrink_policy(policy);
+            fill(&mut map);
+            drain(&mut map);
+            assert_eq!(map.capacity_for(KeyLen::S8), full);
+            map.shrink_to_fit();
+            assert_eq!(
+                map.capacity_for(KeyLen::S8) < full,
+                policy == ShrinkPolicy::OnExplicitCall
+            );
+        }
+
+        map.set_shrink_policy(ShrinkPolicy::Automatic {
+            min_load_percent: 5,
+        });
+        fill(&mut map);
+        drain(&mut map);
+        assert!(map.capacity_for(KeyLen::S8) < full);
+        for i in 990..1000 {
+            assert_eq!(
+                map.get(KeyRef::from(format!("{i:08}").as_bytes())),
+                Some(&i)
+            );
+        }
+    }
+
+    #[test]
+    fn test_into_iter_hashed() {
+        let bump = Bump::new();
+        let hasher = RandomState::new();
+        let mut map = StringMap::with_hasher(hasher.clone(), &bump);
+        let mut copy = StringMap::with_hasher(hasher.clone(), &bump);
+        for i in 0..5000 {
+            let key = i.to_string().repeat(i % 7);
+            map.insert(KeyRef::from(key.as_bytes()), i);
+        }
+        let len = map.len();
+
+        let iter = map.into_iter_hashed();
+        assert_eq!(iter.len(), len);
+        for (key, hash, value) in iter {
+            assert_eq!(hash, hasher.hash_one(KeyRef::from(key)));
+            assert_eq!(copy.insert_hashed(KeyRef::from(key), hash, value), None);
+        }
+        assert_eq!(copy.len(), len);
+        for i in 0..5000 {
+            let key = i.to_string().repeat(i % 7);
+            assert!(copy.get(KeyRef::from(key.as_bytes())).is_some());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let bump = Bump::new();
+        let mut cmp = HashMap::<Vec<u8>, u64>::new();
+        let mut maps = [(); 3].map(|_| StringMap::new(&bump));
+        for (i, map) in maps.iter_mut().enumerate() {
+            for _ in 0..5000 {
+                let key = (0..rand::random::<usize>() % 30)
+                    .map(|_| b'a' + rand::random::<u8>() % 3)
+                    .collect::<Vec<_>>();
+                *map.get_or_insert_with(KeyRef::from(&*key), || 0).0 += i as u64 + 1;
+                *cmp.entry(key).or_default() += i as u64 + 1;
+            }
+        }
+
+        let [mut map, other, third] = maps;
+        map.merge(other, |a, b| *a += b);
+        // Differently seeded maps are rehashed first.
+        map.merge(third, |a, b| *a += b);
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(&**key)), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_auto_reseed() {
+        let bump = Bump::new();
+        let mut map = StringMap::with_hasher(WeakState::default(), &bump);
+        map.set_auto_reseed(true);
+
+        for i in 0..5000usize {
+            map.insert(KeyRef::from(i.to_string().as_bytes()), i);
+        }
+        assert_eq!(map.reseeds(), 1);
+        assert!(map.max_probe() < MIN_PROBE_LIMIT);
+        for i in 0..5000usize {
+            assert_eq!(map.get(KeyRef::from(i.to_string().as_bytes())), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_cluster_rehash() {
+        /// Clears the low bits of the hashes until reseeded, so that keys
+        /// cluster at every 16th slot without ever colliding fully.
+        #[derive(Default)]
+        struct ClusteredState(Option<RandomState>);
+
+        impl BuildHasher for ClusteredState {
+            type Hasher = std::collections::hash_map::DefaultHasher;
+
+            fn build_hasher(&self) -> Self::Hasher {
+                unreachable!()
+            }
+
+            fn hash_one<H: Hash>(&self, x: H) -> u64 {
+                match &self.0 {
+                    None => {
+                        let fixed = std::hash::BuildHasherDefault::<Self::Hasher>::default();
+                        fixed.hash_one(x) & !0xf
+                    }
+                    Some(state) => state.hash_one(x),
+                }
+            }
+        }
+
+        impl Reseed for ClusteredState {
+            fn reseed(&mut self) {
+                self.0 = Some(RandomState::new());
+            }
+        }
+
+        let bump = Bump::new();
+        let mut map = StringMap::with_hasher(ClusteredState::default(), &bump);
+        map.set_auto_reseed(true);
+        map.set_max_mean_probe(Some(3.));
+
+        for i in 0..5000usize {
+            map.insert(KeyRef::from(i.to_string().as_bytes()), i);
+        }
+        assert_eq!(map.rehashes(), 1);
+        assert_eq!(map.reseeds(), 1);
+        assert!(map.small8.mean_probe().unwrap() < 3.);
+        for i in 0..5000usize {
+            assert_eq!(map.get(KeyRef::from(i.to_string().as_bytes())), Some(&i));
+        }
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+    }
+
+    #[test]
+    fn test_insert_with_key() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..10000usize {
+            let len = rand::random::<usize>() % 40;
+            let key = vec![rand::random::<u8>() % 4; len];
+            let old = map.insert_with_key(KeyRef::from(&*key), |stored| {
+                assert_eq!(stored, key);
+                (stored, i)
+            });
+            assert_eq!(old.map(|(_, i)| i), cmp.insert(key, i));
+        }
+
+        assert_eq!(map.len(), cmp.len());
+        for (key, &(stored, i)) in map.iter() {
+            assert_eq!(stored, key.key());
+            assert_eq!(cmp[key.key()], i);
+            if let KeyRef::Large(key) = key {
+                assert_eq!(stored.as_ptr(), key.as_ptr());
+            }
+        }
+
+        // Replacing the value of a large key doesn't copy it again.
+        for key in ["", "a key of the large tier, past 24 bytes"] {
+            map.insert_with_key(key, |stored| (stored, 0));
+            let used = bump.allocated_bytes() - bump.chunk_capacity();
+            for i in 0..10000 {
+                let old = map.insert_with_key(key, |stored| (stored, i));
+                assert_eq!(old.map(|(stored, _)| stored), Some(key.as_bytes()));
+            }
+            assert_eq!(bump.allocated_bytes() - bump.chunk_capacity(), used);
+        }
+    }
+
+    #[test]
+    fn test_insert_borrowed() {
+        let bump = Bump::new();
+        let input = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..10000usize {
+            let len = rand::random::<usize>() % 40;
+            let key = &*input.alloc_slice_fill_copy(len, rand::random::<u8>() % 4);
+            assert_eq!(map.insert_borrowed(key, i), cmp.insert(key, i));
+        }
+
+        assert_eq!(map.len(), cmp.len());
+        for (key, &i) in map.iter() {
+            assert_eq!(cmp[key.key()], i);
+            if let KeyRef::Large(key) = key {
+                let (stored, _) = cmp.get_key_value(key).unwrap();
+                assert_eq!(stored.as_ptr(), key.as_ptr());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..10000usize {
+            let key = [&i.to_ne_bytes()[..], &[0x5a; 32]].concat();
+            map.insert(KeyRef::from(&*key), i);
+            cmp.insert(key, i);
+        }
+        for i in 0..1000usize {
+            map.insert(KeyRef::from(&i.to_ne_bytes()[..4]), i);
+            cmp.insert(i.to_ne_bytes()[..4].to_vec(), i);
+        }
+        let live = map.key_bytes_live();
+        assert_eq!(live, 10000 * 40);
+
+        for i in (0..10000usize).step_by(2) {
+            let key = [&i.to_ne_bytes()[..], &[0x5a; 32]].concat();
+            assert_eq!(map.remove(KeyRef::from(&*key)), cmp.remove(&key));
+        }
+        assert_eq!(map.key_bytes_live(), live / 2);
+        assert!(map.key_bytes_dead() >= live / 2);
+
+        let fresh = Bump::new();
+        let map = map.compact_into(&fresh);
+        drop(bump);
+        assert_eq!(map.key_bytes_live(), live / 2);
+        assert!(map.key_bytes_dead() < live / 20);
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(&**key)), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_for_each() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..10000usize {
+            let len = rand::random::<usize>() % 40;
+            map.insert(KeyRef::from(&*vec![rand::random::<u8>() % 4; len]), i);
+        }
+        map.for_each_mut(|key, value| *value += key.key().len());
+
+        let mut entries = vec![];
+        map.for_each(|key, &value| entries.push((key.key().to_vec(), value)));
+        let expected = map
+            .iter()
+            .map(|(key, &value)| (key.key().to_vec(), value))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_drop_values() {
+        let bump = Bump::new();
+        let value = std::rc::Rc::new(());
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..1000usize {
+            map.insert(
+                KeyRef::from(i.to_string().repeat(i % 10).as_bytes()),
+                value.clone(),
+            );
+        }
+        let count = std::rc::Rc::strong_count(&value);
+        assert_eq!(count, map.len() + 1);
+
+        let clone = map.clone();
+        assert_eq!(std::rc::Rc::strong_count(&value), count * 2 - 1);
+        drop(clone);
+
+        for i in (0..1000usize).step_by(3) {
+            map.remove(KeyRef::from(i.to_string().repeat(i % 10).as_bytes()));
+        }
+        assert_eq!(std::rc::Rc::strong_count(&value), map.len() + 1);
+
+        let mut iter = map.clone().into_iter();
+        iter.next();
+        drop(iter);
+        assert_eq!(std::rc::Rc::strong_count(&value), map.len() + 1);
+
+        map.clear();
+        assert_eq!(std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_get_unchecked() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let keys = (0..5000usize)
+            .map(|i| (i, i.to_string().repeat(i % 9)))
+            .collect::<Vec<_>>();
+        for (i, key) in &keys {
+            map.insert(KeyRef::from(key.as_bytes()), *i);
+        }
+
+        for (_, key) in &keys {
+            let key = KeyRef::from(key.as_bytes());
+            let hash = map.hasher().hash_one(key);
+            let expected = map.get_hashed(key, hash).copied();
+            unsafe {
+                *map.get_unchecked_mut_hashed(key, hash) += 1;
+                assert_eq!(Some(*map.get_unchecked_hashed(key, hash) - 1), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_tiers() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..40usize {
+            map.insert(KeyRef::from(&*vec![1; i]), i);
+        }
+        for (_, value) in map.iter_small16_mut() {
+            *value *= 10;
+        }
+
+        assert_eq!(map.iter_small8().len(), 8);
+        assert!(map.iter_small8().all(|(key, value)| key.len() == *value));
+        assert!(map
+            .iter_small16()
+            .all(|(key, value)| key.len() * 10 == *value));
+        assert!(map
+            .iter_small24()
+            .all(|(key, _)| (17..=24).contains(&key.len())));
+        assert_eq!(
+            map.iter_large().map(|(_, value)| value).sum::<usize>(),
+            (25..40).sum()
+        );
+    }
+
+    #[test]
+    fn test_exact_size() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..1000usize {
+            map.insert(KeyRef::from(&*vec![i as u8; i % 50]), i);
+        }
+        let len = map.len();
+
+        let mut iter = map.iter();
+        for rem in (0..len).rev() {
+            iter.next().unwrap();
+            assert_eq!(iter.len(), rem);
+        }
+        assert!(iter.next().is_none());
+
+        assert_eq!(map.iter_mut().len(), len);
+        assert_eq!(map.into_iter().collect::<Vec<_>>().len(), len);
+    }
+
+    #[test]
+    fn test_recycle() {
+        let mut bump = Bump::new();
+        let mut recycled = StringMap::new(&bump).into_recycled();
+
+        for round in 0..3usize {
+            let mut map = recycled.reset(&mut bump);
+            assert!(map.is_empty());
+
+            for i in 0..1000usize {
+                map.insert(KeyRef::from(&*vec![round as u8; i % 40]), i);
+            }
+            assert_eq!(map.len(), 40);
+            let caps = [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+                .map(|tier| map.capacity_for(tier));
+
+            recycled = map.into_recycled();
+            let map = recycled.attach(&bump);
+            assert!(map.is_empty());
+            assert_eq!(
+                caps,
+                [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+                    .map(|tier| map.capacity_for(tier))
+            );
+            recycled = map.into_recycled();
+        }
+    }
+
+    #[test]
+    fn test_shallow_clone() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..1000usize {
+            map.insert(KeyRef::from(&*vec![1; i % 40]), i);
+        }
+        let allocated = bump.allocated_bytes();
+
+        let mut fork = map.clone();
+        assert_eq!(bump.allocated_bytes(), allocated);
+
+        for (_, value) in fork.iter_mut() {
+            *value = 0;
+        }
+        assert!(map.iter().all(|(key, value)| value % 40 == key.key().len()));
+
+        let (a, b) = (
+            map.iter_large().next().unwrap(),
+            fork.iter_large().next().unwrap(),
+        );
+        assert!(std::ptr::eq(a.0, b.0));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..1000usize {
+            map.insert(KeyRef::from(&i.to_ne_bytes()), i);
+        }
+        for i in 0..100usize {
+            map.get(KeyRef::from(
+                &*[&i.to_ne_bytes() as &[u8], &[0; 10]].concat(),
+            ));
+        }
+
+        let metrics = map.take_metrics();
+        assert_eq!(metrics.small8.probes, 1000);
+        assert_eq!(metrics.small24.probes, 100);
+        assert_eq!(metrics.small16, Default::default());
+        assert!(metrics.small8.resizes > 0);
+        assert!(metrics.small8.reinsertions >= 500);
+
+        let total = metrics.total();
+        assert_eq!(total.probe_lengths.iter().sum::<u64>(), total.probes);
+        assert_eq!(map.take_metrics(), Default::default());
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn test_probe_percentiles() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        for i in 0..100000usize {
+            map.insert(KeyRef::from(i.to_string().repeat(i % 5).as_bytes()), i);
+        }
+        let total = map.take_metrics().total();
+        assert_eq!(total.probe_samples.len(), 1024);
+
+        let (p50, p99) = (total.probe_p50(), total.probe_p99());
+        assert!(1 <= p50 && p50 <= p99);
+        // The median falls in the bucket of the histogram holding the middle
+        // probe, give or take one for the sampling error.
+        let mut seen = 0;
+        let median = total
+            .probe_lengths
+            .iter()
+            .position(|&count| {
+                seen += count;
+                seen * 2 >= total.probes
+            })
+            .unwrap();
+        let bucket = (usize::BITS - p50.leading_zeros()) as usize;
+        assert!(bucket.abs_diff(median) <= 1);
+    }
+
+    fn gen_record<'a, S: BuildHasher>(hasher: &S, bump: &'a Bump) -> (&'a [u8], u64) {
+        let len = rand::random::<usize>() % 1000;
+        let key = bump.alloc_slice_fill_with(len, |_| rand::random());
+
+        let hash = hasher.hash_one(KeyRef::from(&*key));
+
+        (key, hash)
+    }
+
+    fn gen_group_data<'a, S: BuildHasher>(hasher: &S, bump: &'a Bump) -> Vec<(&'a [u8], u64)> {
+        let mut data = vec![];
+        for _ in 0..10000 {
+            data.push(gen_record(hasher, bump))
+        }
+        data
+    }
+
+    #[bench]
+    fn bench_group(bencher: &mut test::Bencher) {
+        bencher.iter(|| {
+            let bump = Bump::new();
+            let mut map = StringMap::new(&bump);
+
+            let data = gen_group_data(map.hasher(), &bump);
+
+            data.into_iter().for_each(|(key, hash)| {
+                if let Err(err) = map.try_insert_hashed(KeyRef::from(key), hash, 1) {
+                    *err.entry += 1;
+                }
+            });
+
+            for (key, value) in map {
+                let _ = key;
+                let _ = value;
+            }
+        })
+    }
+
+    #[bench]
+    fn bench_group_accumulate(bencher: &mut test::Bencher) {
+        bencher.iter(|| {
+            let bump = Bump::new();
+            let mut map = StringMap::new(&bump);
+
+            let data = gen_group_data(map.hasher(), &bump);
+            let keys = data.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+
+            map.accumulate(&keys, &vec![(); keys.len()], |_| 1, |count, _| *count += 1);
+
+            for (key, value) in map {
+                let _ = key;
+                let _ = value;
+            }
+        })
+    }
+
+    #[bench]
+    fn bench_group_cmp(bencher: &mut test::Bencher) {
+        bencher.iter(|| {
+            let bump = Bump::new();
+            let mut map = HashMap::new();
+
+            let data = gen_group_data(map.hasher(), &bump);
+
+            data.into_iter().for_each(|(key, _)| {
+                if let Err(mut err) = map.try_insert(key, 1) {
+                    *err.entry.get_mut() += 1;
+                }
+            });
+
+            for (key, value) in map {
+                let _ = key;
+                let _ = value;
+            }
+        })
+    }
+
+    fn gen_iter_map(bump: &Bump) -> StringMap<'_, u64, RandomState> {
+        let mut map = StringMap::new(bump);
+        for (i, (key, _)) in gen_group_data(map.hasher(), bump).into_iter().enumerate() {
+            map.insert(KeyRef::from(key), i as u64);
+        }
+        map
+    }
+
+    #[bench]
+    fn bench_iter(bencher: &mut test::Bencher) {
+        let bump = Bump::new();
+        let map = gen_iter_map(&bump);
+        bencher.iter(|| map.iter().map(|(_, value)| value).sum::<u64>());
+    }
+
+    #[bench]
+    fn bench_for_each(bencher: &mut test::Bencher) {
+        let bump = Bump::new();
+        let map = gen_iter_map(&bump);
+        bencher.iter(|| {
+            let mut sum = 0;
+            map.for_each(|_, value| sum += value);
+            sum
+        });
     }
 
     #[allow(clippy::type_complexity)]
@@ -389,7 +3271,7 @@ mod tests {
             let [data1, data2] = gen_join_data(map.hasher(), &bump);
 
             data1.into_iter().for_each(|(key, hash)| {
-                map.try_insert_hashed(KeyRef::from(key), hash, ());
+                let _ = map.try_insert_hashed(KeyRef::from(key), hash, ());
             });
 
             let mut data = vec![];
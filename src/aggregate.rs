@@ -0,0 +1,263 @@
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+use std::ops::AddAssign;
+
+use crate::adaptive::{KeyRef, StringMap};
+
+/// The state of an aggregate function computed per group.
+pub trait AggregateState {
+    type Input;
+    type Output;
+
+    /// Creates the state of a new group from its first input.
+    fn init(input: &Self::Input) -> Self;
+
+    fn update(&mut self, input: &Self::Input);
+
+    /// Merges the state of the same group computed elsewhere, e.g. on another
+    /// partition.
+    fn merge(&mut self, other: Self);
+
+    fn finalize(self) -> Self::Output;
+}
+
+/// Primitive numeric inputs of the built-in aggregates.
+pub trait Number: Copy + PartialOrd + AddAssign {
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! impl_number {
+    ($($ty:ty),*) => {
+        $(impl Number for $ty {
+            #[inline]
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+        })*
+    };
+}
+impl_number!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+/// `COUNT(*)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Count<I>(u64, PhantomData<fn(I)>);
+
+impl<I> AggregateState for Count<I> {
+    type Input = I;
+    type Output = u64;
+
+    fn init(_: &I) -> Self {
+        Count(1, PhantomData)
+    }
+
+    fn update(&mut self, _: &I) {
+        self.0 += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+
+    fn finalize(self) -> u64 {
+        self.0
+    }
+}
+
+/// `SUM(x)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sum<N>(N);
+
+impl<N: Number> AggregateState for Sum<N> {
+    type Input = N;
+    type Output = N;
+
+    fn init(input: &N) -> Self {
+        Sum(*input)
+    }
+
+    fn update(&mut self, input: &N) {
+        self.0 += *input;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+
+    fn finalize(self) -> N {
+        self.0
+    }
+}
+
+/// `MIN(x)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Min<N>(N);
+
+impl<N: Number> AggregateState for Min<N> {
+    type Input = N;
+    type Output = N;
+
+    fn init(input: &N) -> Self {
+        Min(*input)
+    }
+
+    fn update(&mut self, input: &N) {
+        if *input < self.0 {
+            self.0 = *input;
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.update(&other.0)
+    }
+
+    fn finalize(self) -> N {
+        self.0
+    }
+}
+
+/// `MAX(x)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Max<N>(N);
+
+impl<N: Number> AggregateState for Max<N> {
+    type Input = N;
+    type Output = N;
+
+    fn init(input: &N) -> Self {
+        Max(*input)
+    }
+
+    fn update(&mut self, input: &N) {
+        if *input > self.0 {
+            self.0 = *input;
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.update(&other.0)
+    }
+
+    fn finalize(self) -> N {
+        self.0
+    }
+}
+
+/// `AVG(x)`, computed in `f64`.
+#[derive(Debug, Clone, Copy)]
+pub struct Avg<N> {
+    sum: f64,
+    count: u64,
+    marker: PhantomData<fn(N)>,
+}
+
+impl<N: Number> AggregateState for Avg<N> {
+    type Input = N;
+    type Output = f64;
+
+    fn init(input: &N) -> Self {
+        Avg {
+            sum: input.to_f64(),
+            count: 1,
+            marker: PhantomData,
+        }
+    }
+
+    fn update(&mut self, input: &N) {
+        self.sum += input.to_f64();
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    fn finalize(self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+impl<'a, A: AggregateState, S: BuildHasher> StringMap<'a, A, S> {
+    /// Updates the states of the groups of `keys` with `inputs`.
+    pub fn aggregate(&mut self, keys: &[&[u8]], inputs: &[A::Input]) {
+        self.accumulate(keys, inputs, A::init, A::update)
+    }
+
+    /// Merges the states of `other` into the states of the same groups.
+    pub fn merge_states<S2>(&mut self, other: StringMap<'_, A, S2>) {
+        for (key, state) in other {
+            if let Err(err) = self.try_insert(KeyRef::from(key), state) {
+                err.entry.merge(err.value);
+            }
+        }
+    }
+}
+
+impl<'a, A: AggregateState, S> StringMap<'a, A, S> {
+    /// Consumes the map, yielding the final result of every group.
+    pub fn finalize(self) -> impl Iterator<Item = (&'a [u8], A::Output)> {
+        self.into_iter().map(|(key, state)| (key, state.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bumpalo::Bump;
+
+    use super::*;
+
+    #[test]
+    fn test_builtin_aggregates() {
+        let bump = Bump::new();
+        let keys = (0..10000)
+            .map(|i| (i % 37).to_string().repeat(i % 5))
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_bytes()).collect::<Vec<_>>();
+        let inputs = (0..keys.len() as i64)
+            .map(|i| i * 7 % 1000 - 500)
+            .collect::<Vec<_>>();
+
+        let mut expected = HashMap::<&[u8], Vec<i64>>::new();
+        for (key, input) in keys.iter().zip(&inputs) {
+            expected.entry(key).or_default().push(*input);
+        }
+
+        let (left, right) = keys.split_at(4321);
+        let (left_inputs, right_inputs) = inputs.split_at(4321);
+
+        let mut sum = StringMap::<Sum<i64>, _>::new(&bump);
+        sum.aggregate(left, left_inputs);
+        let mut other = StringMap::<Sum<i64>, _>::new(&bump);
+        other.aggregate(right, right_inputs);
+        sum.merge_states(other);
+        for (key, sum) in sum.finalize() {
+            assert_eq!(sum, expected[key].iter().sum::<i64>());
+        }
+
+        let mut count = StringMap::<Count<i64>, _>::new(&bump);
+        count.aggregate(&keys, &inputs);
+        let mut min = StringMap::<Min<i64>, _>::new(&bump);
+        min.aggregate(&keys, &inputs);
+        let mut max = StringMap::<Max<i64>, _>::new(&bump);
+        max.aggregate(&keys, &inputs);
+        let mut avg = StringMap::<Avg<i64>, _>::new(&bump);
+        avg.aggregate(&keys, &inputs);
+
+        for (key, values) in &expected {
+            let key = KeyRef::from(*key);
+            assert_eq!(count.get(key).unwrap().finalize(), values.len() as u64);
+            assert_eq!(
+                min.get(key).unwrap().finalize(),
+                *values.iter().min().unwrap()
+            );
+            assert_eq!(
+                max.get(key).unwrap().finalize(),
+                *values.iter().max().unwrap()
+            );
+            let mean = values.iter().sum::<i64>() as f64 / values.len() as f64;
+            assert!((avg.get(key).unwrap().finalize() - mean).abs() < 1e-9);
+        }
+    }
+}
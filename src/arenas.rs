@@ -0,0 +1,139 @@
+use std::hash::BuildHasher;
+use std::thread;
+
+use bumpalo::Bump;
+
+use crate::adaptive::StringMap;
+
+/// A map moved to another thread.
+///
+/// Maps are not `Send` since their arena isn't, but merging maps only moves
+/// their slots around and never allocates from their arenas, which are only
+/// read through the keys of the large tier.
+pub(crate) struct Unshared<M>(pub(crate) M);
+
+// SAFETY: See above; the values and hashers are required to be `Send` by the
+// callers.
+unsafe impl<M> Send for Unshared<M> {}
+
+/// One key arena per shard, for building maps on several threads at once,
+/// since a single arena can't be shared between threads.
+///
+/// Each shard is built on its own thread, in its own arena, which no other
+/// thread allocates from. The arenas are owned together and borrowed for as
+/// long as the maps built from them, so that merging the maps keeps their
+/// keys where they are, and the merged map still hands out keys living as
+/// long as the arenas.
+pub struct ThreadArenas {
+    arenas: Vec<Bump>,
+}
+
+impl ThreadArenas {
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "No shards");
+        ThreadArenas {
+            arenas: (0..shards).map(|_| Bump::new()).collect(),
+        }
+    }
+
+    /// Creates one arena per thread the machine can run in parallel.
+    pub fn per_thread() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn shards(&self) -> usize {
+        self.arenas.len()
+    }
+
+    /// Returns the number of bytes allocated by all the arenas.
+    pub fn allocated_bytes(&self) -> usize {
+        self.arenas.iter().map(Bump::allocated_bytes).sum()
+    }
+
+    /// Builds a map per shard with `build`, each on its own thread and with
+    /// its own arena, and returns them in shard order.
+    pub fn build<'a, T: Send, S: BuildHasher + Clone + Send>(
+        &'a mut self,
+        hasher: S,
+        build: impl Fn(usize, &mut StringMap<'a, T, S>) + Sync,
+    ) -> Vec<StringMap<'a, T, S>> {
+        let build = &build;
+        thread::scope(|s| {
+            let shards = self.arenas.iter_mut().enumerate().map(|(shard, arena)| {
+                let hasher = hasher.clone();
+                s.spawn(move || {
+                    let arena: &'a Bump = arena;
+                    let mut map = StringMap::with_hasher(hasher, arena);
+                    build(shard, &mut map);
+                    // The arena is only borrowed by this map, which leaves
+                    // the thread once it is done with it.
+                    Unshared(map)
+                })
+            });
+            let shards = shards.collect::<Vec<_>>();
+            (shards.into_iter())
+                .map(|shard| shard.join().unwrap().0)
+                .collect()
+        })
+    }
+
+    /// Builds the shards like [`build`](Self::build), then merges them into
+    /// the first one, calling `combine` on the values of the same key.
+    ///
+    /// Keys are not copied by the merge, but stay in the arenas of their
+    /// shards.
+    pub fn build_merged<'a, T: Send, S: BuildHasher + Clone + Send>(
+        &'a mut self,
+        hasher: S,
+        build: impl Fn(usize, &mut StringMap<'a, T, S>) + Sync,
+        mut combine: impl FnMut(&mut T, T),
+    ) -> StringMap<'a, T, S> {
+        let mut shards = self.build(hasher, build).into_iter();
+        let mut map = shards.next().unwrap();
+        for shard in shards {
+            map.merge(shard, &mut combine);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_thread_arenas() {
+        let rows = (0..40000u32)
+            .map(|i| (i % 3001).to_string().repeat(i as usize % 13))
+            .collect::<Vec<_>>();
+        let mut cmp = HashMap::new();
+        for row in &rows {
+            *cmp.entry(row.as_bytes()).or_insert(0) += 1;
+        }
+
+        let mut arenas = ThreadArenas::new(4);
+        let chunk = rows.len().div_ceil(arenas.shards());
+        let map = arenas.build_merged(
+            RandomState::new(),
+            |shard, map| {
+                for row in rows.chunks(chunk).nth(shard).unwrap() {
+                    *map.get_or_insert_with(KeyRef::from(row.as_bytes()), || 0).0 += 1;
+                }
+            },
+            |count, other| *count += other,
+        );
+        assert_eq!(map.len(), cmp.len());
+        for (key, count) in &cmp {
+            assert_eq!(map.get(KeyRef::from(*key)), Some(count));
+        }
+
+        let keys = map.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(keys.len(), cmp.len());
+        assert!(keys.iter().all(|key| cmp.contains_key(key)));
+        assert!(arenas.allocated_bytes() > 0);
+    }
+}
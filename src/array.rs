@@ -1,111 +1,243 @@
-use std::fmt;
-use std::iter::{self, FusedIterator};
-
-pub struct StringMap<T> {
-    data: Vec<Option<T>>,
+use std::iter::FusedIterator;
+use std::{array, error, fmt, mem};
+
+use crate::error::Error;
+
+/// A map of keys of at most 2 bytes with room for `N` entries, stored inline
+/// so that it never allocates, e.g. as a scratch map on the stack.
+///
+/// Keys are read as big-endian numbers, so leading zero bytes don't count.
+///
+/// Maps can be built in const contexts, e.g. as lookup tables in statics:
+/// see [`with_entry`](Self::with_entry).
+pub struct StringMap<T, const N: usize> {
+    slots: [Option<(u16, T)>; N],
     len: usize,
 }
 
-fn to_index(key: &[u8]) -> usize {
-    key.iter().fold(0, |acc, &v| ((acc << 8) | v as usize))
+/// The error of inserting a new key into a full [`StringMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityFull;
+
+impl fmt::Display for CapacityFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "map is full")
+    }
+}
+
+impl error::Error for CapacityFull {}
+
+impl From<CapacityFull> for Error {
+    fn from(_: CapacityFull) -> Self {
+        Error::CapacityExhausted
+    }
+}
+
+const fn to_index(key: &[u8]) -> Option<u16> {
+    match *key {
+        [] => Some(0),
+        [b] => Some(b as u16),
+        [b0, b1] => Some(u16::from_be_bytes([b0, b1])),
+        _ => None,
+    }
 }
 
-fn from_index(index: usize) -> [u8; 2] {
-    (index as u16).to_be_bytes()
+fn from_index(index: u16) -> [u8; 2] {
+    index.to_be_bytes()
 }
 
-impl<T> StringMap<T> {
-    pub fn new() -> Self {
+impl<T, const N: usize> StringMap<T, N> {
+    pub const fn new() -> Self {
         StringMap {
-            data: iter::repeat_with(|| None).take(65536).collect(),
+            slots: [const { None }; N],
             len: 0,
         }
     }
-    pub fn len(&self) -> usize {
+
+    /// Inserts `key` into a map being built, typically in a const context,
+    /// where a panic is a compile error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map is full or already holds `key`, or if `key` is longer
+    /// than 2 bytes.
+    pub const fn with_entry(mut self, key: &[u8], value: T) -> Self {
+        let Some(key) = to_index(key) else {
+            panic!("Key is too long")
+        };
+        match self.probe(key) {
+            Some(Err(index)) => {
+                // The slot is free, so nothing is dropped.
+                mem::forget(self.slots[index].replace((key, value)));
+                self.len += 1;
+                self
+            }
+            Some(Ok(_)) => panic!("Key already present"),
+            None => panic!("Map is full"),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
         self.len
     }
 
     #[must_use]
-    pub fn is_empty(&self) -> bool {
+    pub const fn is_empty(&self) -> bool {
         self.len == 0
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<&T> {
-        self.data.get(to_index(key)).and_then(|slot| slot.as_ref())
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the first slot probed for `key`.
+    const fn home(key: u16) -> usize {
+        ((key as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize % N
+    }
+
+    /// Returns the slot of `key`, or the free slot ending its probe sequence.
+    const fn probe(&self, key: u16) -> Option<Result<usize, usize>> {
+        if N == 0 {
+            return None;
+        }
+        let home = Self::home(key);
+        let mut i = 0;
+        while i < N {
+            let index = (home + i) % N;
+            match &self.slots[index] {
+                Some((k, _)) if *k == key => return Some(Ok(index)),
+                Some(_) => {}
+                None => return Some(Err(index)),
+            }
+            i += 1;
+        }
+        None
+    }
+
+    fn find(&self, key: &[u8]) -> Option<usize> {
+        self.probe(to_index(key)?)?.ok()
+    }
+
+    pub const fn get(&self, key: &[u8]) -> Option<&T> {
+        let Some(key) = to_index(key) else {
+            return None;
+        };
+        match self.probe(key) {
+            Some(Ok(index)) => match &self.slots[index] {
+                Some((_, value)) => Some(value),
+                None => None,
+            },
+            _ => None,
+        }
     }
 
     pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
-        self.data
-            .get_mut(to_index(key))
-            .and_then(|slot| slot.as_mut())
+        let index = self.find(key)?;
+        self.slots[index].as_mut().map(|(_, value)| value)
     }
 
-    pub fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
-        let ret = self.data.get_mut(to_index(key))?.replace(value);
-        if ret.is_none() {
-            self.len += 1;
+    /// Returns the slot of `key`, or a free one to insert it into.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is longer than 2 bytes.
+    fn slot(&self, key: &[u8]) -> Result<Result<usize, usize>, CapacityFull> {
+        let key = to_index(key).unwrap_or_else(|| panic!("Key of {} bytes is too long", key.len()));
+        self.probe(key).ok_or(CapacityFull)
+    }
+
+    /// Inserts `key`, failing without inserting if the map is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is longer than 2 bytes.
+    pub fn insert(&mut self, key: &[u8], value: T) -> Result<Option<T>, CapacityFull> {
+        match self.slot(key)? {
+            Ok(index) => Ok(self.slots[index]
+                .as_mut()
+                .map(|(_, old)| mem::replace(old, value))),
+            Err(index) => {
+                self.slots[index] = Some((to_index(key).unwrap(), value));
+                self.len += 1;
+                Ok(None)
+            }
         }
-        ret
     }
 
-    pub fn try_insert(&mut self, key: &[u8], value: T) -> Option<(&mut T, T)> {
-        let slot = self.data.get_mut(to_index(key))?;
-        match slot {
-            Some(s) => Some((s, value)),
-            None => {
-                *slot = Some(value);
+    /// Inserts `key` if it is absent, or returns its value along with `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is longer than 2 bytes.
+    pub fn try_insert(
+        &mut self,
+        key: &[u8],
+        value: T,
+    ) -> Result<Option<(&mut T, T)>, CapacityFull> {
+        match self.slot(key)? {
+            Ok(index) => Ok(self.slots[index].as_mut().map(|(_, old)| (old, value))),
+            Err(index) => {
+                self.slots[index] = Some((to_index(key).unwrap(), value));
                 self.len += 1;
-                None
+                Ok(None)
             }
         }
     }
 
     pub fn remove(&mut self, key: &[u8]) -> Option<T> {
-        let ret = self.data.get_mut(to_index(key))?.take();
-        if ret.is_some() {
-            self.len -= 1;
+        let mut hole = self.find(key)?;
+        let (_, value) = self.slots[hole].take()?;
+        self.len -= 1;
+
+        // Move back the following entries of the run that were probed past
+        // the freed slot, so that no probe sequence gets cut.
+        let mut index = (hole + 1) % N;
+        while let Some((key, _)) = &self.slots[index] {
+            let home = Self::home(*key);
+            if (index + N - home) % N >= (index + N - hole) % N {
+                self.slots[hole] = self.slots[index].take();
+                hole = index;
+            }
+            index = (index + 1) % N;
         }
-        ret
+        Some(value)
     }
 
     pub fn iter(&self) -> Iter<T> {
         Iter {
-            bucket: &self.data,
-            index: 0,
+            bucket: &self.slots,
             rem: self.len,
         }
     }
 
     pub fn iter_mut(&mut self) -> IterMut<T> {
         IterMut {
-            bucket: &mut self.data,
-            index: 0,
+            bucket: &mut self.slots,
             rem: self.len,
         }
     }
 }
 
-impl<T> Default for StringMap<T> {
+impl<T, const N: usize> Default for StringMap<T, N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T> IntoIterator for StringMap<T> {
+impl<T, const N: usize> IntoIterator for StringMap<T, N> {
     type Item = ([u8; 2], T);
 
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, N>;
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter {
-            bucket: self.data.into_iter(),
-            index: 0,
+            bucket: self.slots.into_iter(),
             rem: self.len,
         }
     }
 }
 
-impl<'a, T> IntoIterator for &'a StringMap<T> {
+impl<'a, T, const N: usize> IntoIterator for &'a StringMap<T, N> {
     type Item = ([u8; 2], &'a T);
 
     type IntoIter = Iter<'a, T>;
@@ -115,7 +247,7 @@ impl<'a, T> IntoIterator for &'a StringMap<T> {
     }
 }
 
-impl<'a, T> IntoIterator for &'a mut StringMap<T> {
+impl<'a, T, const N: usize> IntoIterator for &'a mut StringMap<T, N> {
     type Item = ([u8; 2], &'a mut T);
 
     type IntoIter = IterMut<'a, T>;
@@ -126,8 +258,7 @@ impl<'a, T> IntoIterator for &'a mut StringMap<T> {
 }
 
 pub struct Iter<'a, T> {
-    bucket: &'a [Option<T>],
-    index: usize,
+    bucket: &'a [Option<(u16, T)>],
     rem: usize,
 }
 
@@ -141,14 +272,11 @@ impl<'a, T> Iterator for Iter<'a, T> {
         loop {
             match self.bucket.split_first() {
                 Some((slot, rem)) => {
-                    let index = self.index;
-
                     self.bucket = rem;
-                    self.index += 1;
 
-                    if let Some(data) = slot {
+                    if let Some((key, data)) = slot {
                         self.rem -= 1;
-                        break Some((from_index(index), data));
+                        break Some((from_index(*key), data));
                     }
                 }
                 None => break None,
@@ -171,7 +299,6 @@ impl<'a, T> Clone for Iter<'a, T> {
     fn clone(&self) -> Self {
         Self {
             bucket: self.bucket,
-            index: self.index,
             rem: self.rem,
         }
     }
@@ -181,8 +308,7 @@ impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
 impl<'a, T> FusedIterator for Iter<'a, T> {}
 
 pub struct IterMut<'a, T> {
-    bucket: &'a mut [Option<T>],
-    index: usize,
+    bucket: &'a mut [Option<(u16, T)>],
     rem: usize,
 }
 
@@ -190,7 +316,6 @@ impl<'a, T> IterMut<'a, T> {
     fn iter(&self) -> Iter<T> {
         Iter {
             bucket: self.bucket,
-            index: self.index,
             rem: self.rem,
         }
     }
@@ -204,16 +329,13 @@ impl<'a, T> Iterator for IterMut<'a, T> {
             return None;
         }
         loop {
-            match unsafe { &mut *(self.bucket as *mut [Option<T>]) }.split_first_mut() {
+            match mem::take(&mut self.bucket).split_first_mut() {
                 Some((slot, rem)) => {
-                    let index = self.index;
-
                     self.bucket = rem;
-                    self.index += 1;
 
-                    if let Some(data) = slot {
+                    if let Some((key, data)) = slot {
                         self.rem -= 1;
-                        break Some((from_index(index), data));
+                        break Some((from_index(*key), data));
                     }
                 }
                 None => break None,
@@ -235,23 +357,21 @@ impl<'a, T: fmt::Debug> fmt::Debug for IterMut<'a, T> {
 impl<'a, T> ExactSizeIterator for IterMut<'a, T> {}
 impl<'a, T> FusedIterator for IterMut<'a, T> {}
 
-pub struct IntoIter<T> {
-    bucket: std::vec::IntoIter<Option<T>>,
-    index: usize,
+pub struct IntoIter<T, const N: usize> {
+    bucket: array::IntoIter<Option<(u16, T)>, N>,
     rem: usize,
 }
 
-impl<T> IntoIter<T> {
+impl<T, const N: usize> IntoIter<T, N> {
     fn iter(&self) -> Iter<T> {
         Iter {
             bucket: self.bucket.as_slice(),
-            index: self.index,
             rem: self.rem,
         }
     }
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
     type Item = ([u8; 2], T);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -260,16 +380,11 @@ impl<T> Iterator for IntoIter<T> {
         }
         loop {
             match self.bucket.next() {
-                Some(slot) => {
-                    let index = self.index;
-
-                    self.index += 1;
-
-                    if let Some(data) = slot {
-                        self.rem -= 1;
-                        break Some((from_index(index), data));
-                    }
+                Some(Some((key, data))) => {
+                    self.rem -= 1;
+                    break Some((from_index(key), data));
                 }
+                Some(None) => {}
                 None => break None,
             }
         }
@@ -280,14 +395,14 @@ impl<T> Iterator for IntoIter<T> {
     }
 }
 
-impl<'a, T: fmt::Debug> fmt::Debug for IntoIter<T> {
+impl<T: fmt::Debug, const N: usize> fmt::Debug for IntoIter<T, N> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<T> ExactSizeIterator for IntoIter<T> {}
-impl<T> FusedIterator for IntoIter<T> {}
+impl<T, const N: usize> ExactSizeIterator for IntoIter<T, N> {}
+impl<T, const N: usize> FusedIterator for IntoIter<T, N> {}
 
 #[cfg(test)]
 mod tests {
@@ -297,16 +412,22 @@ mod tests {
 
     #[test]
     fn test_hash_map() {
-        let mut map = StringMap::new();
+        let mut map = StringMap::<_, 256>::new();
         let mut cmp = HashMap::new();
 
         // Test conflict conditions
         for _ in 0..100000 {
-            let value = rand::random::<u16>();
+            let value = rand::random::<u16>() % 512;
             let key = value.to_be_bytes();
 
-            map.insert(&key, value);
-            cmp.insert(key, value);
+            match rand::random::<u8>() % 3 {
+                0 => assert_eq!(map.remove(&key), cmp.remove(&key)),
+                _ if cmp.len() == 256 && !cmp.contains_key(&key) => {
+                    assert_eq!(map.insert(&key, value), Err(CapacityFull));
+                }
+                _ => assert_eq!(map.insert(&key, value), Ok(cmp.insert(key, value))),
+            }
+            assert_eq!(map.len(), cmp.len());
         }
 
         for (k, v) in map {
@@ -315,4 +436,54 @@ mod tests {
         }
         assert!(cmp.is_empty());
     }
+
+    #[test]
+    fn test_const() {
+        static OPS: StringMap<u8, 8> = StringMap::new()
+            .with_entry(b"+", 1)
+            .with_entry(b"-", 1)
+            .with_entry(b"*", 2)
+            .with_entry(b"**", 3);
+        const _: () = assert!(matches!(OPS.get(b"**"), Some(3)));
+
+        assert_eq!(OPS.len(), 4);
+        assert_eq!(OPS.get(b"*"), Some(&2));
+        assert_eq!(OPS.get(b"/"), None);
+        assert_eq!(OPS.iter().map(|(_, &v)| v as u32).sum::<u32>(), 7);
+    }
+
+    #[test]
+    fn test_full() {
+        let mut map = StringMap::<u8, 4>::new();
+        for i in 0..4 {
+            assert_eq!(map.try_insert(&[i], i), Ok(None));
+        }
+        assert_eq!(map.insert(&[4], 4), Err(CapacityFull));
+        assert_eq!(map.try_insert(&[0], 1), Ok(Some((&mut 0, 1))));
+        assert_eq!(map.remove(&[2]), Some(2));
+        assert_eq!(map.insert(&[4], 4), Ok(None));
+        assert_eq!(map.iter().count(), 4);
+
+        let mut empty = StringMap::<u8, 0>::new();
+        assert_eq!(empty.insert(b"", 0), Err(CapacityFull));
+        assert_eq!(empty.get(b""), None);
+    }
+    #[test]
+    fn test_long_key() {
+        let mut map = StringMap::<u8, 4>::new();
+        let long = b"\x01\0\0\0\0\0\0\0\x05";
+        assert_eq!(map.insert(b"\x05", 1), Ok(None));
+        assert_eq!(map.get(long), None);
+        assert_eq!(map.get_mut(long), None);
+        assert_eq!(map.remove(long), None);
+        assert_eq!(map.get(b"\x05"), Some(&1));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn test_insert_long_key() {
+        let mut map = StringMap::<u8, 4>::new();
+        map.insert(b"\x01\0\0\0\0\0\0\0\x05", 2).ok();
+    }
 }
@@ -0,0 +1,260 @@
+use std::hash::BuildHasher;
+use std::str;
+use std::sync::Arc;
+
+use arrow_array::builder::ArrayBuilder;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    ArrowDictionaryKeyType, BinaryViewType, GenericBinaryType, GenericStringType, StringViewType,
+};
+use arrow_array::{
+    AnyDictionaryArray, Array, ArrayRef, BinaryArray, BinaryViewArray, DictionaryArray,
+    LargeBinaryArray, LargeStringArray, RecordBatch, StringArray, StringViewArray,
+};
+use arrow_schema::{ArrowError, DataType, SchemaRef};
+
+use crate::adaptive::{KeyRef, StringMap};
+
+/// Collects the values of a string or binary array as byte slices.
+fn byte_values(array: &dyn Array) -> Option<Vec<Option<&[u8]>>> {
+    macro_rules! try_bytes {
+        ($($downcast:ident::<$ty:ty>),*) => {
+            $(if let Some(array) = array.$downcast::<$ty>() {
+                return Some(array.iter().map(|v| v.map(AsRef::as_ref)).collect());
+            })*
+        };
+    }
+    try_bytes!(
+        as_bytes_opt::<GenericStringType<i32>>,
+        as_bytes_opt::<GenericStringType<i64>>,
+        as_bytes_opt::<GenericBinaryType<i32>>,
+        as_bytes_opt::<GenericBinaryType<i64>>,
+        as_byte_view_opt::<StringViewType>,
+        as_byte_view_opt::<BinaryViewType>
+    );
+    None
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Groups the rows of a dictionary-encoded string or binary column.
+    ///
+    /// Each dictionary value is hashed at most once, and rows are then probed
+    /// by their dictionary index with the cached hash. The state of each new
+    /// key is created by `init` from the index of its first row, and the
+    /// following rows are folded into it with `fold`. Null rows are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the dictionary values are not strings or binaries.
+    pub fn insert_from_dictionary<K: ArrowDictionaryKeyType>(
+        &mut self,
+        array: &DictionaryArray<K>,
+        init: impl FnMut(usize) -> T,
+        fold: impl FnMut(&mut T, usize),
+    ) {
+        self.insert_from_any_dictionary(array, init, fold)
+    }
+
+    fn insert_from_any_dictionary(
+        &mut self,
+        array: &dyn AnyDictionaryArray,
+        mut init: impl FnMut(usize) -> T,
+        mut fold: impl FnMut(&mut T, usize),
+    ) {
+        let values = byte_values(array.values()).expect("Unsupported dictionary value type");
+        if values.is_empty() {
+            return;
+        }
+        let mut hashes = vec![None; values.len()];
+        let keys = array.keys();
+
+        for (row, index) in array.normalized_keys().into_iter().enumerate() {
+            let Some(key) = values[index].filter(|_| keys.is_valid(row)) else {
+                continue;
+            };
+            let key = KeyRef::from(key);
+            let hash = *hashes[index].get_or_insert_with(|| self.hasher().hash_one(key));
+
+            let (state, inserted) = self.get_or_insert_with_hashed(key, hash, || init(row));
+            if !inserted {
+                fold(state, row);
+            }
+        }
+    }
+
+    /// Groups the rows of a string or binary column, taking the dictionary
+    /// fast path of [`insert_from_dictionary`](Self::insert_from_dictionary)
+    /// if the column is dictionary-encoded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the column is neither strings nor binaries, nor a dictionary
+    /// of them.
+    pub fn insert_from_array(
+        &mut self,
+        array: &dyn Array,
+        mut init: impl FnMut(usize) -> T,
+        mut fold: impl FnMut(&mut T, usize),
+    ) {
+        if let Some(array) = array.as_any_dictionary_opt() {
+            return self.insert_from_any_dictionary(array, init, fold);
+        }
+
+        let values = byte_values(array).expect("Unsupported key array type");
+        for (row, key) in values.into_iter().enumerate() {
+            let Some(key) = key else { continue };
+            let (state, inserted) = self.get_or_insert_with(key, || init(row));
+            if !inserted {
+                fold(state, row);
+            }
+        }
+    }
+}
+
+/// Builds a string or binary array of type `data_type` from `keys`.
+fn key_array(keys: Vec<&[u8]>, data_type: &DataType) -> Result<ArrayRef, ArrowError> {
+    let strs = || {
+        keys.iter()
+            .map(|key| str::from_utf8(key))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ArrowError::InvalidArgumentError(format!("Key is not UTF-8: {err}")))
+    };
+    Ok(match data_type {
+        DataType::Binary => Arc::new(BinaryArray::from_iter_values(&keys)),
+        DataType::LargeBinary => Arc::new(LargeBinaryArray::from_iter_values(&keys)),
+        DataType::BinaryView => Arc::new(BinaryViewArray::from_iter_values(&keys)),
+        DataType::Utf8 => Arc::new(StringArray::from_iter_values(strs()?)),
+        DataType::LargeUtf8 => Arc::new(LargeStringArray::from_iter_values(strs()?)),
+        DataType::Utf8View => Arc::new(StringViewArray::from_iter_values(strs()?)),
+        data_type => {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "Unsupported key column type {data_type}"
+            )))
+        }
+    })
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Emits the map as a record batch in one pass, e.g. the groups built by
+    /// [`insert_from_array`](Self::insert_from_array) with their aggregates.
+    ///
+    /// The first field of `schema` is the key column, of any string or binary
+    /// type. The following ones are built by `columns`, to which `append`
+    /// adds the cells of every value, in the same order as the keys.
+    ///
+    /// Fails if the key column is of another type or gets a key that isn't
+    /// UTF-8, or if the columns don't match the schema.
+    pub fn to_record_batch(
+        &self,
+        schema: SchemaRef,
+        mut columns: Vec<Box<dyn ArrayBuilder>>,
+        mut append: impl FnMut(&T, &mut [Box<dyn ArrayBuilder>]),
+    ) -> Result<RecordBatch, ArrowError> {
+        let key_type = match schema.fields().first() {
+            Some(field) => field.data_type(),
+            None => {
+                return Err(ArrowError::SchemaError(
+                    "Schema has no key column".to_string(),
+                ))
+            }
+        };
+
+        let mut keys = Vec::with_capacity(self.len());
+        for (key, value) in self.iter() {
+            keys.push(key.key());
+            append(value, &mut columns);
+        }
+
+        let arrays = [key_array(keys, key_type)?]
+            .into_iter()
+            .chain(columns.iter_mut().map(|column| column.finish()))
+            .collect();
+        RecordBatch::try_new(schema, arrays)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arrow_array::builder::{Float64Builder, UInt64Builder};
+    use arrow_array::types::{Float64Type, Int32Type, UInt64Type};
+    use arrow_schema::{Field, Schema};
+    use bumpalo::Bump;
+
+    use super::*;
+
+    #[test]
+    fn test_dictionary() {
+        let rows = (0..10000)
+            .map(|i| (i % 7 != 0).then(|| (i % 37).to_string().repeat(i % 5)))
+            .collect::<Vec<_>>();
+        let mut cmp = HashMap::new();
+        for row in rows.iter().flatten() {
+            *cmp.entry(row.as_bytes()).or_insert(0) += 1;
+        }
+
+        let dictionary = rows
+            .iter()
+            .map(Option::as_deref)
+            .collect::<DictionaryArray<Int32Type>>();
+        let plain = rows.iter().map(Option::as_deref).collect::<StringArray>();
+
+        let bump = Bump::new();
+        for array in [&dictionary as &dyn Array, &plain] {
+            let mut map = StringMap::new(&bump);
+            map.insert_from_array(array, |_| 1, |count, _| *count += 1);
+
+            assert_eq!(map.len(), cmp.len());
+            for (key, count) in &cmp {
+                assert_eq!(map.get(key), Some(count));
+            }
+        }
+    }
+    #[test]
+    fn test_record_batch() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        for i in 0..1000u64 {
+            let (count, sum) = map.get_or_insert_with((i % 37).to_string(), || (0, 0.)).0;
+            *count += 1;
+            *sum += i as f64;
+        }
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("count", DataType::UInt64, false),
+            Field::new("avg", DataType::Float64, false),
+        ]));
+        let columns: Vec<Box<dyn ArrayBuilder>> = vec![
+            Box::new(UInt64Builder::new()),
+            Box::new(Float64Builder::new()),
+        ];
+        let batch = map
+            .to_record_batch(schema.clone(), columns, |&(count, sum), columns| {
+                let [counts, avgs] = columns else {
+                    unreachable!()
+                };
+                let counts = counts.as_any_mut().downcast_mut::<UInt64Builder>();
+                counts.unwrap().append_value(count);
+                let avgs = avgs.as_any_mut().downcast_mut::<Float64Builder>();
+                avgs.unwrap().append_value(sum / count as f64);
+            })
+            .unwrap();
+
+        assert_eq!(batch.num_rows(), map.len());
+        let keys = batch.column(0).as_string::<i32>();
+        let counts = batch.column(1).as_primitive::<UInt64Type>();
+        let avgs = batch.column(2).as_primitive::<Float64Type>();
+        for row in 0..batch.num_rows() {
+            let &(count, sum) = map.get(keys.value(row)).unwrap();
+            assert_eq!(counts.value(row), count);
+            assert_eq!(avgs.value(row), sum / count as f64);
+        }
+
+        let mut map = StringMap::new(&bump);
+        map.insert(&b"\xff"[..], (1, 0.));
+        let columns: Vec<Box<dyn ArrayBuilder>> = Vec::new();
+        assert!(map.to_record_batch(schema, columns, |_, _| {}).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{AsKeyBytes, KeyRef, StringMap};
+use crate::common::{SlotAccess, SlotData};
+use crate::small;
+
+/// The longest key stored inline, as in the largest small tier.
+const INLINE_KEY: usize = 24;
+
+type InlineSlot<T> = small::SlotData<T, INLINE_KEY>;
+
+/// A map of up to `N` short keys stored inline and found by a linear scan,
+/// that moves its entries into a [`StringMap`] once they don't fit any more,
+/// like a `SmallVec`, for maps that are usually tiny.
+///
+/// Entries stay inline while there are at most `N` of them, all of at most 24
+/// bytes. Afterwards, the map stays a [`StringMap`] even if entries are
+/// removed.
+pub struct AutoStringMap<'a, T, const N: usize, S = RandomState> {
+    /// The first `len` slots hold the entries while they are inline.
+    inline: [Option<InlineSlot<T>>; N],
+    len: usize,
+    heap: Option<StringMap<'a, T, S>>,
+    /// The hasher of the heap map, until it is created.
+    hasher: Option<S>,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T, const N: usize, S> AutoStringMap<'a, T, N, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        AutoStringMap {
+            inline: [const { None }; N],
+            len: 0,
+            heap: None,
+            hasher: Some(hasher),
+            key_alloc,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        match &self.heap {
+            Some(map) => map.hasher(),
+            None => self.hasher.as_ref().unwrap(),
+        }
+    }
+
+    /// Returns whether the entries are still stored inline.
+    pub fn is_inline(&self) -> bool {
+        self.heap.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.heap {
+            Some(map) => map.len(),
+            None => self.len,
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &T)> + '_ {
+        let inline = { self.inline[..self.len].iter() }
+            .flatten()
+            .map(|slot| (slot.key(), slot.value()));
+        let heap = { self.heap.iter() }
+            .flat_map(|map| map.iter())
+            .map(|(key, value)| (key.key(), value));
+        inline.chain(heap)
+    }
+
+    /// Returns the index of the inline slot of `key`.
+    fn find_inline(&self, key: &[u8]) -> Option<usize> {
+        if key.len() > INLINE_KEY {
+            return None;
+        }
+        { self.inline[..self.len].iter() }
+            .position(|slot| slot.as_ref().is_some_and(|slot| slot.matches(key, 0)))
+    }
+}
+
+impl<'a, T, const N: usize> AutoStringMap<'a, T, N, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, const N: usize, S: BuildHasher> AutoStringMap<'a, T, N, S> {
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        match &self.heap {
+            Some(map) => map.get(key),
+            None => Some(self.inline[self.find_inline(key)?].as_ref()?.value()),
+        }
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        if self.heap.is_none() {
+            let index = self.find_inline(key)?;
+            return Some(self.inline[index].as_mut()?.kv_mut().1);
+        }
+        self.heap.as_mut().unwrap().get_mut(key)
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts `key`, moving the entries into a [`StringMap`] first if there
+    /// are already `N` of them or if it is too long to be stored inline.
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        if self.heap.is_none() {
+            if let Some(old) = self.get_mut(key) {
+                return Some(std::mem::replace(old, value));
+            }
+            if self.len < N && key.len() <= INLINE_KEY {
+                let slot = InlineSlot::new(self.key_alloc, key, 0, value);
+                self.inline[self.len] = Some(slot);
+                self.len += 1;
+                return None;
+            }
+        }
+        self.promote().insert(KeyRef::from(key), value)
+    }
+
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        if self.heap.is_none() {
+            let index = self.find_inline(key)?;
+            self.len -= 1;
+            self.inline.swap(index, self.len);
+            return self.inline[self.len].take().map(InlineSlot::into_value);
+        }
+        self.heap.as_mut().unwrap().remove(key)
+    }
+
+    /// Returns the heap map, moving the entries stored inline into it.
+    #[cold]
+    fn promote(&mut self) -> &mut StringMap<'a, T, S> {
+        if self.heap.is_none() {
+            let hasher = self.hasher.take().unwrap();
+            let mut map = StringMap::with_hasher(hasher, self.key_alloc);
+            for slot in self.inline[..self.len].iter_mut() {
+                let slot = slot.take().unwrap();
+                let mut key = [0; INLINE_KEY];
+                let len = slot.key().len();
+                key[..len].copy_from_slice(slot.key());
+                map.insert(KeyRef::from(&key[..len]), slot.into_value());
+            }
+            self.len = 0;
+            self.heap = Some(map);
+        }
+        self.heap.as_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_auto_map() {
+        let bump = Bump::new();
+        let mut map = AutoStringMap::<_, 8>::new(&bump);
+        let mut cmp = HashMap::new();
+
+        let key = |i: u8| format!("key number {i}").into_bytes();
+        for i in 0..8u8 {
+            assert_eq!(map.insert(key(i), i), None);
+            cmp.insert(key(i), i);
+        }
+        assert_eq!(map.insert(key(0), 0), Some(0));
+        assert_eq!(map.remove(key(3)), Some(3));
+        assert_eq!(map.insert(key(3), 3), None);
+        assert!(map.is_inline());
+        assert_eq!(map.insert(b"\0a", 8), None);
+        cmp.insert(b"\0a".to_vec(), 8);
+        assert!(!map.is_inline());
+
+        for i in 0..10000u32 {
+            let key = vec![(i % 3) as u8; rand::random::<usize>() % 30];
+            match rand::random::<u8>() % 3 {
+                0 => assert_eq!(map.remove(&key), cmp.remove(&key)),
+                _ => assert_eq!(map.insert(&key, i as u8), cmp.insert(key, i as u8)),
+            }
+        }
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in map.iter() {
+            assert_eq!(cmp.get(key), Some(value));
+        }
+
+        let mut map = AutoStringMap::<_, 4>::new(&bump);
+        let keys = [&b""[..], b"\0", b"\0\0", b"twenty-four bytes of key"];
+        for key in keys {
+            map.insert(key, key.len());
+        }
+        assert!(map.is_inline());
+        assert_eq!(map.get(b"\0\0\0"), None);
+        let mut stored = map.iter().map(|(key, _)| key).collect::<Vec<_>>();
+        stored.sort();
+        assert_eq!(stored, keys);
+        map.insert(b"a key of more than 24 bytes", 0);
+        assert!(!map.is_inline());
+        for key in keys {
+            assert_eq!(map.get(key), Some(&key.len()));
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::hash::BuildHasher;
+use std::mem;
+
+use crate::adaptive::{KeyRef, StringMap};
+use crate::simd::BatchState;
+
+const ACCUMULATE_CHUNK: usize = 16;
+/// The number of selected keys gathered at a time to be hashed together.
+const SELECTED_CHUNK: usize = 64;
+
+/// Iterates over the rows of `rows` picked by `selection`, or all of them if
+/// there is no selection vector.
+pub(crate) fn selected<'k, K>(
+    rows: &'k [K],
+    selection: Option<&'k [u32]>,
+) -> impl Iterator<Item = &'k K> + 'k {
+    let (all, picked) = match selection {
+        Some(selection) => (None, Some(selection.iter().map(|&row| &rows[row as usize]))),
+        None => (Some(rows.iter()), None),
+    };
+    all.into_iter()
+        .flatten()
+        .chain(picked.into_iter().flatten())
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Hashes the keys selected by `selection` (or all of them) into `hashes`,
+    /// in selection order.
+    pub fn hash_batch(&self, keys: &[&[u8]], selection: Option<&[u32]>, hashes: &mut Vec<u64>) {
+        hashes.clear();
+        hashes.extend(
+            selected(keys, selection).map(|key| self.hasher().hash_one(KeyRef::from(*key))),
+        );
+    }
+
+    /// Probes the keys selected by `selection` (or all of them) with their
+    /// precomputed `hashes`, writing the results into `out` in selection
+    /// order.
+    pub fn get_batch_hashed<'m>(
+        &'m self,
+        keys: &[&[u8]],
+        selection: Option<&[u32]>,
+        hashes: &[u64],
+        out: &mut Vec<Option<&'m T>>,
+    ) {
+        out.clear();
+        out.extend(
+            selected(keys, selection)
+                .zip(hashes)
+                .map(|(key, &hash)| self.get_hashed(KeyRef::from(*key), hash)),
+        );
+    }
+
+    /// Hashes and probes the keys selected by `selection` (or all of them),
+    /// writing the results into `out` in selection order.
+    ///
+    /// Each key is probed right after being hashed, so no hashes are
+    /// buffered; see [`hash_batch`](Self::hash_batch) and
+    /// [`get_batch_hashed`](Self::get_batch_hashed) to keep them.
+    pub fn get_batch<'m>(
+        &'m self,
+        keys: &[&[u8]],
+        selection: Option<&[u32]>,
+        out: &mut Vec<Option<&'m T>>,
+    ) {
+        out.clear();
+        out.extend(selected(keys, selection).map(|key| self.get(KeyRef::from(*key))));
+    }
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Hashes a chunk of `keys` into `hashes` and prefetches their slots.
+    fn prefetch_chunk(&self, keys: &[&[u8]], hashes: &mut [u64; ACCUMULATE_CHUNK]) {
+        for (key, hash) in keys.iter().zip(hashes) {
+            let key = KeyRef::from(*key);
+            *hash = self.hasher().hash_one(key);
+            self.prefetch_hashed(key, *hash);
+        }
+    }
+
+    /// Groups `inputs` by `keys` in one fused pass: the state of each new key
+    /// is created by `init` from its first input, and subsequent inputs are
+    /// folded into the existing state with `fold`.
+    ///
+    /// Keys are hashed and their slots prefetched a chunk ahead of probing:
+    /// the next chunk is prefetched before the current one is probed.
+    pub fn accumulate<I>(
+        &mut self,
+        keys: &[&[u8]],
+        inputs: &[I],
+        mut init: impl FnMut(&I) -> T,
+        mut fold: impl FnMut(&mut T, &I),
+    ) {
+        assert_eq!(keys.len(), inputs.len());
+
+        let (mut hashes, mut next) = ([0; ACCUMULATE_CHUNK], [0; ACCUMULATE_CHUNK]);
+        let mut chunks = { keys.chunks(ACCUMULATE_CHUNK) }
+            .zip(inputs.chunks(ACCUMULATE_CHUNK))
+            .peekable();
+        if let Some((keys, _)) = chunks.peek() {
+            self.prefetch_chunk(keys, &mut next);
+        }
+        while let Some((keys, inputs)) = chunks.next() {
+            let reseeds = self.reseeds();
+            self.guard_probes();
+            if self.reseeds() != reseeds {
+                // The chunk was hashed with the previous seed.
+                self.prefetch_chunk(keys, &mut next);
+            }
+            mem::swap(&mut hashes, &mut next);
+            if let Some((keys, _)) = chunks.peek() {
+                self.prefetch_chunk(keys, &mut next);
+            }
+            for ((key, hash), input) in keys.iter().zip(hashes).zip(inputs) {
+                let (state, inserted) =
+                    self.get_or_insert_with_hashed(KeyRef::from(*key), hash, || init(input));
+                if !inserted {
+                    fold(state, input);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Removes the entries of `keys`, returning how many were present.
+    ///
+    /// Keys are hashed and their slots prefetched a chunk ahead of probing,
+    /// and the tiers are only shrunk once at the end, however many entries
+    /// the batch evicts.
+    pub fn remove_batch(&mut self, keys: &[&[u8]]) -> usize {
+        self.remove_batch_with(keys, drop)
+    }
+
+    /// Same as [`remove_batch`](Self::remove_batch), but also writes the
+    /// removed values into `out` in key order.
+    pub fn remove_batch_into(&mut self, keys: &[&[u8]], out: &mut Vec<Option<T>>) -> usize {
+        out.clear();
+        out.reserve(keys.len());
+        self.remove_batch_with(keys, |value| out.push(value))
+    }
+
+    fn remove_batch_with(&mut self, keys: &[&[u8]], mut f: impl FnMut(Option<T>)) -> usize {
+        let mut removed = 0;
+        let (mut hashes, mut next) = ([0; ACCUMULATE_CHUNK], [0; ACCUMULATE_CHUNK]);
+        let mut chunks = keys.chunks(ACCUMULATE_CHUNK).peekable();
+        if let Some(keys) = chunks.peek() {
+            self.prefetch_chunk(keys, &mut next);
+        }
+        while let Some(keys) = chunks.next() {
+            mem::swap(&mut hashes, &mut next);
+            if let Some(keys) = chunks.peek() {
+                self.prefetch_chunk(keys, &mut next);
+            }
+            for (key, hash) in keys.iter().zip(hashes) {
+                let value = self.take_hashed(KeyRef::from(*key), hash);
+                removed += value.is_some() as usize;
+                f(value);
+            }
+        }
+        self.shrink_underloaded();
+        removed
+    }
+}
+
+impl BatchState {
+    /// Same as [`hash_batch`](Self::hash_batch), but only hashes the keys
+    /// selected by `selection`, writing the hashes in selection order.
+    ///
+    /// The selected keys are gathered on the stack a chunk at a time, so
+    /// nothing is allocated besides growing `hashes`.
+    pub fn hash_selected(&self, keys: &[&[u8]], selection: &[u32], hashes: &mut Vec<u64>) {
+        hashes.clear();
+        hashes.resize(selection.len(), 0);
+        let mut gathered: [&[u8]; SELECTED_CHUNK] = [&[]; SELECTED_CHUNK];
+        for (selection, hashes) in selection
+            .chunks(SELECTED_CHUNK)
+            .zip(hashes.chunks_mut(SELECTED_CHUNK))
+        {
+            for (key, &row) in gathered.iter_mut().zip(selection) {
+                *key = keys[row as usize];
+            }
+            self.hash_batch(&gathered[..selection.len()], hashes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::adaptive::KeyLen;
+    use crate::common::ShrinkPolicy;
+    use crate::testing::WeakState;
+
+    #[test]
+    fn test_selection() {
+        let bump = Bump::new();
+        let mut map = StringMap::with_hasher(BatchState::new(), &bump);
+
+        let keys = (0..1000u32)
+            .map(|i| i.to_string().repeat(i as usize % 7).into_bytes())
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| &**key).collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate().step_by(2) {
+            map.insert(KeyRef::from(*key), i);
+        }
+
+        let selection = (0..1000).rev().step_by(3).collect::<Vec<u32>>();
+        let mut hashes = vec![];
+        map.hash_batch(&keys, Some(&selection), &mut hashes);
+        let mut simd_hashes = vec![];
+        map.hasher()
+            .hash_selected(&keys, &selection, &mut simd_hashes);
+        assert_eq!(hashes, simd_hashes);
+
+        let mut out = vec![];
+        map.get_batch(&keys, Some(&selection), &mut out);
+        assert_eq!(out.len(), selection.len());
+        for (&row, value) in selection.iter().zip(&out) {
+            let expected = map.get(KeyRef::from(keys[row as usize]));
+            assert_eq!(*value, expected);
+        }
+
+        map.get_batch(&keys, None, &mut out);
+        let expected = keys.iter().map(|key| map.get(KeyRef::from(*key)));
+        assert!(out.iter().copied().eq(expected));
+    }
+
+    #[test]
+    fn test_remove_batch() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        map.set_shrink_policy(ShrinkPolicy::Automatic {
+            min_load_percent: 25,
+        });
+        let mut cmp = std::collections::HashMap::new();
+
+        let keys = (0..20000u32)
+            .map(|i| i.to_string().repeat(i as usize % 9))
+            .collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(KeyRef::from(key.as_bytes()), i);
+            cmp.insert(key.as_bytes(), i);
+        }
+        let capacity = |map: &StringMap<_, _>| {
+            [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+                .map(|tier| map.capacity_for(tier))
+                .iter()
+                .sum::<usize>()
+        };
+        let full = capacity(&map);
+
+        let evicted = keys.iter().step_by(3).map(|key| key.as_bytes());
+        let evicted = evicted.chain([&b"absent"[..]]).collect::<Vec<_>>();
+        let mut out = vec![];
+        let removed = map.remove_batch_into(&evicted, &mut out);
+        assert_eq!(out.len(), evicted.len());
+        for (key, value) in evicted.iter().zip(&out) {
+            assert_eq!(*value, cmp.remove(key));
+        }
+        assert_eq!(removed, out.iter().flatten().count());
+        assert_eq!(map.len(), cmp.len());
+
+        let rest = cmp.keys().copied().collect::<Vec<_>>();
+        assert_eq!(map.remove_batch(&rest), rest.len());
+        assert_eq!(map.remove_batch(&rest), 0);
+        assert!(map.is_empty());
+        assert!(capacity(&map) < full);
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = std::collections::HashMap::new();
+
+        let keys = (0..10000)
+            .map(|_| {
+                rand::random::<u8>()
+                    .to_string()
+                    .repeat(rand::random::<usize>() % 12)
+            })
+            .collect::<Vec<_>>();
+        let inputs = (0..keys.len() as u64).collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_bytes()).collect::<Vec<_>>();
+
+        map.accumulate(
+            &keys,
+            &inputs,
+            |&i| (1, i),
+            |(count, sum), &i| {
+                *count += 1;
+                *sum += i;
+            },
+        );
+
+        for (key, &i) in keys.iter().zip(&inputs) {
+            let (count, sum) = cmp.entry(*key).or_insert((0, 0));
+            *count += 1;
+            *sum += i;
+        }
+        assert_eq!(map.len(), cmp.len());
+        for (key, state) in &map {
+            assert_eq!(cmp.get(key.key()), Some(state));
+        }
+
+        // Reseeding between chunks must not leave the next one with stale
+        // hashes.
+        let mut map = StringMap::with_hasher(WeakState::default(), &bump);
+        map.set_auto_reseed(true);
+        map.accumulate(&keys, &inputs, |_| 1, |count, _| *count += 1);
+        assert_eq!(map.reseeds(), 1);
+        for (key, (count, _)) in &cmp {
+            assert_eq!(map.get(KeyRef::from(*key)), Some(count));
+        }
+    }
+}
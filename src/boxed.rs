@@ -0,0 +1,157 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::pin::Pin;
+
+use bumpalo::boxed::Box;
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, StringMap};
+
+/// A tiered map whose values are allocated out-of-line in an arena.
+///
+/// Slots only hold a pointer to their value, so large `T`s neither bloat the
+/// bucket arrays nor get moved around on every resize.
+pub type BoxedStringMap<'a, T, S = RandomState> = StringMap<'a, Box<'a, T>, S>;
+
+/// A tiered map whose values are pinned in an arena, for values that must
+/// never move, such as self-referential aggregation states.
+///
+/// Values are only reachable through `Pin`s, so they stay at the same address
+/// from their insertion until they are dropped, however much the table grows,
+/// and even after being removed from the map.
+pub type PinnedStringMap<'a, T, S = RandomState> = StringMap<'a, Pin<Box<'a, T>>, S>;
+
+impl<'a, T, S: BuildHasher> StringMap<'a, Box<'a, T>, S> {
+    /// Allocates `value` in `value_alloc` and inserts it under `key`,
+    /// returning the previous value if any.
+    pub fn insert_boxed_in<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+        value_alloc: &'a Bump,
+    ) -> Option<T> {
+        self.insert(key, Box::new_in(value, value_alloc))
+            .map(Box::into_inner)
+    }
+
+    /// Same as [`insert_boxed_in`](Self::insert_boxed_in), but allocates the
+    /// value in the key arena.
+    pub fn insert_boxed<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<T> {
+        self.insert_boxed_in(key, value, self.key_alloc())
+    }
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, Pin<Box<'a, T>>, S> {
+    /// Pins `value` in `value_alloc` and inserts it under `key`, returning the
+    /// previous value if any.
+    pub fn insert_pinned_in<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+        value_alloc: &'a Bump,
+    ) -> Option<Pin<Box<'a, T>>> {
+        self.insert(key, Box::pin_in(value, value_alloc))
+    }
+
+    /// Same as [`insert_pinned_in`](Self::insert_pinned_in), but allocates the
+    /// value in the key arena.
+    pub fn insert_pinned<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<Pin<Box<'a, T>>> {
+        self.insert_pinned_in(key, value, self.key_alloc())
+    }
+
+    pub fn get_pinned_mut<Q: Equivalent>(&mut self, key: Q) -> Option<Pin<&mut T>> {
+        self.get_mut(key).map(Pin::as_mut)
+    }
+
+    /// Returns the pinned value of `key`, pinning a new one in the key arena
+    /// if the key is absent, along with whether the value was newly inserted.
+    pub fn get_or_insert_pinned_with<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> (Pin<&mut T>, bool) {
+        let value_alloc = self.key_alloc();
+        let (value, inserted) = self.get_or_insert_with(key, || Box::pin_in(value(), value_alloc));
+        (value.as_mut(), inserted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomPinned;
+    use std::mem;
+
+    use super::*;
+
+    #[test]
+    fn test_boxed() {
+        let keys = Bump::new();
+        let values = Bump::new();
+        let mut map = BoxedStringMap::new(&keys);
+
+        assert_eq!(mem::size_of::<Box<[u64; 64]>>(), mem::size_of::<usize>());
+
+        for i in 0..1000u64 {
+            let a = if i % 2 == 0 {
+                map.insert_boxed(i, [i; 64])
+            } else {
+                map.insert_boxed_in(i, [i; 64], &values)
+            };
+            assert_eq!(a, None);
+        }
+
+        let a = map.insert_boxed(10u64, [0; 64]);
+        assert_eq!(a, Some([10; 64]));
+        assert_eq!(map.get(10u64).map(|v| v[0]), Some(0));
+
+        for (key, value) in map.iter() {
+            let i = u64::from_ne_bytes(key.key().try_into().unwrap());
+            assert_eq!(value[1], if i == 10 { 0 } else { i });
+        }
+        assert_eq!(map.len(), 1000);
+    }
+
+    /// A value checking that it never moves once pinned.
+    struct Anchored {
+        addr: usize,
+        _pin: PhantomPinned,
+    }
+
+    impl Anchored {
+        fn anchor(self: Pin<&mut Self>) {
+            let addr = &*self as *const Self as usize;
+            // SAFETY: Only a plain field is written.
+            let this = unsafe { self.get_unchecked_mut() };
+            assert!(this.addr == 0 || this.addr == addr, "Pinned value moved");
+            this.addr = addr;
+        }
+    }
+
+    #[test]
+    fn test_pinned() {
+        let keys = Bump::new();
+        let values = Bump::new();
+        let mut map = PinnedStringMap::new(&keys);
+        let new = || Anchored {
+            addr: 0,
+            _pin: PhantomPinned,
+        };
+
+        for i in 0..10000u64 {
+            let (value, inserted) = map.get_or_insert_pinned_with(i % 5000, new);
+            assert_eq!(inserted, i < 5000);
+            value.anchor();
+            if i % 7 == 0 {
+                map.insert_pinned_in(i + 10000, new(), &values);
+            }
+        }
+        for i in 0..5000u64 {
+            map.get_pinned_mut(i).unwrap().anchor();
+        }
+
+        let mut removed = map.remove(0u64).unwrap();
+        removed.as_mut().anchor();
+        let old = map.insert_pinned(10000u64, new()).unwrap();
+        assert_eq!(old.addr, 0);
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem;
+
+const MIN_CAPACITY: usize = 8;
+
+/// The id of a buffer registered in a [`BufferStringMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferId(u32);
+
+/// An entry, whose key is a range of a registered buffer.
+struct Slot<T> {
+    hash: u64,
+    buffer: u32,
+    offset: u32,
+    len: u32,
+    value: T,
+}
+
+#[inline]
+fn resolve<'a, T>(buffers: &[&'a [u8]], slot: &Slot<T>) -> &'a [u8] {
+    let buffer = buffers[slot.buffer as usize];
+    &buffer[slot.offset as usize..][..slot.len as usize]
+}
+
+/// A map whose keys are never copied, but stay in shared buffers registered
+/// up front, e.g. the data buffers of Arrow binary columns.
+///
+/// Instead of a key pointer, a slot stores the id of the buffer along with
+/// the offset and length of the key in it, which takes 12 bytes next to the
+/// hash. Keys are compared and iterated through the buffers, which must
+/// outlive the map.
+///
+/// The slots are probed linearly, and removals shift the following entries
+/// back instead of leaving tombstones.
+///
+/// This is a map of its own rather than a storage mode of the large tier of
+/// [`StringMap`](crate::StringMap): the tiers take keys as plain slices and
+/// own a copy of them, while a slot here needs the buffer range the key was
+/// inserted from. [`StringMapBuilder::build_buffer`](crate::StringMapBuilder::build_buffer)
+/// creates one with the room reserved for the tiers.
+pub struct BufferStringMap<'a, T, S = RandomState> {
+    slots: Box<[Option<Slot<T>>]>,
+    len: usize,
+    buffers: Vec<&'a [u8]>,
+    hasher: S,
+}
+
+impl<'a, T, S> BufferStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let capacity = (capacity * 8 / 7 + 1).next_power_of_two();
+        BufferStringMap {
+            slots: (0..capacity.max(MIN_CAPACITY)).map(|_| None).collect(),
+            len: 0,
+            buffers: Vec::new(),
+            hasher,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Registers a buffer, which keys are then inserted as ranges of.
+    pub fn register_buffer(&mut self, buffer: &'a [u8]) -> BufferId {
+        assert!(buffer.len() <= u32::MAX as usize, "Buffer too long");
+        let id = u32::try_from(self.buffers.len()).expect("Too many buffers");
+        self.buffers.push(buffer);
+        BufferId(id)
+    }
+
+    pub fn buffer(&self, id: BufferId) -> &'a [u8] {
+        self.buffers[id.0 as usize]
+    }
+
+    /// Removes all the entries, keeping the registered buffers.
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &T)> + '_ {
+        let buffers = &self.buffers;
+        (self.slots.iter().flatten()).map(move |slot| (resolve(buffers, slot), &slot.value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a [u8], &mut T)> + '_ {
+        let buffers = &self.buffers;
+        (self.slots.iter_mut().flatten()).map(move |slot| (resolve(buffers, slot), &mut slot.value))
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    /// Returns the index of the entry of `key`, or the empty slot ending its
+    /// probe sequence.
+    fn find(&self, key: &[u8], hash: u64) -> Result<usize, usize> {
+        let mut index = hash as usize & self.mask();
+        loop {
+            match &self.slots[index] {
+                None => return Err(index),
+                Some(slot) if slot.hash == hash && resolve(&self.buffers, slot) == key => {
+                    return Ok(index)
+                }
+                Some(_) => index = (index + 1) & self.mask(),
+            }
+        }
+    }
+
+    fn grow(&mut self) {
+        let slots = (0..self.slots.len() * 2).map(|_| None).collect();
+        let old = mem::replace(&mut self.slots, slots);
+        for slot in old.into_vec().into_iter().flatten() {
+            let mut index = slot.hash as usize & self.mask();
+            while self.slots[index].is_some() {
+                index = (index + 1) & self.mask();
+            }
+            self.slots[index] = Some(slot);
+        }
+    }
+}
+
+impl<'a, T> BufferStringMap<'a, T, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<'a, T> Default for BufferStringMap<'a, T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T, S: BuildHasher> BufferStringMap<'a, T, S> {
+    /// Looks up a plain key, whichever buffer it was inserted from.
+    pub fn get(&self, key: &[u8]) -> Option<&T> {
+        let index = self.find(key, self.hasher.hash_one(key)).ok()?;
+        self.slots[index].as_ref().map(|slot| &slot.value)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+        let index = self.find(key, self.hasher.hash_one(key)).ok()?;
+        self.slots[index].as_mut().map(|slot| &mut slot.value)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the value of the `len` bytes at `offset` in `buffer`, inserting
+    /// one with `value` if absent, along with whether it was newly inserted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of the bounds of the buffer.
+    pub fn get_or_insert_with(
+        &mut self,
+        buffer: BufferId,
+        offset: u32,
+        len: u32,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = &self.buffers[buffer.0 as usize][offset as usize..][..len as usize];
+        let hash = self.hasher.hash_one(key);
+        let (index, inserted) = match self.find(key, hash) {
+            Ok(index) => (index, false),
+            Err(mut index) => {
+                if (self.len + 1) * 8 > self.slots.len() * 7 {
+                    self.grow();
+                    index = self.find(key, hash).unwrap_err();
+                }
+                self.slots[index] = Some(Slot {
+                    hash,
+                    buffer: buffer.0,
+                    offset,
+                    len,
+                    value: value(),
+                });
+                self.len += 1;
+                (index, true)
+            }
+        };
+        (&mut self.slots[index].as_mut().unwrap().value, inserted)
+    }
+
+    /// Inserts the `len` bytes at `offset` in `buffer` as a key. If the key is
+    /// already present, only the value is replaced.
+    pub fn insert(&mut self, buffer: BufferId, offset: u32, len: u32, value: T) -> Option<T> {
+        let mut value = Some(value);
+        let (slot, inserted) =
+            self.get_or_insert_with(buffer, offset, len, || value.take().unwrap());
+        (!inserted).then(|| mem::replace(slot, value.unwrap()))
+    }
+
+    /// Groups the rows of a column laid out as `buffer` and `offsets`, the
+    /// key of row `i` spanning `offsets[i]..offsets[i + 1]`, like
+    /// [`StringMap::accumulate`](crate::StringMap::accumulate).
+    ///
+    /// The state of each new key is created by `init` from the index of its
+    /// first row, and the following rows are folded into it with `fold`.
+    pub fn insert_from_offsets(
+        &mut self,
+        buffer: BufferId,
+        offsets: &[u32],
+        mut init: impl FnMut(usize) -> T,
+        mut fold: impl FnMut(&mut T, usize),
+    ) {
+        for (row, range) in offsets.windows(2).enumerate() {
+            let (state, inserted) =
+                self.get_or_insert_with(buffer, range[0], range[1] - range[0], || init(row));
+            if !inserted {
+                fold(state, row);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<T> {
+        let mut hole = self.find(key, self.hasher.hash_one(key)).ok()?;
+        let removed = self.slots[hole].take().unwrap();
+        self.len -= 1;
+
+        let mask = self.mask();
+        let mut index = (hole + 1) & mask;
+        while let Some(slot) = &self.slots[index] {
+            // Shift the entry back if the hole lies between its home slot and
+            // its current one.
+            let home = slot.hash as usize & mask;
+            if index.wrapping_sub(home) & mask >= index.wrapping_sub(hole) & mask {
+                self.slots[hole] = self.slots[index].take();
+                hole = index;
+            }
+            index = (index + 1) & mask;
+        }
+        Some(removed.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::StringMapBuilder;
+
+    #[test]
+    fn test_buffer_map() {
+        let rows = (0..10000)
+            .map(|i| (i % 97).to_string().repeat(i % 7))
+            .collect::<Vec<_>>();
+        let data = rows.concat().into_bytes();
+        let offsets = [0]
+            .into_iter()
+            .chain(rows.iter().scan(0, |end, row| {
+                *end += row.len() as u32;
+                Some(*end)
+            }))
+            .collect::<Vec<_>>();
+
+        let mut map = StringMapBuilder::new().capacity(100).build_buffer();
+        assert!(map.capacity() >= 4 * 100 * 8 / 7);
+        let buffer = map.register_buffer(&data);
+        map.insert_from_offsets(buffer, &offsets, |_| 1, |count, _| *count += 1);
+
+        let mut cmp = HashMap::new();
+        for row in &rows {
+            *cmp.entry(row.as_bytes()).or_insert(0) += 1;
+        }
+        assert_eq!(map.len(), cmp.len());
+        for (key, count) in map.iter() {
+            assert!(key.is_empty() || data.as_ptr_range().contains(&key.as_ptr()));
+            assert_eq!(cmp.get(key), Some(count));
+        }
+
+        let other = b"0123456789";
+        let other = map.register_buffer(other);
+        for i in 0..10000u32 {
+            let (offset, len) = (i % 10, rand::random::<u32>() % 4);
+            let key = &map.buffer(other)[offset as usize..][..len.min(10 - offset) as usize];
+            let len = key.len() as u32;
+            match rand::random::<u8>() % 3 {
+                0 => assert_eq!(map.remove(key), cmp.remove(key)),
+                _ => assert_eq!(map.insert(other, offset, len, i), cmp.insert(key, i)),
+            }
+        }
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+}
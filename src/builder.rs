@@ -0,0 +1,296 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{estimated_lengths, CardinalityEstimate, KeyLen, Reseed, StringMap};
+use crate::buffer::BufferStringMap;
+use crate::chained::ChainedStringMap;
+use crate::common::ShrinkPolicy;
+use crate::hopscotch::HopscotchStringMap;
+use crate::radix::RadixStringMap;
+use crate::tiers::{TieredStringMap, Tiers};
+
+/// The configuration of a [`StringMap`], gathering the knobs otherwise set one
+/// by one after construction.
+pub struct StringMapBuilder<T, S = RandomState> {
+    hasher: S,
+    /// The room reserved in the tiers of keys of 1 to 8, 16, 24 bytes, and
+    /// the large tier.
+    capacities: [usize; 4],
+    /// The bytes of the keys expected in the arena.
+    arena_bytes: usize,
+    max_load_percent: Option<u8>,
+    shrink: ShrinkPolicy,
+    reseed: Option<fn(&mut S)>,
+    max_mean_probe: Option<f64>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> StringMapBuilder<T, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<T> Default for StringMapBuilder<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T> StringMap<'a, T, RandomState> {
+    pub fn builder() -> StringMapBuilder<T> {
+        StringMapBuilder::new()
+    }
+}
+
+impl<T, S> StringMapBuilder<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        StringMapBuilder {
+            hasher,
+            capacities: [0; 4],
+            arena_bytes: 0,
+            max_load_percent: None,
+            shrink: ShrinkPolicy::default(),
+            reseed: None,
+            max_mean_probe: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Replaces the hasher, which turns auto reseeding off.
+    pub fn hasher<S2>(self, hasher: S2) -> StringMapBuilder<T, S2> {
+        StringMapBuilder {
+            hasher,
+            capacities: self.capacities,
+            arena_bytes: self.arena_bytes,
+            max_load_percent: self.max_load_percent,
+            shrink: self.shrink,
+            reseed: None,
+            max_mean_probe: self.max_mean_probe,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Reserves room for `capacity` keys in every tier, for inputs whose key
+    /// lengths are unknown.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacities = [capacity; 4];
+        self
+    }
+
+    /// Reserves room for `capacity` keys in the tier `tier` only.
+    pub fn capacity_for(mut self, tier: KeyLen, capacity: usize) -> Self {
+        match tier {
+            KeyLen::None => {}
+            KeyLen::S8 => self.capacities[0] = capacity,
+            KeyLen::S16 => self.capacities[1] = capacity,
+            KeyLen::S24 => self.capacities[2] = capacity,
+            KeyLen::Large => self.capacities[3] = capacity,
+        }
+        self
+    }
+
+    /// Reserves room for `ndv` distinct keys of `avg_key_len` bytes on
+    /// average, split over the tiers like
+    /// [`StringMap::reserve_estimated`], and sizes
+    /// [`key_arena`](Self::key_arena) for them.
+    pub fn estimated_cardinality(
+        mut self,
+        ndv: impl CardinalityEstimate,
+        avg_key_len: usize,
+    ) -> Self {
+        let ndv = ndv.estimate();
+        let mut counts = [0; 5];
+        for (len, count) in estimated_lengths(ndv, avg_key_len) {
+            counts[KeyLen::of(len) as usize] += count;
+        }
+        for (capacity, &count) in self.capacities.iter_mut().zip(&counts[1..]) {
+            if count > 0 {
+                *capacity = count;
+            }
+        }
+        self.arena_bytes = StringMap::<T, S>::estimated_arena_bytes(ndv, avg_key_len);
+        self
+    }
+
+    /// Creates an arena with room for the keys of the estimated cardinality,
+    /// if any, to build the map on so that it allocates no chunk mid-build.
+    pub fn key_arena(&self) -> Bump {
+        Bump::with_capacity(self.arena_bytes)
+    }
+
+    /// See [`StringMap::set_max_load_percent`].
+    pub fn max_load_percent(mut self, percent: u8) -> Self {
+        self.max_load_percent = Some(percent);
+        self
+    }
+
+    /// See [`StringMap::set_shrink_policy`].
+    pub fn shrink_policy(mut self, policy: ShrinkPolicy) -> Self {
+        self.shrink = policy;
+        self
+    }
+
+    /// See [`StringMap::set_max_mean_probe`].
+    pub fn max_mean_probe(mut self, limit: f64) -> Self {
+        self.max_mean_probe = Some(limit);
+        self
+    }
+
+    /// Creates a [`RadixStringMap`] instead, for long keys sharing long
+    /// prefixes. None of the knobs apply to it, since it neither hashes its
+    /// keys nor has a capacity.
+    pub fn build_radix(self, key_alloc: &Bump) -> RadixStringMap<'_, T> {
+        RadixStringMap::new(key_alloc)
+    }
+}
+
+impl<T, S: Reseed> StringMapBuilder<T, S> {
+    /// See [`StringMap::set_auto_reseed`].
+    pub fn auto_reseed(mut self, enabled: bool) -> Self {
+        self.reseed = enabled.then_some(S::reseed as fn(&mut S));
+        self
+    }
+}
+
+impl<T, S: BuildHasher> StringMapBuilder<T, S> {
+    /// Creates the map, with its keys copied into `key_alloc`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the maximum load, the shrink policy or the mean probe length
+    /// limit is out of range.
+    pub fn build(self, key_alloc: &Bump) -> StringMap<'_, T, S> {
+        let mut map = StringMap::with_hasher(self.hasher, key_alloc);
+        if let Some(percent) = self.max_load_percent {
+            map.set_max_load_percent(percent);
+        }
+        map.set_shrink_policy(self.shrink);
+        map.set_reseed(self.reseed);
+        map.set_max_mean_probe(self.max_mean_probe);
+        for (tier, capacity) in [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large]
+            .into_iter()
+            .zip(self.capacities)
+        {
+            if capacity > 0 {
+                map.reserve_for(tier, capacity);
+            }
+        }
+        map
+    }
+
+    /// Creates a [`HopscotchStringMap`] instead, with room for the keys
+    /// reserved in all the tiers. The other knobs only apply to
+    /// [`StringMap`].
+    pub fn build_hopscotch(self, key_alloc: &Bump) -> HopscotchStringMap<'_, T, S> {
+        let capacity = self.capacities.iter().sum();
+        HopscotchStringMap::with_capacity_and_hasher(capacity, self.hasher, key_alloc)
+    }
+
+    /// Creates a [`TieredStringMap`] over the tiers `L` instead, with room
+    /// for the most keys reserved in any tier in every one of them, along
+    /// with the maximum load and the shrink policy. The other knobs only
+    /// apply to [`StringMap`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the maximum load or the shrink policy is out of range.
+    pub fn build_tiered<'a, L: Tiers>(self, key_alloc: &'a Bump) -> TieredStringMap<'a, T, L, S>
+    where
+        T: 'a,
+    {
+        let mut map = TieredStringMap::with_hasher(self.hasher, key_alloc);
+        let capacity = self.capacities.into_iter().max().unwrap();
+        map.configure(capacity, self.max_load_percent, self.shrink);
+        map
+    }
+
+    /// Creates a [`BufferStringMap`] instead, for keys left in registered
+    /// buffers, with room for the keys reserved in all the tiers. The other
+    /// knobs only apply to [`StringMap`].
+    pub fn build_buffer<'a>(self) -> BufferStringMap<'a, T, S> {
+        let capacity = self.capacities.iter().sum();
+        BufferStringMap::with_capacity_and_hasher(capacity, self.hasher)
+    }
+
+    /// Creates a [`ChainedStringMap`] instead, with as many chains as the
+    /// keys reserved in all the tiers. The other knobs only apply to
+    /// [`StringMap`].
+    pub fn build_chained(self, key_alloc: &Bump) -> ChainedStringMap<'_, T, S> {
+        let capacity = self.capacities.iter().sum();
+        ChainedStringMap::with_capacity_and_hasher(capacity, self.hasher, key_alloc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    use super::*;
+    use crate::{KeyRef, Small};
+
+    #[test]
+    fn test_builder() {
+        let bump = Bump::new();
+        let default = StringMap::<u32, _>::new(&bump);
+        let mut map = StringMap::builder()
+            .capacity(100)
+            .capacity_for(KeyLen::Large, 1000)
+            .max_load_percent(90)
+            .shrink_policy(ShrinkPolicy::Never)
+            .auto_reseed(true)
+            .max_mean_probe(4.)
+            .build(&bump);
+        assert_eq!(map.max_load_percent(), 90);
+        assert_eq!(map.max_mean_probe(), Some(4.));
+        assert_eq!(map.shrink_policy(), ShrinkPolicy::Never);
+        for tier in [KeyLen::S8, KeyLen::S16, KeyLen::S24] {
+            assert!(map.capacity_for(tier) > 100);
+        }
+        assert!(map.capacity_for(KeyLen::Large) > 1000);
+
+        let capacity = map.capacity_for(KeyLen::S8);
+        for i in 0..100u32 {
+            map.insert(KeyRef::from(i.to_string().as_bytes()), i);
+        }
+        assert_eq!(map.capacity_for(KeyLen::S8), capacity);
+        assert_eq!(map.len(), 100);
+
+        let map = StringMap::<u32, _>::builder()
+            .estimated_cardinality(1300, 12)
+            .hasher(BuildHasherDefault::<DefaultHasher>::default())
+            .build(&bump);
+        assert!(map.capacity_for(KeyLen::S8) > 300);
+        assert!(map.capacity_for(KeyLen::S16) > 800);
+        assert!(map.capacity_for(KeyLen::S24) > 200);
+        assert_eq!(
+            map.capacity_for(KeyLen::Large),
+            default.capacity_for(KeyLen::Large)
+        );
+        assert_eq!(map.max_load_percent(), default.max_load_percent());
+
+        let builder = StringMap::<u32, _>::builder().estimated_cardinality(1000, 40);
+        let arena = builder.key_arena();
+        assert!(
+            arena.chunk_capacity()
+                >= StringMap::<u32, RandomState>::estimated_arena_bytes(1000, 40)
+        );
+        let map = builder.build(&arena);
+        assert!(map.capacity_for(KeyLen::Large) > 800);
+
+        let mut map = StringMap::<u32, _>::builder()
+            .capacity(100)
+            .max_load_percent(90)
+            .build_tiered::<Small<0, Small<64>>>(&bump);
+        let capacity = map.capacity();
+        assert!(capacity > 300);
+        for i in 0..100u32 {
+            map.insert(i.to_string().repeat(i as usize % 30), i);
+        }
+        assert_eq!(map.capacity(), capacity);
+    }
+}
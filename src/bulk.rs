@@ -0,0 +1,212 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyLen, KeyRef, StringMap};
+
+/// The number of entries of a chunk of the log, which is never reallocated.
+const CHUNK_ENTRIES: usize = 1024;
+
+/// An entry of the log, whose key is already copied into the arena.
+struct Entry<'a, T> {
+    hash: u64,
+    key: &'a [u8],
+    value: T,
+}
+
+/// The log of the entries of a tier, appended to in chunks.
+struct Log<'a, T> {
+    chunks: Vec<Vec<Entry<'a, T>>>,
+    len: usize,
+}
+
+impl<'a, T> Log<'a, T> {
+    fn push(&mut self, entry: Entry<'a, T>) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < CHUNK_ENTRIES => chunk.push(entry),
+            _ => {
+                let mut chunk = Vec::with_capacity(CHUNK_ENTRIES);
+                chunk.push(entry);
+                self.chunks.push(chunk);
+            }
+        }
+        self.len += 1;
+    }
+}
+
+/// A loader for inputs available up front, which only appends the entries to
+/// a log while ingesting them, and builds the map in one pass at the end.
+///
+/// Keys are hashed and copied into the arena on ingestion. The map is then
+/// sized for all the entries logged per tier, so that it never grows, and
+/// large keys are stored as already copied. Optionally, the entries of every
+/// tier are sorted by their home slot first, so that the table is written
+/// sequentially.
+///
+/// As with [`insert`](StringMap::insert), the last entry of a key wins,
+/// unless the map is built with [`build_with`](Self::build_with).
+pub struct BulkLoader<'a, T, S = RandomState> {
+    logs: [Log<'a, T>; 5],
+    sort: bool,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T> BulkLoader<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S> BulkLoader<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        BulkLoader {
+            logs: [(); 5].map(|_| Log {
+                chunks: Vec::new(),
+                len: 0,
+            }),
+            sort: false,
+            hasher,
+            key_alloc,
+        }
+    }
+
+    /// Sets whether the entries are sorted by their home slot before being
+    /// inserted, which pays off once the table is much larger than the CPU
+    /// caches.
+    pub fn sort_by_hash(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Returns the number of entries logged, including duplicate keys.
+    pub fn len(&self) -> usize {
+        self.logs.iter().map(|log| log.len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a, T, S: BuildHasher> BulkLoader<'a, T, S> {
+    /// Appends an entry to the log.
+    pub fn push(&mut self, key: &[u8], value: T) {
+        let key = KeyRef::from(key);
+        let hash = self.hasher.hash_one(key);
+        let key: &'a [u8] = match key {
+            KeyRef::None => &[],
+            key => self.key_alloc.alloc_slice_copy(key.key()),
+        };
+        self.logs[KeyLen::of(key.len()) as usize].push(Entry { hash, key, value });
+    }
+
+    /// Builds the map from the entries logged, the last entry of a key
+    /// winning.
+    pub fn build(self) -> StringMap<'a, T, S> {
+        self.build_with(|old, value| *old = value)
+    }
+
+    /// Builds the map from the entries logged, calling `combine` on the value
+    /// of a key already inserted and the value of a later entry of the key.
+    pub fn build_with(self, mut combine: impl FnMut(&mut T, T)) -> StringMap<'a, T, S> {
+        let BulkLoader {
+            logs,
+            sort,
+            hasher,
+            key_alloc,
+        } = self;
+        let mut map = StringMap::with_hasher(hasher, key_alloc);
+        let tiers = [
+            KeyLen::None,
+            KeyLen::S8,
+            KeyLen::S16,
+            KeyLen::S24,
+            KeyLen::Large,
+        ];
+        for (tier, log) in tiers.into_iter().zip(logs) {
+            if log.len == 0 {
+                continue;
+            }
+            map.reserve_for(tier, log.len);
+            let mut entries = log.chunks.into_iter().flatten().collect::<Vec<_>>();
+            if sort {
+                // The probe sequence of an entry starts at its hash modulo the
+                // capacity; the sort is stable to keep the order of duplicates.
+                let capacity = map.capacity_for(tier);
+                entries.sort_by_key(|entry| entry.hash as usize % capacity);
+            }
+            for Entry { hash, key, value } in entries {
+                match map.get_mut_hashed(KeyRef::from(key), hash) {
+                    Some(old) => combine(old, value),
+                    None => {
+                        map.insert_borrowed_hashed(key, hash, value);
+                    }
+                }
+            }
+        }
+        // Only shrinks if keys were repeated, as the tiers were sized for
+        // every entry logged.
+        map.shrink_to_fit();
+        map
+    }
+}
+
+impl<'a, 'k, T, S: BuildHasher> Extend<(&'k [u8], T)> for BulkLoader<'a, T, S> {
+    fn extend<I: IntoIterator<Item = (&'k [u8], T)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.push(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_bulk_loader() {
+        let rows = (0..50000u32)
+            .map(|i| (i % 7919).to_string().repeat(i as usize % 11))
+            .collect::<Vec<_>>();
+
+        for sort in [false, true] {
+            let bump = Bump::new();
+            let mut loader = BulkLoader::new(&bump).sort_by_hash(sort);
+            loader.extend(rows.iter().map(|row| (row.as_bytes(), 1)));
+            assert_eq!(loader.len(), rows.len());
+            let map = loader.build_with(|count, other| *count += other);
+
+            let mut cmp = HashMap::new();
+            for row in &rows {
+                *cmp.entry(row.as_bytes()).or_insert(0) += 1;
+            }
+            assert_eq!(map.len(), cmp.len());
+            for (key, count) in &cmp {
+                assert_eq!(map.get(KeyRef::from(*key)), Some(count));
+            }
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            map.validate();
+        }
+
+        let bump = Bump::new();
+        let mut loader = BulkLoader::new(&bump);
+        for (i, row) in rows.iter().enumerate() {
+            loader.push(row.as_bytes(), i);
+        }
+        let map = loader.build();
+        let cmp = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row.as_bytes(), i))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(map.len(), cmp.len());
+        for (key, i) in &cmp {
+            assert_eq!(map.get(KeyRef::from(*key)), Some(i));
+        }
+    }
+}
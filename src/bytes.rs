@@ -0,0 +1,106 @@
+use std::hash::BuildHasher;
+
+use crate::adaptive::{Equivalent, KeyRef, StringMap};
+
+impl<'a, S: BuildHasher> StringMap<'a, &'a [u8], S> {
+    /// Inserts `key` with a copy of `value` in the key arena, e.g. to build a
+    /// dictionary without allocating a `Vec` per value, returning the copy.
+    ///
+    /// The last value inserted wins. A value equal to the current one is not
+    /// copied again, but a replaced value stays in the arena.
+    pub fn insert_bytes<Q: Equivalent>(&mut self, key: Q, value: &[u8]) -> &'a [u8] {
+        let key_alloc = self.key_alloc();
+        let (slot, inserted) = self.get_or_insert_with(key, || key_alloc.alloc_slice_copy(value));
+        if !inserted && *slot != value {
+            *slot = key_alloc.alloc_slice_copy(value);
+        }
+        slot
+    }
+
+    /// Returns the canonical copy of `key` in the arena, inserting it if
+    /// absent, so that the map can serve as an interner whose strings outlive
+    /// lookups.
+    ///
+    /// Every key is mapped to its own copy. Large keys are mapped to the copy
+    /// the large tier already made, and others are copied once on insertion.
+    pub fn intern<Q: Equivalent>(&mut self, key: Q) -> &'a [u8] {
+        let key = key.as_key_ref();
+        self.guard_probes();
+        let hash = self.hasher().hash_one(key);
+        let key_alloc = self.key_alloc();
+        let (slot, inserted) = self.get_or_insert_with_hashed(key, hash, || match key {
+            // Filled in below once the large tier has copied the key.
+            KeyRef::Large(_) => &[],
+            _ => key_alloc.alloc_slice_copy(key.key()),
+        });
+        if !inserted || !matches!(key, KeyRef::Large(_)) {
+            return slot;
+        }
+
+        let interned = self.large_key(key.key(), hash).unwrap();
+        *self.get_mut_hashed(key, hash).unwrap() = interned;
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_insert_bytes() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..10000 {
+            let key = (rand::random::<u8>() % 200).to_string().repeat(i % 6);
+            let value = (rand::random::<u8>() % 3).to_string().repeat(i % 40);
+            let copy = map.insert_bytes(KeyRef::from(key.as_bytes()), value.as_bytes());
+            assert_eq!(copy, value.as_bytes());
+            cmp.insert(key, value);
+        }
+
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(
+                map.get(KeyRef::from(key.as_bytes())),
+                Some(&value.as_bytes())
+            );
+        }
+
+        let key = KeyRef::from(&b"same"[..]);
+        let first = map.insert_bytes(key, b"value");
+        let allocated = bump.allocated_bytes();
+        assert!(std::ptr::eq(map.insert_bytes(key, b"value"), first));
+        assert_eq!(bump.allocated_bytes(), allocated);
+    }
+
+    #[test]
+    fn test_intern() {
+        let bump = Bump::new();
+        let mut interner = StringMap::new(&bump);
+        let mut interned = HashMap::new();
+
+        for i in 0..10000 {
+            let key = (rand::random::<u8>() % 200).to_string().repeat(i % 13);
+            let canonical = interner.intern(KeyRef::from(key.as_bytes()));
+            assert_eq!(canonical, key.as_bytes());
+            let first = *interned.entry(key).or_insert(canonical);
+            assert!(std::ptr::eq(canonical, first));
+        }
+        assert_eq!(interner.len(), interned.len());
+
+        // Interning a known key allocates nothing.
+        let allocated = bump.allocated_bytes();
+        for key in interned.keys() {
+            interner.intern(KeyRef::from(key.as_bytes()));
+        }
+        assert_eq!(bump.allocated_bytes(), allocated);
+    }
+}
@@ -0,0 +1,208 @@
+use std::ffi::c_void;
+use std::{ptr, slice};
+
+use bumpalo::Bump;
+
+use crate::adaptive::StringMap;
+
+/// A map owning its key arena, for the C interface declared in
+/// `include/saha.h`.
+///
+/// Values are opaque pointers owned by the caller.
+pub struct SahaMap {
+    map: StringMap<'static, *mut c_void, std::collections::hash_map::RandomState>,
+    // Leaked so that the map can borrow it, and freed by `saha_map_free`
+    // after the map.
+    bump: *mut Bump,
+}
+
+/// Called for each entry by [`saha_map_for_each`]; returning `false` stops
+/// the iteration.
+pub type SahaVisitor = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *mut c_void,
+) -> bool;
+
+unsafe fn key<'k>(key: *const u8, key_len: usize) -> &'k [u8] {
+    match key_len {
+        0 => &[],
+        _ => unsafe { slice::from_raw_parts(key, key_len) },
+    }
+}
+
+unsafe fn write_out(out: *mut *mut c_void, value: Option<*mut c_void>) -> bool {
+    if let (false, Some(value)) = (out.is_null(), value) {
+        unsafe { *out = value };
+    }
+    value.is_some()
+}
+
+/// Creates an empty map, to be freed with [`saha_map_free`].
+#[no_mangle]
+pub extern "C" fn saha_map_new() -> *mut SahaMap {
+    let bump = Box::into_raw(Box::new(Bump::new()));
+    let map = StringMap::new(unsafe { &*bump });
+    Box::into_raw(Box::new(SahaMap { map, bump }))
+}
+
+/// Frees `map` and its keys. The values are left to the caller.
+///
+/// # Safety
+///
+/// `map` must come from [`saha_map_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_free(map: *mut SahaMap) {
+    if map.is_null() {
+        return;
+    }
+    let SahaMap { map, bump } = *unsafe { Box::from_raw(map) };
+    drop(map);
+    drop(unsafe { Box::from_raw(bump) });
+}
+
+/// # Safety
+///
+/// `map` must be a live map.
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_len(map: *const SahaMap) -> usize {
+    unsafe { &*map }.map.len()
+}
+
+/// Inserts `value` under the `key_len` bytes at `key`, copying the key.
+///
+/// Returns whether the key was present, in which case its previous value is
+/// written to `old` unless it is null.
+///
+/// # Safety
+///
+/// `map` must be a live map, and `key` must point to `key_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_insert(
+    map: *mut SahaMap,
+    key: *const u8,
+    key_len: usize,
+    value: *mut c_void,
+    old: *mut *mut c_void,
+) -> bool {
+    let map = unsafe { &mut *map };
+    let old_value = map.map.insert(unsafe { self::key(key, key_len) }, value);
+    unsafe { write_out(old, old_value) }
+}
+
+/// Looks up the `key_len` bytes at `key`, writing the value to `out` unless
+/// it is null. Returns whether the key was found.
+///
+/// # Safety
+///
+/// Same as [`saha_map_insert`].
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_get(
+    map: *const SahaMap,
+    key: *const u8,
+    key_len: usize,
+    out: *mut *mut c_void,
+) -> bool {
+    let map = unsafe { &*map };
+    let value = map.map.get(unsafe { self::key(key, key_len) }).copied();
+    unsafe { write_out(out, value) }
+}
+
+/// Removes the `key_len` bytes at `key`, writing the removed value to `out`
+/// unless it is null. Returns whether the key was found.
+///
+/// # Safety
+///
+/// Same as [`saha_map_insert`].
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_remove(
+    map: *mut SahaMap,
+    key: *const u8,
+    key_len: usize,
+    out: *mut *mut c_void,
+) -> bool {
+    let map = unsafe { &mut *map };
+    let value = map.map.remove(unsafe { self::key(key, key_len) });
+    unsafe { write_out(out, value) }
+}
+
+/// Calls `visitor` with `ctx` for each entry until it returns `false`.
+/// Returns whether all the entries were visited.
+///
+/// # Safety
+///
+/// `map` must be a live map not modified by `visitor`. Debug builds check
+/// for modifications between entries and abort on those they catch, but this
+/// is a best-effort aid, not a guarantee: modifying the map is undefined
+/// behavior either way. The key pointers are only valid until the map is
+/// modified.
+#[no_mangle]
+pub unsafe extern "C" fn saha_map_for_each(
+    map: *const SahaMap,
+    visitor: SahaVisitor,
+    ctx: *mut c_void,
+) -> bool {
+    let map = unsafe { &*map };
+    map.map.iter().all(|(key, &value)| {
+        let key = key.key();
+        let ptr = match key.is_empty() {
+            true => ptr::null(),
+            false => key.as_ptr(),
+        };
+        unsafe { visitor(ctx, ptr, key.len(), value) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    unsafe extern "C" fn collect(
+        ctx: *mut c_void,
+        key: *const u8,
+        key_len: usize,
+        value: *mut c_void,
+    ) -> bool {
+        let entries = unsafe { &mut *(ctx as *mut HashMap<Vec<u8>, usize>) };
+        entries.insert(unsafe { super::key(key, key_len) }.to_vec(), value as usize);
+        true
+    }
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let map = saha_map_new();
+            let keys = (0..1000usize)
+                .map(|i| i.to_string().repeat(i % 7))
+                .collect::<Vec<_>>();
+
+            let mut old = ptr::null_mut();
+            for (i, key) in keys.iter().enumerate() {
+                let present = saha_map_insert(map, key.as_ptr(), key.len(), i as _, &mut old);
+                assert_eq!(present, keys[..i].contains(key));
+            }
+
+            let mut cmp = HashMap::new();
+            for (i, key) in keys.iter().enumerate() {
+                cmp.insert(key.as_bytes().to_vec(), i);
+            }
+            assert_eq!(saha_map_len(map), cmp.len());
+
+            let mut entries = HashMap::<Vec<u8>, usize>::new();
+            let ctx = &mut entries as *mut _ as *mut c_void;
+            assert!(saha_map_for_each(map, collect, ctx));
+            assert_eq!(entries, cmp);
+
+            let mut value = ptr::null_mut();
+            assert!(saha_map_get(map, b"".as_ptr(), 0, &mut value));
+            assert_eq!(value as usize, cmp[&b""[..]]);
+            assert!(saha_map_remove(map, b"1".as_ptr(), 1, ptr::null_mut()));
+            assert!(!saha_map_get(map, b"1".as_ptr(), 1, ptr::null_mut()));
+
+            saha_map_free(map);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::alloc::Layout;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+use std::{iter, mem};
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+
+const MIN_CAPACITY: usize = 8;
+
+struct Node<'a, T> {
+    next: Link<'a, T>,
+    hash: u64,
+    key: &'a [u8],
+    /// `None` only while the node is on the free list.
+    value: Option<T>,
+}
+
+type Link<'a, T> = Option<NonNull<Node<'a, T>>>;
+
+/// A map resolving collisions with chains of nodes allocated in the key arena
+/// instead of open addressing.
+///
+/// Entries never move once inserted: growing the table only relinks the nodes
+/// into a larger array of chain heads, and removing an entry unlinks its node
+/// without leaving a tombstone. The table grows once it holds as many entries
+/// as chains, a load factor open addressing can't sustain.
+///
+/// The nodes of removed entries are reused by later insertions, but their
+/// keys stay in the arena.
+pub struct ChainedStringMap<'a, T, S = RandomState> {
+    heads: Box<[Link<'a, T>]>,
+    len: usize,
+    free: Link<'a, T>,
+    hasher: S,
+    key_alloc: &'a Bump,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, S> ChainedStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        Self::with_capacity_and_hasher(0, hasher, key_alloc)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        let chains = (capacity + 1).next_power_of_two().max(MIN_CAPACITY);
+        ChainedStringMap {
+            heads: vec![None; chains].into(),
+            len: 0,
+            free: None,
+            hasher,
+            key_alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of chains.
+    pub fn capacity(&self) -> usize {
+        self.heads.len()
+    }
+
+    fn chain_of(&self, hash: u64) -> usize {
+        hash as usize & (self.heads.len() - 1)
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = NonNull<Node<'a, T>>> + '_ {
+        self.heads.iter().flat_map(|&head| {
+            // SAFETY: Linked nodes are initialized and live as long as the
+            // arena.
+            iter::successors(head, |node| unsafe { node.as_ref() }.next)
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &T)> + '_ {
+        self.nodes().map(|node| {
+            // SAFETY: The node is linked, so it holds a value, borrowed along
+            // with the map.
+            let node = unsafe { node.as_ref() };
+            (node.key, node.value.as_ref().unwrap())
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a [u8], &mut T)> + '_ {
+        self.nodes().map(|mut node| {
+            // SAFETY: Every linked node is yielded once, while the map is
+            // mutably borrowed.
+            let node = unsafe { node.as_mut() };
+            (node.key, node.value.as_mut().unwrap())
+        })
+    }
+
+    /// Relinks the nodes into `capacity` chains.
+    fn resize(&mut self, capacity: usize) {
+        let heads = mem::replace(&mut self.heads, vec![None; capacity].into());
+        for mut link in heads.into_vec() {
+            while let Some(mut node) = link {
+                // SAFETY: The node is linked, and only relinked once.
+                let node = unsafe { node.as_mut() };
+                link = node.next;
+                let chain = self.chain_of(node.hash);
+                node.next = self.heads[chain];
+                self.heads[chain] = Some(node.into());
+            }
+        }
+    }
+}
+
+impl<'a, T> ChainedStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> ChainedStringMap<'a, T, S> {
+    fn find(&self, key: &[u8], hash: u64) -> Link<'a, T> {
+        let head = self.heads[self.chain_of(hash)];
+        iter::successors(head, |node| unsafe { node.as_ref() }.next).find(|node| {
+            // SAFETY: See `nodes`.
+            let node = unsafe { node.as_ref() };
+            node.hash == hash && node.key == key
+        })
+    }
+
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        let node = self.find(key, self.hasher.hash_one(key))?;
+        // SAFETY: The node is linked and borrowed along with the map.
+        unsafe { node.as_ref() }.value.as_ref()
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        let mut node = self.find(key, self.hasher.hash_one(key))?;
+        // SAFETY: The node is linked and mutably borrowed along with the map.
+        unsafe { node.as_mut() }.value.as_mut()
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Links a new node for `key`, which must be absent.
+    fn push(&mut self, key: &[u8], hash: u64, value: T) -> &mut T {
+        if self.len >= self.heads.len() {
+            self.resize(self.heads.len() * 2);
+        }
+        let key = self.key_alloc.alloc_slice_copy(key);
+        let chain = self.chain_of(hash);
+        let next = self.heads[chain];
+        let node = Node {
+            next,
+            hash,
+            key,
+            value: Some(value),
+        };
+
+        let mut ptr = match self.free {
+            Some(mut ptr) => {
+                // SAFETY: Free nodes are initialized and unreachable from the
+                // chains.
+                let free = unsafe { ptr.as_mut() };
+                self.free = free.next;
+                *free = node;
+                ptr
+            }
+            None => {
+                let ptr = self
+                    .key_alloc
+                    .alloc_layout(Layout::new::<Node<'a, T>>())
+                    .cast();
+                // SAFETY: The allocation fits a node.
+                unsafe { ptr.write(node) };
+                ptr
+            }
+        };
+        self.heads[chain] = Some(ptr);
+        self.len += 1;
+        // SAFETY: The node was just linked.
+        unsafe { ptr.as_mut() }.value.as_mut().unwrap()
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            // SAFETY: See `get_mut`.
+            Some(mut node) => unsafe { node.as_mut() }.value.replace(value),
+            None => {
+                self.push(key, hash, value);
+                None
+            }
+        }
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            // SAFETY: See `get_mut`.
+            Some(mut node) => (unsafe { node.as_mut() }.value.as_mut().unwrap(), false),
+            None => (self.push(key, hash, value()), true),
+        }
+    }
+
+    /// Removes `key`, moving its node to the free list.
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let mut link = &mut self.heads[hash as usize & (self.heads.len() - 1)];
+        while let Some(mut ptr) = *link {
+            // SAFETY: The node is linked, and the map is mutably borrowed.
+            let node = unsafe { ptr.as_mut() };
+            if node.hash == hash && node.key == key {
+                *link = node.next;
+                node.next = self.free;
+                self.free = Some(ptr);
+                self.len -= 1;
+                return node.value.take();
+            }
+            link = &mut node.next;
+        }
+        None
+    }
+}
+
+impl<'a, T, S> Drop for ChainedStringMap<'a, T, S> {
+    fn drop(&mut self) {
+        if mem::needs_drop::<T>() {
+            for mut node in self.nodes().collect::<Vec<_>>() {
+                // SAFETY: The arena doesn't drop the values, and every linked
+                // node is visited once.
+                drop(unsafe { node.as_mut() }.value.take());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::testing::{differential, numbered};
+    use crate::StringMapBuilder;
+
+    #[test]
+    fn test_chained_map() {
+        let bump = Bump::new();
+        let map = StringMapBuilder::new().capacity(1000).build_chained(&bump);
+        assert!(map.capacity() > 4000);
+
+        let map = differential(map, 20000, numbered(3000));
+        assert!(map.len() <= map.inner().capacity());
+    }
+
+    #[test]
+    fn test_stable_entries() {
+        let bump = Bump::new();
+        let mut map = ChainedStringMap::new(&bump);
+        let rc = Rc::new(());
+
+        let first = map.get_or_insert_with("first", || (0, rc.clone())).0 as *const _;
+        for i in 0..10000 {
+            map.insert(i.to_string(), (i, rc.clone()));
+        }
+        assert!(std::ptr::eq(map.get("first").unwrap(), first));
+
+        for i in (0..10000).step_by(2) {
+            assert!(map.remove(i.to_string()).is_some());
+        }
+        assert_eq!(Rc::strong_count(&rc), 5002);
+        drop(map);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, Iter, IterMut, StringMap};
+use crate::error::OccupiedError;
+
+/// A position in the insert log of a [`CheckpointStringMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checkpoint(usize);
+
+/// A tiered map logging the keys it inserts, so that the entries inserted
+/// after a checkpoint can be rolled back without cloning the whole map.
+///
+/// Only insertions are undone: changes made to the values of entries that
+/// existed at the checkpoint are kept.
+pub struct CheckpointStringMap<'a, T, S = RandomState> {
+    map: StringMap<'a, T, S>,
+    /// The bytes of the inserted keys, concatenated.
+    log: Vec<u8>,
+    /// The end offset of each inserted key in `log`.
+    ends: Vec<usize>,
+}
+
+impl<'a, T, S> CheckpointStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        Self::from_map(StringMap::with_hasher(hasher, key_alloc))
+    }
+
+    /// Wraps an existing map, whose entries are never rolled back.
+    pub fn from_map(map: StringMap<'a, T, S>) -> Self {
+        CheckpointStringMap {
+            map,
+            log: Vec::new(),
+            ends: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &StringMap<'a, T, S> {
+        &self.map
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, T, S> {
+        self.map
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, T> {
+        self.map.iter_mut()
+    }
+
+    /// Returns a checkpoint to which the map can later be rolled back.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.ends.len())
+    }
+
+    /// Forgets the insert log, making all the entries permanent and all the
+    /// checkpoints taken so far invalid.
+    pub fn commit(&mut self) {
+        self.log.clear();
+        self.ends.clear();
+    }
+}
+
+impl<'a, T> CheckpointStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> CheckpointStringMap<'a, T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        self.map.get_mut(key)
+    }
+
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<T> {
+        let key = key.as_key_ref();
+        let old = self.map.insert(key, value);
+        if old.is_none() {
+            self.log.extend_from_slice(key.key());
+            self.ends.push(self.log.len());
+        }
+        old
+    }
+
+    pub fn try_insert<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+    ) -> Result<&mut T, OccupiedError<'_, T>> {
+        let key = key.as_key_ref();
+        let result = self.map.try_insert(key, value);
+        if result.is_ok() {
+            self.log.extend_from_slice(key.key());
+            self.ends.push(self.log.len());
+        }
+        result
+    }
+
+    pub fn get_or_insert_with<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_ref();
+        let (value, inserted) = self.map.get_or_insert_with(key, value);
+        if inserted {
+            self.log.extend_from_slice(key.key());
+            self.ends.push(self.log.len());
+        }
+        (value, inserted)
+    }
+
+    /// Removes the entries inserted after `checkpoint`, in reverse insertion
+    /// order, returning their count.
+    ///
+    /// Checkpoints taken after `checkpoint` become invalid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `checkpoint` is invalid, e.g. taken before a
+    /// [`commit`](Self::commit) or a rollback to an earlier checkpoint.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) -> usize {
+        assert!(checkpoint.0 <= self.ends.len(), "Invalid checkpoint");
+
+        let count = self.ends.len() - checkpoint.0;
+        while self.ends.len() > checkpoint.0 {
+            self.ends.pop();
+            let start = self.ends.last().copied().unwrap_or(0);
+            let removed = self.map.remove(&self.log[start..]);
+            debug_assert!(removed.is_some(), "Logged key missing");
+            self.log.truncate(start);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_rollback() {
+        let bump = Bump::new();
+        let mut map = CheckpointStringMap::new(&bump);
+        let mut cmp = HashSet::new();
+        let mut saved = vec![];
+
+        for round in 0..20 {
+            if round % 3 == 2 {
+                let (checkpoint, snapshot) = saved.pop().unwrap();
+                map.rollback(checkpoint);
+                cmp = snapshot;
+                assert_eq!(map.len(), cmp.len());
+                assert!(map.iter().all(|(key, _)| cmp.contains(key.key())));
+            } else {
+                saved.push((map.checkpoint(), cmp.clone()));
+            }
+
+            for _ in 0..1000 {
+                let key = rand::random::<u16>()
+                    .to_string()
+                    .repeat(rand::random::<usize>() % 6);
+                let inserted = match rand::random::<bool>() {
+                    true => map.insert(&key, 0).is_none(),
+                    false => map.get_or_insert_with(&key, || 0).1,
+                };
+                assert_eq!(inserted, cmp.insert(key.into_bytes()));
+            }
+        }
+
+        map.rollback(Checkpoint(0));
+        assert!(map.is_empty());
+    }
+}
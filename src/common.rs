@@ -1,73 +1,492 @@
 use std::hash::BuildHasher;
 use std::iter::FusedIterator;
-use std::{fmt, hint, mem};
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ops::Range;
+#[cfg(any(debug_assertions, feature = "validate"))]
+use std::ptr;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed};
+#[cfg(feature = "metrics")]
+use std::sync::OnceLock;
+use std::{fmt, iter, mem, slice};
 
 use bumpalo::Bump;
 
-const LOAD_FACTOR_N: usize = 3;
-const LOAD_FACTOR_D: usize = 2;
-const MIN_CAPACITY: usize = 8;
+use crate::error::{try_vec, Error};
 
-pub trait SlotData<'a> {
+/// The default maximum load, as a fraction of the capacity.
+const MAX_LOAD: (usize, usize) = (2, 3);
+const MIN_CAPACITY: usize = 8;
+/// The number of overflow slots after the probed ones of a table.
+const STASH_LEN: usize = 8;
+/// The probe length from which entries go to the stash while it has room.
+const STASH_PROBE: usize = 32;
+#[cfg(feature = "metrics")]
+const PROBE_BUCKETS: usize = 16;
+/// The number of probe lengths sampled for percentiles.
+#[cfg(feature = "metrics")]
+const PROBE_SAMPLES: usize = 1024;
+
+/// The part of [`SlotData`] independent from the arena lifetime, so that
+/// entries can be accessed through shorter borrows of the map.
+pub trait SlotAccess {
     type Value;
 
-    fn new(key_alloc: &'a Bump, key: &[u8], hash: u64, value: Self::Value) -> Self;
-
     fn key(&self) -> &[u8];
 
+    fn matches(&self, key: &[u8], hash: u64) -> bool;
+
     fn hash(&self) -> Option<u64>;
 
+    fn set_hash(&mut self, hash: u64);
+
     fn value(&self) -> &Self::Value;
 
     fn kv_mut(&mut self) -> (&[u8], &mut Self::Value);
+}
+
+pub trait SlotData<'a>: SlotAccess {
+    fn new(key_alloc: &'a Bump, key: &[u8], hash: u64, value: Self::Value) -> Self;
 
     fn into_value(self) -> Self::Value;
 
     fn into_kv(self, key_alloc: &'a Bump) -> (&'a [u8], Self::Value);
 }
 
-pub enum Slot<D> {
-    Empty,
-    Deleted,
-    Data(D),
+/// The control byte of a slot that has never been occupied, ending probes.
+const EMPTY: u8 = 0xff;
+/// The control byte of a slot whose entry was removed.
+const DELETED: u8 = 0x80;
+
+/// The control byte of a full slot: the top 7 bits of its hash, so that most
+/// mismatching slots are skipped without touching their data.
+#[inline]
+fn tag(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+#[inline]
+fn is_full(ctrl: u8) -> bool {
+    ctrl & 0x80 == 0
+}
+
+/// Returns the number of slots of a table probing `len` of them, including
+/// the stash.
+fn slot_count(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        len + STASH_LEN
+    }
+}
+
+/// The storage of a table: raw slots, each of which is initialized if and only
+/// if its control byte is full.
+///
+/// The probed slots are followed by a stash of [`STASH_LEN`] slots, holding
+/// the entries that would otherwise lie too far from their first probed slot.
+/// Lookups only scan the stash once probing misses.
+pub(crate) struct Bucket<D> {
+    slots: Box<[MaybeUninit<D>]>,
+    ctrl: Box<[u8]>,
+    /// One bit per slot, set if the slot is full, so that iteration can skip
+    /// runs of free slots.
+    occupied: Box<[u64]>,
 }
 
-impl<D> Slot<D> {
-    pub fn insert(&mut self, data: D) -> Option<D> {
-        let old = self.remove();
-        *self = Slot::Data(data);
-        old
+impl<D> Bucket<D> {
+    fn new(len: usize) -> Self {
+        let count = slot_count(len);
+        Bucket {
+            slots: iter::repeat_with(MaybeUninit::uninit).take(count).collect(),
+            ctrl: vec![EMPTY; count].into(),
+            occupied: vec![0; count.div_ceil(64)].into(),
+        }
     }
 
-    pub fn try_insert(&mut self, data: D) -> Option<(&mut D, D)> {
-        match self {
-            Slot::Deleted | Slot::Empty => {
-                *self = Slot::Data(data);
-                None
-            }
-            Slot::Data(d) => Some((d, data)),
+    fn try_new(len: usize) -> Result<Self, Error> {
+        let count = slot_count(len);
+        Ok(Bucket {
+            slots: try_vec(count, MaybeUninit::uninit)?.into(),
+            ctrl: try_vec(count, || EMPTY)?.into(),
+            occupied: try_vec(count.div_ceil(64), || 0)?.into(),
+        })
+    }
+
+    /// Returns the number of probed slots, excluding the stash.
+    #[inline]
+    fn len(&self) -> usize {
+        self.slots.len().saturating_sub(STASH_LEN)
+    }
+
+    #[inline]
+    fn stash(&self) -> Range<usize> {
+        self.len()..self.slots.len()
+    }
+
+    #[inline]
+    fn is_full(&self, index: usize) -> bool {
+        is_full(self.ctrl[index])
+    }
+
+    /// # Safety
+    ///
+    /// The slot at `index` must be full.
+    #[inline]
+    unsafe fn get(&self, index: usize) -> &D {
+        unsafe { self.slots.get_unchecked(index).assume_init_ref() }
+    }
+
+    /// # Safety
+    ///
+    /// The slot at `index` must be full.
+    #[inline]
+    unsafe fn get_mut(&mut self, index: usize) -> &mut D {
+        unsafe { self.slots.get_unchecked_mut(index).assume_init_mut() }
+    }
+
+    fn full(&self, index: usize) -> Option<&D> {
+        is_full(*self.ctrl.get(index)?).then(|| unsafe { self.get(index) })
+    }
+
+    fn full_mut(&mut self, index: usize) -> Option<&mut D> {
+        is_full(*self.ctrl.get(index)?).then(|| unsafe { self.get_mut(index) })
+    }
+
+    /// Fills the free slot at `index` with `data` hashed to `hash`.
+    #[inline]
+    fn put(&mut self, index: usize, data: D, hash: u64) -> &mut D {
+        debug_assert!(!self.is_full(index));
+        self.ctrl[index] = tag(hash);
+        self.occupied[index / 64] |= 1 << (index % 64);
+        self.slots[index].write(data)
+    }
+
+    /// Empties the slot at `index`, leaving a tombstone if it was full.
+    #[inline]
+    fn take(&mut self, index: usize) -> Option<D> {
+        if !is_full(*self.ctrl.get(index)?) {
+            return None;
         }
+        self.ctrl[index] = DELETED;
+        self.occupied[index / 64] &= !(1 << (index % 64));
+        Some(unsafe { self.slots[index].assume_init_read() })
     }
 
-    pub fn remove(&mut self) -> Option<D> {
-        match mem::replace(self, Slot::Deleted) {
-            Slot::Data(data) => Some(data),
-            _ => None,
+    fn clear(&mut self) {
+        let mut occupied = Bits::new(self.occupied.iter().copied());
+        while let Some(index) = occupied.next_index() {
+            unsafe { self.slots[index].assume_init_drop() };
         }
+        self.ctrl.fill(EMPTY);
+        self.occupied.fill(0);
     }
 
-    unsafe fn data_ptr(ptr: *mut Slot<D>) -> *mut D {
-        match &mut *ptr {
-            Slot::Data(data) => data,
-            _ => unsafe { hint::unreachable_unchecked() },
+    fn is_cleared(&self) -> bool {
+        self.ctrl.iter().all(|&ctrl| ctrl == EMPTY)
+    }
+
+    /// Moves the data out of the full slots.
+    fn into_full(self) -> IntoFull<D> {
+        IntoFull {
+            bucket: self,
+            word: 0,
+        }
+    }
+
+    /// Moves the data of the full slots into a bucket of the same shape,
+    /// converting it with `f`.
+    fn map<E>(mut self, mut f: impl FnMut(D) -> E) -> Bucket<E> {
+        let ctrl = self.ctrl.clone();
+        let occupied = self.occupied.clone();
+        let mut slots = iter::repeat_with(MaybeUninit::uninit)
+            .take(self.slots.len())
+            .collect::<Box<[_]>>();
+        let mut full = Bits::new(occupied.iter().copied());
+        while let Some(index) = full.next_index() {
+            slots[index].write(f(self.take(index).unwrap()));
+        }
+        // The control bytes are only copied once all the slots are written,
+        // so that a panic in `f` leaks the converted data instead of leaving
+        // uninitialized slots marked full.
+        Bucket {
+            slots,
+            ctrl,
+            occupied,
+        }
+    }
+
+    /// Changes the type of the slots of an emptied bucket.
+    ///
+    /// # Safety
+    ///
+    /// `D` and `E` must have the same layout.
+    pub(crate) unsafe fn cast<E>(self) -> Bucket<E> {
+        assert!(self.is_cleared());
+        let mut bucket = ManuallyDrop::new(self);
+        let slots = mem::take(&mut bucket.slots);
+        Bucket {
+            slots: unsafe { Box::from_raw(Box::into_raw(slots) as *mut [MaybeUninit<E>]) },
+            ctrl: mem::take(&mut bucket.ctrl),
+            occupied: mem::take(&mut bucket.occupied),
+        }
+    }
+}
+
+impl<D> Drop for Bucket<D> {
+    fn drop(&mut self) {
+        if mem::needs_drop::<D>() {
+            self.clear();
         }
     }
 }
 
+impl<D: Clone> Clone for Bucket<D> {
+    fn clone(&self) -> Self {
+        let mut bucket = Bucket::new(self.len());
+        let mut occupied = Bits::new(self.occupied.iter().copied());
+        while let Some(index) = occupied.next_index() {
+            bucket.slots[index].write(unsafe { self.get(index) }.clone());
+        }
+        bucket.ctrl.copy_from_slice(&self.ctrl);
+        bucket.occupied.copy_from_slice(&self.occupied);
+        bucket
+    }
+}
+
+/// The owning iterator over the data of the full slots of a bucket.
+///
+/// Taking the data out of a slot clears its occupancy bit, so the lowest bit
+/// left in the current word is always the next full slot.
+struct IntoFull<D> {
+    bucket: Bucket<D>,
+    word: usize,
+}
+
+impl<D> Iterator for IntoFull<D> {
+    type Item = D;
+
+    fn next(&mut self) -> Option<D> {
+        loop {
+            let bits = *self.bucket.occupied.get(self.word)?;
+            if bits != 0 {
+                let index = self.word * 64 + bits.trailing_zeros() as usize;
+                break self.bucket.take(index);
+            }
+            self.word += 1;
+        }
+    }
+}
+
+/// When a table gives back the slots freed by removals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShrinkPolicy {
+    /// Never shrink, not even on explicit calls, e.g. for tables refilled
+    /// right after being drained.
+    Never,
+    /// Only shrink on explicit calls to `shrink_to_fit`.
+    OnExplicitCall,
+    /// Halve the table on removal once at most `min_load_percent` of its slots
+    /// are full, and on explicit calls.
+    ///
+    /// The lower the threshold, the more a table can drain before resizing,
+    /// and the less likely it is to grow back right away.
+    Automatic { min_load_percent: u8 },
+}
+
+impl ShrinkPolicy {
+    /// The highest load under which halving the table doesn't immediately
+    /// bring it to its growth threshold.
+    pub const MAX_MIN_LOAD_PERCENT: u8 = 33;
+}
+
+impl Default for ShrinkPolicy {
+    fn default() -> Self {
+        ShrinkPolicy::Automatic {
+            min_load_percent: Self::MAX_MIN_LOAD_PERCENT,
+        }
+    }
+}
+
+/// Cloning the table copies its buckets and values but not the keys stored in
+/// the arena, which are shared with the clone.
+#[derive(Clone)]
 pub struct StringMap<'a, D: 'a> {
-    bucket: Vec<Slot<D>>,
+    bucket: Bucket<D>,
     len: usize,
+    /// The longest probe sequence reaching an entry placed since the last
+    /// rebuild.
+    max_probe: usize,
+    /// The total length of the probe sequences reaching the entries placed
+    /// since the last rebuild, and their number.
+    probe_sum: usize,
+    placed: usize,
     key_alloc: &'a Bump,
+    generation: u64,
+    /// Bumped on every insertion and removal, so that iterators can check
+    /// that the table didn't change under them.
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    mutations: u64,
+    shrink: ShrinkPolicy,
+    /// The load from which the table grows, as a fraction of its capacity.
+    max_load: (usize, usize),
+    /// The resize counters, updated on mutation.
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    #[cfg(feature = "metrics")]
+    probes: ProbeCounters,
+}
+
+/// Counters collected by a table when the `metrics` feature is enabled.
+///
+/// Lookups through shared references count their probes too, with relaxed
+/// atomic operations, so that concurrent readers of a shared map don't race
+/// on the counters. The feature doesn't change which maps can be shared
+/// between threads, but such readers contend on the counters.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// The number of times the bucket array was resized.
+    pub resizes: u64,
+    /// The number of entries moved by resizes.
+    pub reinsertions: u64,
+    /// The number of probe sequences run, i.e. the key operations routed to
+    /// the table.
+    pub probes: u64,
+    /// The histogram of probe lengths, where `probe_lengths[i]` counts the
+    /// probes that examined `[2^(i-1), 2^i)` slots (and none for `i == 0`).
+    pub probe_lengths: [u64; PROBE_BUCKETS],
+    /// A uniform sample of the probe lengths, kept by reservoir sampling.
+    pub probe_samples: Vec<u32>,
+}
+
+/// Mixes `x` into a pseudo-random number, so that sampling needs no state.
+#[cfg(feature = "metrics")]
+fn splitmix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub fn merge(&mut self, other: &Metrics) {
+        self.merge_samples(other);
+        self.resizes += other.resizes;
+        self.reinsertions += other.reinsertions;
+        self.probes += other.probes;
+        for (a, b) in self.probe_lengths.iter_mut().zip(&other.probe_lengths) {
+            *a += b;
+        }
+    }
+
+    /// Merges the samples of `other`, drawing from each side in proportion
+    /// to its number of probes.
+    fn merge_samples(&mut self, other: &Metrics) {
+        let (a, b) = (&self.probe_samples, &other.probe_samples);
+        if a.len() + b.len() <= PROBE_SAMPLES {
+            self.probe_samples.extend_from_slice(b);
+            return;
+        }
+        let total = (self.probes + other.probes).max(1);
+        let from_a = ((PROBE_SAMPLES as u64 * self.probes / total) as usize).min(a.len());
+        let from_b = (PROBE_SAMPLES - from_a).min(b.len());
+        let spread = |samples: &[u32], count: usize| {
+            (0..count)
+                .map(|i| samples[i * samples.len() / count])
+                .collect::<Vec<_>>()
+        };
+        let mut samples = spread(a, from_a);
+        samples.extend(spread(b, from_b));
+        self.probe_samples = samples;
+    }
+
+    /// Estimates the `q`-quantile of the probe lengths from the samples, or
+    /// returns 0 if no probe was recorded.
+    pub fn probe_percentile(&self, q: f64) -> usize {
+        let mut samples = self.probe_samples.clone();
+        samples.sort_unstable();
+        match samples.len() {
+            0 => 0,
+            len => samples[((len - 1) as f64 * q.clamp(0., 1.)).round() as usize] as usize,
+        }
+    }
+
+    pub fn probe_p50(&self) -> usize {
+        self.probe_percentile(0.5)
+    }
+
+    pub fn probe_p99(&self) -> usize {
+        self.probe_percentile(0.99)
+    }
+}
+
+/// The probe counters of a table, updated through shared references.
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct ProbeCounters {
+    probes: AtomicU64,
+    lengths: [AtomicU64; PROBE_BUCKETS],
+    /// A uniform sample of the probe lengths, allocated on the first probe.
+    samples: OnceLock<Box<[AtomicU32]>>,
+}
+
+#[cfg(feature = "metrics")]
+impl ProbeCounters {
+    fn record(&self, len: usize) {
+        let probes = self.probes.fetch_add(1, Relaxed) + 1;
+        let bucket = (usize::BITS - len.leading_zeros()) as usize;
+        self.lengths[bucket.min(PROBE_BUCKETS - 1)].fetch_add(1, Relaxed);
+
+        // Reservoir sampling: the `n`-th probe replaces a random sample with
+        // a probability of `PROBE_SAMPLES / n`.
+        let index = match probes <= PROBE_SAMPLES as u64 {
+            true => probes - 1,
+            false => splitmix(probes) % probes,
+        };
+        let samples = (self.samples).get_or_init(|| {
+            iter::repeat_with(AtomicU32::default)
+                .take(PROBE_SAMPLES)
+                .collect()
+        });
+        if let Some(sample) = samples.get(index as usize) {
+            sample.store(len.min(u32::MAX as usize) as u32, Relaxed);
+        }
+    }
+
+    /// Moves the counters into `metrics`, resetting them.
+    fn take_into(&mut self, metrics: &mut Metrics) {
+        metrics.probes = mem::take(self.probes.get_mut());
+        for (length, counter) in metrics.probe_lengths.iter_mut().zip(&mut self.lengths) {
+            *length = mem::take(counter.get_mut());
+        }
+        let samples = self.samples.take().unwrap_or_default();
+        metrics.probe_samples = (samples.iter())
+            .take(metrics.probes.min(PROBE_SAMPLES as u64) as usize)
+            .map(|sample| sample.load(Relaxed))
+            .collect();
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Clone for ProbeCounters {
+    fn clone(&self) -> Self {
+        let samples = OnceLock::new();
+        if let Some(source) = self.samples.get() {
+            let _ = samples.set(
+                source
+                    .iter()
+                    .map(|s| AtomicU32::new(s.load(Relaxed)))
+                    .collect(),
+            );
+        }
+        ProbeCounters {
+            probes: AtomicU64::new(self.probes.load(Relaxed)),
+            lengths: (self.lengths.each_ref()).map(|length| AtomicU64::new(length.load(Relaxed))),
+            samples,
+        }
+    }
 }
 
 impl<'a, D> StringMap<'a, D> {
@@ -76,12 +495,22 @@ impl<'a, D> StringMap<'a, D> {
     }
 
     pub fn with_capacity(cap: usize, key_alloc: &'a Bump) -> Self {
-        let mut bucket = Vec::with_capacity(cap.max(MIN_CAPACITY));
-        bucket.resize_with(cap, || Slot::<D>::Empty);
         StringMap {
-            bucket,
+            bucket: Bucket::new(cap),
             len: 0,
+            max_probe: 0,
+            probe_sum: 0,
+            placed: 0,
             key_alloc,
+            generation: 0,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            mutations: 0,
+            shrink: ShrinkPolicy::default(),
+            max_load: MAX_LOAD,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            #[cfg(feature = "metrics")]
+            probes: Default::default(),
         }
     }
 
@@ -93,17 +522,253 @@ impl<'a, D> StringMap<'a, D> {
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
+
+    pub fn capacity(&self) -> usize {
+        self.bucket.len()
+    }
+
+    /// Returns the number of bytes allocated for `capacity` slots and the
+    /// stash.
+    fn bucket_bytes_for(capacity: usize) -> usize {
+        let count = slot_count(capacity);
+        count * (mem::size_of::<D>() + 1) + count.div_ceil(64) * 8
+    }
+
+    /// Returns the number of bytes allocated for the slots and their metadata.
+    pub fn bucket_bytes(&self) -> usize {
+        Self::bucket_bytes_for(self.bucket.len())
+    }
+
+    /// Returns the number of bytes the slots grow by when a new entry is
+    /// inserted.
+    pub fn growth_bytes(&self) -> usize {
+        let capacity = self.bucket.len();
+        let new_capacity = if capacity == 0 {
+            MIN_CAPACITY
+        } else if self.overloaded(self.len + 1) {
+            capacity * 2
+        } else {
+            capacity
+        };
+        Self::bucket_bytes_for(new_capacity) - self.bucket_bytes()
+    }
+
+    /// Returns whether `len` entries reach the maximum load of the table.
+    #[inline]
+    fn overloaded(&self, len: usize) -> bool {
+        let (num, den) = self.max_load;
+        len * den >= self.bucket.len() * num
+    }
+
+    /// Returns the number of slots holding `len` entries under the maximum
+    /// load.
+    fn required_capacity(&self, len: usize) -> usize {
+        let (num, den) = self.max_load;
+        len * den / num + 1
+    }
+
+    /// Returns the load from which the table grows, in percent of its
+    /// capacity.
+    pub fn max_load_percent(&self) -> u8 {
+        let (num, den) = self.max_load;
+        (num * 100 / den) as u8
+    }
+
+    /// Sets the load from which the table grows, e.g. to 90% to trade longer
+    /// probes for memory. Entries that would lie too far from their first
+    /// probed slot overflow to a small stash, which spares the highest loads
+    /// the worst probe sequences.
+    ///
+    /// The new load only applies from the next insertion on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percent` is under the default two thirds or above
+    /// [`MAX_LOAD_PERCENT`](Self::MAX_LOAD_PERCENT).
+    pub fn set_max_load_percent(&mut self, percent: u8) {
+        assert!(
+            (67..=Self::MAX_LOAD_PERCENT).contains(&percent),
+            "Unsupported maximum load {percent}%"
+        );
+        self.max_load = (percent as usize, 100);
+    }
+
+    /// The highest load a table can be set to grow from.
+    pub const MAX_LOAD_PERCENT: u8 = 95;
+
+    /// Returns the length of the longest probe sequence reaching an entry
+    /// placed since the table was last rebuilt.
+    pub fn max_probe(&self) -> usize {
+        self.max_probe
+    }
+
+    /// Returns the mean length of the probe sequences reaching the entries
+    /// placed since the table was last rebuilt, or `None` if there are none.
+    ///
+    /// Uniform hashes keep it around `(1 + 1 / (1 - load)) / 2`, so a much
+    /// higher value means the hasher clusters the keys.
+    pub fn mean_probe(&self) -> Option<f64> {
+        (self.placed > 0).then(|| self.probe_sum as f64 / self.placed as f64)
+    }
+
+    /// Returns a counter bumped whenever entries may move between slots, i.e.
+    /// on resizes and clears, so that slot handles can be checked for
+    /// staleness.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the counters collected since the last call, resetting them.
+    #[cfg(feature = "metrics")]
+    pub fn take_metrics(&mut self) -> Metrics {
+        let mut metrics = mem::take(&mut self.metrics);
+        self.probes.take_into(&mut metrics);
+        metrics
+    }
+
+    #[inline]
+    fn record_probe(&self, _len: usize) {
+        #[cfg(feature = "metrics")]
+        self.probes.record(_len);
+    }
+
+    #[inline]
+    fn record_mutation(&mut self) {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        {
+            self.mutations += 1;
+        }
+    }
+
+    /// Hints the CPU to fetch the first slot probed for `hash` into the cache.
+    #[inline]
+    pub fn prefetch(&self, hash: u64) {
+        if self.bucket.len() == 0 {
+            return;
+        }
+        let index = (hash as usize) % self.bucket.len();
+        let (ctrl, slot) = (&self.bucket.ctrl[index], &self.bucket.slots[index]);
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            _mm_prefetch::<_MM_HINT_T0>(ctrl as *const _ as *const i8);
+            _mm_prefetch::<_MM_HINT_T0>(slot as *const _ as *const i8)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = (ctrl, slot);
+    }
+
+    /// Fills the free slot at `index` with `data`, tracking how far it lies
+    /// from the first slot probed for `hash`.
+    #[inline]
+    fn place(&mut self, index: usize, data: D, hash: u64) -> &mut D {
+        let len = self.bucket.len();
+        if index < len {
+            let probe = (index + len - (hash as usize) % len) % len + 1;
+            self.max_probe = self.max_probe.max(probe);
+            self.probe_sum += probe;
+            self.placed += 1;
+        }
+        self.record_mutation();
+        self.bucket.put(index, data, hash)
+    }
+
+    /// Drops all the entries, keeping the buckets allocated.
+    pub fn clear(&mut self) {
+        self.bucket.clear();
+        self.len = 0;
+        self.max_probe = 0;
+        self.probe_sum = 0;
+        self.placed = 0;
+        self.generation += 1;
+        self.record_mutation();
+    }
+
+    pub(crate) fn into_bucket(mut self) -> Bucket<D> {
+        self.clear();
+        self.bucket
+    }
+
+    pub(crate) fn from_bucket(bucket: Bucket<D>, key_alloc: &'a Bump) -> Self {
+        debug_assert!(bucket.is_cleared());
+        StringMap {
+            bucket,
+            len: 0,
+            max_probe: 0,
+            probe_sum: 0,
+            placed: 0,
+            key_alloc,
+            generation: 0,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            mutations: 0,
+            shrink: ShrinkPolicy::default(),
+            max_load: MAX_LOAD,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            #[cfg(feature = "metrics")]
+            probes: Default::default(),
+        }
+    }
+}
+
+impl<'a, D> StringMap<'a, D> {
+    /// Moves the entries into a table of the same shape using the arena
+    /// `key_alloc`, converting their data with `f`, which must keep the keys
+    /// and hashes unchanged.
+    pub(crate) fn map_data<'b, E: 'b>(
+        self,
+        key_alloc: &'b Bump,
+        f: impl FnMut(D) -> E,
+    ) -> StringMap<'b, E> {
+        StringMap {
+            bucket: self.bucket.map(f),
+            len: self.len,
+            max_probe: self.max_probe,
+            probe_sum: self.probe_sum,
+            placed: self.placed,
+            key_alloc,
+            generation: self.generation,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            mutations: self.mutations,
+            shrink: self.shrink,
+            max_load: self.max_load,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            #[cfg(feature = "metrics")]
+            probes: self.probes,
+        }
+    }
 }
 
 impl<'a, D: SlotData<'a>> StringMap<'a, D> {
     pub fn get(&self, key: &[u8], hash: u64) -> Option<&D::Value> {
         self.lookup(key, hash)
-            .map(|ptr| unsafe { (*Slot::data_ptr(ptr)).value() })
+            .map(|index| unsafe { self.bucket.get(index) }.value())
     }
 
     pub fn get_mut(&mut self, key: &[u8], hash: u64) -> Option<&mut D::Value> {
         self.lookup(key, hash)
-            .map(|ptr| unsafe { (*Slot::data_ptr(ptr)).kv_mut().1 })
+            .map(|index| unsafe { self.bucket.get_mut(index) }.kv_mut().1)
+    }
+
+    pub(crate) fn get_data(&self, key: &[u8], hash: u64) -> Option<&D> {
+        self.lookup(key, hash)
+            .map(|index| unsafe { self.bucket.get(index) })
+    }
+
+    /// # Safety
+    ///
+    /// `key` must be present in the map.
+    pub unsafe fn get_unchecked(&self, key: &[u8], hash: u64) -> &D::Value {
+        unsafe { self.bucket.get(self.lookup_unchecked(key, hash)) }.value()
+    }
+
+    /// # Safety
+    ///
+    /// `key` must be present in the map.
+    pub unsafe fn get_unchecked_mut(&mut self, key: &[u8], hash: u64) -> &mut D::Value {
+        let index = unsafe { self.lookup_unchecked(key, hash) };
+        unsafe { self.bucket.get_mut(index) }.kv_mut().1
     }
 
     pub fn insert<S: BuildHasher>(
@@ -113,21 +778,21 @@ impl<'a, D: SlotData<'a>> StringMap<'a, D> {
         value: D::Value,
         hasher: &S,
     ) -> Option<D::Value> {
-        let slot = self
-            .lookup_or_free(key, hash)
-            .expect("Failed to lookup slot");
-        let old = mem::replace(unsafe { &mut *slot }, {
-            Slot::Data(D::new(self.key_alloc, key, hash, value))
-        });
-        self.len += 1;
-
-        if self.len * LOAD_FACTOR_N / LOAD_FACTOR_D >= self.bucket.len() {
-            self.resize(self.bucket.len() * 2, hasher);
-        }
+        let index = self.lookup_or_grow(key, hash, hasher);
+        let old = self.bucket.take(index);
+        self.place(index, D::new(self.key_alloc, key, hash, value), hash);
 
         match old {
-            Slot::Data(data) => Some(data.into_value()),
-            _ => None,
+            Some(data) => Some(data.into_value()),
+            None => {
+                self.len += 1;
+
+                if self.overloaded(self.len) {
+                    self.resize(self.bucket.len() * 2, hasher);
+                }
+
+                None
+            }
         }
     }
 
@@ -138,22 +803,75 @@ impl<'a, D: SlotData<'a>> StringMap<'a, D> {
         value: D::Value,
         hasher: &S,
     ) -> Option<(&mut D::Value, D::Value)> {
-        let slot = self
-            .lookup_or_free(key, hash)
-            .expect("Failed to lookup slot");
-        match unsafe { &mut *slot } {
-            Slot::Empty | Slot::Deleted => {
-                unsafe { &mut *slot }.insert(D::new(self.key_alloc, key, hash, value));
-                self.len += 1;
+        let index = self.lookup_or_grow(key, hash, hasher);
+        if self.bucket.is_full(index) {
+            return Some((unsafe { self.bucket.get_mut(index) }.kv_mut().1, value));
+        }
 
-                if self.len * LOAD_FACTOR_N / LOAD_FACTOR_D >= self.bucket.len() {
-                    self.resize(self.bucket.len() * 2, hasher);
-                }
+        self.place(index, D::new(self.key_alloc, key, hash, value), hash);
+        self.len += 1;
 
-                None
+        if self.overloaded(self.len) {
+            self.resize(self.bucket.len() * 2, hasher);
+        }
+
+        None
+    }
+
+    /// Inserts `key` without checking whether it is already present.
+    ///
+    /// # Safety
+    ///
+    /// `key` must not be in the map, or the map will end up with duplicate
+    /// entries.
+    pub unsafe fn insert_unique_unchecked<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: D::Value,
+        hasher: &S,
+    ) {
+        debug_assert!(self.lookup(key, hash).is_none(), "Key already present");
+        self.insert_data(D::new(self.key_alloc, key, hash, value), hash, hasher);
+    }
+
+    /// Inserts `data` as is, which must not match any entry of the map.
+    pub(crate) fn insert_data<S: BuildHasher>(&mut self, data: D, hash: u64, hasher: &S) {
+        let index = loop {
+            match self.lookup_free(hash) {
+                Some(index) => break index,
+                None => self.grow(hasher),
             }
-            Slot::Data(data) => Some((data.kv_mut().1, value)),
+        };
+        self.place(index, data, hash);
+        self.len += 1;
+
+        if self.overloaded(self.len) {
+            self.resize(self.bucket.len() * 2, hasher);
+        }
+    }
+
+    pub fn get_or_insert_with<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: impl FnOnce() -> D::Value,
+        hasher: &S,
+    ) -> (&mut D::Value, bool) {
+        let mut index = self.lookup_or_grow(key, hash, hasher);
+        if self.bucket.is_full(index) {
+            return (unsafe { self.bucket.get_mut(index) }.kv_mut().1, false);
+        }
+
+        self.place(index, D::new(self.key_alloc, key, hash, value()), hash);
+        self.len += 1;
+
+        if self.overloaded(self.len) {
+            self.resize(self.bucket.len() * 2, hasher);
+            index = self.lookup(key, hash).unwrap();
         }
+
+        (unsafe { self.bucket.get_mut(index) }.kv_mut().1, true)
     }
 
     pub fn remove<S: BuildHasher>(
@@ -162,81 +880,575 @@ impl<'a, D: SlotData<'a>> StringMap<'a, D> {
         hash: u64,
         hasher: &S,
     ) -> Option<D::Value> {
-        let slot = unsafe { &mut *self.lookup(key, hash)? };
-        let ret = slot.remove()?;
-        self.len -= 1;
+        self.remove_data(key, hash, hasher).map(D::into_value)
+    }
 
-        if self.len > MIN_CAPACITY
-            && self.len * LOAD_FACTOR_N / LOAD_FACTOR_D <= self.bucket.len() / 2
-        {
-            self.resize(self.bucket.len() / 2, hasher);
+    pub fn remove_entry<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        hasher: &S,
+    ) -> Option<(&'a [u8], D::Value)> {
+        let data = self.remove_data(key, hash, hasher)?;
+        Some(data.into_kv(self.key_alloc))
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, without shrinking
+    /// the table.
+    pub fn retain(&mut self, mut f: impl FnMut(&[u8], &mut D::Value) -> bool) {
+        for index in 0..self.bucket.slots.len() {
+            if let Some(data) = self.bucket.full_mut(index) {
+                let (key, value) = data.kv_mut();
+                if !f(key, value) {
+                    self.bucket.take(index);
+                    self.len -= 1;
+                    self.record_mutation();
+                }
+            }
         }
+    }
+
+    /// Grows the table so that `additional` more entries can be inserted
+    /// without resizing.
+    pub fn reserve<S: BuildHasher>(&mut self, additional: usize, hasher: &S) {
+        let required = self.required_capacity(self.len + additional);
+        if required > self.bucket.len() {
+            self.resize(required.next_power_of_two(), hasher);
+        }
+    }
 
-        Some(ret.into_value())
+    /// Grows the table like [`reserve`](Self::reserve), but returns an error
+    /// instead of aborting if the slots can't be allocated.
+    pub fn try_reserve<S: BuildHasher>(
+        &mut self,
+        additional: usize,
+        hasher: &S,
+    ) -> Result<(), Error> {
+        let (num, den) = self.max_load;
+        let required = (self.len.checked_add(additional))
+            .and_then(|len| len.checked_mul(den))
+            .and_then(|len| (len / num + 1).checked_next_power_of_two())
+            .ok_or(Error::CapacityExhausted)?;
+        if required > self.bucket.len() {
+            let bucket = Bucket::try_new(required)?;
+            self.rebuild(bucket, hasher);
+        }
+        Ok(())
+    }
+
+    pub fn shrink_policy(&self) -> ShrinkPolicy {
+        self.shrink
+    }
+
+    pub fn set_shrink_policy(&mut self, policy: ShrinkPolicy) {
+        if let ShrinkPolicy::Automatic { min_load_percent } = policy {
+            assert!(
+                min_load_percent <= ShrinkPolicy::MAX_MIN_LOAD_PERCENT,
+                "Shrinking at {min_load_percent}% load would grow the table back"
+            );
+        }
+        self.shrink = policy;
+    }
+
+    /// Shrinks the table to the smallest capacity holding its entries, unless
+    /// the policy is [`ShrinkPolicy::Never`].
+    pub fn shrink_to_fit<S: BuildHasher>(&mut self, hasher: &S) {
+        if self.shrink == ShrinkPolicy::Never {
+            return;
+        }
+        let required = self
+            .required_capacity(self.len)
+            .next_power_of_two()
+            .max(MIN_CAPACITY);
+        if required < self.bucket.len() {
+            self.resize(required, hasher);
+        }
+    }
+
+    /// Recomputes the hashes of all the entries with `hasher` and rebuilds the
+    /// table accordingly.
+    pub fn rehash<S: BuildHasher>(&mut self, hasher: &S) {
+        for index in 0..self.bucket.slots.len() {
+            if let Some(data) = self.bucket.full_mut(index) {
+                let hash = hasher.hash_one(data.key());
+                data.set_hash(hash);
+            }
+        }
+        self.resize(self.bucket.len(), hasher);
     }
 }
 
 impl<'a, D: SlotData<'a>> StringMap<'a, D> {
-    fn lookup(&self, key: &[u8], hash: u64) -> Option<*mut Slot<D>> {
+    fn lookup(&self, key: &[u8], hash: u64) -> Option<usize> {
         let len = self.bucket.len();
+        let tag = tag(hash);
         for i in 0..len {
-            let slot = &self.bucket[((hash as usize) + i) % len];
-            match slot {
-                Slot::Empty => return None,
-                Slot::Data(data) if data.key() == key => return Some(slot as *const _ as _),
+            let index = ((hash as usize) + i) % len;
+            match self.bucket.ctrl[index] {
+                EMPTY => {
+                    self.record_probe(i + 1);
+                    return self.lookup_stash(key, hash);
+                }
+                ctrl if ctrl == tag && unsafe { self.bucket.get(index) }.matches(key, hash) => {
+                    self.record_probe(i + 1);
+                    return Some(index);
+                }
                 _ => {}
             }
         }
-        None
+        self.record_probe(len);
+        self.lookup_stash(key, hash)
+    }
+
+    /// Looks up `key` among the entries overflowed to the stash.
+    #[inline]
+    fn lookup_stash(&self, key: &[u8], hash: u64) -> Option<usize> {
+        let tag = tag(hash);
+        self.bucket.stash().find(|&index| {
+            self.bucket.ctrl[index] == tag && unsafe { self.bucket.get(index) }.matches(key, hash)
+        })
     }
 
-    fn lookup_or_free(&self, key: &[u8], hash: u64) -> Option<*mut Slot<D>> {
+    /// Probes for `key` without bounds or termination checks.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be present in the map.
+    unsafe fn lookup_unchecked(&self, key: &[u8], hash: u64) -> usize {
+        if let Some(index) = self.lookup_stash(key, hash) {
+            return index;
+        }
         let len = self.bucket.len();
+        let tag = tag(hash);
+        let mut index = (hash as usize) % len;
+        loop {
+            if *unsafe { self.bucket.ctrl.get_unchecked(index) } == tag
+                && unsafe { self.bucket.get(index) }.matches(key, hash)
+            {
+                break index;
+            }
+            index += 1;
+            if index == len {
+                index = 0;
+            }
+        }
+    }
+
+    fn lookup_or_free(&self, key: &[u8], hash: u64) -> Option<usize> {
+        let len = self.bucket.len();
+        let tag = tag(hash);
+        // The probe length of the first free slot.
+        let mut free = None;
+        let mut probe = len;
         for i in 0..len {
-            let slot = &self.bucket[((hash as usize) + i) % len];
-            match slot {
-                Slot::Empty | Slot::Deleted => return Some(slot as *const _ as _),
-                Slot::Data(data) if data.key() == key => return Some(slot as *const _ as _),
+            let index = ((hash as usize) + i) % len;
+            match self.bucket.ctrl[index] {
+                EMPTY => {
+                    free.get_or_insert(i);
+                    probe = i + 1;
+                    break;
+                }
+                DELETED => {
+                    free.get_or_insert(i);
+                }
+                ctrl if ctrl == tag && unsafe { self.bucket.get(index) }.matches(key, hash) => {
+                    self.record_probe(i + 1);
+                    return Some(index);
+                }
                 _ => {}
             }
         }
-        None
+        self.record_probe(probe);
+        self.lookup_stash(key, hash)
+            .or_else(|| Some(self.overflow(hash, free?)))
+    }
+
+    /// Returns the slot of a new entry whose first free slot is `probe` slots
+    /// past its first probed one: a free stash slot if that is too far, and
+    /// the free slot otherwise.
+    #[inline]
+    fn overflow(&self, hash: u64, probe: usize) -> usize {
+        let index = ((hash as usize) + probe) % self.bucket.len();
+        if probe < STASH_PROBE {
+            return index;
+        }
+        self.bucket
+            .stash()
+            .find(|&index| !self.bucket.is_full(index))
+            .unwrap_or(index)
+    }
+
+    /// Looks up the slot of `key` or a free one, growing the table until the
+    /// latter is available.
+    fn lookup_or_grow<S: BuildHasher>(&mut self, key: &[u8], hash: u64, hasher: &S) -> usize {
+        loop {
+            match self.lookup_or_free(key, hash) {
+                Some(index) => break index,
+                None => self.grow(hasher),
+            }
+        }
+    }
+
+    fn grow<S: BuildHasher>(&mut self, hasher: &S) {
+        self.resize((self.bucket.len() * 2).max(MIN_CAPACITY), hasher)
+    }
+
+    /// Returns the length of the probe sequence reaching the first free slot
+    /// for `hash`, which a new entry would take or overflow from.
+    pub(crate) fn insertion_probe(&self, hash: u64) -> Option<usize> {
+        let len = self.bucket.len();
+        (0..len)
+            .position(|i| !self.bucket.is_full(((hash as usize) + i) % len))
+            .map(|i| i + 1)
+    }
+
+    fn lookup_free(&self, hash: u64) -> Option<usize> {
+        let len = self.bucket.len();
+        let probe = (0..len).find(|&i| !self.bucket.is_full(((hash as usize) + i) % len))?;
+        Some(self.overflow(hash, probe))
+    }
+
+    fn remove_data<S: BuildHasher>(&mut self, key: &[u8], hash: u64, hasher: &S) -> Option<D> {
+        let ret = self.take_data(key, hash)?;
+        self.shrink_underloaded(hasher);
+        Some(ret)
+    }
+
+    /// Removes the entry of `key` without shrinking the table, leaving that
+    /// to a later call to [`shrink_underloaded`](Self::shrink_underloaded).
+    pub(crate) fn take_data(&mut self, key: &[u8], hash: u64) -> Option<D> {
+        let ret = self.bucket.take(self.lookup(key, hash)?)?;
+        self.len -= 1;
+        self.record_mutation();
+        Some(ret)
+    }
+
+    /// Halves the table as many times as the automatic shrink policy asks
+    /// for, in a single resize.
+    ///
+    /// Tables are never shrunk for their last few entries, but a batch of
+    /// removals draining one still leaves it at the capacity that removing
+    /// the entries one by one would have.
+    pub(crate) fn shrink_underloaded<S: BuildHasher>(&mut self, hasher: &S) {
+        if let ShrinkPolicy::Automatic { min_load_percent } = self.shrink {
+            let len = self.len.max(MIN_CAPACITY + 1);
+            let mut capacity = self.bucket.len();
+            while len * 100 <= capacity * min_load_percent as usize {
+                capacity /= 2;
+            }
+            if capacity < self.bucket.len() {
+                self.resize(capacity, hasher);
+            }
+        }
+    }
+
+    /// Moves the entries into `parts` maps chosen by `part(hash)`, without
+    /// copying their keys or recomputing the hashes stored in their slots.
+    ///
+    /// The maps keep the maximum load and the shrink policy of the map.
+    pub(crate) fn split<S: BuildHasher>(
+        mut self,
+        parts: usize,
+        part: impl Fn(u64) -> usize,
+        hasher: &S,
+    ) -> Vec<Self> {
+        let mut maps = iter::repeat_with(|| StringMap {
+            shrink: self.shrink,
+            max_load: self.max_load,
+            ..Self::new(self.key_alloc)
+        })
+        .take(parts)
+        .collect::<Vec<_>>();
+        for data in mem::replace(&mut self.bucket, Bucket::new(0)).into_full() {
+            let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+            maps[part(hash)].insert_data(data, hash, hasher);
+        }
+        maps
+    }
+
+    /// Moves the entries of `other` into the map, combining the values of the
+    /// keys present in both, without copying keys.
+    ///
+    /// The hashes stored in the slots of `other` must come from `hasher`.
+    pub(crate) fn merge<S: BuildHasher>(
+        &mut self,
+        mut other: Self,
+        mut combine: impl FnMut(&mut D::Value, D::Value),
+        hasher: &S,
+    ) {
+        for data in mem::replace(&mut other.bucket, Bucket::new(0)).into_full() {
+            let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+            match self.lookup(data.key(), hash) {
+                Some(index) => combine(
+                    unsafe { self.bucket.get_mut(index) }.kv_mut().1,
+                    data.into_value(),
+                ),
+                None => self.insert_data(data, hash, hasher),
+            }
+        }
     }
 
     fn resize<S: BuildHasher>(&mut self, new_len: usize, hasher: &S) {
-        let mut bucket = Vec::with_capacity(new_len);
-        bucket.resize_with(new_len, || Slot::<D>::Empty);
+        self.rebuild(Bucket::new(new_len), hasher);
+    }
+
+    /// Moves the entries into the empty `bucket`, which must have room for
+    /// them.
+    fn rebuild<S: BuildHasher>(&mut self, bucket: Bucket<D>, hasher: &S) {
         let bucket = mem::replace(&mut self.bucket, bucket);
-        for item in bucket {
-            if let Slot::Data(data) = item {
-                let slot = self
-                    .lookup_or_free(
-                        data.key(),
-                        data.hash().unwrap_or_else(|| hasher.hash_one(data.key())),
-                    )
-                    .unwrap();
-                unsafe { (*slot).insert(data) };
+        self.max_probe = 0;
+        self.probe_sum = 0;
+        self.placed = 0;
+        self.generation += 1;
+        self.record_mutation();
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.resizes += 1;
+            self.metrics.reinsertions += self.len as u64;
+        }
+        for data in bucket.into_full() {
+            let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+            let index = self.lookup_free(hash).unwrap();
+            self.place(index, data, hash);
+        }
+    }
+}
+
+/// A handle to a slot of the table, valid until the table is resized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct RawSlot(usize);
+
+impl RawSlot {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Low-level access to the bucket array, for building custom structures
+/// (multimaps, join chains, ...) on top of the table.
+impl<'a, D: SlotData<'a>> StringMap<'a, D> {
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    /// Finds the first occupied slot in the probe sequence of `hash` whose
+    /// data satisfies `eq`.
+    pub fn raw_find(&self, hash: u64, mut eq: impl FnMut(&D) -> bool) -> Option<RawSlot> {
+        let len = self.bucket.len();
+        for i in 0..len {
+            let index = ((hash as usize) + i) % len;
+            match self.bucket.ctrl[index] {
+                EMPTY => break,
+                ctrl if is_full(ctrl) && eq(unsafe { self.bucket.get(index) }) => {
+                    return Some(RawSlot(index))
+                }
+                _ => {}
             }
         }
+        self.raw_find_stash(&mut eq)
+    }
+
+    fn raw_find_stash(&self, mut eq: impl FnMut(&D) -> bool) -> Option<RawSlot> {
+        self.bucket
+            .stash()
+            .find(|&index| self.bucket.full(index).is_some_and(&mut eq))
+            .map(RawSlot)
+    }
+
+    /// Finds the slot satisfying `eq` like [`raw_find`](Self::raw_find), or
+    /// returns a free slot in which an entry can be inserted without resizing.
+    ///
+    /// Any previously obtained handle is invalidated if the table needs to
+    /// grow for the free slot, which only happens once no slot satisfied
+    /// `eq`.
+    pub fn raw_find_or_free<S: BuildHasher>(
+        &mut self,
+        hash: u64,
+        mut eq: impl FnMut(&D) -> bool,
+        hasher: &S,
+    ) -> Result<RawSlot, RawSlot> {
+        let len = self.bucket.len();
+        let mut free = None;
+        for i in 0..len {
+            let index = ((hash as usize) + i) % len;
+            match self.bucket.ctrl[index] {
+                EMPTY => {
+                    free.get_or_insert(i);
+                    break;
+                }
+                DELETED => {
+                    free.get_or_insert(i);
+                }
+                _ if eq(unsafe { self.bucket.get(index) }) => return Ok(RawSlot(index)),
+                _ => {}
+            }
+        }
+        if let Some(slot) = self.raw_find_stash(&mut eq) {
+            return Ok(slot);
+        }
+        match free {
+            Some(probe) if !self.overloaded(self.len + 1) => {
+                Err(RawSlot(self.overflow(hash, probe)))
+            }
+            _ => {
+                self.grow(hasher);
+                Err(RawSlot(
+                    self.lookup_free(hash).expect("No free slot after growing"),
+                ))
+            }
+        }
+    }
+
+    pub fn raw_get(&self, slot: RawSlot) -> Option<&D> {
+        self.bucket.full(slot.0)
+    }
+
+    pub fn raw_get_mut(&mut self, slot: RawSlot) -> Option<&mut D> {
+        self.bucket.full_mut(slot.0)
+    }
+
+    /// Inserts a new entry into the free slot `slot`, as returned by
+    /// [`raw_find_or_free`](Self::raw_find_or_free).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slot` is occupied.
+    pub fn raw_insert(&mut self, slot: RawSlot, key: &[u8], hash: u64, value: D::Value) -> &mut D {
+        self.raw_insert_data(slot, D::new(self.key_alloc, key, hash, value), hash)
+    }
+
+    /// Same as [`raw_insert`](Self::raw_insert), but with the slot data built
+    /// by the caller, e.g. borrowing a key instead of copying it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slot` is occupied.
+    pub fn raw_insert_data(&mut self, slot: RawSlot, data: D, hash: u64) -> &mut D {
+        assert!(!self.bucket.is_full(slot.0), "Slot already occupied");
+        self.len += 1;
+        self.place(slot.0, data, hash)
+    }
+
+    /// Removes the entry in `slot` without shrinking the table, so that other
+    /// handles stay valid.
+    pub fn raw_erase(&mut self, slot: RawSlot) -> Option<D> {
+        let data = self.bucket.take(slot.0)?;
+        self.len -= 1;
+        self.record_mutation();
+        Some(data)
+    }
+}
+
+#[cfg(any(debug_assertions, feature = "validate"))]
+impl<'a, D: SlotData<'a>> StringMap<'a, D> {
+    /// Walks the table and panics if any internal invariant is broken: the
+    /// control bytes, the occupancy bitmap and `len` must agree, and every
+    /// entry must be the first one found when probing for its key, then
+    /// scanning the stash.
+    ///
+    /// `hasher` must be the one the entries were hashed with.
+    pub fn validate<S: BuildHasher>(&self, hasher: &S) {
+        let len = self.bucket.len();
+        let mut full = 0;
+        for index in 0..self.bucket.slots.len() {
+            let ctrl = self.bucket.ctrl[index];
+            let occupied = self.bucket.occupied[index / 64] & (1 << (index % 64)) != 0;
+            assert_eq!(is_full(ctrl), occupied, "Slot {index}: bitmap out of sync");
+            assert!(
+                is_full(ctrl) || ctrl == EMPTY || ctrl == DELETED,
+                "Slot {index}: invalid control byte {ctrl:#x}"
+            );
+            let Some(data) = self.bucket.full(index) else {
+                continue;
+            };
+            full += 1;
+
+            let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+            assert_eq!(ctrl, tag(hash), "Slot {index}: stale hash tag");
+            let found = (0..len)
+                .map(|i| ((hash as usize) + i) % len)
+                .take_while(|&i| self.bucket.ctrl[i] != EMPTY)
+                .find(|&i| {
+                    self.bucket.ctrl[i] == tag(hash)
+                        && unsafe { self.bucket.get(i) }.matches(data.key(), hash)
+                })
+                .or_else(|| self.lookup_stash(data.key(), hash));
+            assert_eq!(
+                found,
+                Some(index),
+                "Slot {index}: entry unreachable or aliased by another slot"
+            );
+        }
+        assert_eq!(full, self.len, "Length out of sync");
+        assert!(len == 0 || self.len < len, "No free slot left");
     }
 }
 
 impl<'a, D> StringMap<'a, D> {
-    pub fn iter(&self) -> Iter<D> {
+    pub fn iter(&self) -> Iter<'_, D> {
         Iter {
-            bucket: &self.bucket,
+            slots: &self.bucket.slots,
+            occupied: Bits::new(self.bucket.occupied.iter().copied()),
             rem: self.len,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            guard: MutationGuard::new(&self.mutations),
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<D> {
+    /// Iterates over the entries as stored, in the order of their slots.
+    pub(crate) fn iter_data(&self) -> impl Iterator<Item = &D> + '_ {
+        let mut occupied = Bits::new(self.bucket.occupied.iter().copied());
+        iter::from_fn(move || occupied.next_index()).map(|index| unsafe { self.bucket.get(index) })
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, D> {
         IterMut {
-            bucket: &mut self.bucket,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            guard: MutationGuard::new(&self.mutations),
+            slots: self.bucket.slots.iter_mut(),
+            index: 0,
+            occupied: Bits::new(self.bucket.occupied.iter().copied()),
             rem: self.len,
         }
     }
 }
 
+impl<'a, D: SlotAccess> StringMap<'a, D> {
+    /// Calls `f` on every entry, in the order of [`iter`](Self::iter), with a
+    /// loop over the occupancy bitmap instead of an iterator's state.
+    #[inline]
+    pub fn for_each(&self, mut f: impl FnMut(&[u8], &D::Value)) {
+        for (word, &bits) in self.bucket.occupied.iter().enumerate() {
+            let mut bits = bits;
+            while bits != 0 {
+                let index = word * 64 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let data = unsafe { self.bucket.get(index) };
+                f(data.key(), data.value());
+            }
+        }
+    }
+
+    /// Calls `f` on every entry like [`for_each`](Self::for_each), with the
+    /// values borrowed mutably.
+    #[inline]
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&[u8], &mut D::Value)) {
+        let Bucket {
+            slots, occupied, ..
+        } = &mut self.bucket;
+        for (word, &bits) in occupied.iter().enumerate() {
+            let mut bits = bits;
+            while bits != 0 {
+                let index = word * 64 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let (key, value) =
+                    unsafe { slots.get_unchecked_mut(index).assume_init_mut() }.kv_mut();
+                f(key, value);
+            }
+        }
+    }
+}
+
 impl<'a, D: SlotData<'a>> IntoIterator for StringMap<'a, D> {
     type Item = (&'a [u8], D::Value);
 
@@ -244,27 +1456,27 @@ impl<'a, D: SlotData<'a>> IntoIterator for StringMap<'a, D> {
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter {
-            bucket: self.bucket.into_iter(),
+            data: self.bucket.into_full(),
             rem: self.len,
             key_alloc: self.key_alloc,
         }
     }
 }
 
-impl<'a, D: SlotData<'a>> IntoIterator for &'a StringMap<'a, D> {
-    type Item = (&'a [u8], &'a D::Value);
+impl<'b, 'a, D: SlotData<'a>> IntoIterator for &'b StringMap<'a, D> {
+    type Item = (&'b [u8], &'b D::Value);
 
-    type IntoIter = Iter<'a, D>;
+    type IntoIter = Iter<'b, D>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<'a, D: SlotData<'a>> IntoIterator for &'a mut StringMap<'a, D> {
-    type Item = (&'a [u8], &'a mut D::Value);
+impl<'b, 'a, D: SlotData<'a>> IntoIterator for &'b mut StringMap<'a, D> {
+    type Item = (&'b [u8], &'b mut D::Value);
 
-    type IntoIter = IterMut<'a, D>;
+    type IntoIter = IterMut<'b, D>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter_mut()
@@ -277,30 +1489,84 @@ impl<'a, D: SlotData<'a>> IntoIterator for &'a mut StringMap<'a, D> {
 //     }
 // }
 
+/// Yields the indices of the set bits in a sequence of words, skipping runs
+/// of free slots a word at a time.
+#[derive(Clone)]
+struct Bits<I> {
+    words: I,
+    word: u64,
+    base: usize,
+}
+
+impl<I: Iterator<Item = u64>> Bits<I> {
+    fn new(words: I) -> Self {
+        Bits {
+            words,
+            word: 0,
+            base: 0usize.wrapping_sub(64),
+        }
+    }
+
+    #[inline]
+    fn next_index(&mut self) -> Option<usize> {
+        while self.word == 0 {
+            self.word = self.words.next()?;
+            self.base = self.base.wrapping_add(64);
+        }
+        let index = self.base + self.word.trailing_zeros() as usize;
+        self.word &= self.word - 1;
+        Some(index)
+    }
+}
+
+/// Checks that a table isn't modified during an iteration, which only unsafe
+/// code can do, e.g. a C visitor given the map being iterated over.
+#[cfg(any(debug_assertions, feature = "validate"))]
+#[derive(Clone, Copy)]
+struct MutationGuard<'a> {
+    mutations: &'a u64,
+    expected: u64,
+}
+
+#[cfg(any(debug_assertions, feature = "validate"))]
+impl<'a> MutationGuard<'a> {
+    fn new(mutations: &'a u64) -> Self {
+        MutationGuard {
+            mutations,
+            expected: *mutations,
+        }
+    }
+
+    #[inline]
+    fn check(&self) {
+        // Read through the pointer each time, since the table can only be
+        // modified behind the back of the borrow.
+        let mutations = unsafe { ptr::read_volatile(self.mutations) };
+        assert_eq!(mutations, self.expected, "Table modified during iteration");
+    }
+}
+
 pub struct Iter<'a, D> {
-    bucket: &'a [Slot<D>],
+    slots: &'a [MaybeUninit<D>],
+    occupied: Bits<iter::Copied<slice::Iter<'a, u64>>>,
     rem: usize,
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    guard: MutationGuard<'a>,
 }
 
-impl<'a, D: SlotData<'a>> Iterator for Iter<'a, D> {
+impl<'a, D: SlotAccess> Iterator for Iter<'a, D> {
     type Item = (&'a [u8], &'a D::Value);
 
     fn next(&mut self) -> Option<Self::Item> {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        self.guard.check();
         if self.rem == 0 {
             return None;
         }
-        loop {
-            match self.bucket.split_first() {
-                Some((slot, rem)) => {
-                    self.bucket = rem;
-                    if let Slot::Data(data) = slot {
-                        self.rem -= 1;
-                        break Some((data.key(), data.value()));
-                    }
-                }
-                None => break None,
-            }
-        }
+        let index = self.occupied.next_index()?;
+        let data = unsafe { self.slots[index].assume_init_ref() };
+        self.rem -= 1;
+        Some((data.key(), data.value()))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -308,7 +1574,7 @@ impl<'a, D: SlotData<'a>> Iterator for Iter<'a, D> {
     }
 }
 
-impl<'a, D: fmt::Debug + SlotData<'a, Value: fmt::Debug>> fmt::Debug for Iter<'a, D> {
+impl<'a, D: fmt::Debug + SlotAccess<Value: fmt::Debug>> fmt::Debug for Iter<'a, D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let entries: Iter<'a, _> = self.clone();
         f.debug_map().entries(entries).finish()
@@ -318,39 +1584,45 @@ impl<'a, D: fmt::Debug + SlotData<'a, Value: fmt::Debug>> fmt::Debug for Iter<'a
 impl<'a, D> Clone for Iter<'a, D> {
     fn clone(&self) -> Self {
         Self {
-            bucket: self.bucket,
+            slots: self.slots,
+            occupied: self.occupied.clone(),
             rem: self.rem,
+            #[cfg(any(debug_assertions, feature = "validate"))]
+            guard: self.guard,
         }
     }
 }
 
-impl<'a, D: SlotData<'a>> ExactSizeIterator for Iter<'a, D> {}
-impl<'a, D: SlotData<'a>> FusedIterator for Iter<'a, D> {}
+impl<'a, D: SlotAccess> ExactSizeIterator for Iter<'a, D> {}
+impl<'a, D: SlotAccess> FusedIterator for Iter<'a, D> {}
 
 pub struct IterMut<'a, D> {
-    bucket: &'a mut [Slot<D>],
+    /// The slots from `index` on, split off the table as they are skipped or
+    /// yielded, so that no reference ever covers a yielded slot again.
+    slots: slice::IterMut<'a, MaybeUninit<D>>,
+    index: usize,
+    occupied: Bits<iter::Copied<slice::Iter<'a, u64>>>,
     rem: usize,
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    guard: MutationGuard<'a>,
 }
 
-impl<'a, D: SlotData<'a>> Iterator for IterMut<'a, D> {
+impl<'a, D: SlotAccess> Iterator for IterMut<'a, D> {
     type Item = (&'a [u8], &'a mut D::Value);
 
     fn next(&mut self) -> Option<Self::Item> {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        self.guard.check();
         if self.rem == 0 {
             return None;
         }
-        loop {
-            match unsafe { &mut *(self.bucket as *mut [Slot<D>]) }.split_first_mut() {
-                Some((slot, rem)) => {
-                    self.bucket = rem;
-                    if let Slot::Data(data) = slot {
-                        self.rem -= 1;
-                        break Some(data.kv_mut());
-                    }
-                }
-                None => break None,
-            }
-        }
+        let index = self.occupied.next_index()?;
+        let slot = self.slots.nth(index - self.index)?;
+        self.index = index + 1;
+        // The occupancy bit was set, so the slot is initialized.
+        let data = unsafe { slot.assume_init_mut() };
+        self.rem -= 1;
+        Some(data.kv_mut())
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -361,22 +1633,40 @@ impl<'a, D: SlotData<'a>> Iterator for IterMut<'a, D> {
 // impl<'a, D: fmt::Debug + SlotData<'a, Value: fmt::Debug>> fmt::Debug for IterMut<'a, D> {
 //     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 //         let iter = Iter {
-//             bucket: self.bucket,
+//             slots: self.slots,
 //             rem: self.rem,
 //         };
 //         f.debug_map().entries(iter).finish()
 //     }
 // }
 
-impl<'a, D: SlotData<'a>> ExactSizeIterator for IterMut<'a, D> {}
-impl<'a, D: SlotData<'a>> FusedIterator for IterMut<'a, D> {}
+impl<'a, D: SlotAccess> ExactSizeIterator for IterMut<'a, D> {}
+impl<'a, D: SlotAccess> FusedIterator for IterMut<'a, D> {}
 
 pub struct IntoIter<'a, D: 'a> {
-    bucket: std::vec::IntoIter<Slot<D>>,
+    data: IntoFull<D>,
     rem: usize,
     key_alloc: &'a Bump,
 }
 
+impl<'a, D: SlotData<'a>> IntoIter<'a, D> {
+    /// Returns the next entry along with the hash of its key, either stored
+    /// in its slot or computed with `hasher`.
+    pub(crate) fn next_hashed<S: BuildHasher>(
+        &mut self,
+        hasher: &S,
+    ) -> Option<(&'a [u8], u64, D::Value)> {
+        if self.rem == 0 {
+            return None;
+        }
+        let data = self.data.next()?;
+        self.rem -= 1;
+        let hash = data.hash().unwrap_or_else(|| hasher.hash_one(data.key()));
+        let (key, value) = data.into_kv(self.key_alloc);
+        Some((key, hash, value))
+    }
+}
+
 impl<'a, D: SlotData<'a>> Iterator for IntoIter<'a, D> {
     type Item = (&'a [u8], D::Value);
 
@@ -384,17 +1674,9 @@ impl<'a, D: SlotData<'a>> Iterator for IntoIter<'a, D> {
         if self.rem == 0 {
             return None;
         }
-        loop {
-            match self.bucket.next() {
-                Some(slot) => {
-                    if let Slot::Data(data) = slot {
-                        self.rem -= 1;
-                        break Some(data.into_kv(self.key_alloc));
-                    }
-                }
-                None => break None,
-            }
-        }
+        let data = self.data.next()?;
+        self.rem -= 1;
+        Some(data.into_kv(self.key_alloc))
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
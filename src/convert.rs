@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyRef, StringMap};
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Moves the entries of `map` into this one, reserving room in every tier
+    /// beforehand. Values of keys already present are replaced.
+    pub fn extend_from_hash_map<H>(&mut self, map: HashMap<Vec<u8>, T, H>) {
+        self.reserve_hint(map.keys().map(|key| (key.len(), 1)));
+        for (key, value) in map {
+            self.insert(KeyRef::from(&*key), value);
+        }
+    }
+}
+
+/// Builds a map from a [`HashMap`], with its keys copied into the arena.
+impl<'a, T, H, S: BuildHasher + Default> From<(HashMap<Vec<u8>, T, H>, &'a Bump)>
+    for StringMap<'a, T, S>
+{
+    fn from((map, key_alloc): (HashMap<Vec<u8>, T, H>, &'a Bump)) -> Self {
+        let mut ret = StringMap::with_hasher(S::default(), key_alloc);
+        ret.extend_from_hash_map(map);
+        ret
+    }
+}
+
+/// Builds a map from a [`BTreeMap`], with its keys copied into the arena.
+impl<'a, T, S: BuildHasher + Default> From<(BTreeMap<Vec<u8>, T>, &'a Bump)>
+    for StringMap<'a, T, S>
+{
+    fn from((map, key_alloc): (BTreeMap<Vec<u8>, T>, &'a Bump)) -> Self {
+        let mut ret = StringMap::with_hasher(S::default(), key_alloc);
+        ret.reserve_hint(map.keys().map(|key| (key.len(), 1)));
+        for (key, value) in map {
+            ret.insert(KeyRef::from(&*key), value);
+        }
+        ret
+    }
+}
+
+impl<'a, T, S, H: BuildHasher + Default> From<StringMap<'a, T, S>> for HashMap<Vec<u8>, T, H> {
+    fn from(map: StringMap<'a, T, S>) -> Self {
+        map.into_iter()
+            .map(|(key, value)| (key.to_vec(), value))
+            .collect()
+    }
+}
+
+impl<'a, T, S> From<StringMap<'a, T, S>> for BTreeMap<Vec<u8>, T> {
+    fn from(map: StringMap<'a, T, S>) -> Self {
+        map.into_iter()
+            .map(|(key, value)| (key.to_vec(), value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let bump = Bump::new();
+        let cmp: HashMap<Vec<u8>, usize> = (0..1000)
+            .map(|i| (vec![b'a' + (i % 26) as u8; i % 40], i))
+            .collect();
+
+        let map = StringMap::<_, RandomState>::from((cmp.clone(), &bump));
+        assert_eq!(map.len(), cmp.len());
+        assert_eq!(HashMap::<_, _>::from(map), cmp);
+
+        let sorted: BTreeMap<_, _> = cmp.clone().into_iter().collect();
+        let map = StringMap::<_, RandomState>::from((sorted.clone(), &bump));
+        assert_eq!(BTreeMap::from(map), sorted);
+
+        let mut map = StringMap::new(&bump);
+        map.insert(b"", usize::MAX);
+        map.insert(b"extra", usize::MAX);
+        map.extend_from_hash_map(cmp.clone());
+        assert_eq!(map.len(), cmp.len() + 1);
+        for (key, value) in &cmp {
+            assert_eq!(map.get(key), Some(value));
+        }
+        assert_eq!(map.get(b"extra"), Some(&usize::MAX));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, Iter, KeyRef, StringMap};
+
+const ADD_CHUNK: usize = 16;
+
+/// The counters of [`StringCounts`].
+pub trait Counter: Copy + Ord + Default {
+    const ONE: Self;
+
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+macro_rules! impl_counter {
+    ($($ty:ty),*) => {
+        $(impl Counter for $ty {
+            const ONE: Self = 1;
+
+            #[inline]
+            fn saturating_add(self, other: Self) -> Self {
+                <$ty>::saturating_add(self, other)
+            }
+        })*
+    };
+}
+impl_counter!(u32, u64);
+
+/// A tiered map counting occurrences of keys, with counters stored inline in
+/// the slots and saturating instead of overflowing.
+///
+/// `u32` counters keep the slots of the small tiers smaller than `u64` ones,
+/// for inputs known to stay under 4 billion rows per key or for which a
+/// saturated count is good enough.
+pub struct StringCounts<'a, C = u64, S = RandomState> {
+    map: StringMap<'a, C, S>,
+}
+
+impl<'a, C, S> StringCounts<'a, C, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        StringCounts {
+            map: StringMap::with_hasher(hasher, key_alloc),
+        }
+    }
+
+    /// Returns the number of distinct keys counted.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, C> {
+        self.map.iter()
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, C, S> {
+        self.map
+    }
+}
+
+impl<'a, C> StringCounts<'a, C, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, C: Counter, S: BuildHasher> StringCounts<'a, C, S> {
+    /// Returns the count of `key`, which is zero if it was never added.
+    pub fn get<Q: Equivalent>(&self, key: Q) -> C {
+        self.map.get(key).copied().unwrap_or_default()
+    }
+
+    /// Adds `n` to the count of `key`, returning the new count.
+    pub fn add_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64, n: C) -> C {
+        let count = self.map.get_or_insert_with_hashed(key, hash, C::default).0;
+        *count = count.saturating_add(n);
+        *count
+    }
+
+    pub fn add<Q: Equivalent>(&mut self, key: Q, n: C) -> C {
+        let key = key.as_key_ref();
+        self.add_hashed(key, self.map.hasher().hash_one(key), n)
+    }
+
+    pub fn increment<Q: Equivalent>(&mut self, key: Q) -> C {
+        self.add(key, C::ONE)
+    }
+
+    /// Counts every key of `keys` once, with their precomputed `hashes`, e.g.
+    /// from [`BatchState::hash_batch`](crate::BatchState::hash_batch).
+    ///
+    /// The slots of a chunk of keys are prefetched before any of them is
+    /// probed.
+    pub fn add_batch_hashed(&mut self, keys: &[&[u8]], hashes: &[u64]) {
+        assert_eq!(keys.len(), hashes.len());
+        for (keys, hashes) in keys.chunks(ADD_CHUNK).zip(hashes.chunks(ADD_CHUNK)) {
+            self.map.guard_probes();
+            for (key, &hash) in keys.iter().zip(hashes) {
+                self.map.prefetch_hashed(KeyRef::from(*key), hash);
+            }
+            for (key, &hash) in keys.iter().zip(hashes) {
+                self.add_hashed(KeyRef::from(*key), hash, C::ONE);
+            }
+        }
+    }
+
+    /// Counts every key of `keys` once.
+    pub fn add_batch(&mut self, keys: &[&[u8]]) {
+        let mut hashes = Vec::new();
+        self.map.hash_batch(keys, None, &mut hashes);
+        self.add_batch_hashed(keys, &hashes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::BatchState;
+
+    #[test]
+    fn test_string_counts() {
+        let bump = Bump::new();
+        let keys = (0..20000)
+            .map(|_| {
+                (rand::random::<u8>() % 50)
+                    .to_string()
+                    .repeat(rand::random::<usize>() % 10)
+            })
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_bytes()).collect::<Vec<_>>();
+        let mut cmp = HashMap::<&[u8], u64>::new();
+        for key in &keys {
+            *cmp.entry(key).or_default() += 1;
+        }
+
+        let mut counts = StringCounts::<u64>::new(&bump);
+        let (left, right) = keys.split_at(7777);
+        counts.add_batch(left);
+        for key in right {
+            counts.increment(KeyRef::from(*key));
+        }
+        assert_eq!(counts.len(), cmp.len());
+        for (key, count) in &cmp {
+            assert_eq!(counts.get(KeyRef::from(*key)), *count);
+        }
+        assert_eq!(counts.get(KeyRef::from(&b"missing"[..])), 0);
+
+        let state = BatchState::new();
+        let mut hashes = vec![0; keys.len()];
+        state.hash_batch(&keys, &mut hashes);
+        let mut counts = StringCounts::<u32, _>::with_hasher(state, &bump);
+        counts.add_batch_hashed(&keys, &hashes);
+        for (key, count) in counts.iter() {
+            assert_eq!(u64::from(*count), cmp[key.key()]);
+        }
+
+        let key = KeyRef::from(&b"saturated"[..]);
+        assert_eq!(counts.add(key, u32::MAX - 1), u32::MAX - 1);
+        assert_eq!(counts.add(key, 5), u32::MAX);
+        assert_eq!(counts.increment(key), u32::MAX);
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem;
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+
+/// The number of slots of a bucket.
+const BUCKET_SLOTS: usize = 4;
+
+const MIN_BUCKETS: usize = 4;
+
+/// The number of entries displaced by an insertion before giving up on the
+/// current table.
+const MAX_KICKS: usize = 256;
+
+struct Slot<'a, T> {
+    hash: u64,
+    key: &'a [u8],
+    value: T,
+}
+
+type Bucket<'a, T> = [Option<Slot<'a, T>>; BUCKET_SLOTS];
+
+/// Where an entry is stored.
+#[derive(Clone, Copy)]
+enum Location {
+    Table(usize, usize),
+    Stash(usize),
+}
+
+/// A map using bucketized cuckoo hashing instead of linear probing.
+///
+/// Every key has two candidate buckets of four slots, one chosen
+/// by the low half of its hash and one by the high half, and is always stored
+/// in one of them. A lookup thus reads at most two buckets, however full the
+/// table is, at the cost of insertions that may displace entries to their
+/// other bucket, in chains of up to 256 entries.
+///
+/// An entry that can't be placed once the chain is exhausted makes the table
+/// grow, unless it is still at most half full, e.g. because of too many keys
+/// with the same hash: the entry then goes to a small stash, also searched by
+/// lookups if not empty.
+pub struct CuckooStringMap<'a, T, S = RandomState> {
+    buckets: Box<[Bucket<'a, T>]>,
+    stash: Vec<Slot<'a, T>>,
+    len: usize,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T, S> CuckooStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        CuckooStringMap {
+            buckets: Self::empty_buckets(MIN_BUCKETS),
+            stash: Vec::new(),
+            len: 0,
+            hasher,
+            key_alloc,
+        }
+    }
+
+    fn empty_buckets(count: usize) -> Box<[Bucket<'a, T>]> {
+        (0..count)
+            .map(|_| [(); BUCKET_SLOTS].map(|_| None))
+            .collect()
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of slots of the table, excluding the stash.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SLOTS
+    }
+
+    /// Returns the number of entries that didn't fit in either of their
+    /// buckets.
+    pub fn stashed(&self) -> usize {
+        self.stash.len()
+    }
+
+    fn buckets_of(&self, hash: u64) -> [usize; 2] {
+        let mask = self.buckets.len() - 1;
+        [
+            hash as usize & mask,
+            hash.rotate_left(32).wrapping_mul(0x9e37_79b9_7f4a_7c15) as usize & mask,
+        ]
+    }
+
+    fn slots(&self) -> impl Iterator<Item = &Slot<'a, T>> + '_ {
+        (self.buckets.iter().flatten().flatten()).chain(&self.stash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &T)> + '_ {
+        self.slots().map(|slot| (slot.key, &slot.value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a [u8], &mut T)> + '_ {
+        (self.buckets.iter_mut().flatten().flatten())
+            .chain(&mut self.stash)
+            .map(|slot| (slot.key, &mut slot.value))
+    }
+
+    fn slot_mut(&mut self, location: Location) -> &mut Slot<'a, T> {
+        match location {
+            Location::Table(bucket, slot) => self.buckets[bucket][slot].as_mut().unwrap(),
+            Location::Stash(index) => &mut self.stash[index],
+        }
+    }
+
+    /// Stores `slot` in one of its buckets, displacing other entries to their
+    /// other bucket if both are full.
+    ///
+    /// Returns the entry left without a slot if the chain of displacements
+    /// is too long, which may not be `slot`.
+    fn place(&mut self, mut slot: Slot<'a, T>) -> Result<(), Slot<'a, T>> {
+        let [first, second] = self.buckets_of(slot.hash);
+        let mut bucket = first;
+        for kick in 0..=MAX_KICKS {
+            let candidates = if kick == 0 {
+                [first, second]
+            } else {
+                [bucket, bucket]
+            };
+            for candidate in candidates {
+                if let Some(free) = self.buckets[candidate].iter_mut().find(|s| s.is_none()) {
+                    *free = Some(slot);
+                    return Ok(());
+                }
+            }
+            if kick == MAX_KICKS {
+                break;
+            }
+            // Varies the victim with the entry and the length of the chain,
+            // so that the chain doesn't cycle between the same slots.
+            let victim = (slot.hash as usize).wrapping_add(kick) % BUCKET_SLOTS;
+            slot = self.buckets[bucket][victim].replace(slot).unwrap();
+            let [first, second] = self.buckets_of(slot.hash);
+            bucket = if first == bucket { second } else { first };
+        }
+        Err(slot)
+    }
+
+    /// Doubles the number of buckets and places every entry again, including
+    /// the stashed ones.
+    fn grow(&mut self) {
+        let count = self.buckets.len() * 2;
+        let buckets = mem::replace(&mut self.buckets, Self::empty_buckets(count));
+        let stash = mem::take(&mut self.stash);
+        let slots = (buckets.into_vec().into_iter().flatten().flatten()).chain(stash);
+        for slot in slots {
+            if let Err(slot) = self.place(slot) {
+                self.stash.push(slot);
+            }
+        }
+    }
+}
+
+impl<'a, T> CuckooStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> CuckooStringMap<'a, T, S> {
+    fn find(&self, key: &[u8], hash: u64) -> Option<Location> {
+        let matches = |slot: &Slot<'a, T>| slot.hash == hash && slot.key == key;
+        for bucket in self.buckets_of(hash) {
+            let mut slots = self.buckets[bucket].iter();
+            if let Some(slot) = slots.position(|slot| slot.as_ref().is_some_and(matches)) {
+                return Some(Location::Table(bucket, slot));
+            }
+        }
+        if self.stash.is_empty() {
+            return None;
+        }
+        self.stash.iter().position(matches).map(Location::Stash)
+    }
+
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        let slot = match self.find(key, self.hasher.hash_one(key))? {
+            Location::Table(bucket, slot) => self.buckets[bucket][slot].as_ref().unwrap(),
+            Location::Stash(index) => &self.stash[index],
+        };
+        Some(&slot.value)
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        let location = self.find(key, self.hasher.hash_one(key))?;
+        Some(&mut self.slot_mut(location).value)
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Stores a new entry for `key`, which must be absent.
+    fn push(&mut self, key: &[u8], hash: u64, value: T) -> &mut T {
+        let key = self.key_alloc.alloc_slice_copy(key);
+        self.len += 1;
+        let mut slot = Slot { hash, key, value };
+        while let Err(homeless) = self.place(slot) {
+            if self.len <= self.capacity() / 2 {
+                self.stash.push(homeless);
+                break;
+            }
+            self.grow();
+            slot = homeless;
+        }
+        // The entry may have been displaced since it was placed.
+        let location = self.find(key, hash).unwrap();
+        &mut self.slot_mut(location).value
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            Some(location) => Some(mem::replace(&mut self.slot_mut(location).value, value)),
+            None => {
+                self.push(key, hash, value);
+                None
+            }
+        }
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            Some(location) => (&mut self.slot_mut(location).value, false),
+            None => (self.push(key, hash, value()), true),
+        }
+    }
+
+    /// Removes `key`. Its key stays in the arena.
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        let slot = match self.find(key, self.hasher.hash_one(key))? {
+            Location::Table(bucket, slot) => self.buckets[bucket][slot].take().unwrap(),
+            Location::Stash(index) => self.stash.swap_remove(index),
+        };
+        self.len -= 1;
+        Some(slot.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{differential, numbered, ConstState};
+
+    #[test]
+    fn test_cuckoo_map() {
+        let bump = Bump::new();
+        let map = CuckooStringMap::new(&bump);
+
+        let map = differential(map, 50000, numbered(8000));
+        assert_eq!(map.inner().stashed(), 0);
+    }
+
+    #[test]
+    fn test_stash() {
+        let bump = Bump::new();
+        let hasher = ConstState::default();
+        let mut map = CuckooStringMap::with_hasher(hasher, &bump);
+
+        // Only two buckets can hold keys with the same hash.
+        for i in 0..100 {
+            assert_eq!(map.insert(i.to_string(), i), None);
+        }
+        assert_eq!(map.stashed(), 100 - 2 * BUCKET_SLOTS);
+        assert!(map.capacity() <= 400);
+        for i in 0..100 {
+            assert_eq!(map.get(i.to_string()), Some(&i));
+        }
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.remove(i.to_string()), Some(i));
+        }
+        for (key, value) in map.iter_mut() {
+            assert_eq!(*key, *value.to_string().as_bytes());
+            *value += 1;
+        }
+        for i in 0..100 {
+            let expected = (i % 3 != 0).then_some(i + 1);
+            assert_eq!(map.get(i.to_string()), expected.as_ref());
+        }
+    }
+}
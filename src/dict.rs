@@ -0,0 +1,200 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::Equivalent;
+use crate::indexed::IndexedStringMap;
+
+const UNRESOLVED: u32 = u32::MAX;
+
+/// The id of a dictionary registered in a [`DictStringMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DictId(u32);
+
+struct Dictionary<'a> {
+    values: Vec<&'a [u8]>,
+    /// The index of the entry of each code, or [`UNRESOLVED`] if the code has
+    /// not been seen yet.
+    indices: Vec<u32>,
+}
+
+/// A map whose keys arrive dictionary-encoded, as codes into dictionaries
+/// registered up front.
+///
+/// Each code is hashed and probed only the first time it is seen; repeated
+/// codes go straight to their entry through a per-dictionary cache. Equal
+/// values of different dictionaries share the same entry.
+pub struct DictStringMap<'a, T, S = RandomState> {
+    map: IndexedStringMap<'a, T, S>,
+    dicts: Vec<Dictionary<'a>>,
+}
+
+impl<'a, T, S> DictStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        DictStringMap {
+            map: IndexedStringMap::with_hasher(hasher, key_alloc),
+            dicts: Vec::new(),
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Registers a dictionary whose values outlive the map, e.g. in the
+    /// buffers of the batch they come from. Codes passed along with the
+    /// returned id index into `values`.
+    ///
+    /// The values are borrowed rather than copied, both here and when they
+    /// become keys of the map.
+    pub fn register_dictionary(&mut self, values: impl IntoIterator<Item = &'a [u8]>) -> DictId {
+        let id = u32::try_from(self.dicts.len()).expect("Too many dictionaries");
+        let values = values.into_iter().collect::<Vec<_>>();
+        self.dicts.push(Dictionary {
+            indices: vec![UNRESOLVED; values.len()],
+            values,
+        });
+        DictId(id)
+    }
+
+    /// Returns the value of `code` in the dictionary `dict`.
+    pub fn decode(&self, dict: DictId, code: u32) -> &'a [u8] {
+        self.dicts[dict.0 as usize].values[code as usize]
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&'a [u8], &T)> + '_ {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl ExactSizeIterator<Item = (&'a [u8], &mut T)> + '_ {
+        self.map.iter_mut()
+    }
+
+    pub fn into_inner(self) -> IndexedStringMap<'a, T, S> {
+        self.map
+    }
+}
+
+impl<'a, T> DictStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> DictStringMap<'a, T, S> {
+    pub fn get(&self, dict: DictId, code: u32) -> Option<&T> {
+        let dict = &self.dicts[dict.0 as usize];
+        match dict.indices[code as usize] {
+            UNRESOLVED => self.map.get(dict.values[code as usize]),
+            index => Some(&self.map.values()[index as usize]),
+        }
+    }
+
+    /// Looks up a plain key, as if it were not dictionary-encoded.
+    pub fn get_by_key<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    /// Returns the value of `code` in the dictionary `dict`, inserting one
+    /// with `value` if absent, along with whether it was newly inserted.
+    pub fn get_or_insert_with(
+        &mut self,
+        dict: DictId,
+        code: u32,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let dict = &mut self.dicts[dict.0 as usize];
+        let cached = &mut dict.indices[code as usize];
+        if *cached != UNRESOLVED {
+            return (&mut self.map.values_mut()[*cached as usize], false);
+        }
+
+        let key = dict.values[code as usize];
+        let (_, inserted) = self.map.get_or_insert_borrowed_with(key, value);
+        *cached = match inserted {
+            true => self.map.len() as u32 - 1,
+            false => self.map.index_of(key).unwrap(),
+        };
+        (&mut self.map.values_mut()[*cached as usize], inserted)
+    }
+
+    pub fn insert(&mut self, dict: DictId, code: u32, value: T) -> Option<T> {
+        let mut value = Some(value);
+        let (slot, inserted) = self.get_or_insert_with(dict, code, || value.take().unwrap());
+        (!inserted).then(|| std::mem::replace(slot, value.unwrap()))
+    }
+
+    /// Groups `inputs` by the dictionary-encoded `codes`, like
+    /// [`StringMap::accumulate`](crate::StringMap::accumulate).
+    pub fn accumulate<I>(
+        &mut self,
+        dict: DictId,
+        codes: &[u32],
+        inputs: &[I],
+        mut init: impl FnMut(&I) -> T,
+        mut fold: impl FnMut(&mut T, &I),
+    ) {
+        assert_eq!(codes.len(), inputs.len());
+        for (&code, input) in codes.iter().zip(inputs) {
+            let mut input = Some(input);
+            let (state, inserted) =
+                self.get_or_insert_with(dict, code, || init(input.take().unwrap()));
+            if !inserted {
+                fold(state, input.unwrap());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_dict_map() {
+        let values = (0..100)
+            .map(|i| (i % 37).to_string().repeat(i % 11))
+            .collect::<Vec<_>>();
+        let bump = Bump::new();
+        let mut map = DictStringMap::new(&bump);
+        let mut cmp = HashMap::<Vec<u8>, u64>::new();
+
+        let dicts = [
+            map.register_dictionary(values[..60].iter().map(String::as_bytes)),
+            map.register_dictionary(values[40..].iter().map(String::as_bytes)),
+        ];
+
+        for i in 0..20000u64 {
+            let which = rand::random::<usize>() % 2;
+            let code = rand::random::<u32>() % 60;
+            let key = map.decode(dicts[which], code);
+            assert_eq!(key, values[which * 40 + code as usize].as_bytes());
+
+            let (count, inserted) = map.get_or_insert_with(dicts[which], code, || 0);
+            *count += i;
+            assert_eq!(inserted, !cmp.contains_key(key));
+            *cmp.entry(key.to_vec()).or_insert(0) += i;
+        }
+        assert_eq!(map.len(), cmp.len());
+        // The keys are the values of the dictionaries themselves.
+        assert_eq!(bump.allocated_bytes() - bump.chunk_capacity(), 0);
+
+        for (key, value) in map.iter() {
+            assert_eq!(map.get_by_key(key), Some(value));
+            assert_eq!(cmp.remove(key), Some(*value));
+        }
+        assert!(cmp.is_empty());
+    }
+}
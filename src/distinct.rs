@@ -0,0 +1,37 @@
+use bumpalo::Bump;
+
+use crate::adaptive::{AsKeyBytes, StringMap};
+
+/// Returns the distinct keys of `keys` as slices of `key_alloc`, in no
+/// particular order, e.g. for a DISTINCT operator.
+///
+/// The keys are gathered by a map without values, so that the slots of the
+/// inline tiers only hold the keys, and are then emitted in one pass over it
+/// (see [`StringMap::unique_keys`]).
+pub fn distinct<K: AsKeyBytes>(keys: impl IntoIterator<Item = K>, key_alloc: &Bump) -> Vec<&[u8]> {
+    let mut map = StringMap::<(), _>::new(key_alloc);
+    for key in keys {
+        map.get_or_insert_with(key.as_key_bytes(), || ());
+    }
+    map.unique_keys()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_distinct() {
+        let keys = (0..10000)
+            .map(|_| vec![b'a' + rand::random::<u8>() % 4; rand::random::<usize>() % 40])
+            .collect::<Vec<_>>();
+        let cmp = keys.iter().map(Vec::as_slice).collect::<HashSet<_>>();
+
+        let bump = Bump::new();
+        let distinct = distinct(&keys, &bump);
+        assert_eq!(distinct.len(), cmp.len());
+        assert_eq!(distinct.into_iter().collect::<HashSet<_>>(), cmp);
+    }
+}
@@ -0,0 +1,86 @@
+use std::alloc::Layout;
+use std::fmt;
+
+use crate::pool::BudgetExceeded;
+
+/// The error of a fallible operation, e.g. for services that must shed load
+/// rather than abort when memory runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The table would have to grow past what it can address, or past what
+    /// it was allowed to allocate up front.
+    CapacityExhausted,
+    /// The allocator failed to provide `bytes` bytes, either for the slots or
+    /// for a key copied into the arena.
+    AllocFailed { bytes: usize },
+    /// The allocation would exceed a memory budget.
+    BudgetExceeded(BudgetExceeded),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::CapacityExhausted => write!(f, "capacity overflow"),
+            Error::AllocFailed { bytes } => write!(f, "failed to allocate {bytes} bytes"),
+            Error::BudgetExceeded(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::BudgetExceeded(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BudgetExceeded> for Error {
+    fn from(err: BudgetExceeded) -> Self {
+        Error::BudgetExceeded(err)
+    }
+}
+
+/// The error returned by [`StringMap::try_insert`](crate::StringMap::try_insert)
+/// when the key is already present, holding the present value and the value
+/// that was not inserted.
+pub struct OccupiedError<'b, T> {
+    /// The value already in the map.
+    pub entry: &'b mut T,
+    /// The value that was not inserted.
+    pub value: T,
+}
+
+impl<T: fmt::Debug> fmt::Debug for OccupiedError<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("old_value", &self.entry)
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Display for OccupiedError<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key already exists with value {:?}",
+            self.value, self.entry,
+        )
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for OccupiedError<'_, T> {}
+
+/// Allocates a vector of `len` copies of `f()` without aborting on failure.
+pub(crate) fn try_vec<T>(len: usize, f: impl FnMut() -> T) -> Result<Vec<T>, Error> {
+    let layout = Layout::array::<T>(len).map_err(|_| Error::CapacityExhausted)?;
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(len).map_err(|_| Error::AllocFailed {
+        bytes: layout.size(),
+    })?;
+    vec.resize_with(len, f);
+    Ok(vec)
+}
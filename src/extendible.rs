@@ -0,0 +1,254 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem;
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+use crate::large;
+
+/// The number of entries at which a segment is split.
+const SEGMENT_ENTRIES: usize = 512;
+/// The deepest a segment is split, past which it grows like a plain table.
+///
+/// The directory is indexed by the hash bits from 32 up, clear of the bits
+/// the segments probe with and of those their control bytes are made of.
+const MAX_DEPTH: u32 = 24;
+
+#[inline]
+fn directory_bits(hash: u64) -> usize {
+    (hash >> 32) as usize
+}
+
+struct Segment<'a, T> {
+    map: large::StringMap<'a, T>,
+    /// The number of low directory bits shared by the hashes in the segment.
+    depth: u32,
+}
+
+impl<'a, T> Segment<'a, T> {
+    fn new<S: BuildHasher>(mut map: large::StringMap<'a, T>, depth: u32, hasher: &S) -> Self {
+        // Size the table for a full segment up front, so that it never resizes
+        // before being split.
+        map.reserve(SEGMENT_ENTRIES.saturating_sub(map.len()), hasher);
+        Segment { map, depth }
+    }
+}
+
+/// A map that grows by splitting one segment at a time instead of doubling a
+/// whole table, for streaming pipelines where no single insertion may stall.
+///
+/// The entries are spread over segments of up to 512 entries, found through
+/// a directory indexed by hash bits, as in extendible hashing. An insertion
+/// into a full segment splits it in two by one more hash bit, which moves at
+/// most the entries of that segment and, once in a while, doubles the
+/// directory, which only holds one index per segment. Removals never merge
+/// segments.
+pub struct ExtendibleStringMap<'a, T, S = RandomState> {
+    directory: Vec<u32>,
+    segments: Vec<Segment<'a, T>>,
+    /// The number of low directory bits indexing the directory.
+    depth: u32,
+    len: usize,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T, S> ExtendibleStringMap<'a, T, S> {
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of hash bits indexing the directory.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &T)> + '_ {
+        self.segments.iter().flat_map(|segment| segment.map.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&[u8], &mut T)> + use<'_, 'a, T, S> {
+        (self.segments.iter_mut()).flat_map(|segment| segment.map.iter_mut())
+    }
+
+    fn segment_of(&self, hash: u64) -> usize {
+        let mask = (1 << self.depth) - 1;
+        self.directory[directory_bits(hash) & mask] as usize
+    }
+}
+
+impl<'a, T> ExtendibleStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> ExtendibleStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        let segment = Segment::new(large::StringMap::new(key_alloc), 0, &hasher);
+        ExtendibleStringMap {
+            directory: vec![0],
+            segments: vec![segment],
+            depth: 0,
+            len: 0,
+            hasher,
+            key_alloc,
+        }
+    }
+
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        self.segments[self.segment_of(hash)].map.get(key, hash)
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let segment = self.segment_of(hash);
+        self.segments[segment].map.get_mut(key, hash)
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let segment = self.make_room(key, hash);
+        let old = self.segments[segment]
+            .map
+            .insert(key, hash, value, &self.hasher);
+        self.len += old.is_none() as usize;
+        old
+    }
+
+    /// Returns the value of `key`, inserting one with `value` if absent, along
+    /// with whether it was newly inserted.
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let segment = self.make_room(key, hash);
+        let (value, inserted) =
+            self.segments[segment]
+                .map
+                .get_or_insert_with(key, hash, value, &self.hasher);
+        self.len += inserted as usize;
+        (value, inserted)
+    }
+
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let segment = self.segment_of(hash);
+        let old = self.segments[segment].map.remove(key, hash, &self.hasher);
+        self.len -= old.is_some() as usize;
+        old
+    }
+
+    /// Returns the segment `key` goes to, splitting it first if it is full and
+    /// `key` is absent.
+    fn make_room(&mut self, key: &[u8], hash: u64) -> usize {
+        loop {
+            let index = self.segment_of(hash);
+            let segment = &self.segments[index];
+            if segment.map.len() < SEGMENT_ENTRIES
+                || segment.depth == MAX_DEPTH
+                || segment.map.get(key, hash).is_some()
+            {
+                return index;
+            }
+            self.split(index, hash);
+        }
+    }
+
+    /// Splits the segment `index`, which `hash` maps to, by one more hash bit.
+    #[cold]
+    fn split(&mut self, index: usize, hash: u64) {
+        let depth = self.segments[index].depth;
+        if depth == self.depth {
+            self.directory.extend_from_within(..);
+            self.depth += 1;
+        }
+
+        let map = mem::replace(
+            &mut self.segments[index].map,
+            large::StringMap::with_capacity(0, self.key_alloc),
+        );
+        let part = |hash| directory_bits(hash) >> depth & 1;
+        let [low, high] = <[_; 2]>::try_from(map.split(2, part, &self.hasher))
+            .ok()
+            .unwrap();
+        self.segments[index] = Segment::new(low, depth + 1, &self.hasher);
+
+        // The directory entries of the segment are those sharing its low
+        // `depth` bits; the ones with the next bit set now go to the new one.
+        let new = u32::try_from(self.segments.len()).unwrap();
+        self.segments
+            .push(Segment::new(high, depth + 1, &self.hasher));
+        let first = directory_bits(hash) & ((1 << depth) - 1) | 1 << depth;
+        for entry in self.directory[first..].iter_mut().step_by(2 << depth) {
+            *entry = new;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_extendible_map() {
+        let bump = Bump::new();
+        let mut map = ExtendibleStringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..100000 {
+            let value = rand::random::<u16>() as u64;
+            let key = [&value.to_ne_bytes()[..], &[0xef; 8]].concat();
+            match value % 5 {
+                0 => assert_eq!(map.remove(&key), cmp.remove(&key)),
+                _ => assert_eq!(map.insert(&key, value), cmp.insert(key, value)),
+            }
+        }
+        assert_eq!(map.len(), cmp.len());
+        assert!(map.segment_count() > 1);
+        assert!(map.segment_count() <= 1 << map.depth());
+        for segment in &map.segments {
+            assert!(segment.map.len() <= SEGMENT_ENTRIES);
+            assert!(segment.depth <= map.depth());
+        }
+
+        for (key, value) in map.iter() {
+            assert_eq!(cmp.get(key), Some(value));
+        }
+        for (key, value) in &cmp {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+}
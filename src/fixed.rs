@@ -0,0 +1,166 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, Iter, KeyRef, StringMap};
+use crate::builder::StringMapBuilder;
+use crate::common::ShrinkPolicy;
+use crate::error::Error;
+
+/// The probe length past which insertions are refused by default.
+const DEFAULT_PROBE_LIMIT: usize = 64;
+
+/// A tiered map that never allocates once created, for latency-critical paths
+/// such as audio callbacks or packet processing.
+///
+/// The tiers keep the slots they were created with: instead of growing, an
+/// insertion fails with [`Error::CapacityExhausted`], as it does when the
+/// probe sequence it would take exceeds the probe limit. Large keys are only
+/// copied into the current chunk of the arena, so create it with
+/// [`Bump::with_capacity`]; an insertion whose key doesn't fit there fails
+/// with [`Error::AllocFailed`]. Removals never shrink the tiers.
+pub struct FixedStringMap<'a, T, S = RandomState> {
+    map: StringMap<'a, T, S>,
+    probe_limit: usize,
+}
+
+impl<'a, T, S> FixedStringMap<'a, T, S> {
+    pub fn probe_limit(&self) -> usize {
+        self.probe_limit
+    }
+
+    /// Sets the longest probe sequence an insertion may take, which bounds
+    /// the time of every operation on the keys inserted from now on.
+    pub fn set_probe_limit(&mut self, limit: usize) {
+        self.probe_limit = limit;
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        self.map.iter()
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, T, S> {
+        self.map
+    }
+}
+
+impl<'a, T, S: BuildHasher> FixedStringMap<'a, T, S> {
+    /// Freezes `map` at its current capacities, e.g. as configured by a
+    /// [`StringMapBuilder`]. Auto reseeding is turned off, since it rebuilds
+    /// the tiers.
+    pub fn from_map(mut map: StringMap<'a, T, S>) -> Self {
+        map.set_shrink_policy(ShrinkPolicy::Never);
+        map.set_reseed(None);
+        FixedStringMap {
+            map,
+            probe_limit: DEFAULT_PROBE_LIMIT,
+        }
+    }
+
+    /// Creates a map with room for `capacity` keys in every tier.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        let builder = StringMapBuilder::with_hasher(hasher).capacity(capacity);
+        Self::from_map(builder.build(key_alloc))
+    }
+
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        let key = key.as_key_ref();
+        self.map
+            .get_mut_hashed(key, self.map.hasher().hash_one(key))
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Inserts `key` without allocating, leaving the map unchanged if that is
+    /// not possible.
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Result<Option<T>, Error> {
+        let key = key.as_key_ref();
+        let hash = self.map.hasher().hash_one(key);
+        if let Some(old) = self.map.get_mut_hashed(key, hash) {
+            return Ok(Some(std::mem::replace(old, value)));
+        }
+
+        let (grows, key_len) = match key {
+            KeyRef::Large(key) => (self.map.insertion_bytes(key) > key.len(), key.len()),
+            _ => (self.map.insertion_bytes(key) > 0, 0),
+        };
+        let probe = self.map.insertion_probe(key, hash);
+        if grows || probe.is_none_or(|probe| probe > self.probe_limit) {
+            return Err(Error::CapacityExhausted);
+        }
+        if key_len > self.map.key_alloc().chunk_capacity() {
+            return Err(Error::AllocFailed { bytes: key_len });
+        }
+        Ok(self.map.insert_hashed(key, hash, value))
+    }
+
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<T> {
+        let key = key.as_key_ref();
+        self.map.remove_hashed(key, self.map.hasher().hash_one(key))
+    }
+}
+
+impl<'a, T> FixedStringMap<'a, T, RandomState> {
+    pub fn with_capacity(capacity: usize, key_alloc: &'a Bump) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new(), key_alloc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_map() {
+        let bump = Bump::with_capacity(4096);
+        let mut map = FixedStringMap::with_capacity(100, &bump);
+        let (bucket_bytes, arena_bytes) = (map.map.bucket_bytes(), bump.allocated_bytes());
+
+        let mut keys = Vec::new();
+        let err = loop {
+            let i = keys.len();
+            let key = format!("{i:0width$}", width = [4, 12, 20, 30][i % 4]);
+            match map.insert(KeyRef::from(key.as_bytes()), i) {
+                Ok(old) => assert_eq!(old, None),
+                Err(err) => break err,
+            }
+            keys.push(key);
+        };
+        assert!(matches!(
+            err,
+            Error::CapacityExhausted | Error::AllocFailed { .. }
+        ));
+        assert!(keys.len() >= 100);
+        assert_eq!(map.map.bucket_bytes(), bucket_bytes);
+        assert_eq!(bump.allocated_bytes(), arena_bytes);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.get(KeyRef::from(key.as_bytes())), Some(&i));
+        }
+        assert_eq!(map.insert(KeyRef::from(keys[0].as_bytes()), 7), Ok(Some(0)));
+        for key in &keys {
+            map.remove(KeyRef::from(key.as_bytes()));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.map.bucket_bytes(), bucket_bytes);
+        assert_eq!(map.insert(KeyRef::from(keys[1].as_bytes()), 1), Ok(None));
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.map.validate();
+    }
+}
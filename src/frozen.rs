@@ -0,0 +1,350 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::iter::FusedIterator;
+use std::slice;
+
+use crate::adaptive::{Equivalent, StringMap};
+
+/// The average number of keys per bucket of the perfect hash function, which
+/// trades its size (4 bytes per bucket) for the time spent building it.
+const KEYS_PER_BUCKET: usize = 4;
+
+/// The marker of an empty slot of the probing index.
+const EMPTY: u32 = u32::MAX;
+
+struct Entry<'a, T> {
+    hash: u64,
+    key: &'a [u8],
+    value: T,
+}
+
+enum Index {
+    /// Linear probing over the positions of the entries.
+    Probing { slots: Box<[u32]> },
+    /// A minimal perfect hash function: the entry of a key is at the position
+    /// given by the pilot of its bucket.
+    Perfect { pilots: Box<[u32]> },
+}
+
+/// Maps `x` to `[0, n)` without a division.
+fn fastrange(x: u64, n: usize) -> usize {
+    ((x as u128 * n as u128) >> 64) as usize
+}
+
+/// Mixes the bits of `x`, so that every pilot yields unrelated positions.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn position(hash: u64, pilot: u32, n: usize) -> usize {
+    fastrange(
+        mix(hash ^ (pilot as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+        n,
+    )
+}
+
+/// Searches a pilot for every bucket, largest buckets first, such that the
+/// keys of all the buckets land on distinct positions of `[0, n)`.
+///
+/// Returns the pilots and the position of every key, or `None` if two keys
+/// share the same hash and thus can't be told apart.
+fn build_pilots(hashes: &[u64]) -> Option<(Box<[u32]>, Vec<usize>)> {
+    let n = hashes.len();
+    let buckets = n.div_ceil(KEYS_PER_BUCKET);
+
+    // Sorts the keys by bucket, keeping where every bucket starts.
+    let mut starts = vec![0; buckets + 1];
+    for &hash in hashes {
+        starts[fastrange(hash, buckets) + 1] += 1;
+    }
+    for bucket in 0..buckets {
+        starts[bucket + 1] += starts[bucket];
+    }
+    let mut members = vec![0; n];
+    let mut next = starts.clone();
+    for (i, &hash) in hashes.iter().enumerate() {
+        let bucket = fastrange(hash, buckets);
+        members[next[bucket]] = i;
+        next[bucket] += 1;
+    }
+    let mut order = (0..buckets).collect::<Vec<_>>();
+    order.sort_unstable_by_key(|&bucket| Reverse(starts[bucket + 1] - starts[bucket]));
+
+    let mut pilots = vec![0; buckets].into_boxed_slice();
+    let mut positions = vec![0; n];
+    let mut taken = vec![false; n];
+    let mut candidates = Vec::new();
+    'bucket: for bucket in order {
+        let members = &members[starts[bucket]..starts[bucket + 1]];
+        if members.is_empty() {
+            // The buckets are sorted by size, so the others are empty too.
+            break;
+        }
+        for (j, &i) in members.iter().enumerate() {
+            if members[..j].iter().any(|&other| hashes[other] == hashes[i]) {
+                return None;
+            }
+        }
+        'pilot: for pilot in 0..=u32::MAX {
+            candidates.clear();
+            for &i in members {
+                let pos = position(hashes[i], pilot, n);
+                if taken[pos] || candidates.contains(&pos) {
+                    continue 'pilot;
+                }
+                candidates.push(pos);
+            }
+            pilots[bucket] = pilot;
+            for (&i, &pos) in members.iter().zip(&candidates) {
+                taken[pos] = true;
+                positions[i] = pos;
+            }
+            continue 'bucket;
+        }
+        return None;
+    }
+    Some((pilots, positions))
+}
+
+/// An immutable map built by [`StringMap::freeze`] or
+/// [`StringMap::freeze_perfect`], whose entries are stored densely.
+///
+/// With a perfect hash function, the table has no empty slots and no
+/// collisions: a lookup reads the pilot of the bucket of its key, then
+/// compares the key against the single entry it leads to.
+pub struct FrozenStringMap<'a, T, S = RandomState> {
+    entries: Box<[Entry<'a, T>]>,
+    index: Index,
+    hasher: S,
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Freezes the map into a [`FrozenStringMap`], indexed by linear probing
+    /// at a load factor of at most 7/8.
+    ///
+    /// Small keys are copied into the arena of the map.
+    pub fn freeze(self) -> FrozenStringMap<'a, T, S> {
+        let (entries, hasher) = self.frozen_entries();
+        let index = probing_index(&entries);
+        FrozenStringMap {
+            entries,
+            index,
+            hasher,
+        }
+    }
+
+    /// Freezes the map into a [`FrozenStringMap`] indexed by a minimal perfect
+    /// hash function, for tables probed far more often than built.
+    ///
+    /// Building the function takes a few passes over the keys. If two keys
+    /// have the same hash, no such function exists, and the map falls back to
+    /// the index of [`freeze`](Self::freeze).
+    pub fn freeze_perfect(self) -> FrozenStringMap<'a, T, S> {
+        let (entries, hasher) = self.frozen_entries();
+        let hashes = entries.iter().map(|entry| entry.hash).collect::<Vec<_>>();
+        let Some((pilots, positions)) = build_pilots(&hashes) else {
+            let index = probing_index(&entries);
+            return FrozenStringMap {
+                entries,
+                index,
+                hasher,
+            };
+        };
+        let mut entries = positions.into_iter().zip(entries).collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(pos, _)| pos);
+        FrozenStringMap {
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+            index: Index::Perfect { pilots },
+            hasher,
+        }
+    }
+
+    fn frozen_entries(self) -> (Box<[Entry<'a, T>]>, S) {
+        assert!(self.len() < EMPTY as usize, "Too many entries to freeze");
+        let mut iter = self.into_iter_hashed();
+        let entries = { iter.by_ref() }
+            .map(|(key, hash, value)| Entry { hash, key, value })
+            .collect();
+        (entries, iter.into_hasher())
+    }
+}
+
+fn probing_index<T>(entries: &[Entry<'_, T>]) -> Index {
+    let capacity = (entries.len() * 8 / 7 + 1).next_power_of_two();
+    let mut slots = vec![EMPTY; capacity].into_boxed_slice();
+    for (i, entry) in entries.iter().enumerate() {
+        let mut pos = entry.hash as usize & (capacity - 1);
+        while slots[pos] != EMPTY {
+            pos = (pos + 1) & (capacity - 1);
+        }
+        slots[pos] = i as u32;
+    }
+    Index::Probing { slots }
+}
+
+impl<'a, T, S> FrozenStringMap<'a, T, S> {
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns whether the map is indexed by a minimal perfect hash function.
+    pub fn is_perfect(&self) -> bool {
+        matches!(self.index, Index::Perfect { .. })
+    }
+
+    /// Returns the number of bytes taken by the index, besides the entries.
+    pub fn index_bytes(&self) -> usize {
+        match &self.index {
+            Index::Probing { slots } => size_of_val(&**slots),
+            Index::Perfect { pilots } => size_of_val(&**pilots),
+        }
+    }
+
+    pub fn iter(&self) -> FrozenIter<'_, 'a, T> {
+        FrozenIter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    pub fn get_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> Option<&T> {
+        let key = key.as_key_ref().key();
+        let matches = |entry: &Entry<'a, T>| entry.hash == hash && entry.key == key;
+        match &self.index {
+            Index::Perfect { pilots } => {
+                if self.entries.is_empty() {
+                    return None;
+                }
+                let pilot = pilots[fastrange(hash, pilots.len())];
+                let entry = &self.entries[position(hash, pilot, self.entries.len())];
+                matches(entry).then_some(&entry.value)
+            }
+            Index::Probing { slots } => {
+                let mask = slots.len() - 1;
+                let mut pos = hash as usize & mask;
+                loop {
+                    let entry = self.entries.get(slots[pos] as usize)?;
+                    if matches(entry) {
+                        return Some(&entry.value);
+                    }
+                    pos = (pos + 1) & mask;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, S: BuildHasher> FrozenStringMap<'a, T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        let key = key.as_key_ref();
+        self.get_hashed(key, self.hasher.hash_one(key))
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl<'m, 'a, T, S> IntoIterator for &'m FrozenStringMap<'a, T, S> {
+    type Item = (&'a [u8], &'m T);
+
+    type IntoIter = FrozenIter<'m, 'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The iterator of [`FrozenStringMap::iter`].
+pub struct FrozenIter<'m, 'a, T> {
+    entries: slice::Iter<'m, Entry<'a, T>>,
+}
+
+impl<'m, 'a, T> Iterator for FrozenIter<'m, 'a, T> {
+    type Item = (&'a [u8], &'m T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some((entry.key, &entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'m, 'a, T> ExactSizeIterator for FrozenIter<'m, 'a, T> {}
+impl<'m, 'a, T> FusedIterator for FrozenIter<'m, 'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::testing::ConstState;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_freeze() {
+        let mut cmp = HashMap::new();
+        for i in 0..30000u32 {
+            let len = rand::random::<u8>() as usize % 40;
+            let key = (0..len).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+            cmp.insert(key, i);
+        }
+
+        for perfect in [false, true] {
+            let bump = Bump::new();
+            let mut map = StringMap::new(&bump);
+            for (key, &i) in &cmp {
+                map.insert(KeyRef::from(&key[..]), i);
+            }
+            let frozen = if perfect {
+                map.freeze_perfect()
+            } else {
+                map.freeze()
+            };
+            assert_eq!(frozen.is_perfect(), perfect);
+            assert_eq!(frozen.len(), cmp.len());
+            for (key, i) in &cmp {
+                assert_eq!(frozen.get(KeyRef::from(&key[..])), Some(i));
+            }
+            for (key, i) in &frozen {
+                assert_eq!(cmp.get(key), Some(i));
+            }
+            for _ in 0..1000 {
+                let key = (0..41).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+                assert_eq!(frozen.get(KeyRef::from(&key[..])), None);
+            }
+        }
+
+        let bump = Bump::new();
+        let frozen: FrozenStringMap<u32> = StringMap::new(&bump).freeze_perfect();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.get(KeyRef::from(&b"missing"[..])), None);
+
+        // No perfect hash function separates keys with the same hash.
+        let mut map = StringMap::with_hasher(ConstState::default(), &bump);
+        for i in 0..100u32 {
+            map.insert(KeyRef::from(i.to_string().as_bytes()), i);
+        }
+        let frozen = map.freeze_perfect();
+        assert!(!frozen.is_perfect());
+        for i in 0..100u32 {
+            assert_eq!(frozen.get(KeyRef::from(i.to_string().as_bytes())), Some(&i));
+        }
+    }
+}
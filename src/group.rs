@@ -0,0 +1,178 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Entry, Equivalent, KeyRef, StringMap};
+
+/// A map assigning dense, monotonically increasing group ids to keys.
+///
+/// Ids start from 0 in first-insertion order, so aggregate states can live in
+/// plain `Vec`s indexed by id.
+pub struct GroupIdMap<'a, S = RandomState> {
+    map: StringMap<'a, u32, S>,
+    keys: Vec<&'a [u8]>,
+}
+
+impl<'a, S> GroupIdMap<'a, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        GroupIdMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn num_groups(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the key of the group `id`.
+    pub fn key(&self, id: u32) -> Option<&'a [u8]> {
+        self.keys.get(id as usize).copied()
+    }
+
+    /// Iterates over the keys in id order.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (u32, &'a [u8])> + '_ {
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(id, &key)| (id as u32, key))
+    }
+}
+
+impl<'a> GroupIdMap<'a, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, S: BuildHasher> GroupIdMap<'a, S> {
+    pub fn get_id_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> Option<u32> {
+        self.map.get_hashed(key, hash).copied()
+    }
+
+    pub fn get_id<Q: Equivalent>(&self, key: Q) -> Option<u32> {
+        self.map.get(key).copied()
+    }
+
+    /// Returns the id of the group of `key`, assigning the next id if the key
+    /// is new.
+    ///
+    /// A new key is copied into the arena once, and the map borrows that copy
+    /// rather than making its own.
+    pub fn get_or_assign_id_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> u32 {
+        let key_alloc = self.map.key_alloc();
+        self.get_or_assign(key.as_key_ref(), hash, |key| {
+            key_alloc.alloc_slice_copy(key)
+        })
+    }
+
+    pub fn get_or_assign_id<Q: Equivalent>(&mut self, key: Q) -> u32 {
+        let key = key.as_key_ref();
+        self.get_or_assign_id_hashed(key, self.map.hasher().hash_one(key))
+    }
+
+    /// Returns the id of the group of `key`, assigning the next id if the key
+    /// is new, and keeping the slice itself instead of copying it into the
+    /// arena, e.g. for keys already in an input arena that outlives the map.
+    pub fn get_or_assign_id_borrowed_hashed(&mut self, key: &'a [u8], hash: u64) -> u32 {
+        self.get_or_assign(KeyRef::from(key), hash, |_| key)
+    }
+
+    pub fn get_or_assign_id_borrowed(&mut self, key: &'a [u8]) -> u32 {
+        let hash = self.map.hasher().hash_one(KeyRef::from(key));
+        self.get_or_assign_id_borrowed_hashed(key, hash)
+    }
+
+    /// Returns the id of `key`, or assigns the next one to the key `stored`
+    /// returns, which the map borrows, in a single probe.
+    fn get_or_assign(
+        &mut self,
+        key: KeyRef<'_>,
+        hash: u64,
+        stored: impl FnOnce(&[u8]) -> &'a [u8],
+    ) -> u32 {
+        match self.map.entry_hashed(key, hash) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let id = u32::try_from(self.keys.len()).expect("Too many groups");
+                let key = stored(key.key());
+                entry.insert_borrowed(key, id);
+                self.keys.push(key);
+                id
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_group_ids() {
+        let bump = Bump::new();
+        let mut map = GroupIdMap::new(&bump);
+        let mut cmp = HashMap::new();
+        let mut counts = vec![];
+
+        for _ in 0..20000 {
+            let key = rand::random::<u16>()
+                .to_string()
+                .repeat(rand::random::<usize>() % 5);
+
+            let id = map.get_or_assign_id(&key);
+            let next = cmp.len() as u32;
+            assert_eq!(id, *cmp.entry(key).or_insert(next));
+
+            if id as usize == counts.len() {
+                counts.push(0);
+            }
+            counts[id as usize] += 1;
+        }
+        assert_eq!(map.num_groups(), cmp.len());
+        assert_eq!(counts.iter().sum::<i32>(), 20000);
+
+        for (id, key) in map.iter() {
+            assert_eq!(cmp.remove(std::str::from_utf8(key).unwrap()), Some(id));
+        }
+        assert!(cmp.is_empty());
+    }
+
+    #[test]
+    fn test_key_storage() {
+        let keys = (0..1000)
+            .map(|i| format!("a key of the large tier {i}"))
+            .collect::<Vec<_>>();
+        let used = |bump: &Bump| bump.allocated_bytes() - bump.chunk_capacity();
+
+        let bump = Bump::new();
+        let mut map = GroupIdMap::new(&bump);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.get_or_assign_id_borrowed(key.as_bytes()), i as u32);
+            assert_eq!(map.get_or_assign_id(key), i as u32);
+            assert_eq!(map.key(i as u32), Some(key.as_bytes()));
+        }
+        assert_eq!(used(&bump), 0);
+
+        // The map borrows the copy of the key kept for its group.
+        let bump = Bump::new();
+        let mut map = GroupIdMap::new(&bump);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.get_or_assign_id(key), i as u32);
+            assert_eq!(map.get_or_assign_id_borrowed(key.as_bytes()), i as u32);
+        }
+        assert!(used(&bump) < 2 * keys.iter().map(String::len).sum::<usize>());
+    }
+}
@@ -0,0 +1,160 @@
+use std::hash::BuildHasher;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::mem;
+
+use ::hashbrown::raw::{RawIter, RawTable};
+use bumpalo::Bump;
+
+use crate::common::ShrinkPolicy;
+use crate::tiers::{TierStorage, Tiers};
+
+/// The last tier, copying keys of any length into the arena like
+/// [`Large`](crate::Large), but delegating the table to hashbrown's SIMD
+/// probing, e.g. `Small<8, Small<16, Small<24, HashbrownLarge>>>`.
+pub struct HashbrownLarge;
+
+impl Tiers for HashbrownLarge {
+    type Storage<'a, T: 'a> = HashbrownTier<'a, T>;
+}
+
+/// The entries of the tier, along with their hashes so that the table grows
+/// without rehashing the keys.
+pub struct HashbrownTier<'a, T> {
+    table: RawTable<(u64, &'a [u8], T)>,
+    key_alloc: &'a Bump,
+}
+
+pub struct HashbrownIter<'b, 'a, T> {
+    iter: RawIter<(u64, &'a [u8], T)>,
+    _marker: PhantomData<&'b T>,
+}
+
+impl<'b, 'a: 'b, T> Iterator for HashbrownIter<'b, 'a, T> {
+    type Item = (&'b [u8], &'b T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // SAFETY: The table is borrowed for `'b`, so the bucket stays valid.
+        let (_, key, value) = unsafe { self.iter.next()?.as_ref() };
+        Some((key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'b, 'a: 'b, T> ExactSizeIterator for HashbrownIter<'b, 'a, T> {}
+impl<'b, 'a: 'b, T> FusedIterator for HashbrownIter<'b, 'a, T> {}
+
+#[inline]
+fn eq<T>(key: &[u8], hash: u64) -> impl Fn(&(u64, &[u8], T)) -> bool + '_ {
+    move |&(h, k, _)| h == hash && k == key
+}
+
+impl<'a, T: 'a> TierStorage<'a, T> for HashbrownTier<'a, T> {
+    type Iter<'b>
+        = HashbrownIter<'b, 'a, T>
+    where
+        Self: 'b,
+        T: 'b;
+
+    fn new(key_alloc: &'a Bump) -> Self {
+        HashbrownTier {
+            table: RawTable::new(),
+            key_alloc,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.table.buckets()
+    }
+
+    #[inline]
+    fn get(&self, key: &[u8], hash: u64) -> Option<&T> {
+        self.table
+            .get(hash, eq(key, hash))
+            .map(|(_, _, value)| value)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &[u8], hash: u64) -> Option<&mut T> {
+        (self.table.get_mut(hash, eq(key, hash))).map(|(_, _, value)| value)
+    }
+
+    #[inline]
+    fn insert<S: BuildHasher>(&mut self, key: &[u8], hash: u64, value: T, _: &S) -> Option<T> {
+        match (self.table).find_or_find_insert_slot(hash, eq(key, hash), |&(h, ..)| h) {
+            // SAFETY: The bucket is full.
+            Ok(bucket) => Some(mem::replace(&mut unsafe { bucket.as_mut() }.2, value)),
+            Err(slot) => {
+                let key = self.key_alloc.alloc_slice_copy(key);
+                // SAFETY: The slot was just found and the table is unchanged.
+                unsafe { self.table.insert_in_slot(hash, slot, (hash, key, value)) };
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn get_or_insert_with<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: impl FnOnce() -> T,
+        _: &S,
+    ) -> (&mut T, bool) {
+        let (bucket, inserted) =
+            match (self.table).find_or_find_insert_slot(hash, eq(key, hash), |&(h, ..)| h) {
+                Ok(bucket) => (bucket, false),
+                Err(slot) => {
+                    let key = self.key_alloc.alloc_slice_copy(key);
+                    // SAFETY: The slot was just found and the table is unchanged.
+                    let bucket =
+                        unsafe { self.table.insert_in_slot(hash, slot, (hash, key, value())) };
+                    (bucket, true)
+                }
+            };
+        // SAFETY: The bucket is full and borrowed along with the table.
+        (&mut unsafe { bucket.as_mut() }.2, inserted)
+    }
+
+    #[inline]
+    fn remove<S: BuildHasher>(&mut self, key: &[u8], hash: u64, _: &S) -> Option<T> {
+        (self.table.remove_entry(hash, eq(key, hash))).map(|(_, _, value)| value)
+    }
+
+    fn clear(&mut self) {
+        self.table.clear();
+    }
+
+    fn reserve<S: BuildHasher>(&mut self, additional: usize, _: &S) {
+        self.table.reserve(additional, |&(hash, _, _)| hash);
+    }
+
+    /// Hashbrown has a load of its own and never shrinks on removal.
+    fn configure(&mut self, _: Option<u8>, _: ShrinkPolicy) {}
+
+    fn iter(&self) -> Self::Iter<'_> {
+        HashbrownIter {
+            // SAFETY: The iterator borrows the table.
+            iter: unsafe { self.table.iter() },
+            _marker: PhantomData,
+        }
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    fn validate<S: BuildHasher>(&self, hasher: &S) {
+        // SAFETY: The buckets are only read while the table is borrowed.
+        for bucket in unsafe { self.table.iter() } {
+            let (hash, key, value) = unsafe { bucket.as_ref() };
+            assert_eq!(*hash, hasher.hash_one(key), "Stale hash of key {key:?}");
+            let found = self.get(key, *hash).expect("Entry not found by its key");
+            assert!(std::ptr::eq(found, value), "Duplicate key {key:?}");
+        }
+    }
+}
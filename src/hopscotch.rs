@@ -0,0 +1,350 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem;
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+
+/// The number of slots from its home slot an entry may be stored in.
+const NEIGHBORHOOD: usize = 32;
+
+const MIN_CAPACITY: usize = 64;
+
+/// The number of slots scanned for a free one before giving up on the
+/// current table.
+const MAX_SCAN: usize = 512;
+
+struct Slot<'a, T> {
+    hash: u64,
+    key: &'a [u8],
+    value: T,
+}
+
+/// Where an entry is stored.
+#[derive(Clone, Copy)]
+enum Location {
+    Table(usize),
+    Overflow(usize),
+}
+
+/// A map using hopscotch hashing instead of linear probing.
+///
+/// Every entry is stored within 32 slots of its home slot, and every slot
+/// keeps a bitmap of the neighboring slots holding the entries it is home
+/// to. A lookup thus only compares the entries of its own home, and never
+/// scans past its neighborhood. An insertion probes for a free slot as
+/// usual, then moves it closer by swapping it with entries that can move
+/// further from their homes while staying in their neighborhoods.
+///
+/// Removing an entry only clears its bit, leaving no tombstone behind.
+///
+/// The table grows once it is 7/8 full, or if no free slot can be brought
+/// close enough. If it is still at most half full, e.g. because of too many
+/// keys with the same hash, the entry goes to a small overflow list instead,
+/// also searched by lookups if not empty.
+pub struct HopscotchStringMap<'a, T, S = RandomState> {
+    slots: Box<[Option<Slot<'a, T>>]>,
+    /// The bitmap of the neighborhood of every slot, whose bit `i` tells if
+    /// the `i`-th slot from it holds one of its entries.
+    hops: Box<[u32]>,
+    overflow: Vec<Slot<'a, T>>,
+    len: usize,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T, S> HopscotchStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        Self::with_capacity_and_hasher(0, hasher, key_alloc)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        let capacity = (capacity * 8 / 7 + 1).next_power_of_two();
+        HopscotchStringMap {
+            slots: Self::empty_slots(capacity.max(MIN_CAPACITY)),
+            hops: vec![0; capacity.max(MIN_CAPACITY)].into(),
+            overflow: Vec::new(),
+            len: 0,
+            hasher,
+            key_alloc,
+        }
+    }
+
+    fn empty_slots(capacity: usize) -> Box<[Option<Slot<'a, T>>]> {
+        (0..capacity).map(|_| None).collect()
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of slots of the table, excluding the overflow list.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns the number of entries that didn't fit in their neighborhood.
+    pub fn overflowed(&self) -> usize {
+        self.overflow.len()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &Slot<'a, T>> + '_ {
+        self.slots.iter().flatten().chain(&self.overflow)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &T)> + '_ {
+        self.entries().map(|slot| (slot.key, &slot.value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'a [u8], &mut T)> + '_ {
+        (self.slots.iter_mut().flatten())
+            .chain(&mut self.overflow)
+            .map(|slot| (slot.key, &mut slot.value))
+    }
+
+    fn slot_mut(&mut self, location: Location) -> &mut Slot<'a, T> {
+        match location {
+            Location::Table(index) => self.slots[index].as_mut().unwrap(),
+            Location::Overflow(index) => &mut self.overflow[index],
+        }
+    }
+
+    /// Stores `slot` in the neighborhood of its home slot, and returns where.
+    ///
+    /// Gives `slot` back if no free slot is found near enough, or if the free
+    /// slot found can't be brought into the neighborhood.
+    fn place(&mut self, slot: Slot<'a, T>) -> Result<usize, Slot<'a, T>> {
+        let mask = self.slots.len() - 1;
+        let home = slot.hash as usize & mask;
+        let scan = self.slots.len().min(MAX_SCAN);
+        let Some(mut distance) = (0..scan).find(|d| self.slots[(home + d) & mask].is_none()) else {
+            return Err(slot);
+        };
+
+        while distance >= NEIGHBORHOOD {
+            let free = (home + distance) & mask;
+            // Moves into the free slot the entry of the farthest slot before
+            // it that has one between itself and the free slot.
+            let hop = (1..NEIGHBORHOOD).rev().find_map(|back| {
+                let bucket = free.wrapping_sub(back) & mask;
+                let movable = self.hops[bucket] & ((1 << back) - 1);
+                (movable != 0).then(|| (back, bucket, movable.trailing_zeros() as usize))
+            });
+            let Some((back, bucket, offset)) = hop else {
+                return Err(slot);
+            };
+            self.slots[free] = self.slots[(bucket + offset) & mask].take();
+            self.hops[bucket] ^= (1 << offset) | (1 << back);
+            distance -= back - offset;
+        }
+
+        let index = (home + distance) & mask;
+        self.slots[index] = Some(slot);
+        self.hops[home] |= 1 << distance;
+        Ok(index)
+    }
+
+    /// Doubles the number of slots and places every entry again, including
+    /// the overflowed ones.
+    fn grow(&mut self) {
+        let capacity = self.slots.len() * 2;
+        let slots = mem::replace(&mut self.slots, Self::empty_slots(capacity));
+        self.hops = vec![0; capacity].into();
+        let overflow = mem::take(&mut self.overflow);
+        for slot in (slots.into_vec().into_iter().flatten()).chain(overflow) {
+            if let Err(slot) = self.place(slot) {
+                self.overflow.push(slot);
+            }
+        }
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    pub fn validate(&self) {
+        let mask = self.slots.len() - 1;
+        let mut hops = vec![0u32; self.slots.len()];
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(slot) = slot {
+                let home = slot.hash as usize & mask;
+                let distance = index.wrapping_sub(home) & mask;
+                assert!(distance < NEIGHBORHOOD, "Entry {distance} slots away");
+                hops[home] |= 1 << distance;
+            }
+        }
+        assert!(*hops == *self.hops, "Stale neighborhood bitmaps");
+        let table = self.slots.iter().flatten().count();
+        assert_eq!(table + self.overflow.len(), self.len);
+    }
+}
+
+impl<'a, T> HopscotchStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> HopscotchStringMap<'a, T, S> {
+    fn find(&self, key: &[u8], hash: u64) -> Option<Location> {
+        let matches = |slot: &Slot<'a, T>| slot.hash == hash && slot.key == key;
+        let mask = self.slots.len() - 1;
+        let home = hash as usize & mask;
+        let mut hop = self.hops[home];
+        while hop != 0 {
+            let index = (home + hop.trailing_zeros() as usize) & mask;
+            if self.slots[index].as_ref().is_some_and(matches) {
+                return Some(Location::Table(index));
+            }
+            hop &= hop - 1;
+        }
+        if self.overflow.is_empty() {
+            return None;
+        }
+        self.overflow
+            .iter()
+            .position(matches)
+            .map(Location::Overflow)
+    }
+
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        let slot = match self.find(key, self.hasher.hash_one(key))? {
+            Location::Table(index) => self.slots[index].as_ref().unwrap(),
+            Location::Overflow(index) => &self.overflow[index],
+        };
+        Some(&slot.value)
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        let location = self.find(key, self.hasher.hash_one(key))?;
+        Some(&mut self.slot_mut(location).value)
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Stores a new entry for `key`, which must be absent.
+    fn push(&mut self, key: &[u8], hash: u64, value: T) -> &mut T {
+        let key = self.key_alloc.alloc_slice_copy(key);
+        self.len += 1;
+        if self.len * 8 > self.slots.len() * 7 {
+            self.grow();
+        }
+        let mut slot = Slot { hash, key, value };
+        let location = loop {
+            match self.place(slot) {
+                Ok(index) => break Location::Table(index),
+                Err(homeless) if self.len <= self.slots.len() / 2 => {
+                    self.overflow.push(homeless);
+                    break Location::Overflow(self.overflow.len() - 1);
+                }
+                Err(homeless) => {
+                    self.grow();
+                    slot = homeless;
+                }
+            }
+        };
+        &mut self.slot_mut(location).value
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            Some(location) => Some(mem::replace(&mut self.slot_mut(location).value, value)),
+            None => {
+                self.push(key, hash, value);
+                None
+            }
+        }
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        match self.find(key, hash) {
+            Some(location) => (&mut self.slot_mut(location).value, false),
+            None => (self.push(key, hash, value()), true),
+        }
+    }
+
+    /// Removes `key`, clearing its bit in the bitmap of its home slot. Its
+    /// key stays in the arena.
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        let slot = match self.find(key, hash)? {
+            Location::Table(index) => {
+                let mask = self.slots.len() - 1;
+                let home = hash as usize & mask;
+                self.hops[home] &= !(1 << (index.wrapping_sub(home) & mask));
+                self.slots[index].take().unwrap()
+            }
+            Location::Overflow(index) => self.overflow.swap_remove(index),
+        };
+        self.len -= 1;
+        Some(slot.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{differential, numbered, ConstState};
+    use crate::StringMapBuilder;
+
+    #[test]
+    fn test_hopscotch_map() {
+        let bump = Bump::new();
+        let map = StringMapBuilder::new()
+            .capacity(1000)
+            .build_hopscotch(&bump);
+        assert!(map.capacity() >= 4000);
+
+        let map = differential(map, 50000, numbered(8000));
+        assert_eq!(map.inner().overflowed(), 0);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let bump = Bump::new();
+        let hasher = ConstState::default();
+        let mut map = HopscotchStringMap::with_hasher(hasher, &bump);
+
+        for i in 0..100 {
+            assert_eq!(map.insert(i.to_string(), i), None);
+        }
+        assert_eq!(map.overflowed(), 100 - NEIGHBORHOOD);
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.remove(i.to_string()), Some(i));
+        }
+        for (key, value) in map.iter_mut() {
+            assert_eq!(*key, *value.to_string().as_bytes());
+            *value += 1;
+        }
+        for i in 0..100 {
+            let expected = (i % 3 != 0).then_some(i + 1);
+            assert_eq!(map.get(i.to_string()), expected.as_ref());
+        }
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        map.validate();
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyRef, StringMap};
+use crate::aggregate::AggregateState;
+
+/// The number of rows over which the hit rate of the map is measured.
+const WINDOW: usize = 4096;
+/// The hashing phase ends once fewer than `1 / MIN_HIT_RATIO` of the rows of a
+/// window hit an existing group.
+const MIN_HIT_RATIO: usize = 8;
+
+/// An aggregation starting with hashing, and falling back to sorting when
+/// almost every row starts a new group.
+///
+/// In that case the map only grows, and every insertion is a cache miss into
+/// an ever larger table. So once the hit rate of a window of rows drops too
+/// low, the map stops growing: rows of groups already in it still update them
+/// in place, and the others are appended as `(hash, key, state)` triples,
+/// grouped at the end by sorting them and merging the runs of equal keys.
+pub struct HybridAggregator<'a, A, S = RandomState> {
+    map: StringMap<'a, A, S>,
+    rows: Vec<(u64, &'a [u8], A)>,
+    window: usize,
+    hits: usize,
+    sorting: bool,
+}
+
+impl<'a, A, S> HybridAggregator<'a, A, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        HybridAggregator {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            rows: Vec::new(),
+            window: 0,
+            hits: 0,
+            sorting: false,
+        }
+    }
+
+    /// Returns whether the aggregation has switched to sorting.
+    pub fn is_sorting(&self) -> bool {
+        self.sorting
+    }
+
+    /// Returns the number of groups in the map.
+    pub fn hashed_groups(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns the number of rows appended since switching to sorting.
+    pub fn sorted_rows(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+impl<'a, A> HybridAggregator<'a, A, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, A: AggregateState, S: BuildHasher> HybridAggregator<'a, A, S> {
+    /// Updates the state of the group of `key` with `input`.
+    pub fn update(&mut self, key: &[u8], input: &A::Input) {
+        let key = KeyRef::from(key);
+        let hash = self.map.hasher().hash_one(key);
+        if self.sorting {
+            match self.map.get_mut_hashed(key, hash) {
+                Some(state) => state.update(input),
+                None => {
+                    let key = self.map.key_alloc().alloc_slice_copy(key.key());
+                    self.rows.push((hash, key, A::init(input)));
+                }
+            }
+            return;
+        }
+
+        let (state, inserted) = self
+            .map
+            .get_or_insert_with_hashed(key, hash, || A::init(input));
+        if !inserted {
+            state.update(input);
+            self.hits += 1;
+        }
+        self.window += 1;
+        if self.window == WINDOW {
+            self.sorting = self.hits * MIN_HIT_RATIO < WINDOW;
+            self.window = 0;
+            self.hits = 0;
+        }
+    }
+
+    /// Updates the states of the groups of `keys` with `inputs`.
+    pub fn aggregate(&mut self, keys: &[&[u8]], inputs: &[A::Input]) {
+        assert_eq!(keys.len(), inputs.len());
+        for (key, input) in keys.iter().zip(inputs) {
+            self.update(key, input);
+        }
+    }
+}
+
+impl<'a, A: AggregateState, S> HybridAggregator<'a, A, S> {
+    /// Consumes the aggregation, yielding the final result of every group.
+    ///
+    /// The appended rows are sorted by hash and key, so that the rows of a
+    /// group are adjacent; none of them belongs to a group of the map.
+    pub fn finish(self) -> impl Iterator<Item = (&'a [u8], A::Output)> {
+        let mut rows = self.rows;
+        rows.sort_unstable_by(|(h1, k1, _), (h2, k2, _)| h1.cmp(h2).then_with(|| k1.cmp(k2)));
+
+        let mut groups = Vec::<(u64, &'a [u8], A)>::new();
+        for (hash, key, state) in rows {
+            match groups.last_mut() {
+                Some((h, k, group)) if *h == hash && *k == key => group.merge(state),
+                _ => groups.push((hash, key, state)),
+            }
+        }
+
+        self.map.finalize().chain(
+            groups
+                .into_iter()
+                .map(|(_, key, state)| (key, state.finalize())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::aggregate::Sum;
+
+    fn check(keys: &[String]) -> bool {
+        let bump = Bump::new();
+        let mut agg = HybridAggregator::<Sum<u64>>::new(&bump);
+        let mut expected = HashMap::<&[u8], u64>::new();
+        for (i, key) in keys.iter().enumerate() {
+            agg.update(key.as_bytes(), &(i as u64));
+            *expected.entry(key.as_bytes()).or_default() += i as u64;
+        }
+
+        let sorting = agg.is_sorting();
+        let mut groups = 0;
+        for (key, sum) in agg.finish() {
+            assert_eq!(expected.remove(key), Some(sum));
+            groups += 1;
+        }
+        assert!(expected.is_empty());
+        assert!(groups > 0);
+        sorting
+    }
+
+    #[test]
+    fn test_hybrid_aggregate() {
+        // Few groups stay hashed.
+        let keys = (0..20000)
+            .map(|_| (rand::random::<u8>() % 100).to_string())
+            .collect::<Vec<_>>();
+        assert!(!check(&keys));
+
+        // Mostly distinct keys fall back to sorting, with a few repeats both
+        // of groups hashed before the switch and of appended rows.
+        let keys = (0..50000)
+            .map(|i| match rand::random::<u8>() % 16 {
+                0 => format!("{}", rand::random::<u32>() % 1000),
+                1 => format!("{}", rand::random::<u32>() % 40000 + 10000),
+                _ => format!("key-{i}"),
+            })
+            .collect::<Vec<_>>();
+        assert!(check(&keys));
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, KeyRef};
+use crate::group::GroupIdMap;
+
+/// A map storing only `u32` indices in its tiers, with the values kept in a
+/// parallel dense `Vec`.
+///
+/// Resizes only move the small index slots around, and the values can be
+/// iterated contiguously during finalization. Callers wanting to own the value
+/// storage themselves can use [`GroupIdMap`] directly.
+pub struct IndexedStringMap<'a, T, S = RandomState> {
+    ids: GroupIdMap<'a, S>,
+    values: Vec<T>,
+}
+
+impl<'a, T, S> IndexedStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        IndexedStringMap {
+            ids: GroupIdMap::with_hasher(hasher, key_alloc),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.ids.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn ids(&self) -> &GroupIdMap<'a, S> {
+        &self.ids
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+
+    pub fn into_values(self) -> Vec<T> {
+        self.values
+    }
+
+    /// Returns the entry at `index`, i.e. the `index`-th key first inserted.
+    pub fn get_index(&self, index: u32) -> Option<(&'a [u8], &T)> {
+        Some((self.ids.key(index)?, &self.values[index as usize]))
+    }
+
+    /// Iterates over the entries in index order.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&'a [u8], &T)> + '_ {
+        self.ids.iter().map(|(_, key)| key).zip(&self.values)
+    }
+
+    pub fn iter_mut(&mut self) -> impl ExactSizeIterator<Item = (&'a [u8], &mut T)> + '_ {
+        self.ids.iter().map(|(_, key)| key).zip(&mut self.values)
+    }
+}
+
+impl<'a, T> IndexedStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> IndexedStringMap<'a, T, S> {
+    pub fn index_of<Q: Equivalent>(&self, key: Q) -> Option<u32> {
+        self.ids.get_id(key)
+    }
+
+    pub fn get_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> Option<&T> {
+        let index = self.ids.get_id_hashed(key, hash)?;
+        Some(&self.values[index as usize])
+    }
+
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        let index = self.ids.get_id(key)?;
+        Some(&self.values[index as usize])
+    }
+
+    pub fn get_mut_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> Option<&mut T> {
+        let index = self.ids.get_id_hashed(key, hash)?;
+        Some(&mut self.values[index as usize])
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        let index = self.ids.get_id(key)?;
+        Some(&mut self.values[index as usize])
+    }
+
+    /// Returns the value of `key`, pushing a new one if the key is absent,
+    /// along with whether the value was newly inserted.
+    pub fn get_or_insert_with_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let index = self.ids.get_or_assign_id_hashed(key, hash);
+        self.get_or_push_with(index, value)
+    }
+
+    pub fn get_or_insert_with<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_ref();
+        self.get_or_insert_with_hashed(key, self.ids.hasher().hash_one(key), value)
+    }
+
+    /// Like [`get_or_insert_with_hashed`](Self::get_or_insert_with_hashed),
+    /// but keeps the slice itself instead of copying it into the arena, e.g.
+    /// for keys already in an input arena that outlives the map.
+    pub fn get_or_insert_borrowed_with_hashed(
+        &mut self,
+        key: &'a [u8],
+        hash: u64,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let index = self.ids.get_or_assign_id_borrowed_hashed(key, hash);
+        self.get_or_push_with(index, value)
+    }
+
+    pub fn get_or_insert_borrowed_with(
+        &mut self,
+        key: &'a [u8],
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let hash = self.ids.hasher().hash_one(KeyRef::from(key));
+        self.get_or_insert_borrowed_with_hashed(key, hash, value)
+    }
+
+    /// Returns the value at `index`, pushing one if the index was just
+    /// assigned.
+    fn get_or_push_with(&mut self, index: u32, value: impl FnOnce() -> T) -> (&mut T, bool) {
+        let inserted = index as usize == self.values.len();
+        if inserted {
+            self.values.push(value());
+        }
+        (&mut self.values[index as usize], inserted)
+    }
+
+    /// Inserts `key`, returning its index along with the value it replaced,
+    /// so that per-entry data stored elsewhere can be updated without looking
+    /// the key up again.
+    pub fn insert_full_hashed<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        hash: u64,
+        value: T,
+    ) -> (u32, Option<T>) {
+        let index = self.ids.get_or_assign_id_hashed(key, hash);
+        if index as usize == self.values.len() {
+            self.values.push(value);
+            (index, None)
+        } else {
+            let old = std::mem::replace(&mut self.values[index as usize], value);
+            (index, Some(old))
+        }
+    }
+
+    pub fn insert_full<Q: Equivalent>(&mut self, key: Q, value: T) -> (u32, Option<T>) {
+        let key = key.as_key_ref();
+        self.insert_full_hashed(key, self.ids.hasher().hash_one(key), value)
+    }
+
+    pub fn insert_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64, value: T) -> Option<T> {
+        self.insert_full_hashed(key, hash, value).1
+    }
+
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<T> {
+        let key = key.as_key_ref();
+        self.insert_hashed(key, self.ids.hasher().hash_one(key), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_indexed_map() {
+        let bump = Bump::new();
+        let mut map = IndexedStringMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..20000 {
+            let value = rand::random::<u16>();
+            let key = value.to_string().repeat(value as usize % 9);
+
+            let len = map.len() as u32;
+            let (index, a1) = map.insert_full(&key, value);
+            assert_eq!(index < len, a1.is_some());
+            let a2 = cmp.insert(key, value);
+            assert_eq!(a1, a2);
+        }
+        assert_eq!(map.len(), cmp.len());
+        assert_eq!(map.get_index(map.len() as u32), None);
+
+        for (index, (key, value)) in map.iter().enumerate() {
+            assert_eq!(map.index_of(key), Some(index as u32));
+            assert_eq!(map.get_index(index as u32), Some((key, value)));
+            assert_eq!(map.values()[index], *value);
+            assert_eq!(cmp.remove(std::str::from_utf8(key).unwrap()), Some(*value));
+        }
+        assert!(cmp.is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+use crate::common;
+
+const K: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The slot of an 8-byte key stored as a single `u64`.
+#[derive(Clone)]
+pub struct SlotData<T> {
+    key: u64,
+    value: T,
+}
+
+#[inline]
+fn to_int(key: &[u8]) -> Option<u64> {
+    Some(u64::from_ne_bytes(key.try_into().ok()?))
+}
+
+impl<T> common::SlotAccess for SlotData<T> {
+    type Value = T;
+
+    #[inline]
+    fn key(&self) -> &[u8] {
+        self.key.as_key_bytes()
+    }
+
+    #[inline]
+    fn matches(&self, key: &[u8], _hash: u64) -> bool {
+        to_int(key) == Some(self.key)
+    }
+
+    #[inline]
+    fn hash(&self) -> Option<u64> {
+        None
+    }
+
+    #[inline]
+    fn set_hash(&mut self, _: u64) {}
+
+    #[inline]
+    fn value(&self) -> &Self::Value {
+        &self.value
+    }
+
+    #[inline]
+    fn kv_mut(&mut self) -> (&[u8], &mut Self::Value) {
+        (self.key.as_key_bytes(), &mut self.value)
+    }
+}
+
+impl<'a, T> common::SlotData<'a> for SlotData<T> {
+    #[inline]
+    fn new(_: &'a Bump, key: &[u8], _hash: u64, value: Self::Value) -> Self {
+        SlotData {
+            key: to_int(key).expect("Integer keys must be 8 bytes long"),
+            value,
+        }
+    }
+
+    #[inline]
+    fn into_value(self) -> Self::Value {
+        self.value
+    }
+
+    #[inline]
+    fn into_kv(self, key_alloc: &'a Bump) -> (&'a [u8], Self::Value) {
+        (
+            key_alloc.alloc_slice_copy(self.key.as_key_bytes()),
+            self.value,
+        )
+    }
+}
+
+/// A hasher for [`IntStringMap`]s treating the key bytes as a single integer.
+///
+/// The key is only multiplied by a constant, which is close to the identity
+/// but spreads sequential keys over the table. Length prefixes are ignored,
+/// so it is unfit for variable-length keys, and it does not resist collision
+/// flooding at all.
+#[derive(Debug, Clone, Default)]
+pub struct IntHasher {
+    state: u64,
+}
+
+impl Hasher for IntHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.state = self.state.rotate_left(5) ^ u64::from_ne_bytes(word);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.state = self.state.rotate_left(5) ^ i;
+    }
+
+    fn write_usize(&mut self, _: usize) {}
+
+    fn finish(&self) -> u64 {
+        let x = self.state.wrapping_mul(K);
+        x ^ (x >> 32)
+    }
+}
+
+/// The [`BuildHasher`] of [`IntHasher`]s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntHashState;
+
+impl BuildHasher for IntHashState {
+    type Hasher = IntHasher;
+
+    fn build_hasher(&self) -> IntHasher {
+        IntHasher::default()
+    }
+}
+
+/// A map of 8-byte keys stored and compared as `u64`s.
+///
+/// Many group keys are encoded integers; this map avoids the byte-wise
+/// comparisons of the generic 8-byte tier. Keys hash the same as their
+/// native-endian bytes would in a [`StringMap`](crate::StringMap) with the
+/// same hasher.
+#[derive(Clone)]
+pub struct IntStringMap<'a, T, S = RandomState> {
+    map: common::StringMap<'a, SlotData<T>>,
+    hasher: S,
+}
+
+impl<'a, T, S> IntStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        IntStringMap {
+            map: common::StringMap::new(key_alloc),
+            hasher,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (u64, &T)> {
+        self.map
+            .iter()
+            .map(|(key, value)| (to_int(key).unwrap(), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl ExactSizeIterator<Item = (u64, &mut T)> {
+        self.map
+            .iter_mut()
+            .map(|(key, value)| (to_int(key).unwrap(), value))
+    }
+}
+
+impl<'a, T> IntStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> IntStringMap<'a, T, S> {
+    #[inline]
+    pub fn hash_key(&self, key: u64) -> u64 {
+        self.hasher.hash_one(key.as_key_bytes())
+    }
+
+    pub fn get_hashed(&self, key: u64, hash: u64) -> Option<&T> {
+        self.map.get(key.as_key_bytes(), hash)
+    }
+
+    pub fn get(&self, key: u64) -> Option<&T> {
+        self.get_hashed(key, self.hash_key(key))
+    }
+
+    pub fn get_mut_hashed(&mut self, key: u64, hash: u64) -> Option<&mut T> {
+        self.map.get_mut(key.as_key_bytes(), hash)
+    }
+
+    pub fn get_mut(&mut self, key: u64) -> Option<&mut T> {
+        self.get_mut_hashed(key, self.hash_key(key))
+    }
+
+    pub fn insert_hashed(&mut self, key: u64, hash: u64, value: T) -> Option<T> {
+        self.map
+            .insert(key.as_key_bytes(), hash, value, &self.hasher)
+    }
+
+    pub fn insert(&mut self, key: u64, value: T) -> Option<T> {
+        self.insert_hashed(key, self.hash_key(key), value)
+    }
+
+    pub fn get_or_insert_with_hashed(
+        &mut self,
+        key: u64,
+        hash: u64,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        self.map
+            .get_or_insert_with(key.as_key_bytes(), hash, value, &self.hasher)
+    }
+
+    pub fn get_or_insert_with(&mut self, key: u64, value: impl FnOnce() -> T) -> (&mut T, bool) {
+        self.get_or_insert_with_hashed(key, self.hash_key(key), value)
+    }
+
+    pub fn remove_hashed(&mut self, key: u64, hash: u64) -> Option<T> {
+        self.map.remove(key.as_key_bytes(), hash, &self.hasher)
+    }
+
+    pub fn remove(&mut self, key: u64) -> Option<T> {
+        self.remove_hashed(key, self.hash_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_int_map() {
+        let bump = Bump::new();
+        let mut map = IntStringMap::with_hasher(IntHashState, &bump);
+        let mut cmp = HashMap::new();
+
+        for i in 0..20000u64 {
+            let key = rand::random::<u16>() as u64 * 3;
+            let (count, inserted) = map.get_or_insert_with(key, || 0);
+            *count += i;
+            assert_eq!(inserted, !cmp.contains_key(&key));
+            *cmp.entry(key).or_insert(0) += i;
+        }
+        assert_eq!(map.len(), cmp.len());
+
+        for key in 0..1000 {
+            assert_eq!(map.remove(key), cmp.remove(&key));
+        }
+        for (key, value) in map.iter() {
+            assert_eq!(cmp.remove(&key), Some(*value));
+        }
+        assert!(cmp.is_empty());
+    }
+
+    #[test]
+    fn test_hash_compat() {
+        let bump = Bump::new();
+        let mut map = IntStringMap::new(&bump);
+        for key in 0..100u64 {
+            map.insert(key, key);
+        }
+        let hasher = map.hasher().clone();
+        let mut string_map = crate::StringMap::with_hasher(hasher, &bump);
+        for key in 0..100u64 {
+            let hash = map.hash_key(key);
+            string_map.insert_hashed(key, hash, key);
+            assert_eq!(string_map.get(key), map.get(key));
+        }
+    }
+}
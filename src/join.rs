@@ -0,0 +1,404 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyRef, StringMap, TAG_BITS};
+
+/// The number of hash bits splitting the rows into partitions at each level.
+const PARTITION_BITS: u32 = 4;
+const PARTITIONS: usize = 1 << PARTITION_BITS;
+/// The number of levels of partitioning, past which the rows left share so
+/// many hash bits, e.g. because of a skewed key, that they are joined in
+/// memory whatever the limit.
+const MAX_LEVELS: u32 = 8;
+
+/// The callback of the matches, taking the key, the build row and the probe
+/// row, made a trait object so that the recursion over the levels doesn't
+/// nest its type.
+type OnMatch<'f> = dyn FnMut(&[u8], &[u8], &[u8]) + 'f;
+
+/// Numbers the spill files of the process.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A file removed when dropped.
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The records of a partition written to disk.
+struct SpillFile {
+    writer: BufWriter<File>,
+    path: TempPath,
+}
+
+impl SpillFile {
+    fn create(dir: &Path) -> io::Result<Self> {
+        let id = SPILL_FILES.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("saha-join-{}-{id}.spill", process::id()));
+        let file = (OpenOptions::new().read(true).write(true))
+            .create_new(true)
+            .open(&path)?;
+        Ok(SpillFile {
+            writer: BufWriter::new(file),
+            path: TempPath(path),
+        })
+    }
+
+    fn into_reader(self) -> io::Result<SpillReader> {
+        let mut file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(SpillReader {
+            reader: BufReader::new(file),
+            _path: self.path,
+        })
+    }
+}
+
+struct SpillReader {
+    reader: BufReader<File>,
+    _path: TempPath,
+}
+
+impl SpillReader {
+    /// Reads the next record into `key` and `row`, returning whether there
+    /// was one.
+    fn next(&mut self, key: &mut Vec<u8>, row: &mut Vec<u8>) -> io::Result<bool> {
+        let mut header = [0; 8];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        let (key_len, row_len) = header.split_at(4);
+        key.resize(u32::from_le_bytes(key_len.try_into().unwrap()) as usize, 0);
+        row.resize(u32::from_le_bytes(row_len.try_into().unwrap()) as usize, 0);
+        self.reader.read_exact(key)?;
+        self.reader.read_exact(row)?;
+        Ok(true)
+    }
+}
+
+/// Appends a record of `key` and `row` to `out`.
+fn write_record(out: &mut impl Write, key: &[u8], row: &[u8]) -> io::Result<()> {
+    let key_len = u32::try_from(key.len()).expect("Key too long");
+    let row_len = u32::try_from(row.len()).expect("Row too long");
+    out.write_all(&key_len.to_le_bytes())?;
+    out.write_all(&row_len.to_le_bytes())?;
+    out.write_all(key)?;
+    out.write_all(row)
+}
+
+/// Returns the key and the row of the record at `offset` of `records`, along
+/// with the offset of the next record.
+fn read_record(records: &[u8], offset: usize) -> (&[u8], &[u8], usize) {
+    let len = |at: usize| u32::from_le_bytes(records[at..at + 4].try_into().unwrap()) as usize;
+    let key_len = len(offset);
+    let row_len = len(offset + 4);
+    let key = offset + 8;
+    let row = key + key_len;
+    (
+        &records[key..row],
+        &records[row..row + row_len],
+        row + row_len,
+    )
+}
+
+struct Partition<'a, S> {
+    /// The build records, while the partition is held in memory.
+    records: Vec<u8>,
+    /// The offsets of the build records of every key, once built.
+    table: Option<StringMap<'a, Vec<usize>, S>>,
+    build_spill: Option<SpillFile>,
+    probe_spill: Option<SpillFile>,
+}
+
+/// An inner equi-join of byte rows on byte keys, spilling to disk what
+/// doesn't fit in memory.
+///
+/// Both inputs are split into 16 partitions by hash bits. The build rows of
+/// a partition are kept in memory until they exceed the memory limit, at
+/// which point the largest partitions are written to spill files. Once the
+/// build input is done, the partitions left in memory are indexed by a
+/// [`StringMap`], and the probe rows of these partitions are joined as they
+/// come, while those of the spilled partitions are spilled as well. Every
+/// pair of spilled partitions is then joined the same way on the next hash
+/// bits, until it fits.
+///
+/// The memory limit bounds the build rows held in memory, not the tables
+/// indexing them.
+pub struct HybridHashJoin<'a, S = RandomState> {
+    partitions: Vec<Partition<'a, S>>,
+    /// The bytes of the build records held in memory.
+    in_memory: usize,
+    memory_limit: usize,
+    level: u32,
+    built: bool,
+    spill_dir: PathBuf,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a> HybridHashJoin<'a, RandomState> {
+    pub fn new(memory_limit: usize, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(memory_limit, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, S> HybridHashJoin<'a, S> {
+    pub fn with_hasher(memory_limit: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        HybridHashJoin {
+            partitions: (0..PARTITIONS)
+                .map(|_| Partition {
+                    records: Vec::new(),
+                    table: None,
+                    build_spill: None,
+                    probe_spill: None,
+                })
+                .collect(),
+            in_memory: 0,
+            memory_limit,
+            level: 0,
+            built: false,
+            spill_dir: std::env::temp_dir(),
+            hasher,
+            key_alloc,
+        }
+    }
+
+    /// Sets the directory of the spill files, the temporary directory by
+    /// default.
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = dir.into();
+        self
+    }
+
+    /// Returns the number of partitions spilled at this level.
+    pub fn spilled_partitions(&self) -> usize {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.build_spill.is_some())
+            .count()
+    }
+
+    fn partition_of(&self, hash: u64) -> usize {
+        // Taken below the tag bits, so that the keys of a partition still
+        // differ in their tags.
+        let shift = 64 - TAG_BITS - PARTITION_BITS * (self.level + 1);
+        (hash >> shift) as usize & (PARTITIONS - 1)
+    }
+
+    /// Writes the largest partitions held in memory to disk until the rest
+    /// fits in the memory limit.
+    fn spill(&mut self) -> io::Result<()> {
+        while self.in_memory > self.memory_limit {
+            let partition = (self.partitions.iter_mut())
+                .filter(|partition| partition.build_spill.is_none())
+                .max_by_key(|partition| partition.records.len())
+                .unwrap();
+            let mut file = SpillFile::create(&self.spill_dir)?;
+            file.writer.write_all(&partition.records)?;
+            self.in_memory -= partition.records.len();
+            partition.records = Vec::new();
+            partition.build_spill = Some(file);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S: BuildHasher + Clone> HybridHashJoin<'a, S> {
+    /// Adds a row of the build input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probe input was started.
+    pub fn build(&mut self, key: &[u8], row: &[u8]) -> io::Result<()> {
+        assert!(!self.built, "Build input after probing");
+        let index = self.partition_of(self.hasher.hash_one(KeyRef::from(key)));
+        let partition = &mut self.partitions[index];
+        match &mut partition.build_spill {
+            Some(file) => write_record(&mut file.writer, key, row),
+            None => {
+                let len = partition.records.len();
+                write_record(&mut partition.records, key, row)?;
+                self.in_memory += partition.records.len() - len;
+                self.spill()
+            }
+        }
+    }
+
+    /// Indexes the partitions held in memory, ending the build input.
+    fn finish_build(&mut self) {
+        if self.built {
+            return;
+        }
+        self.built = true;
+        for partition in &mut self.partitions {
+            if partition.build_spill.is_some() {
+                continue;
+            }
+            let mut table = StringMap::with_hasher(self.hasher.clone(), self.key_alloc);
+            let mut offset = 0;
+            while offset < partition.records.len() {
+                let (key, _, next) = read_record(&partition.records, offset);
+                let (offsets, _) = table.get_or_insert_with(KeyRef::from(key), Vec::new);
+                offsets.push(offset);
+                offset = next;
+            }
+            partition.table = Some(table);
+        }
+    }
+
+    /// Joins a row of the probe input, calling `on_match` with the key, the
+    /// build row and the probe row of every match found in memory. The row is
+    /// spilled if its partition was.
+    pub fn probe(
+        &mut self,
+        key: &[u8],
+        row: &[u8],
+        mut on_match: impl FnMut(&[u8], &[u8], &[u8]),
+    ) -> io::Result<()> {
+        self.finish_build();
+        let key_ref = KeyRef::from(key);
+        let hash = self.hasher.hash_one(key_ref);
+        let index = self.partition_of(hash);
+        let partition = &mut self.partitions[index];
+        let Some(table) = &partition.table else {
+            let file = match &mut partition.probe_spill {
+                Some(file) => file,
+                None => partition
+                    .probe_spill
+                    .insert(SpillFile::create(&self.spill_dir)?),
+            };
+            return write_record(&mut file.writer, key, row);
+        };
+        for &offset in table.get_hashed(key_ref, hash).into_iter().flatten() {
+            let (_, build, _) = read_record(&partition.records, offset);
+            on_match(key, build, row);
+        }
+        Ok(())
+    }
+
+    /// Ends the probe input, joining the spilled partitions and calling
+    /// `on_match` for their matches.
+    pub fn finish(self, mut on_match: impl FnMut(&[u8], &[u8], &[u8])) -> io::Result<()> {
+        self.finish_spilled(&mut on_match)
+    }
+
+    fn finish_spilled(mut self, on_match: &mut OnMatch) -> io::Result<()> {
+        self.finish_build();
+        let (mut key, mut row) = (Vec::new(), Vec::new());
+        for partition in self.partitions {
+            let (Some(build), Some(probe)) = (partition.build_spill, partition.probe_spill) else {
+                // Only spilled partitions with probe rows can have matches.
+                continue;
+            };
+            let key_alloc = Bump::new();
+            let mut join = HybridHashJoin::with_hasher(
+                match self.level + 1 < MAX_LEVELS {
+                    true => self.memory_limit,
+                    false => usize::MAX,
+                },
+                self.hasher.clone(),
+                &key_alloc,
+            );
+            join.level = self.level + 1;
+            join.spill_dir = self.spill_dir.clone();
+
+            let mut reader = build.into_reader()?;
+            while reader.next(&mut key, &mut row)? {
+                join.build(&key, &row)?;
+            }
+            drop(reader);
+            let mut reader = probe.into_reader()?;
+            while reader.next(&mut key, &mut row)? {
+                join.probe(&key, &row, &mut *on_match)?;
+            }
+            drop(reader);
+            join.finish_spilled(on_match)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn check(build: &[(String, u32)], probe: &[(String, u32)], memory_limit: usize) -> usize {
+        let dir = std::env::temp_dir().join(format!("saha-join-test-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+
+        let mut expected = Vec::new();
+        let mut rows = HashMap::<&str, Vec<u32>>::new();
+        for (key, row) in build {
+            rows.entry(key).or_default().push(*row);
+        }
+        for (key, row) in probe {
+            for build in rows.get(&**key).into_iter().flatten() {
+                expected.push((key.as_bytes().to_vec(), *build, *row));
+            }
+        }
+
+        let key_alloc = Bump::new();
+        let mut join = HybridHashJoin::new(memory_limit, &key_alloc).spill_dir(&dir);
+        let mut matches = Vec::new();
+        let mut on_match = |key: &[u8], build: &[u8], probe: &[u8]| {
+            let build = u32::from_le_bytes(build.try_into().unwrap());
+            let probe = u32::from_le_bytes(probe.try_into().unwrap());
+            matches.push((key.to_vec(), build, probe));
+        };
+        for (key, row) in build {
+            join.build(key.as_bytes(), &row.to_le_bytes()).unwrap();
+        }
+        for (key, row) in probe {
+            join.probe(key.as_bytes(), &row.to_le_bytes(), &mut on_match)
+                .unwrap();
+        }
+        let spilled = join.spilled_partitions();
+        join.finish(&mut on_match).unwrap();
+
+        matches.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(matches, expected);
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+        fs::remove_dir(&dir).unwrap();
+        spilled
+    }
+
+    #[test]
+    fn test_hybrid_hash_join() {
+        let build = (0..20000u32)
+            .map(|i| ((i % 5000).to_string().repeat(i as usize % 7 + 1), i))
+            .collect::<Vec<_>>();
+        let probe = (0..20000u32)
+            .map(|i| {
+                let key = rand::random::<u32>() % 8000;
+                (key.to_string().repeat(key as usize % 5000 % 7 + 1), i)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(check(&build, &probe, usize::MAX), 0);
+        assert!(check(&build, &probe, 64 << 10) > 0);
+        // Spills everything down to the last level.
+        assert_eq!(check(&build, &probe, 0), PARTITIONS);
+
+        // A single key can't be split, and is joined in memory at last.
+        let build = (0..2000)
+            .map(|i| ("skew".to_string(), i))
+            .collect::<Vec<_>>();
+        let probe = (0..10).map(|i| ("skew".to_string(), i)).collect::<Vec<_>>();
+        assert_eq!(check(&build, &probe, 1 << 10), 1);
+    }
+}
@@ -1,28 +1,45 @@
+use std::hash::BuildHasher;
+use std::mem;
+
 use bumpalo::Bump;
 
 use crate::common;
 
+/// The slot data of the large tier.
+///
+/// Besides the hash, the first bytes of the key are stored inline next to the
+/// key pointer, so that most mismatches are rejected without touching the
+/// arena.
+#[derive(Clone)]
 pub struct SlotData<'a, T> {
     hash: u64,
+    prefix: u64,
     key: &'a [u8],
     value: T,
 }
 
-impl<'a, T> common::SlotData<'a> for SlotData<'a, T> {
+#[inline]
+fn prefix_of(key: &[u8]) -> u64 {
+    let mut prefix = [0; 8];
+    let len = key.len().min(8);
+    prefix[..len].copy_from_slice(&key[..len]);
+    u64::from_ne_bytes(prefix)
+}
+
+impl<'a, T> common::SlotAccess for SlotData<'a, T> {
     type Value = T;
 
     #[inline]
-    fn new(key_alloc: &'a Bump, key: &[u8], hash: u64, value: Self::Value) -> Self {
-        SlotData {
-            hash,
-            key: key_alloc.alloc_slice_copy(key),
-            value,
-        }
+    fn key(&self) -> &[u8] {
+        self.key
     }
 
     #[inline]
-    fn key(&self) -> &[u8] {
-        self.key
+    fn matches(&self, key: &[u8], hash: u64) -> bool {
+        self.hash == hash
+            && self.key.len() == key.len()
+            && self.prefix == prefix_of(key)
+            && self.key == key
     }
 
     #[inline]
@@ -30,6 +47,11 @@ impl<'a, T> common::SlotData<'a> for SlotData<'a, T> {
         Some(self.hash)
     }
 
+    #[inline]
+    fn set_hash(&mut self, hash: u64) {
+        self.hash = hash;
+    }
+
     #[inline]
     fn value(&self) -> &Self::Value {
         &self.value
@@ -39,6 +61,18 @@ impl<'a, T> common::SlotData<'a> for SlotData<'a, T> {
     fn kv_mut(&mut self) -> (&[u8], &mut Self::Value) {
         (self.key, &mut self.value)
     }
+}
+
+impl<'a, T> common::SlotData<'a> for SlotData<'a, T> {
+    #[inline]
+    fn new(key_alloc: &'a Bump, key: &[u8], hash: u64, value: Self::Value) -> Self {
+        SlotData {
+            hash,
+            prefix: prefix_of(key),
+            key: key_alloc.alloc_slice_copy(key),
+            value,
+        }
+    }
 
     #[inline]
     fn into_value(self) -> Self::Value {
@@ -51,8 +85,66 @@ impl<'a, T> common::SlotData<'a> for SlotData<'a, T> {
     }
 }
 
+impl<'a, T> SlotData<'a, T> {
+    /// Creates the data of `key`, already copied into the arena.
+    pub(crate) fn from_arena(key: &'a [u8], hash: u64, value: T) -> Self {
+        SlotData {
+            hash,
+            prefix: prefix_of(key),
+            key,
+            value,
+        }
+    }
+
+    /// Returns the copy of the key in the arena.
+    pub(crate) fn arena_key(&self) -> &'a [u8] {
+        self.key
+    }
+
+    /// Copies the key into `key_alloc`.
+    pub(crate) fn move_key(self, key_alloc: &Bump) -> SlotData<'_, T> {
+        SlotData {
+            hash: self.hash,
+            prefix: self.prefix,
+            key: key_alloc.alloc_slice_copy(self.key),
+            value: self.value,
+        }
+    }
+}
+
 pub type StringMap<'a, T> = common::StringMap<'a, SlotData<'a, T>>;
 
+impl<'a, T> StringMap<'a, T> {
+    /// Inserts `key` like [`insert`](Self::insert), but stores the slice
+    /// itself instead of copying it into the arena, e.g. for keys already in
+    /// an input arena that outlives the map.
+    ///
+    /// If `key` is already present, only the value is replaced.
+    pub fn insert_borrowed<S: BuildHasher>(
+        &mut self,
+        key: &'a [u8],
+        hash: u64,
+        value: T,
+        hasher: &S,
+    ) -> Option<T> {
+        if let Some(old) = self.get_mut(key, hash) {
+            return Some(mem::replace(old, value));
+        }
+        self.insert_data(SlotData::from_arena(key, hash, value), hash, hasher);
+        None
+    }
+}
+
+/// Detaches an emptied bucket array from the arena lifetime, so that it can be
+/// reused with another arena.
+pub(crate) fn erase_bucket<T>(
+    bucket: common::Bucket<SlotData<'_, T>>,
+) -> common::Bucket<SlotData<'static, T>> {
+    // SAFETY: The slots are all empty, so no key reference is carried over,
+    // and lifetimes don't affect the layout.
+    unsafe { bucket.cast() }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::hash_map::RandomState;
@@ -93,4 +185,80 @@ mod tests {
         }
         assert!(cmp.is_empty());
     }
+
+    #[test]
+    fn test_insert_borrowed() {
+        let bump = Bump::new();
+        let input = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let hasher = RandomState::default();
+
+        let keys = (0..1000u32)
+            .map(|i| &*input.alloc_slice_copy(&[&[0xcdu8; 28] as &[u8], &i.to_ne_bytes()].concat()))
+            .collect::<Vec<_>>();
+        let allocated = bump.allocated_bytes();
+        for (i, key) in keys.iter().enumerate() {
+            let hash = hasher.hash_one(key);
+            assert_eq!(map.insert_borrowed(key, hash, i, &hasher), None);
+        }
+        assert_eq!(bump.allocated_bytes(), allocated);
+
+        let hash = hasher.hash_one(keys[42]);
+        assert_eq!(map.insert_borrowed(keys[42], hash, 0, &hasher), Some(42));
+        let (key, value) = map.remove_entry(keys[42], hash, &hasher).unwrap();
+        assert!(std::ptr::eq(key, keys[42]));
+        assert_eq!(value, 0);
+        for (i, key) in keys.iter().enumerate().skip(43) {
+            assert_eq!(map.get(key, hasher.hash_one(key)), Some(&i));
+        }
+    }
+
+    #[test]
+    fn test_shared_prefix() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let hasher = RandomState::default();
+
+        let keys = (0..1000u32)
+            .map(|i| [&[0xabu8; 32] as &[u8], &i.to_ne_bytes()].concat())
+            .collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate() {
+            let hash = hasher.hash_one(key);
+            assert_eq!(map.insert(key, hash, i, &hasher), None);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            let hash = hasher.hash_one(key);
+            assert_eq!(map.get(key, hash), Some(&i));
+            assert_eq!(map.get(&key[..35], hash), None);
+        }
+    }
+
+    #[test]
+    fn test_raw_multimap() {
+        use crate::common::{SlotAccess, SlotData as _};
+
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let hasher = RandomState::default();
+
+        let keys = (0..100u8).map(|i| [i; 40]).collect::<Vec<_>>();
+        for (i, key) in keys.iter().cycle().take(1000).enumerate() {
+            let hash = hasher.hash_one(key);
+            // Never match, so every entry is kept as a separate slot.
+            let slot = map.raw_find_or_free(hash, |_| false, &hasher).unwrap_err();
+            let data = map.raw_insert(slot, key, hash, i);
+            assert_eq!(data.key(), key);
+        }
+        assert_eq!(map.len(), 1000);
+
+        let key = &keys[42];
+        let hash = hasher.hash_one(key);
+        let mut values = vec![];
+        while let Some(slot) = map.raw_find(hash, |data| data.key() == key) {
+            values.push(map.raw_erase(slot).unwrap().into_value());
+        }
+        values.sort_unstable();
+        assert_eq!(values, (0..10).map(|i| i * 100 + 42).collect::<Vec<_>>());
+        assert_eq!(map.len(), 990);
+    }
 }
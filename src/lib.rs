@@ -1,21 +1,136 @@
 #![allow(unused_unsafe)]
-#![feature(associated_type_bounds)]
-#![feature(build_hasher_simple_hash_one)]
-#![feature(once_cell)]
-#![feature(type_alias_impl_trait)]
+#![cfg_attr(not(target_arch = "wasm32"), feature(portable_simd))]
 #![cfg_attr(test, feature(test, map_try_insert))]
 
 #[cfg(test)]
 extern crate test;
 
 mod adaptive;
+mod aggregate;
+mod arenas;
 mod array;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod auto;
+mod batch;
+mod boxed;
+mod buffer;
+mod builder;
+mod bulk;
+mod bytes;
+#[cfg(feature = "capi")]
+mod capi;
+mod chained;
+mod checkpoint;
 mod common;
+mod convert;
+mod counts;
+mod cuckoo;
+mod dict;
+mod distinct;
+mod error;
+mod extendible;
+mod fixed;
+mod frozen;
+mod group;
+#[cfg(feature = "hashbrown")]
+mod hashbrown;
+mod hopscotch;
+mod hybrid;
+mod indexed;
+mod int;
+mod join;
 mod large;
+mod limit;
+#[cfg(feature = "rayon")]
+mod merge;
+mod normalize;
+mod owned;
+#[cfg(feature = "polars")]
+mod polars;
+mod pool;
+mod radix;
+mod row;
+#[cfg(any(test, feature = "test-util"))]
+mod shadow;
+mod simd;
+mod sketch;
 mod small;
+#[cfg(feature = "arc-swap")]
+mod snapshot;
+mod sorted;
+mod stats;
+mod str;
+mod stream;
+mod swmr;
+#[cfg(test)]
+mod testing;
+mod tiers;
+mod topk;
+mod ttl;
+mod window;
 
-pub use self::adaptive::StringMap;
-pub use self::array::StringMap as ArrayStringMap;
-pub use self::common::Slot;
-pub use self::large::StringMap as LargeStringMap;
-pub use self::small::StringMap as SmallStringMap;
+#[cfg(feature = "metrics")]
+pub use self::adaptive::TierMetrics;
+pub use self::adaptive::{
+    AsKeyBytes, CardinalityEstimate, Entry, Equivalent, IntoIter, IntoIterHashed, Iter, IterMut,
+    KeyLen, KeyRef, OccupiedEntry, Recycled, Reseed, SlotHandle, StringMap, VacantEntry,
+};
+pub use self::aggregate::{AggregateState, Avg, Count, Max, Min, Number, Sum};
+pub use self::arenas::ThreadArenas;
+pub use self::array::{CapacityFull, StringMap as ArrayStringMap};
+pub use self::auto::AutoStringMap;
+pub use self::boxed::{BoxedStringMap, PinnedStringMap};
+pub use self::buffer::{BufferId, BufferStringMap};
+pub use self::builder::StringMapBuilder;
+pub use self::bulk::BulkLoader;
+#[cfg(feature = "capi")]
+pub use self::capi::{SahaMap, SahaVisitor};
+pub use self::chained::ChainedStringMap;
+pub use self::checkpoint::{Checkpoint, CheckpointStringMap};
+#[cfg(feature = "metrics")]
+pub use self::common::Metrics;
+pub use self::common::{
+    IntoIter as TierIntoIter, Iter as TierIter, IterMut as TierIterMut, RawSlot, ShrinkPolicy,
+    SlotAccess, SlotData,
+};
+pub use self::counts::{Counter, StringCounts};
+pub use self::cuckoo::CuckooStringMap;
+pub use self::dict::{DictId, DictStringMap};
+pub use self::distinct::distinct;
+pub use self::error::{Error, OccupiedError};
+pub use self::extendible::ExtendibleStringMap;
+pub use self::fixed::FixedStringMap;
+pub use self::frozen::{FrozenIter, FrozenStringMap};
+pub use self::group::GroupIdMap;
+#[cfg(feature = "hashbrown")]
+pub use self::hashbrown::HashbrownLarge;
+pub use self::hopscotch::HopscotchStringMap;
+pub use self::hybrid::HybridAggregator;
+pub use self::indexed::IndexedStringMap;
+pub use self::int::{IntHashState, IntHasher, IntStringMap, SlotData as IntSlotData};
+pub use self::join::HybridHashJoin;
+pub use self::large::{SlotData as LargeSlotData, StringMap as LargeStringMap};
+pub use self::limit::ByteLimitedStringMap;
+pub use self::normalize::{AsciiLowercase, Normalize, NormalizedStringMap, TrimAscii};
+pub use self::owned::{LargeTierMap, SmallTierMap, TierMap};
+pub use self::pool::{BudgetExceeded, MemoryPool, PooledStringMap};
+pub use self::radix::RadixStringMap;
+pub use self::row::RowStringMap;
+#[cfg(any(test, feature = "test-util"))]
+pub use self::shadow::{RawTable, ShadowMap, Shadowed};
+pub use self::simd::{BatchHasher, BatchState};
+pub use self::sketch::{DistinctCounts, DistinctSketch};
+pub use self::small::{SlotData as SmallSlotData, StringMap as SmallStringMap};
+#[cfg(feature = "arc-swap")]
+pub use self::snapshot::{Snapshot, SnapshotBuilder, SnapshotStringMap};
+pub use self::sorted::SortedIndex;
+pub use self::stats::{KeyStats, KEY_LENGTH_BUCKETS};
+pub use self::str::StrMap;
+pub use self::stream::{GroupBatch, GroupByStream};
+pub use self::swmr::{ReadGuard, SwmrStringMap, SwmrWriter};
+pub use self::tiers::{DefaultTiers, Large, Small, TierStorage, TieredStringMap, Tiers};
+pub use self::topk::TopKStrings;
+pub use self::ttl::TtlStringMap;
+pub use self::window::WindowedStringMap;
+pub use bumpalo::boxed::Box as ArenaBox;
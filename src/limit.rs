@@ -0,0 +1,183 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, KeyRef, StringMap};
+use crate::pool::BudgetExceeded;
+
+/// A tiered map limited in bytes rather than in entries, since the copies of
+/// large keys make the number of entries a poor measure of memory.
+///
+/// The bytes counted are the slots of all the tiers, the large keys copied
+/// into the arena, and the sizes reported for the values, i.e. what they own
+/// on the heap besides their inline part, which is already in the slots. The
+/// arena never frees keys, so their bytes stay counted after removals.
+pub struct ByteLimitedStringMap<'a, T, S = RandomState> {
+    /// The values along with their reported sizes.
+    map: StringMap<'a, (usize, T), S>,
+    limit: usize,
+    key_bytes: usize,
+    value_bytes: usize,
+}
+
+impl<'a, T, S> ByteLimitedStringMap<'a, T, S> {
+    pub fn with_hasher(limit: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        ByteLimitedStringMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            limit,
+            key_bytes: 0,
+            value_bytes: 0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of bytes counted against the limit.
+    pub fn used(&self) -> usize {
+        self.map.bucket_bytes() + self.key_bytes + self.value_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the entries along with the sizes reported for their
+    /// values.
+    pub fn iter(&self) -> impl Iterator<Item = (KeyRef<'_>, usize, &T)> + '_ {
+        self.map
+            .iter()
+            .map(|(key, (size, value))| (key, *size, value))
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, (usize, T), S> {
+        self.map
+    }
+}
+
+impl<'a, T> ByteLimitedStringMap<'a, T, RandomState> {
+    pub fn new(limit: usize, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(limit, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> ByteLimitedStringMap<'a, T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key).map(|(_, value)| value)
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Returns the number of bytes inserting `key` with a value of
+    /// `value_size` bytes would add.
+    fn growth(&self, key: KeyRef, hash: u64, value_size: usize) -> usize {
+        match self.map.get_hashed(key, hash) {
+            Some((old, _)) => value_size.saturating_sub(*old),
+            None => self.map.insertion_bytes(key) + value_size,
+        }
+    }
+
+    /// Returns whether inserting `key` with a value of `value_size` bytes
+    /// would go past the limit.
+    pub fn would_exceed<Q: Equivalent>(&self, key: Q, value_size: usize) -> bool {
+        let key = key.as_key_ref();
+        let hash = self.map.hasher().hash_one(key);
+        self.used() + self.growth(key, hash, value_size) > self.limit
+    }
+
+    /// Inserts `key` with a value owning `value_size` bytes, unless that would
+    /// go past the limit.
+    pub fn try_insert<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+        value_size: usize,
+    ) -> Result<Option<T>, BudgetExceeded> {
+        let key = key.as_key_ref();
+        let hash = self.map.hasher().hash_one(key);
+        let (requested, used) = (self.growth(key, hash, value_size), self.used());
+        if used + requested > self.limit {
+            return Err(BudgetExceeded {
+                requested,
+                available: self.limit.saturating_sub(used),
+            });
+        }
+
+        self.value_bytes += value_size;
+        match self.map.insert_hashed(key, hash, (value_size, value)) {
+            Some((old_size, old)) => {
+                self.value_bytes -= old_size;
+                Ok(Some(old))
+            }
+            None => {
+                if let KeyRef::Large(key) = key {
+                    self.key_bytes += key.len();
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<T> {
+        let (size, value) = self.map.remove(key)?;
+        self.value_bytes -= size;
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_limit() {
+        let bump = Bump::new();
+        let mut map = ByteLimitedStringMap::new(1 << 16, &bump);
+
+        let mut inserted = 0;
+        let err = loop {
+            let key = format!("{inserted:0width$}", width = 8 + inserted % 64);
+            let value = vec![0u8; inserted % 100];
+            let exceeds = map.would_exceed(KeyRef::from(key.as_bytes()), value.len());
+            let size = value.len();
+            match map.try_insert(KeyRef::from(key.as_bytes()), value, size) {
+                Ok(old) => {
+                    assert!(!exceeds);
+                    assert_eq!(old, None);
+                }
+                Err(err) => {
+                    assert!(exceeds);
+                    break err;
+                }
+            }
+            assert!(map.used() <= map.limit());
+            inserted += 1;
+        };
+        assert_eq!(map.len(), inserted);
+        assert!(err.requested > err.available);
+
+        // Shrinking a value always fits, and frees its bytes.
+        let key = format!("{:0width$}", 99, width = 8 + 99 % 64);
+        let used = map.used();
+        let old = map.try_insert(KeyRef::from(key.as_bytes()), Vec::new(), 0);
+        assert_eq!(old.unwrap().map(|old| old.len()), Some(99));
+        assert_eq!(map.used(), used - 99);
+
+        let used = map.used();
+        let key = format!("{:0width$}", 98, width = 8 + 98 % 64);
+        assert_eq!(
+            map.remove(KeyRef::from(key.as_bytes())).map(|v| v.len()),
+            Some(98)
+        );
+        assert!(map.used() <= used - 98);
+    }
+}
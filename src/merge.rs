@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use rayon::prelude::*;
+
+use crate::adaptive::StringMap;
+use crate::arenas::Unshared;
+
+impl<'a, T: Send, S: BuildHasher + Clone + Send + Sync> StringMap<'a, T, S> {
+    /// Merges `maps` in parallel into `2^bits` partitions by the high bits of
+    /// the hashes of their keys (see [`repartition`](Self::repartition)),
+    /// calling `combine` on the values of the same key.
+    ///
+    /// Every map is first repartitioned on its own thread, then each
+    /// partition is merged on its own thread, so that the entries of a key
+    /// all meet on the same thread. The partitions hash like the first map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `maps` is empty.
+    pub fn merge_partitioned(
+        maps: Vec<Self>,
+        bits: u32,
+        combine: impl Fn(&mut T, T) + Sync,
+    ) -> Vec<Self> {
+        let first = maps.first().expect("No maps to merge");
+        let hasher = first.hasher().clone();
+        let same = maps
+            .iter()
+            .map(|map| first.hashes_like(map.hasher()))
+            .collect::<Vec<_>>();
+
+        let mut columns = maps
+            .into_iter()
+            .zip(same)
+            .map(Unshared)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|Unshared((map, same))| {
+                let map = match same {
+                    true => map,
+                    false => map.rehash_with(hasher.clone()),
+                };
+                Unshared(map.repartition(bits))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|parts| parts.0.into_iter());
+
+        // Transpose the partitions of every map into the maps of every
+        // partition.
+        let mut partitions = Vec::<Unshared<Vec<Self>>>::new();
+        if let Some(first) = columns.next() {
+            partitions.extend(first.map(|part| Unshared(vec![part])));
+        }
+        for column in columns {
+            for (partition, part) in partitions.iter_mut().zip(column) {
+                partition.0.push(part);
+            }
+        }
+
+        partitions
+            .into_par_iter()
+            .map(|Unshared(parts)| {
+                let mut parts = parts.into_iter();
+                let mut map = parts.next().unwrap();
+                for part in parts {
+                    map.merge(part, &combine);
+                }
+                Unshared(map)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|map| map.0)
+            .collect()
+    }
+
+    /// Merges `maps` in parallel into one map, calling `combine` on the
+    /// values of the same key.
+    ///
+    /// The maps are merged by partition as in
+    /// [`merge_partitioned`](Self::merge_partitioned), with a partition per
+    /// few threads of the current rayon pool, and the partitions are then
+    /// gathered into the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `maps` is empty.
+    pub fn merge_all(maps: Vec<Self>, combine: impl Fn(&mut T, T) + Sync) -> Self {
+        let bits = (rayon::current_num_threads() * 4)
+            .next_power_of_two()
+            .trailing_zeros()
+            .min(16);
+        let mut partitions = Self::merge_partitioned(maps, bits, &combine).into_iter();
+        let mut map = partitions.next().unwrap();
+        for partition in partitions {
+            // The partitions have disjoint keys, so `combine` is never called.
+            map.merge(partition, &combine);
+        }
+        map
+    }
+}
+
+/// Gathers the entries into a std map per rayon job, since the arena can't be
+/// shared between threads, then moves them into the map, whose keys are copied
+/// into its arena on the current thread. As with
+/// [`insert`](StringMap::insert), the last entry of a key wins.
+impl<'a, T: Send, S: BuildHasher> ParallelExtend<(Vec<u8>, T)> for StringMap<'a, T, S> {
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (Vec<u8>, T)>,
+    {
+        let map = par_iter
+            .into_par_iter()
+            .fold(HashMap::new, |mut map, (key, value)| {
+                map.insert(key, value);
+                map
+            })
+            .reduce(HashMap::new, |mut map, later| {
+                map.extend(later);
+                map
+            });
+        self.extend_from_hash_map(map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bumpalo::Bump;
+
+    use crate::KeyRef;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_all() {
+        let bump = Bump::new();
+        let mut cmp = HashMap::<Vec<u8>, u64>::new();
+        let maps = (0..8)
+            .map(|i| {
+                let mut map = StringMap::new(&bump);
+                for _ in 0..5000 {
+                    let key = (0..rand::random::<usize>() % 30)
+                        .map(|_| b'a' + rand::random::<u8>() % 4)
+                        .collect::<Vec<_>>();
+                    *map.get_or_insert_with(KeyRef::from(&*key), || 0).0 += i;
+                    *cmp.entry(key).or_default() += i;
+                }
+                map
+            })
+            .collect::<Vec<_>>();
+
+        let partitions = StringMap::merge_partitioned(maps.clone(), 3, |a, b| *a += b);
+        assert_eq!(partitions.len(), 8);
+        assert_eq!(
+            partitions.iter().map(|map| map.len()).sum::<usize>(),
+            cmp.len()
+        );
+
+        let map = StringMap::merge_all(maps, |a, b| *a += b);
+        assert_eq!(map.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(&**key)), Some(value));
+        }
+    }
+    #[test]
+    fn test_par_extend() {
+        let bump = Bump::new();
+        let entries = (0..50000usize)
+            .map(|i| (vec![b'a' + (i % 7) as u8; i % 37], i))
+            .collect::<Vec<_>>();
+
+        let mut map = StringMap::new(&bump);
+        map.insert(KeyRef::from(&b"other"[..]), 0);
+        map.par_extend(entries.clone());
+        let cmp = entries.into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(map.len(), cmp.len() + 1);
+        for (key, value) in &cmp {
+            assert_eq!(map.get(KeyRef::from(&**key)), Some(value));
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{AsKeyBytes, Iter, IterMut, KeyRef, StringMap};
+
+/// A normalization applied to keys before they are hashed and compared.
+pub trait Normalize {
+    /// Writes the normalized form of `key` into `out`, which is empty on entry.
+    fn normalize(&self, key: &[u8], out: &mut Vec<u8>);
+}
+
+impl<F: Fn(&[u8], &mut Vec<u8>)> Normalize for F {
+    fn normalize(&self, key: &[u8], out: &mut Vec<u8>) {
+        self(key, out)
+    }
+}
+
+/// Lowercases ASCII letters, leaving other bytes untouched.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsciiLowercase;
+
+impl Normalize for AsciiLowercase {
+    fn normalize(&self, key: &[u8], out: &mut Vec<u8>) {
+        out.extend(key.iter().map(u8::to_ascii_lowercase))
+    }
+}
+
+/// Strips leading and trailing ASCII whitespace.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrimAscii;
+
+impl Normalize for TrimAscii {
+    fn normalize(&self, key: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(key.trim_ascii())
+    }
+}
+
+thread_local! {
+    /// The buffer keys are normalized into, reused across calls and maps.
+    static SCRATCH: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+/// Normalizes `key` into the scratch buffer of the thread and passes it to
+/// `f`.
+///
+/// The buffer is taken out while in use, so that a normalizer looking up
+/// another map just gets a fresh one.
+fn with_normalized<R>(normalizer: &impl Normalize, key: &[u8], f: impl FnOnce(KeyRef) -> R) -> R {
+    let mut buf = SCRATCH.take();
+    buf.clear();
+    normalizer.normalize(key, &mut buf);
+    let ret = f(KeyRef::from(&buf[..]));
+    SCRATCH.set(buf);
+    ret
+}
+
+/// A tiered map normalizing every key on insertion and lookup.
+///
+/// Keys are normalized into a scratch buffer reused by the thread, and only
+/// the normalized bytes of new keys get copied into the arena, so callers
+/// need not allocate normalized copies per row. The map holds no buffer of
+/// its own, so lookups through `&self` need no interior mutability.
+pub struct NormalizedStringMap<'a, T, N, S = RandomState> {
+    map: StringMap<'a, T, S>,
+    normalizer: N,
+}
+
+impl<'a, T, N, S> NormalizedStringMap<'a, T, N, S> {
+    pub fn with_hasher(normalizer: N, hasher: S, key_alloc: &'a Bump) -> Self {
+        NormalizedStringMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            normalizer,
+        }
+    }
+
+    pub fn normalizer(&self) -> &N {
+        &self.normalizer
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the normalized keys and their values.
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, T> {
+        self.map.iter_mut()
+    }
+
+    /// Returns the underlying map, keyed by normalized keys.
+    pub fn into_inner(self) -> StringMap<'a, T, S> {
+        self.map
+    }
+}
+
+impl<'a, T, N> NormalizedStringMap<'a, T, N, RandomState> {
+    pub fn new(normalizer: N, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(normalizer, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, N: Normalize, S: BuildHasher> NormalizedStringMap<'a, T, N, S> {
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        with_normalized(&self.normalizer, key.as_key_bytes(), |key| {
+            self.map.get(key)
+        })
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let map = &mut self.map;
+        with_normalized(&self.normalizer, key.as_key_bytes(), |key| map.get_mut(key))
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let map = &mut self.map;
+        with_normalized(&self.normalizer, key.as_key_bytes(), |key| {
+            map.insert(key, value)
+        })
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let map = &mut self.map;
+        with_normalized(&self.normalizer, key.as_key_bytes(), |key| {
+            map.get_or_insert_with(key, value)
+        })
+    }
+
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let map = &mut self.map;
+        with_normalized(&self.normalizer, key.as_key_bytes(), |key| map.remove(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_normalized() {
+        let bump = Bump::new();
+        let mut map = NormalizedStringMap::new(AsciiLowercase, &bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..10000 {
+            let key = rand::random::<[u8; 2]>().map(|b| b"aAbBcC "[b as usize % 7]);
+            let len = rand::random::<usize>() % 30;
+            let key = &key.repeat(len / 2 + 1)[..len];
+
+            let (count, inserted) = map.get_or_insert_with(key, || 0);
+            *count += 1;
+            let count = cmp.entry(key.to_ascii_lowercase()).or_insert(0);
+            assert_eq!(inserted, *count == 0);
+            *count += 1;
+        }
+        assert_eq!(map.len(), cmp.len());
+
+        for (key, &count) in map.iter() {
+            assert_eq!(map.get(key.key().to_ascii_uppercase()), Some(&count));
+            assert_eq!(cmp.remove(key.key()), Some(count));
+        }
+        assert!(cmp.is_empty());
+
+        let mut map = NormalizedStringMap::new(TrimAscii, &bump);
+        map.insert(b"  key\t", 1);
+        assert_eq!(map.get(b"key"), Some(&1));
+        assert_eq!(map.remove(b"\nkey "), Some(1));
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::common::{self, IntoIter, Iter, IterMut, SlotData};
+use crate::{large, small};
+
+/// A single tier that owns its hasher, for using the tiers standalone.
+///
+/// The bare tiers take the hash and the hasher on every call, and hashing
+/// keys with different hashers corrupts them; this wrapper hashes the keys
+/// itself and offers the plain map surface instead.
+pub struct TierMap<'a, D, S = RandomState> {
+    map: common::StringMap<'a, D>,
+    hasher: S,
+}
+
+/// A [`SmallStringMap`](crate::SmallStringMap) owning its hasher.
+pub type SmallTierMap<'a, T, const N: usize, S = RandomState> =
+    TierMap<'a, small::SlotData<T, N>, S>;
+
+/// A [`LargeStringMap`](crate::LargeStringMap) owning its hasher.
+pub type LargeTierMap<'a, T, S = RandomState> = TierMap<'a, large::SlotData<'a, T>, S>;
+
+impl<'a, D, S> TierMap<'a, D, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        TierMap {
+            map: common::StringMap::new(key_alloc),
+            hasher,
+        }
+    }
+
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        TierMap {
+            map: common::StringMap::with_capacity(cap, key_alloc),
+            hasher,
+        }
+    }
+
+    /// Wraps `map`, whose entries must have been hashed with `hasher`.
+    pub fn from_parts(map: common::StringMap<'a, D>, hasher: S) -> Self {
+        TierMap { map, hasher }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+
+    pub fn iter(&self) -> Iter<'_, D> {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, D> {
+        self.map.iter_mut()
+    }
+
+    /// Returns the bare tier, along with the hasher of its entries.
+    pub fn into_parts(self) -> (common::StringMap<'a, D>, S) {
+        (self.map, self.hasher)
+    }
+}
+
+impl<'a, D> TierMap<'a, D, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+
+    pub fn with_capacity(cap: usize, key_alloc: &'a Bump) -> Self {
+        Self::with_capacity_and_hasher(cap, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, D: SlotData<'a>, S: BuildHasher> TierMap<'a, D, S> {
+    pub fn get(&self, key: &[u8]) -> Option<&D::Value> {
+        self.map.get(key, self.hasher.hash_one(key))
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut D::Value> {
+        self.map.get_mut(key, self.hasher.hash_one(key))
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: D::Value) -> Option<D::Value> {
+        let hash = self.hasher.hash_one(key);
+        self.map.insert(key, hash, value, &self.hasher)
+    }
+
+    /// Inserts `key` if it is absent, or returns the present value along
+    /// with `value` otherwise.
+    pub fn try_insert(&mut self, key: &[u8], value: D::Value) -> Option<(&mut D::Value, D::Value)> {
+        let hash = self.hasher.hash_one(key);
+        self.map.try_insert(key, hash, value, &self.hasher)
+    }
+
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        value: impl FnOnce() -> D::Value,
+    ) -> (&mut D::Value, bool) {
+        let hash = self.hasher.hash_one(key);
+        self.map.get_or_insert_with(key, hash, value, &self.hasher)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<D::Value> {
+        let hash = self.hasher.hash_one(key);
+        self.map.remove(key, hash, &self.hasher)
+    }
+
+    pub fn remove_entry(&mut self, key: &[u8]) -> Option<(&'a [u8], D::Value)> {
+        let hash = self.hasher.hash_one(key);
+        self.map.remove_entry(key, hash, &self.hasher)
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&[u8], &mut D::Value) -> bool) {
+        self.map.retain(f)
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional, &self.hasher)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit(&self.hasher)
+    }
+}
+
+impl<'a, D: SlotData<'a>, S> IntoIterator for TierMap<'a, D, S> {
+    type Item = (&'a [u8], D::Value);
+
+    type IntoIter = IntoIter<'a, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.into_iter()
+    }
+}
+
+impl<'b, 'a, D: SlotData<'a>, S> IntoIterator for &'b TierMap<'a, D, S> {
+    type Item = (&'b [u8], &'b D::Value);
+
+    type IntoIter = Iter<'b, D>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, D: SlotData<'a>, S: BuildHasher> Extend<(&'a [u8], D::Value)> for TierMap<'a, D, S> {
+    fn extend<I: IntoIterator<Item = (&'a [u8], D::Value)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_tier_maps() {
+        let bump = Bump::new();
+        let mut small = SmallTierMap::<u64, 8>::new(&bump);
+        let mut large = LargeTierMap::new(&bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..10000 {
+            let value = rand::random::<u16>() as u64 % 1000;
+            let key = value.to_ne_bytes();
+            let long = [&key[..], &[0xab; 16]].concat();
+            match value % 4 {
+                0 => {
+                    assert_eq!(small.remove(&key), cmp.remove(&key[..]));
+                    large.remove(&long);
+                }
+                _ => {
+                    assert_eq!(small.insert(&key, value), cmp.insert(key.to_vec(), value));
+                    large.insert(&long, value);
+                }
+            }
+        }
+        assert_eq!(small.len(), cmp.len());
+        assert_eq!(large.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(small.get(key), Some(value));
+            assert_eq!(large.get(&[&key[..], &[0xab; 16]].concat()), Some(value));
+        }
+
+        let (map, hasher) = small.into_parts();
+        let small = TierMap::from_parts(map, hasher);
+        for (key, value) in small {
+            assert_eq!(cmp.remove(key), Some(value));
+        }
+        assert!(cmp.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::hash::BuildHasher;
+use std::str;
+
+use polars_core::prelude::{
+    polars_err, BinaryChunked, NewChunkedArray, PlSmallStr, PolarsResult, StringChunked,
+};
+
+use crate::adaptive::StringMap;
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Groups the rows of a binary column, e.g. in the group-by kernel of a
+    /// polars plugin.
+    ///
+    /// The state of each new key is created by `init` from the index of its
+    /// first row, and the following rows are folded into it with `fold`. Null
+    /// rows are skipped.
+    pub fn insert_from_binary_chunked(
+        &mut self,
+        column: &BinaryChunked,
+        mut init: impl FnMut(usize) -> T,
+        mut fold: impl FnMut(&mut T, usize),
+    ) {
+        for (row, key) in column.iter().enumerate() {
+            let Some(key) = key else { continue };
+            let (state, inserted) = self.get_or_insert_with(key, || init(row));
+            if !inserted {
+                fold(state, row);
+            }
+        }
+    }
+
+    /// Groups the rows of a string column, as
+    /// [`insert_from_binary_chunked`](Self::insert_from_binary_chunked) does.
+    pub fn insert_from_string_chunked(
+        &mut self,
+        column: &StringChunked,
+        init: impl FnMut(usize) -> T,
+        fold: impl FnMut(&mut T, usize),
+    ) {
+        self.insert_from_binary_chunked(&column.as_binary(), init, fold)
+    }
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Emits the keys as a binary column named `name`, in the order of
+    /// iteration.
+    pub fn keys_to_binary_chunked(&self, name: PlSmallStr) -> BinaryChunked {
+        BinaryChunked::from_iter_values(name, self.iter().map(|(key, _)| key.key()))
+    }
+
+    /// Emits the keys as a string column named `name`, in the order of
+    /// iteration.
+    ///
+    /// Fails if some key isn't UTF-8.
+    pub fn keys_to_string_chunked(&self, name: PlSmallStr) -> PolarsResult<StringChunked> {
+        let keys = self
+            .iter()
+            .map(|(key, _)| str::from_utf8(key.key()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| polars_err!(ComputeError: "key is not UTF-8: {}", err))?;
+        Ok(StringChunked::from_iter_values(name, keys.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bumpalo::Bump;
+
+    use super::*;
+
+    #[test]
+    fn test_chunked() {
+        let rows = (0..10000)
+            .map(|i| (i % 7 != 0).then(|| (i % 37).to_string().repeat(i % 5)))
+            .collect::<Vec<_>>();
+        let mut cmp = HashMap::new();
+        for row in rows.iter().flatten() {
+            *cmp.entry(row.as_bytes()).or_insert(0) += 1;
+        }
+
+        let column =
+            StringChunked::from_iter_options("rows".into(), rows.iter().map(Option::as_deref));
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        map.insert_from_string_chunked(&column, |_| 1, |count, _| *count += 1);
+        assert_eq!(map.len(), cmp.len());
+        for (key, count) in &cmp {
+            assert_eq!(map.get(key), Some(count));
+        }
+
+        let keys = map.keys_to_string_chunked("keys".into()).unwrap();
+        assert_eq!(keys.len(), map.len());
+        for (key, (stored, _)) in keys.iter().zip(map.iter()) {
+            assert_eq!(key.map(str::as_bytes), Some(stored.key()));
+        }
+
+        let mut map = StringMap::new(&bump);
+        map.insert_from_binary_chunked(&keys.as_binary(), |_| 0, |_, _| {});
+        map.insert(&b"\xff"[..], 0);
+        assert_eq!(map.keys_to_binary_chunked("keys".into()).len(), map.len());
+        assert!(map.keys_to_string_chunked("keys".into()).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, Iter, IterMut, KeyRef, StringMap};
+
+/// The error of an insertion refused because it would allocate past a memory
+/// budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    /// The number of bytes the insertion would allocate, or a lower bound of
+    /// them if it needs a new chunk of the key arena.
+    pub requested: usize,
+    /// The number of bytes left in the budget.
+    pub available: usize,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memory budget exceeded: {} bytes requested, {} available",
+            self.requested, self.available
+        )
+    }
+}
+
+impl Error for BudgetExceeded {}
+
+/// A budget of bytes shared by many maps, e.g. the per-partition maps of a
+/// query, so that they are capped as a whole rather than one by one.
+///
+/// The pool is only an account: maps charge it before growing and credit it
+/// back when they shrink or are dropped. While a map allocates a new chunk
+/// of its key arena, it holds whatever is left of the budget, so insertions
+/// into other maps may be refused meanwhile.
+#[derive(Debug)]
+pub struct MemoryPool {
+    budget: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryPool {
+    pub fn new(budget: usize) -> Self {
+        MemoryPool {
+            budget,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn available(&self) -> usize {
+        self.budget.saturating_sub(self.used())
+    }
+
+    /// Charges `bytes` to the pool if they fit in the budget.
+    pub fn try_reserve(&self, bytes: usize) -> Result<(), BudgetExceeded> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.budget)
+            })
+            .map(drop)
+            .map_err(|used| BudgetExceeded {
+                requested: bytes,
+                available: self.budget.saturating_sub(used),
+            })
+    }
+
+    /// Charges `bytes` to the pool even past the budget.
+    fn reserve_unchecked(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Charges whatever is left of the budget, returning its size.
+    fn reserve_rest(&self) -> usize {
+        let used = self.used.fetch_max(self.budget, Ordering::Relaxed);
+        self.budget.saturating_sub(used)
+    }
+
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A tiered map charging a [`MemoryPool`] for the slots of its tiers and the
+/// chunks its arena allocates to copy keys, and refusing insertions past the
+/// budget of the pool.
+///
+/// The initial slots are charged even if they don't fit in the budget. The
+/// arena never frees chunks, so they stay charged until the map is dropped.
+/// A key fitting in the current chunk is free, even if another map, sharing
+/// the arena, paid for the chunk.
+pub struct PooledStringMap<'a, 'p, T, S = RandomState> {
+    map: StringMap<'a, T, S>,
+    pool: &'p MemoryPool,
+    charged: usize,
+}
+
+impl<'a, 'p, T, S> PooledStringMap<'a, 'p, T, S> {
+    pub fn with_hasher(pool: &'p MemoryPool, hasher: S, key_alloc: &'a Bump) -> Self {
+        let map = StringMap::with_hasher(hasher, key_alloc);
+        let charged = map.bucket_bytes();
+        pool.reserve_unchecked(charged);
+        PooledStringMap { map, pool, charged }
+    }
+
+    pub fn pool(&self) -> &'p MemoryPool {
+        self.pool
+    }
+
+    /// Returns the number of bytes charged to the pool by this map.
+    pub fn charged(&self) -> usize {
+        self.charged
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        self.map.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, 'a, T> {
+        self.map.iter_mut()
+    }
+
+    /// Returns the underlying map, crediting its bytes back to the pool.
+    pub fn into_inner(mut self) -> StringMap<'a, T, S> {
+        self.pool.release(std::mem::take(&mut self.charged));
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never used again, and its other fields need no
+        // drop.
+        unsafe { std::ptr::read(&this.map) }
+    }
+}
+
+impl<'a, 'p, T> PooledStringMap<'a, 'p, T, RandomState> {
+    pub fn new(pool: &'p MemoryPool, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(pool, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, 'p, T, S: BuildHasher> PooledStringMap<'a, 'p, T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Charges the pool for inserting `key` if it is absent, returning its
+    /// hash along with the copy of a large key, already in the arena.
+    fn charge(&mut self, key: KeyRef) -> Result<(u64, Option<&'a [u8]>), BudgetExceeded> {
+        let hash = self.map.hasher().hash_one(key);
+        if self.map.get_hashed(key, hash).is_some() {
+            return Ok((hash, None));
+        }
+        let growth = self.map.slot_growth_bytes(key);
+        self.pool.try_reserve(growth)?;
+        self.charged += growth;
+        let KeyRef::Large(key) = key else {
+            return Ok((hash, None));
+        };
+
+        // The size of a new chunk is only known once it is allocated, so hold
+        // the rest of the budget meanwhile.
+        let available = self.pool.reserve_rest();
+        match copy_key_within(self.map.key_alloc(), key, available) {
+            Some((copy, grown)) => {
+                self.pool.release(available - grown);
+                self.charged += grown;
+                Ok((hash, Some(copy)))
+            }
+            None => {
+                self.pool.release(available + growth);
+                self.charged -= growth;
+                Err(BudgetExceeded {
+                    requested: growth + key.len().max(available + 1),
+                    available: available + growth,
+                })
+            }
+        }
+    }
+
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Result<Option<T>, BudgetExceeded> {
+        let key = key.as_key_ref();
+        Ok(match self.charge(key)? {
+            (hash, Some(copy)) => self.map.insert_borrowed_hashed(copy, hash, value),
+            (hash, None) => self.map.insert_hashed(key, hash, value),
+        })
+    }
+
+    pub fn get_or_insert_with<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> Result<(&mut T, bool), BudgetExceeded> {
+        let key = key.as_key_ref();
+        Ok(match self.charge(key)? {
+            (hash, Some(copy)) => {
+                self.map.insert_borrowed_hashed(copy, hash, value());
+                (self.map.get_mut_hashed(key, hash).unwrap(), true)
+            }
+            (hash, None) => self.map.get_or_insert_with_hashed(key, hash, value),
+        })
+    }
+
+    /// Removes `key`, crediting the pool if its tier shrinks.
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<T> {
+        let before = self.map.bucket_bytes();
+        let value = self.map.remove(key);
+        let freed = before - self.map.bucket_bytes();
+        self.pool.release(freed);
+        self.charged -= freed;
+        value
+    }
+}
+
+/// Copies `key` into `key_alloc` unless that allocates a chunk of more than
+/// `limit` bytes, returning the copy along with the number of bytes the arena
+/// grew by.
+fn copy_key_within<'a>(key_alloc: &'a Bump, key: &[u8], limit: usize) -> Option<(&'a [u8], usize)> {
+    let (allocated, previous) = (key_alloc.allocated_bytes(), key_alloc.allocation_limit());
+    let allowed = allocated.saturating_add(limit);
+    key_alloc.set_allocation_limit(Some(
+        previous.map_or(allowed, |previous| previous.min(allowed)),
+    ));
+    let copy = key_alloc.try_alloc_slice_copy(key);
+    key_alloc.set_allocation_limit(previous);
+    Some((copy.ok()?, key_alloc.allocated_bytes() - allocated))
+}
+
+impl<'a, T, S: BuildHasher> StringMap<'a, T, S> {
+    /// Returns the number of bytes the map occupies: the slots of all the
+    /// tiers, plus the whole key arena, which may be shared with other maps.
+    pub fn footprint(&self) -> usize {
+        self.bucket_bytes() + self.key_alloc().allocated_bytes()
+    }
+
+    /// Inserts `key` unless it is absent and inserting it would grow the
+    /// footprint of the map past `budget` bytes, so that the caller can spill
+    /// or emit early instead.
+    ///
+    /// Keys already present are always updated, as that allocates nothing.
+    pub fn insert_within_budget<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+        budget: usize,
+    ) -> Result<Option<T>, BudgetExceeded> {
+        let key = key.as_key_ref();
+        let hash = self.hasher().hash_one(key);
+        if let Some(slot) = self.get_mut_hashed(key, hash) {
+            return Ok(Some(std::mem::replace(slot, value)));
+        }
+
+        let available = budget.saturating_sub(self.footprint());
+        let growth = self.slot_growth_bytes(key);
+        let exceeded = |requested| BudgetExceeded {
+            requested,
+            available,
+        };
+        if growth > available {
+            return Err(exceeded(self.insertion_bytes(key)));
+        }
+        match key {
+            KeyRef::Large(bytes) => {
+                let (copy, _) = copy_key_within(self.key_alloc(), bytes, available - growth)
+                    .ok_or_else(|| exceeded(growth + bytes.len().max(available - growth + 1)))?;
+                Ok(self.insert_borrowed_hashed(copy, hash, value))
+            }
+            _ => Ok(self.insert_hashed(key, hash, value)),
+        }
+    }
+}
+
+impl<'a, 'p, T, S> Drop for PooledStringMap<'a, 'p, T, S> {
+    fn drop(&mut self) {
+        self.pool.release(self.charged);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_pool() {
+        let bump = Bump::new();
+        let pool = MemoryPool::new(1 << 16);
+        let mut maps = [(); 4].map(|_| PooledStringMap::new(&pool, &bump));
+        assert_eq!(pool.used(), maps.iter().map(|map| map.charged()).sum());
+
+        let mut inserted = 0;
+        let err = 'fill: loop {
+            for map in &mut maps {
+                let key = format!("{inserted:032}");
+                match map.insert(KeyRef::from(key.as_bytes()), inserted) {
+                    Ok(old) => assert_eq!(old, None),
+                    Err(err) => break 'fill err,
+                }
+                inserted += 1;
+            }
+        };
+        assert!(err.requested > err.available);
+        assert!(pool.used() <= pool.budget());
+        assert_eq!(pool.used(), maps.iter().map(|map| map.charged()).sum());
+        assert_eq!(maps.iter().map(|map| map.len()).sum::<usize>(), inserted);
+
+        // Existing keys are still updated past the budget.
+        let key = format!("{:032}", 0);
+        assert_eq!(maps[0].insert(KeyRef::from(key.as_bytes()), 1), Ok(Some(0)));
+
+        let mut map = maps.into_iter().next().unwrap();
+        assert_eq!(pool.used(), map.charged());
+        let charged = map.charged();
+        for i in (0..inserted).step_by(4) {
+            let key = format!("{i:032}");
+            assert_eq!(map.remove(KeyRef::from(key.as_bytes())), Some(i.max(1)));
+        }
+        assert!(map.charged() < charged);
+        assert_eq!(pool.used(), map.charged());
+        drop(map);
+        assert_eq!(pool.used(), 0);
+    }
+
+    #[test]
+    fn test_insert_within_budget() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let budget = map.footprint() + (1 << 14);
+
+        let mut inserted = 0;
+        let err = loop {
+            let key = format!("{inserted:040}");
+            match map.insert_within_budget(KeyRef::from(key.as_bytes()), inserted, budget) {
+                Ok(old) => assert_eq!(old, None),
+                Err(err) => break err,
+            }
+            inserted += 1;
+        };
+        assert_eq!(map.len(), inserted);
+        assert!(map.footprint() <= budget);
+        assert!(err.requested > err.available);
+
+        let key = format!("{:040}", 0);
+        let result = map.insert_within_budget(KeyRef::from(key.as_bytes()), 1, 0);
+        assert_eq!(result, Ok(Some(0)));
+    }
+    #[test]
+    fn test_arena_chunks() {
+        // Keys that don't fit in the current chunk of the arena make it
+        // allocate a new one, larger than the key.
+        for extra in [1000, 20000, 300000] {
+            let key = |i: usize| format!("{i:0300}");
+
+            let bump = Bump::new();
+            let mut map = StringMap::new(&bump);
+            let budget = map.footprint() + extra;
+            let mut inserted = 0;
+            while let Ok(old) =
+                map.insert_within_budget(KeyRef::from(key(inserted).as_bytes()), inserted, budget)
+            {
+                assert_eq!(old, None);
+                inserted += 1;
+            }
+            assert!(map.footprint() <= budget);
+            assert_eq!(map.len(), inserted);
+            assert!(inserted * 300 > extra / 4);
+
+            let bump = Bump::new();
+            let pool = MemoryPool::new(StringMap::<usize, _>::new(&bump).bucket_bytes() + extra);
+            let mut map = PooledStringMap::new(&pool, &bump);
+            let mut inserted = 0;
+            let err = loop {
+                match map.get_or_insert_with(KeyRef::from(key(inserted).as_bytes()), || inserted) {
+                    Ok((_, inserted_now)) => assert!(inserted_now),
+                    Err(err) => break err,
+                }
+                inserted += 1;
+            };
+            assert!(err.requested > err.available);
+            assert!(pool.used() <= pool.budget());
+            assert_eq!(
+                map.charged(),
+                map.map.bucket_bytes() + bump.allocated_bytes()
+            );
+            for i in 0..inserted {
+                assert_eq!(map.get(KeyRef::from(key(i).as_bytes())), Some(&i));
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::mem;
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+
+struct Node<'a, T> {
+    /// The bytes of the edge leading to this node.
+    label: &'a [u8],
+    value: Option<T>,
+    /// Sorted by the first byte of their labels, which are distinct.
+    children: Vec<Node<'a, T>>,
+}
+
+impl<'a, T> Node<'a, T> {
+    fn new(label: &'a [u8]) -> Self {
+        Node {
+            label,
+            value: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn find(&self, byte: u8) -> Result<usize, usize> {
+        self.children
+            .binary_search_by_key(&byte, |child| child.label[0])
+    }
+
+    /// Returns the value slot of `key` below this node, splitting edges and
+    /// adding nodes as needed.
+    fn slot(&mut self, key_alloc: &'a Bump, mut key: &[u8]) -> &mut Option<T> {
+        let mut node = self;
+        loop {
+            let Some(&byte) = key.first() else {
+                return &mut node.value;
+            };
+            match node.find(byte) {
+                Err(pos) => {
+                    // Only the suffix not shared with existing keys is copied.
+                    let label = key_alloc.alloc_slice_copy(key);
+                    node.children.insert(pos, Node::new(label));
+                    return &mut node.children[pos].value;
+                }
+                Ok(pos) => {
+                    let child = &mut node.children[pos];
+                    let common = common_prefix(child.label, key);
+                    if common < child.label.len() {
+                        let label = child.label;
+                        let mut tail = mem::replace(child, Node::new(&label[..common]));
+                        tail.label = &label[common..];
+                        child.children.push(tail);
+                    }
+                    key = &key[common..];
+                    node = child;
+                }
+            }
+        }
+    }
+
+    fn for_each<'b>(&'b self, key: &mut Vec<u8>, f: &mut impl FnMut(&[u8], &'b T)) {
+        key.extend_from_slice(self.label);
+        if let Some(value) = &self.value {
+            f(key, value);
+        }
+        for child in &self.children {
+            child.for_each(key, f);
+        }
+        key.truncate(key.len() - self.label.len());
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// A map backed by a path-compressed radix tree instead of a hash table, for
+/// long keys sharing long prefixes such as URLs and paths.
+///
+/// Keys are never hashed, and a shared prefix is stored once in the arena:
+/// inserting a key only copies the bytes past its longest common prefix with
+/// the keys already present.
+///
+/// It is a map of its own rather than a large-key tier of [`StringMap`],
+/// since its keys aren't stored contiguously, while the tiers hand out every
+/// key as a single slice, e.g. when iterating. It is created by
+/// [`StringMapBuilder::build_radix`] for such workloads instead.
+///
+/// [`StringMap`]: crate::StringMap
+/// [`StringMapBuilder::build_radix`]: crate::StringMapBuilder::build_radix
+pub struct RadixStringMap<'a, T> {
+    root: Node<'a, T>,
+    len: usize,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T> RadixStringMap<'a, T> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        RadixStringMap {
+            root: Node::new(&[]),
+            len: 0,
+            key_alloc,
+        }
+    }
+
+    pub fn key_alloc(&self) -> &'a Bump {
+        self.key_alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, mut key: &[u8]) -> Option<&Node<'a, T>> {
+        let mut node = &self.root;
+        loop {
+            let Some(&byte) = key.first() else {
+                return Some(node);
+            };
+            node = &node.children[node.find(byte).ok()?];
+            key = key.strip_prefix(node.label)?;
+        }
+    }
+
+    fn node_mut(&mut self, mut key: &[u8]) -> Option<&mut Node<'a, T>> {
+        let mut node = &mut self.root;
+        loop {
+            let Some(&byte) = key.first() else {
+                return Some(node);
+            };
+            let pos = node.find(byte).ok()?;
+            node = &mut node.children[pos];
+            key = key.strip_prefix(node.label)?;
+        }
+    }
+
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        self.node(key.as_key_bytes())?.value.as_ref()
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        self.node_mut(key.as_key_bytes())?.value.as_mut()
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let old = self
+            .root
+            .slot(self.key_alloc, key.as_key_bytes())
+            .replace(value);
+        self.len += old.is_none() as usize;
+        old
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let slot = self.root.slot(self.key_alloc, key.as_key_bytes());
+        let inserted = slot.is_none();
+        self.len += inserted as usize;
+        (slot.get_or_insert_with(value), inserted)
+    }
+
+    /// Removes `key` from the map.
+    ///
+    /// The nodes on its path are kept, since the arena can't reclaim their
+    /// labels anyway.
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let old = self.node_mut(key.as_key_bytes())?.value.take();
+        self.len -= old.is_some() as usize;
+        old
+    }
+
+    /// Calls `f` on every entry in key order.
+    ///
+    /// Keys are not stored contiguously, so they are assembled into a buffer
+    /// only valid during each call.
+    pub fn for_each<'b>(&'b self, mut f: impl FnMut(&[u8], &'b T)) {
+        self.root.for_each(&mut Vec::new(), &mut f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::testing::differential;
+    use crate::StringMapBuilder;
+
+    #[test]
+    fn test_radix_map() {
+        let bump = Bump::new();
+        let map = StringMapBuilder::new().build_radix(&bump);
+
+        let paths = [
+            "",
+            "https://example.com/",
+            "https://example.org/a/",
+            "/usr/lib/",
+        ];
+        let key = |_| {
+            let path = paths[rand::random::<usize>() % paths.len()];
+            format!("{path}{}", rand::random::<u16>() % 2000)
+        };
+        let (map, cmp) = differential(map, 10000, key).into_parts();
+        assert_eq!(map.get("https://example.com"), None);
+
+        let mut entries = Vec::new();
+        map.for_each(|key, value| entries.push((key.to_vec(), *value)));
+        assert!(entries
+            .into_iter()
+            .eq(cmp.into_iter().collect::<BTreeMap<_, _>>()));
+    }
+}
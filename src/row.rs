@@ -0,0 +1,136 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, KeyRef, StringMap};
+
+/// A tiered map whose values are opaque byte rows of a size fixed at
+/// construction.
+///
+/// Rows are allocated zero-initialized in the key arena, which makes the map
+/// usable as a group hash table for aggregate states only known at runtime.
+pub struct RowStringMap<'a, S = RandomState> {
+    map: StringMap<'a, &'a mut [u8], S>,
+    row_size: usize,
+}
+
+impl<'a, S> RowStringMap<'a, S> {
+    pub fn with_hasher(row_size: usize, hasher: S, key_alloc: &'a Bump) -> Self {
+        RowStringMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            row_size,
+        }
+    }
+
+    pub fn row_size(&self) -> usize {
+        self.row_size
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (KeyRef<'_>, &[u8])> {
+        self.map.iter().map(|(key, row)| (key, &**row))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (KeyRef<'_>, &mut [u8])> + use<'_, 'a, S> {
+        self.map.iter_mut().map(|(key, row)| (key, &mut **row))
+    }
+}
+
+impl<'a> RowStringMap<'a, RandomState> {
+    pub fn new(row_size: usize, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(row_size, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, S: BuildHasher> RowStringMap<'a, S> {
+    pub fn get_hashed<Q: Equivalent>(&self, key: Q, hash: u64) -> Option<&[u8]> {
+        self.map.get_hashed(key, hash).map(|row| &**row)
+    }
+
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&[u8]> {
+        self.map.get(key).map(|row| &**row)
+    }
+
+    pub fn get_mut_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> Option<&mut [u8]> {
+        self.map.get_mut_hashed(key, hash).map(|row| &mut **row)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut [u8]> {
+        self.map.get_mut(key).map(|row| &mut **row)
+    }
+
+    /// Returns the row of `key`, inserting a zeroed one if it is absent.
+    ///
+    /// The returned flag tells whether the row was newly inserted, so that
+    /// callers can initialize their aggregate states.
+    pub fn get_or_insert_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> (&mut [u8], bool) {
+        let (key_alloc, row_size) = (self.map.key_alloc(), self.row_size);
+        let (row, inserted) = self
+            .map
+            .get_or_insert_with_hashed(key, hash, || key_alloc.alloc_slice_fill_copy(row_size, 0));
+        (row, inserted)
+    }
+
+    pub fn get_or_insert<Q: Equivalent>(&mut self, key: Q) -> (&mut [u8], bool) {
+        let key = key.as_key_ref();
+        self.get_or_insert_hashed(key, self.map.hasher().hash_one(key))
+    }
+
+    pub fn remove_hashed<Q: Equivalent>(&mut self, key: Q, hash: u64) -> Option<&'a mut [u8]> {
+        self.map.remove_hashed(key, hash)
+    }
+
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<&'a mut [u8]> {
+        self.map.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_row_map() {
+        let bump = Bump::new();
+        let mut map = RowStringMap::new(12, &bump);
+        let mut cmp = HashMap::new();
+
+        for _ in 0..10000 {
+            let key = rand::random::<[u8; 2]>();
+            let len = rand::random::<usize>() % 30;
+            let key = &key.repeat(len / 2 + 1)[..len];
+
+            let (row, inserted) = map.get_or_insert(KeyRef::from(key));
+            assert_eq!(row.len(), 12);
+            assert_eq!(inserted, !cmp.contains_key(key));
+
+            let count = u32::from_ne_bytes(row[..4].try_into().unwrap()) + 1;
+            row[..4].copy_from_slice(&count.to_ne_bytes());
+
+            *cmp.entry(key.to_vec()).or_insert(0u32) += 1;
+        }
+        assert_eq!(map.len(), cmp.len());
+
+        for (key, row) in map.iter() {
+            let count = u32::from_ne_bytes(row[..4].try_into().unwrap());
+            assert_eq!(cmp.remove(key.key()), Some(count));
+            assert!(row[4..].iter().all(|&b| b == 0));
+        }
+        assert!(cmp.is_empty());
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::common::{SlotData, StringMap};
+use crate::{
+    ChainedStringMap, CuckooStringMap, HopscotchStringMap, RadixStringMap, StrMap, TieredStringMap,
+    Tiers,
+};
+
+/// A map that [`ShadowMap`] can mirror: the operations every map of the crate
+/// shares, on byte keys.
+pub trait Shadowed {
+    type Value;
+
+    fn len(&self) -> usize;
+
+    #[must_use]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&Self::Value>;
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut Self::Value>;
+
+    fn insert(&mut self, key: &[u8], value: Self::Value) -> Option<Self::Value>;
+
+    fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        value: impl FnOnce() -> Self::Value,
+    ) -> (&Self::Value, bool);
+
+    fn remove(&mut self, key: &[u8]) -> Option<Self::Value>;
+
+    /// Calls `f` on every entry.
+    fn for_each(&self, f: impl FnMut(&[u8], &Self::Value));
+
+    /// Removes the entries for which `f` returns `false`, by default through
+    /// [`remove`](Self::remove).
+    fn retain(&mut self, mut f: impl FnMut(&[u8], &Self::Value) -> bool) {
+        let mut removed = Vec::new();
+        self.for_each(|key, value| {
+            if !f(key, value) {
+                removed.push(key.to_vec());
+            }
+        });
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.retain(|_, _| false);
+    }
+
+    /// Panics if an internal invariant is broken. Does nothing by default.
+    fn validate(&self) {}
+}
+
+/// A raw table of `D` slots along with the hasher its operations take, so
+/// that [`ShadowMap`] can mirror custom [`SlotData`] implementations.
+pub struct RawTable<'a, D: SlotData<'a>, S = RandomState> {
+    map: StringMap<'a, D>,
+    hasher: S,
+}
+
+impl<'a, D: SlotData<'a>, S> RawTable<'a, D, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        RawTable {
+            map: StringMap::new(key_alloc),
+            hasher,
+        }
+    }
+
+    pub fn inner(&self) -> &StringMap<'a, D> {
+        &self.map
+    }
+}
+
+impl<'a, D: SlotData<'a>> RawTable<'a, D, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, D: SlotData<'a>, S: BuildHasher> Shadowed for RawTable<'a, D, S> {
+    type Value = D::Value;
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&D::Value> {
+        self.map.get(key, self.hasher.hash_one(key))
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut D::Value> {
+        self.map.get_mut(key, self.hasher.hash_one(key))
+    }
+
+    fn insert(&mut self, key: &[u8], value: D::Value) -> Option<D::Value> {
+        let hash = self.hasher.hash_one(key);
+        self.map.insert(key, hash, value, &self.hasher)
+    }
+
+    fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        value: impl FnOnce() -> D::Value,
+    ) -> (&D::Value, bool) {
+        let hash = self.hasher.hash_one(key);
+        let (value, inserted) = self.map.get_or_insert_with(key, hash, value, &self.hasher);
+        (value, inserted)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<D::Value> {
+        let hash = self.hasher.hash_one(key);
+        self.map.remove(key, hash, &self.hasher)
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &D::Value)) {
+        self.map.iter().for_each(|(key, value)| f(key, value));
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&[u8], &D::Value) -> bool) {
+        self.map.retain(|key, value| f(key, value));
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    fn validate(&self) {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        self.map.validate(&self.hasher);
+    }
+}
+
+macro_rules! impl_shadowed {
+    ($($map:ident),*) => {$(
+        impl<T, S: BuildHasher> Shadowed for $map<'_, T, S> {
+            type Value = T;
+
+            fn len(&self) -> usize {
+                self.len()
+            }
+
+            fn get(&self, key: &[u8]) -> Option<&T> {
+                self.get(key)
+            }
+
+            fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+                self.get_mut(key)
+            }
+
+            fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+                self.insert(key, value)
+            }
+
+            fn get_or_insert_with(
+                &mut self,
+                key: &[u8],
+                value: impl FnOnce() -> T,
+            ) -> (&T, bool) {
+                let (value, inserted) = self.get_or_insert_with(key, value);
+                (value, inserted)
+            }
+
+            fn remove(&mut self, key: &[u8]) -> Option<T> {
+                self.remove(key)
+            }
+
+            fn for_each(&self, mut f: impl FnMut(&[u8], &T)) {
+                self.iter().for_each(|(key, value)| f(key, value));
+            }
+        }
+    )*};
+}
+
+impl_shadowed!(ChainedStringMap, CuckooStringMap);
+
+impl<T, S: BuildHasher> Shadowed for HopscotchStringMap<'_, T, S> {
+    type Value = T;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&T> {
+        self.get(key)
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+        self.get_mut(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+        self.insert(key, value)
+    }
+
+    fn get_or_insert_with(&mut self, key: &[u8], value: impl FnOnce() -> T) -> (&T, bool) {
+        let (value, inserted) = self.get_or_insert_with(key, value);
+        (value, inserted)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<T> {
+        self.remove(key)
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &T)) {
+        self.iter().for_each(|(key, value)| f(key, value));
+    }
+
+    fn validate(&self) {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        self.validate();
+    }
+}
+
+impl<'a, T: 'a, L: Tiers, S: BuildHasher> Shadowed for TieredStringMap<'a, T, L, S> {
+    type Value = T;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&T> {
+        self.get(key)
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+        self.get_mut(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+        self.insert(key, value)
+    }
+
+    fn get_or_insert_with(&mut self, key: &[u8], value: impl FnOnce() -> T) -> (&T, bool) {
+        let (value, inserted) = self.get_or_insert_with(key, value);
+        (value, inserted)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<T> {
+        self.remove(key)
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &T)) {
+        self.iter().for_each(|(key, value)| f(key, value));
+    }
+
+    fn clear(&mut self) {
+        self.clear();
+    }
+
+    fn validate(&self) {
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        self.validate();
+    }
+}
+
+impl<T> Shadowed for RadixStringMap<'_, T> {
+    type Value = T;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&T> {
+        self.get(key)
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+        self.get_mut(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+        self.insert(key, value)
+    }
+
+    fn get_or_insert_with(&mut self, key: &[u8], value: impl FnOnce() -> T) -> (&T, bool) {
+        let (value, inserted) = self.get_or_insert_with(key, value);
+        (value, inserted)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<T> {
+        self.remove(key)
+    }
+
+    fn for_each(&self, f: impl FnMut(&[u8], &T)) {
+        self.for_each(f);
+    }
+}
+
+/// Mirrors a [`StrMap`]; the keys must be valid UTF-8.
+impl<T, S: BuildHasher> Shadowed for StrMap<'_, T, S> {
+    type Value = T;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<&T> {
+        self.get(std::str::from_utf8(key).unwrap())
+    }
+
+    fn get_mut(&mut self, key: &[u8]) -> Option<&mut T> {
+        self.get_mut(std::str::from_utf8(key).unwrap())
+    }
+
+    fn insert(&mut self, key: &[u8], value: T) -> Option<T> {
+        self.insert(std::str::from_utf8(key).unwrap(), value)
+    }
+
+    fn get_or_insert_with(&mut self, key: &[u8], value: impl FnOnce() -> T) -> (&T, bool) {
+        let key = std::str::from_utf8(key).unwrap();
+        let (value, inserted) = self.get_or_insert_with(key, value);
+        (value, inserted)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<T> {
+        self.remove(std::str::from_utf8(key).unwrap())
+    }
+
+    fn for_each(&self, mut f: impl FnMut(&[u8], &T)) {
+        self.iter()
+            .for_each(|(key, value)| f(key.as_bytes(), value));
+    }
+
+    fn clear(&mut self) {
+        self.clear();
+    }
+}
+
+/// A map mirrored by a std `HashMap`, asserting that both agree on the result
+/// of every operation, so that tests and fuzzers can exercise any map of the
+/// crate, custom [`SlotData`] implementations or new tiers against a trusted
+/// model.
+///
+/// Each operation only compares its own results; [`check`](Self::check)
+/// compares the whole contents, which is linear in the length.
+pub struct ShadowMap<M: Shadowed> {
+    map: M,
+    shadow: HashMap<Vec<u8>, M::Value>,
+}
+
+impl<M: Shadowed> ShadowMap<M> {
+    /// Mirrors `map`, which must be empty.
+    pub fn new(map: M) -> Self {
+        assert_eq!(map.len(), 0, "the map to mirror must be empty");
+        ShadowMap {
+            map,
+            shadow: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shadow.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.shadow.is_empty()
+    }
+
+    /// Returns the map under test.
+    pub fn inner(&self) -> &M {
+        &self.map
+    }
+
+    /// Returns the model the map is checked against.
+    pub fn shadow(&self) -> &HashMap<Vec<u8>, M::Value> {
+        &self.shadow
+    }
+
+    /// Returns the map under test and its model.
+    pub fn into_parts(self) -> (M, HashMap<Vec<u8>, M::Value>) {
+        (self.map, self.shadow)
+    }
+}
+
+impl<M> ShadowMap<M>
+where
+    M: Shadowed,
+    M::Value: Clone + PartialEq + Debug,
+{
+    pub fn get(&self, key: &[u8]) -> Option<&M::Value> {
+        let value = self.map.get(key);
+        assert_eq!(value, self.shadow.get(key), "get({key:?})");
+        value
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: M::Value) -> Option<M::Value> {
+        let old = self.map.insert(key, value.clone());
+        assert_eq!(
+            old,
+            self.shadow.insert(key.to_vec(), value),
+            "insert({key:?})"
+        );
+        old
+    }
+
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &[u8],
+        value: impl FnOnce() -> M::Value,
+    ) -> (&M::Value, bool) {
+        let expected = !self.shadow.contains_key(key);
+        let (value, inserted) = self.map.get_or_insert_with(key, value);
+        assert_eq!(inserted, expected, "get_or_insert_with({key:?})");
+        let shadow = self
+            .shadow
+            .entry(key.to_vec())
+            .or_insert_with(|| value.clone());
+        assert_eq!(value, shadow, "get_or_insert_with({key:?})");
+        (value, inserted)
+    }
+
+    /// Applies `f` to the value of `key` in both maps, returning whether the
+    /// key was present.
+    pub fn update(&mut self, key: &[u8], mut f: impl FnMut(&mut M::Value)) -> bool {
+        let value = self.map.get_mut(key);
+        let shadow = self.shadow.get_mut(key);
+        assert_eq!(value.is_some(), shadow.is_some(), "update({key:?})");
+        let (Some(value), Some(shadow)) = (value, shadow) else {
+            return false;
+        };
+        f(value);
+        f(shadow);
+        assert_eq!(value, shadow, "update({key:?})");
+        true
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<M::Value> {
+        let value = self.map.remove(key);
+        assert_eq!(value, self.shadow.remove(key), "remove({key:?})");
+        value
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&[u8], &M::Value) -> bool) {
+        self.map.retain(|key, value| f(key, value));
+        self.shadow.retain(|key, value| f(key, value));
+        assert_eq!(self.map.len(), self.shadow.len(), "retain");
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.shadow.clear();
+        assert_eq!(self.map.len(), 0, "clear");
+    }
+
+    /// Asserts that both maps hold the same entries: the map must yield every
+    /// key of the model exactly once, with an equal value, and agree on the
+    /// length. The map must also pass its own validation, if enabled.
+    pub fn check(&self) {
+        assert_eq!(self.map.len(), self.shadow.len(), "len");
+        let mut seen = HashMap::with_capacity(self.shadow.len());
+        self.map.for_each(|key, value| {
+            assert!(
+                seen.insert(key.to_vec(), ()).is_none(),
+                "{key:?} yielded twice"
+            );
+            assert_eq!(Some(value), self.shadow.get(key), "iter at {key:?}");
+        });
+        assert_eq!(seen.len(), self.shadow.len(), "iter");
+        self.map.validate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::differential;
+    use crate::{large, small};
+
+    fn exercise<'a, D: SlotData<'a, Value = usize>>(table: RawTable<'a, D>, repeat: usize) {
+        let key = |_| (rand::random::<u16>() % 2000).to_string().repeat(repeat);
+        let mut map = differential(table, 20000, key);
+        map.retain(|_, value| value % 3 != 0);
+        map.check();
+        map.clear();
+        map.check();
+    }
+
+    #[test]
+    fn test_shadow_map() {
+        let bump = Bump::new();
+        exercise(RawTable::<small::SlotData<usize, 16>>::new(&bump), 2);
+        exercise(RawTable::<large::SlotData<usize>>::new(&bump), 20);
+    }
+}
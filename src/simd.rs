@@ -0,0 +1,253 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+#[cfg(not(target_arch = "wasm32"))]
+use std::simd::cmp::SimdPartialOrd;
+#[cfg(not(target_arch = "wasm32"))]
+use std::simd::Select;
+#[cfg(not(target_arch = "wasm32"))]
+use std::simd::{u64x4, Simd};
+
+use crate::adaptive::Reseed;
+
+#[cfg(not(target_arch = "wasm32"))]
+const LANES: usize = 4;
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_SIMD_LEN: usize = 24;
+const K0: u64 = 0x9e37_79b9_7f4a_7c15;
+const K1: u64 = 0xff51_afd7_ed55_8ccd;
+
+#[inline]
+fn mix(h: u64, word: u64) -> u64 {
+    let x = (h ^ word).wrapping_mul(K0);
+    x ^ (x >> 29)
+}
+
+#[inline]
+fn finish(h: u64) -> u64 {
+    let x = (h ^ (h >> 32)).wrapping_mul(K1);
+    x ^ (x >> 33)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[inline]
+fn mix_x4(h: u64x4, word: u64x4) -> u64x4 {
+    let x = (h ^ word) * u64x4::splat(K0);
+    x ^ (x >> u64x4::splat(29))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[inline]
+fn finish_x4(h: u64x4) -> u64x4 {
+    let x = (h ^ (h >> u64x4::splat(32))) * u64x4::splat(K1);
+    x ^ (x >> u64x4::splat(33))
+}
+
+#[inline]
+fn word(key: &[u8], index: usize) -> u64 {
+    let mut word = [0; 8];
+    if let Some(chunk) = key.get(index * 8..) {
+        let len = chunk.len().min(8);
+        word[..len].copy_from_slice(&chunk[..len]);
+    }
+    u64::from_le_bytes(word)
+}
+
+/// A word-at-a-time hasher whose results can also be computed for batches of
+/// short keys with SIMD, see [`BatchState::hash_batch`].
+///
+/// It is not meant to resist collision flooding as SipHash does.
+#[derive(Debug, Clone)]
+pub struct BatchHasher {
+    state: u64,
+}
+
+impl Hasher for BatchHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for index in 0..bytes.len().div_ceil(8) {
+            self.state = mix(self.state, word(bytes, index));
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.state = mix(self.state, i);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        finish(self.state)
+    }
+}
+
+/// The [`BuildHasher`] of [`BatchHasher`]s.
+#[derive(Debug, Clone)]
+pub struct BatchState {
+    seed: u64,
+}
+
+impl BatchState {
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().hash_one(K0))
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        BatchState { seed }
+    }
+
+    /// Creates a state with a fixed seed, hashing identically across runs
+    /// and platforms, e.g. for reproducible tests or targets without a source
+    /// of randomness.
+    pub fn deterministic() -> Self {
+        Self::with_seed(K0)
+    }
+
+    /// Computes the hashes of `keys` into `hashes`, identical to hashing the
+    /// corresponding `KeyRef`s one by one.
+    ///
+    /// Keys of the small tiers are hashed four at a time, except on wasm32
+    /// where portable SIMD is not used.
+    pub fn hash_batch(&self, keys: &[&[u8]], hashes: &mut [u64]) {
+        assert_eq!(keys.len(), hashes.len());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let (keys, hashes) = self.hash_lanes(keys, hashes);
+        for (key, hash) in keys.iter().zip(hashes) {
+            *hash = self.hash_one(crate::KeyRef::from(*key));
+        }
+    }
+
+    /// Hashes the keys `LANES` at a time, returning the remainders.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn hash_lanes<'k, 'h>(
+        &self,
+        keys: &'k [&'k [u8]],
+        hashes: &'h mut [u64],
+    ) -> (&'k [&'k [u8]], &'h mut [u64]) {
+        let mut keys = keys.chunks_exact(LANES);
+        let mut hashes = hashes.chunks_exact_mut(LANES);
+        for (keys, hashes) in (&mut keys).zip(&mut hashes) {
+            if keys.iter().all(|key| key.len() <= MAX_SIMD_LEN) {
+                let mut words = [[0; MAX_SIMD_LEN / 8]; LANES];
+                for (words, key) in words.iter_mut().zip(keys) {
+                    let mut buf = [0; MAX_SIMD_LEN];
+                    buf[..key.len()].copy_from_slice(key);
+                    for (word, chunk) in words.iter_mut().zip(buf.chunks_exact(8)) {
+                        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+                    }
+                }
+
+                let lens = u64x4::from_array([0, 1, 2, 3].map(|i| keys[i].len() as u64));
+                let mut h = mix_x4(u64x4::splat(self.seed), lens);
+                for index in 0..MAX_SIMD_LEN / 8 {
+                    let words = u64x4::from_array([0, 1, 2, 3].map(|i| words[i][index]));
+                    let active = lens.simd_gt(Simd::splat(index as u64 * 8));
+                    h = active.select(mix_x4(h, words), h);
+                }
+                hashes.copy_from_slice(finish_x4(h).as_array());
+            } else {
+                for (key, hash) in keys.iter().zip(hashes) {
+                    *hash = self.hash_one(crate::KeyRef::from(*key));
+                }
+            }
+        }
+        (keys.remainder(), hashes.into_remainder())
+    }
+}
+
+impl Reseed for BatchState {
+    fn reseed(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for BatchState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildHasher for BatchState {
+    type Hasher = BatchHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        BatchHasher { state: self.seed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::{KeyRef, StringMap};
+
+    #[test]
+    fn test_hash_batch() {
+        let state = BatchState::new();
+
+        let keys = (0..1003)
+            .map(|i| {
+                let len = if i % 97 == 0 { 40 } else { i % 25 };
+                (0..len).map(|_| rand::random()).collect::<Vec<u8>>()
+            })
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| &**key).collect::<Vec<_>>();
+
+        let mut hashes = vec![0; keys.len()];
+        state.hash_batch(&keys, &mut hashes);
+        for (key, hash) in keys.iter().zip(&hashes) {
+            assert_eq!(state.hash_one(KeyRef::from(*key)), *hash);
+        }
+
+        let bump = Bump::new();
+        let mut map = StringMap::with_hasher(state, &bump);
+        for (i, (key, hash)) in keys.iter().zip(&hashes).enumerate() {
+            map.insert_hashed(KeyRef::from(*key), *hash, i);
+        }
+        for key in &keys {
+            assert!(map.get(KeyRef::from(*key)).is_some());
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let (a, b) = (BatchState::deterministic(), BatchState::deterministic());
+        for key in [&b""[..], b"short", &[7; 100]] {
+            assert_eq!(a.hash_one(KeyRef::from(key)), b.hash_one(KeyRef::from(key)));
+        }
+    }
+
+    fn gen_short_keys() -> Vec<Vec<u8>> {
+        (0..10000)
+            .map(|_| {
+                let len = rand::random::<usize>() % 25;
+                (0..len).map(|_| rand::random()).collect()
+            })
+            .collect()
+    }
+
+    #[bench]
+    fn bench_hash_batch(bencher: &mut test::Bencher) {
+        let state = BatchState::new();
+        let keys = gen_short_keys();
+        let keys = keys.iter().map(|key| &**key).collect::<Vec<_>>();
+        let mut hashes = vec![0; keys.len()];
+
+        bencher.iter(|| state.hash_batch(&keys, &mut hashes))
+    }
+
+    #[bench]
+    fn bench_hash_scalar(bencher: &mut test::Bencher) {
+        let state = BatchState::new();
+        let keys = gen_short_keys();
+        let mut hashes = vec![0; keys.len()];
+
+        bencher.iter(|| {
+            for (key, hash) in keys.iter().zip(&mut hashes) {
+                *hash = state.hash_one(KeyRef::from(&**key));
+            }
+        })
+    }
+}
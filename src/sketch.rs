@@ -0,0 +1,262 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{CardinalityEstimate, Equivalent, Iter, KeyRef, StringMap};
+
+/// A HyperLogLog sketch of the distinct items of a group, whose registers
+/// are allocated in an arena, e.g. the key arena of the map holding it.
+///
+/// Items are added by their 64-bit hashes, which must be well mixed. With
+/// `2^p` registers, the standard error of the estimate is about
+/// `1.04 / sqrt(2^p)`, e.g. 1.6% for the default precision of 12, taking
+/// 4 KiB per sketch.
+pub struct DistinctSketch<'a> {
+    registers: &'a mut [u8],
+}
+
+impl<'a> DistinctSketch<'a> {
+    /// Creates an empty sketch of `2^precision` registers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `precision` is not within `4..=18`.
+    pub fn new_in(precision: u8, alloc: &'a Bump) -> Self {
+        assert!((4..=18).contains(&precision), "Precision out of range");
+        DistinctSketch {
+            registers: alloc.alloc_slice_fill_copy(1 << precision, 0),
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.registers.len().trailing_zeros() as u8
+    }
+
+    /// Adds the item of `hash`.
+    pub fn add(&mut self, hash: u64) {
+        let precision = self.precision() as u32;
+        let index = (hash >> (64 - precision)) as usize;
+        // The position of the first set bit of the rest of the hash, counting
+        // from 1, capped for a rest of zeros.
+        let rank = ((hash << precision).leading_zeros()).min(64 - precision) + 1;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank as u8);
+    }
+
+    /// Merges the items of `other` into the sketch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sketches have different precisions.
+    pub fn merge(&mut self, other: &DistinctSketch<'_>) {
+        assert_eq!(
+            self.registers.len(),
+            other.registers.len(),
+            "Precisions differ"
+        );
+        for (register, other) in self.registers.iter_mut().zip(&*other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Returns the estimated number of distinct items added.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum = (self.registers.iter())
+            .map(|&register| f64::powi(2., -(register as i32)))
+            .sum::<f64>();
+        let raw = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        // Linear counting is more accurate for small cardinalities.
+        match raw <= 2.5 * m && zeros > 0 {
+            true => m * (m / zeros as f64).ln(),
+            false => raw,
+        }
+    }
+}
+
+impl CardinalityEstimate for DistinctSketch<'_> {
+    fn estimate(&self) -> usize {
+        DistinctSketch::estimate(self).round() as usize
+    }
+}
+
+const DEFAULT_PRECISION: u8 = 12;
+
+/// A tiered map of [`DistinctSketch`]es, approximating `COUNT(DISTINCT)` per
+/// group.
+///
+/// The registers of the sketches are allocated in the key arena of the map,
+/// and live as long as the keys.
+pub struct DistinctCounts<'a, S = RandomState> {
+    map: StringMap<'a, DistinctSketch<'a>, S>,
+    precision: u8,
+}
+
+impl<'a, S> DistinctCounts<'a, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        DistinctCounts {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            precision: DEFAULT_PRECISION,
+        }
+    }
+
+    /// Sets the precision of the sketches created from now on, see
+    /// [`DistinctSketch::new_in`].
+    pub fn precision(mut self, precision: u8) -> Self {
+        assert!((4..=18).contains(&precision), "Precision out of range");
+        self.precision = precision;
+        self
+    }
+
+    /// Returns the number of groups.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'a, DistinctSketch<'a>> {
+        self.map.iter()
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, DistinctSketch<'a>, S> {
+        self.map
+    }
+}
+
+impl<'a> DistinctCounts<'a, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, S: BuildHasher> DistinctCounts<'a, S> {
+    /// Returns the sketch of `key`, creating an empty one if absent.
+    fn sketch_mut(&mut self, key: KeyRef) -> &mut DistinctSketch<'a> {
+        let (precision, key_alloc) = (self.precision, self.map.key_alloc());
+        let (sketch, _) = self
+            .map
+            .get_or_insert_with(key, || DistinctSketch::new_in(precision, key_alloc));
+        sketch
+    }
+
+    /// Adds the item of `item_hash` to the group of `key`.
+    pub fn add<Q: Equivalent>(&mut self, key: Q, item_hash: u64) {
+        self.sketch_mut(key.as_key_ref()).add(item_hash);
+    }
+
+    /// Adds the items of `item_hashes` to the groups of `keys`.
+    pub fn add_batch(&mut self, keys: &[&[u8]], item_hashes: &[u64]) {
+        let (precision, key_alloc) = (self.precision, self.map.key_alloc());
+        self.map.accumulate(
+            keys,
+            item_hashes,
+            |&hash| {
+                let mut sketch = DistinctSketch::new_in(precision, key_alloc);
+                sketch.add(hash);
+                sketch
+            },
+            |sketch, &hash| sketch.add(hash),
+        );
+    }
+
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&DistinctSketch<'a>> {
+        self.map.get(key)
+    }
+
+    /// Returns the estimated number of distinct items of the group of `key`,
+    /// which is zero if it was never added to.
+    pub fn estimate<Q: Equivalent>(&self, key: Q) -> f64 {
+        self.map.get(key).map_or(0., DistinctSketch::estimate)
+    }
+
+    /// Merges the sketches of `other`, e.g. computed on another partition,
+    /// into those of the same groups, copying them into the arena of the map.
+    pub fn merge<S2>(&mut self, other: &DistinctCounts<'_, S2>) {
+        for (key, sketch) in other.iter() {
+            self.sketch_mut(key).merge(sketch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    use super::*;
+
+    #[test]
+    fn test_distinct_counts() {
+        // Fixed keys, so that the estimates don't vary from run to run.
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let groups = [1usize, 10, 1000, 30000];
+        let rows = groups
+            .iter()
+            .flat_map(|&n| (0..n * 2).map(move |i| (n.to_string(), i % n)))
+            .collect::<Vec<_>>();
+
+        let bump = Bump::new();
+        let mut counts = DistinctCounts::new(&bump);
+        let mut left = DistinctCounts::new(&bump).precision(10);
+        let mut right = DistinctCounts::new(&bump).precision(10);
+        for (i, (key, item)) in rows.iter().enumerate() {
+            let hash = hasher.hash_one(item);
+            counts.add(key, hash);
+            match i % 2 {
+                0 => left.add(key, hash),
+                _ => right.add(key, hash),
+            }
+        }
+        left.merge(&right);
+
+        let keys = rows
+            .iter()
+            .map(|(key, _)| key.as_bytes())
+            .collect::<Vec<_>>();
+        let hashes = rows
+            .iter()
+            .map(|(_, item)| hasher.hash_one(item))
+            .collect::<Vec<_>>();
+        let mut batch = DistinctCounts::new(&bump).precision(10);
+        batch.add_batch(&keys, &hashes);
+
+        assert_eq!(counts.len(), groups.len());
+        for n in groups {
+            let key = n.to_string();
+            let estimate = counts.estimate(&key);
+            assert!(
+                (estimate - n as f64).abs() <= n as f64 * 0.08,
+                "{estimate} for {n}"
+            );
+            let sketch = counts.get(&key).unwrap();
+            assert_eq!(
+                CardinalityEstimate::estimate(sketch),
+                estimate.round() as usize
+            );
+
+            let merged = left.estimate(&key);
+            assert!(
+                (merged - n as f64).abs() <= n as f64 * 0.15,
+                "{merged} for {n}"
+            );
+            assert_eq!(batch.estimate(&key), merged);
+        }
+        assert_eq!(counts.estimate("missing"), 0.);
+    }
+}
@@ -1,30 +1,72 @@
+use std::ptr;
+
 use bumpalo::Bump;
 
 use crate::common;
 
+/// The number of key bytes packed along with the length into the head word.
+const HEAD_BYTES: usize = 7;
+
+/// Packs the length and the first bytes of `key` into a word, as laid out at
+/// the start of a slot.
+#[inline]
+fn head_of(key: &[u8]) -> u64 {
+    let mut head = [0; 8];
+    let len = key.len().min(HEAD_BYTES);
+    head[0] = key.len() as u8;
+    head[1..][..len].copy_from_slice(&key[..len]);
+    u64::from_ne_bytes(head)
+}
+
+/// The slot data of a small tier, storing keys of up to `N` bytes inline.
+///
+/// The length takes a single byte, so that with zero-sized values, e.g. when
+/// the map is used as a set, a slot is only `N + 1` bytes, and with small
+/// values it is not padded to a word boundary. Zero-sized values need no code
+/// path of their own: they take no room in the slot, and moving them compiles
+/// to nothing. The iterators still yield a reference to each of them, which
+/// is free as well.
+///
+/// The length is followed by the key, so that they can be read as a single
+/// word holding the first 7 bytes of the key, which rejects most mismatches
+/// with one comparison.
+#[derive(Clone)]
+#[repr(C)]
 pub struct SlotData<T, const N: usize> {
+    len: u8,
     key: [u8; N],
-    len: usize,
     value: T,
 }
 
-impl<'a, T, const N: usize> common::SlotData<'a> for SlotData<T, N> {
+impl<T, const N: usize> SlotData<T, N> {
+    /// Returns the length and the first bytes of the key as packed by
+    /// [`head_of`].
+    #[inline]
+    fn head(&self) -> u64 {
+        if N >= HEAD_BYTES {
+            // SAFETY: The slot is `repr(C)`, so the length and at least
+            // `HEAD_BYTES` key bytes lie at its start, and the key bytes past
+            // the length are zeroed.
+            unsafe { ptr::read_unaligned(ptr::from_ref(self).cast::<u64>()) }
+        } else {
+            head_of(&self.key[..self.len as usize])
+        }
+    }
+}
+
+impl<T, const N: usize> common::SlotAccess for SlotData<T, N> {
     type Value = T;
 
     #[inline]
-    fn new(_: &'a Bump, key: &[u8], _hash: u64, value: Self::Value) -> Self {
-        let mut data = SlotData {
-            key: [0; N],
-            len: key.len(),
-            value,
-        };
-        data.key[..data.len].copy_from_slice(key);
-        data
+    fn key(&self) -> &[u8] {
+        &self.key[..self.len as usize]
     }
 
     #[inline]
-    fn key(&self) -> &[u8] {
-        &self.key[..self.len]
+    fn matches(&self, key: &[u8], _hash: u64) -> bool {
+        self.head() == head_of(key)
+            && (key.len() <= HEAD_BYTES
+                || self.key[HEAD_BYTES..self.len as usize] == key[HEAD_BYTES..])
     }
 
     #[inline]
@@ -32,6 +74,9 @@ impl<'a, T, const N: usize> common::SlotData<'a> for SlotData<T, N> {
         None
     }
 
+    #[inline]
+    fn set_hash(&mut self, _: u64) {}
+
     #[inline]
     fn value(&self) -> &Self::Value {
         &self.value
@@ -39,7 +84,21 @@ impl<'a, T, const N: usize> common::SlotData<'a> for SlotData<T, N> {
 
     #[inline]
     fn kv_mut(&mut self) -> (&[u8], &mut Self::Value) {
-        (&self.key[..self.len], &mut self.value)
+        (&self.key[..self.len as usize], &mut self.value)
+    }
+}
+
+impl<'a, T, const N: usize> common::SlotData<'a> for SlotData<T, N> {
+    #[inline]
+    fn new(_: &'a Bump, key: &[u8], _hash: u64, value: Self::Value) -> Self {
+        const { assert!(N <= u8::MAX as usize, "Inline keys too long") };
+        let mut data = SlotData {
+            key: [0; N],
+            len: key.len() as u8,
+            value,
+        };
+        data.key[..key.len()].copy_from_slice(key);
+        data
     }
 
     #[inline]
@@ -50,7 +109,7 @@ impl<'a, T, const N: usize> common::SlotData<'a> for SlotData<T, N> {
     #[inline]
     fn into_kv(self, key_alloc: &'a Bump) -> (&'a [u8], Self::Value) {
         (
-            key_alloc.alloc_slice_copy(&self.key[..self.len]),
+            key_alloc.alloc_slice_copy(&self.key[..self.len as usize]),
             self.value,
         )
     }
@@ -66,6 +125,17 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_slot_size() {
+        use std::mem::{align_of, size_of};
+
+        assert_eq!(size_of::<SlotData<(), 8>>(), 9);
+        assert_eq!(align_of::<SlotData<(), 24>>(), 1);
+        assert_eq!(size_of::<SlotData<(), 24>>(), 25);
+        assert_eq!(size_of::<SlotData<u32, 8>>(), 16);
+        assert_eq!(size_of::<SlotData<u64, 24>>(), 40);
+    }
+
     #[test]
     fn test_hash_map() {
         let bump = Bump::new();
@@ -124,4 +194,73 @@ mod tests {
         let a = map.insert(&key, hash, value, &hasher);
         assert_eq!(a, Some(value));
     }
+
+    #[test]
+    fn test_full_table() {
+        let bump = Bump::new();
+        let hasher = RandomState::default();
+
+        for cap in 0..4 {
+            let mut map = StringMap::<u64, 8>::with_capacity(cap, &bump);
+            for i in 0..100u64 {
+                let key = i.to_ne_bytes();
+                let hash = hasher.hash_one(key);
+                assert_eq!(map.try_insert(&key, hash, i, &hasher), None);
+            }
+            assert_eq!(map.len(), 100);
+        }
+
+        let mut map = StringMap::<u64, 8>::with_capacity(0, &bump);
+        let key = 0u64.to_ne_bytes();
+        let hash = hasher.hash_one(key);
+        assert!(map.get_or_insert_with(&key, hash, || 1, &hasher).1);
+    }
+
+    #[test]
+    fn test_head_word() {
+        let bump = Bump::new();
+        let mut map = StringMap::<usize, 16>::with_capacity(64, &bump);
+        let hasher = RandomState::default();
+
+        // Keys differing only in their length, or past the packed bytes.
+        let keys: [&[u8]; 8] = [
+            b"",
+            b"\0",
+            b"\0\0",
+            b"abcdefg",
+            b"abcdefg\0",
+            b"abcdefgh",
+            b"abcdefgi",
+            b"abcdefghijklmnop",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            // Share one probe chain, so that every key is compared with the
+            // others.
+            assert_eq!(map.insert(key, 0, i, &hasher), None);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.get(key, 0), Some(&i));
+        }
+        assert_eq!(map.get(b"abcdefgj", 0), None);
+        assert_eq!(map.get(b"\0\0\0", 0), None);
+    }
+
+    #[test]
+    fn test_deleted_slots() {
+        let bump = Bump::new();
+        let mut map = StringMap::<u64, 8>::new(&bump);
+        let hasher = RandomState::default();
+
+        // All keys share one hash, so they all live in the same probe chain.
+        for i in 0..4u64 {
+            assert_eq!(map.insert(&i.to_ne_bytes(), 0, i, &hasher), None);
+        }
+        assert_eq!(map.remove(&0u64.to_ne_bytes(), 0, &hasher), Some(0));
+
+        // Reinserting a key past the deleted slot must not duplicate it.
+        assert_eq!(map.insert(&3u64.to_ne_bytes(), 0, 30, &hasher), Some(3));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.remove(&3u64.to_ne_bytes(), 0, &hasher), Some(30));
+        assert_eq!(map.get(&3u64.to_ne_bytes(), 0), None);
+    }
 }
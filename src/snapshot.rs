@@ -0,0 +1,322 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, Iter, KeyRef, StringMap};
+
+/// The number of arenas from which the next version copies its large keys
+/// into a fresh one.
+const MAX_ARENAS: usize = 8;
+
+/// A key arena shared by the snapshots borrowing from it.
+struct Arena(Bump);
+
+impl Arena {
+    fn used_bytes(&self) -> usize {
+        self.0.allocated_bytes() - self.0.chunk_capacity()
+    }
+}
+
+// SAFETY: An arena is only allocated into by the builder that created it,
+// before it is shared through a snapshot; afterwards its keys are only read.
+unsafe impl Sync for Arena {}
+
+/// An immutable version of a map, along with the arenas of its keys.
+///
+/// Large keys are borrowed from the arenas of the versions they were first
+/// inserted in rather than copied again, so that the arenas are shared
+/// between successive versions. An arena is only freed along with the last
+/// version sharing it, so once most of the bytes of the arenas belong to keys
+/// that are gone, or once there are too many arenas, the next version copies
+/// its large keys into a fresh arena instead of sharing the old ones.
+pub struct Snapshot<T: 'static, S = RandomState> {
+    // Declared first so that it is dropped before the arenas it borrows.
+    map: StringMap<'static, T, S>,
+    arenas: Vec<Arc<Arena>>,
+}
+
+// SAFETY: Only the reference to its arena keeps the map from being `Send` and
+// `Sync`, since `Bump` isn't `Sync`. A snapshot never allocates into it, and
+// the arenas it borrows are kept alive by it; the lookups and iterators of the
+// map only read its tables, whose probe counters are atomic. The rest of the
+// map is as thread-safe as its values and hasher.
+unsafe impl<T: Send + 'static, S: Send> Send for Snapshot<T, S> {}
+unsafe impl<T: Sync + 'static, S: Sync> Sync for Snapshot<T, S> {}
+
+impl<T: 'static, S> Snapshot<T, S> {
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, 'static, T> {
+        self.map.iter()
+    }
+
+    /// Returns the number of arenas holding the keys of the snapshot.
+    pub fn arena_count(&self) -> usize {
+        self.arenas.len()
+    }
+}
+
+impl<T: 'static, S: BuildHasher> Snapshot<T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.map.contains_key(key)
+    }
+}
+
+impl<T: Clone + 'static, S: BuildHasher + Clone> Snapshot<T, S> {
+    /// Returns a builder starting from the entries of the snapshot.
+    ///
+    /// The values are cloned, but the large keys are borrowed from the
+    /// arenas of the snapshot, which the next version keeps alive, unless
+    /// they are mostly dead or too many.
+    pub fn to_builder(&self) -> SnapshotBuilder<T, S> {
+        let mut builder = SnapshotBuilder::with_hasher(self.hasher().clone());
+        let live = (self.iter())
+            .map(|(key, _)| match key {
+                KeyRef::Large(key) => key.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+        let used = self
+            .arenas
+            .iter()
+            .map(|arena| arena.used_bytes())
+            .sum::<usize>();
+        let share = self.arenas.len() < MAX_ARENAS && used - live <= live;
+        if share {
+            builder.arenas.splice(..0, self.arenas.iter().cloned());
+        }
+        for (key, value) in self.iter() {
+            match key {
+                // SAFETY: The key lives in one of the arenas, now also owned by
+                // the builder.
+                KeyRef::Large(key) if share => {
+                    let key = unsafe { &*(key as *const [u8]) };
+                    builder.map.insert_borrowed(key, value.clone());
+                }
+                key => {
+                    builder.map.insert(key, value.clone());
+                }
+            }
+        }
+        builder
+    }
+}
+
+/// The next version of a [`Snapshot`], built by a single writer.
+pub struct SnapshotBuilder<T: 'static, S = RandomState> {
+    map: StringMap<'static, T, S>,
+    /// The arenas of the keys, ending with the one new keys are copied into.
+    arenas: Vec<Arc<Arena>>,
+}
+
+impl<T: 'static> SnapshotBuilder<T, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<T: 'static> Default for SnapshotBuilder<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static, S> SnapshotBuilder<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        let arena = Arc::new(Arena(Bump::new()));
+        // SAFETY: The arena is kept alive by the builder and then by the
+        // snapshot, which both drop the map first.
+        let key_alloc = unsafe { &*(&arena.0 as *const Bump) };
+        SnapshotBuilder {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            arenas: vec![arena],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Freezes the builder into a snapshot.
+    pub fn build(self) -> Snapshot<T, S> {
+        Snapshot {
+            map: self.map,
+            arenas: self.arenas,
+        }
+    }
+}
+
+// The map is not handed out, since it would leak its arena with a `'static`
+// lifetime, e.g. through `key_alloc`.
+impl<T: 'static, S: BuildHasher> SnapshotBuilder<T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q) -> Option<&mut T> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Inserts `key`, copying it into the arena of the builder if it is new.
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T) -> Option<T> {
+        self.map.insert(key, value)
+    }
+
+    pub fn remove<Q: Equivalent>(&mut self, key: Q) -> Option<T> {
+        self.map.remove(key)
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&[u8], &mut T) -> bool) {
+        self.map.retain(f)
+    }
+}
+
+/// A map whose readers always see an immutable [`Snapshot`], swapped
+/// atomically while a writer builds the next version, e.g. for reference
+/// data refreshed periodically under lookups that must never block.
+pub struct SnapshotStringMap<T: 'static, S = RandomState> {
+    current: ArcSwap<Snapshot<T, S>>,
+}
+
+impl<T: 'static> SnapshotStringMap<T, RandomState> {
+    pub fn new() -> Self {
+        Self::from_snapshot(SnapshotBuilder::new().build())
+    }
+}
+
+impl<T: 'static> Default for SnapshotStringMap<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static, S> SnapshotStringMap<T, S> {
+    pub fn from_snapshot(snapshot: Snapshot<T, S>) -> Self {
+        SnapshotStringMap {
+            current: ArcSwap::from_pointee(snapshot),
+        }
+    }
+
+    /// Returns the current snapshot, without blocking or being blocked by the
+    /// writer. The guard is meant to be short-lived; use
+    /// [`load_full`](Self::load_full) to keep a snapshot around.
+    pub fn load(&self) -> Guard<Arc<Snapshot<T, S>>> {
+        self.current.load()
+    }
+
+    pub fn load_full(&self) -> Arc<Snapshot<T, S>> {
+        self.current.load_full()
+    }
+
+    /// Makes `snapshot` the current one. Readers holding the previous one
+    /// keep it, along with its arenas, until they drop it.
+    pub fn publish(&self, snapshot: Snapshot<T, S>) {
+        self.current.store(Arc::new(snapshot));
+    }
+}
+
+impl<T: Clone + 'static, S: BuildHasher + Clone> SnapshotStringMap<T, S> {
+    /// Builds the next version from the current one with `f`, and publishes
+    /// it.
+    ///
+    /// Updates are not serialized: with several writers, an update may be
+    /// lost to a concurrent one.
+    pub fn update(&self, f: impl FnOnce(&mut SnapshotBuilder<T, S>)) {
+        let mut builder = self.load().to_builder();
+        f(&mut builder);
+        self.publish(builder.build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshots() {
+        let map = SnapshotStringMap::new();
+        let mut cmp = HashMap::new();
+        let key = |i: u32| i.to_string().repeat(i as usize % 11);
+
+        let old = map.load_full();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for round in 0..10u32 {
+                    map.update(|next| {
+                        for i in round * 100..round * 100 + 200 {
+                            next.insert(KeyRef::from(key(i).as_bytes()), round);
+                        }
+                        next.remove(KeyRef::from(key(round).as_bytes()));
+                    });
+                }
+            });
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let snapshot = map.load();
+                        for (key, &round) in snapshot.iter() {
+                            assert_eq!(snapshot.get(key), Some(&round));
+                        }
+                    }
+                });
+            }
+        });
+        assert!(old.is_empty());
+
+        for round in 0..10u32 {
+            for i in round * 100..round * 100 + 200 {
+                cmp.insert(key(i), round);
+            }
+            cmp.remove(&key(round));
+        }
+        let snapshot = map.load();
+        assert_eq!(snapshot.len(), cmp.len());
+        for (key, value) in &cmp {
+            assert_eq!(snapshot.get(KeyRef::from(key.as_bytes())), Some(value));
+        }
+        assert!(snapshot.arena_count() <= MAX_ARENAS);
+
+        // Replaced keys don't keep their arenas alive for good.
+        let map = SnapshotStringMap::new();
+        for round in 0..100 {
+            map.update(|next| {
+                next.retain(|_, _| false);
+                for i in 0..100 {
+                    next.insert(KeyRef::from(format!("{round} {i:040}").as_bytes()), i);
+                }
+            });
+            assert!(map.load().arena_count() <= 3);
+        }
+        assert_eq!(map.load().len(), 100);
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::{Bound, RangeBounds};
+
+use crate::adaptive::StringMap;
+
+/// A sorted view of the keys of a [`StringMap`], built on demand for prefix
+/// and range scans over the same entries as point lookups.
+///
+/// The index borrows the map, so it can't go stale; rebuild it after the map
+/// is modified.
+#[derive(Debug, Clone)]
+pub struct SortedIndex<'b, T> {
+    entries: Vec<(&'b [u8], &'b T)>,
+}
+
+impl<'b, T> SortedIndex<'b, T> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over all the entries in key order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&'b [u8], &'b T)> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Iterates in key order over the entries whose keys are in `range`.
+    pub fn range<'k>(
+        &self,
+        range: impl RangeBounds<&'k [u8]>,
+    ) -> impl DoubleEndedIterator<Item = (&'b [u8], &'b T)> + '_ {
+        let start = match range.start_bound() {
+            Bound::Included(start) => self.entries.partition_point(|(key, _)| key < start),
+            Bound::Excluded(start) => self.entries.partition_point(|(key, _)| key <= start),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => self.entries.partition_point(|(key, _)| key <= end),
+            Bound::Excluded(end) => self.entries.partition_point(|(key, _)| key < end),
+            Bound::Unbounded => self.entries.len(),
+        };
+        self.entries[start..end.max(start)].iter().copied()
+    }
+
+    /// Iterates in key order over the entries whose keys start with `prefix`.
+    pub fn keys_with_prefix<'k>(
+        &'k self,
+        prefix: &'k [u8],
+    ) -> impl DoubleEndedIterator<Item = (&'b [u8], &'b T)> + 'k {
+        let start = self.entries.partition_point(|(key, _)| *key < prefix);
+        let len = self.entries[start..].partition_point(|(key, _)| key.starts_with(prefix));
+        self.entries[start..start + len].iter().copied()
+    }
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Builds a [`SortedIndex`] of the current entries of the map.
+    pub fn sorted_index(&self) -> SortedIndex<'_, T> {
+        let mut entries = self
+            .iter()
+            .map(|(key, value)| (key.key(), value))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        SortedIndex { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_sorted_index() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+        let mut cmp = BTreeMap::new();
+
+        for i in 0..5000 {
+            let key = (0..rand::random::<usize>() % 40)
+                .map(|_| b'a' + rand::random::<u8>() % 4)
+                .collect::<Vec<_>>();
+            map.insert(KeyRef::from(&*key), i);
+            cmp.insert(key, i);
+        }
+
+        let index = map.sorted_index();
+        assert_eq!(index.len(), cmp.len());
+        assert!(index
+            .iter()
+            .eq(cmp.iter().map(|(key, value)| (&**key, value))));
+
+        for prefix in [&b""[..], b"a", b"abc", b"dddd", b"e"] {
+            let expected = cmp
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| (&**key, value));
+            assert!(index.keys_with_prefix(prefix).eq(expected));
+        }
+
+        let (low, high) = (&b"ab"[..], &b"ca"[..]);
+        let expected = cmp
+            .range::<[u8], _>((Bound::Excluded(low), Bound::Included(high)))
+            .map(|(key, value)| (&**key, value));
+        assert!(index
+            .range((Bound::Excluded(low), Bound::Included(high)))
+            .eq(expected));
+        assert!(index.range(high..low).next().is_none());
+        assert_eq!(index.range(..).count(), cmp.len());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::adaptive::{KeyLen, StringMap};
+
+/// The number of buckets of [`KeyStats::lengths`].
+pub const KEY_LENGTH_BUCKETS: usize = 65;
+
+/// The distribution of the lengths of the keys of a map, to check how keys
+/// spread over the tiers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStats {
+    /// `lengths[i]` counts the keys of `i` bytes, except for the last bucket
+    /// which counts all the keys of `KEY_LENGTH_BUCKETS - 1` bytes or more.
+    pub lengths: [usize; KEY_LENGTH_BUCKETS],
+    /// The number of keys in every tier, indexed by `KeyLen as usize`.
+    pub tiers: [usize; 5],
+}
+
+impl KeyStats {
+    /// Returns the number of keys routed to `tier`.
+    pub fn tier(&self, tier: KeyLen) -> usize {
+        self.tiers[tier as usize]
+    }
+
+    pub fn total(&self) -> usize {
+        self.tiers.iter().sum()
+    }
+}
+
+impl Default for KeyStats {
+    fn default() -> Self {
+        KeyStats {
+            lengths: [0; KEY_LENGTH_BUCKETS],
+            tiers: [0; 5],
+        }
+    }
+}
+
+impl<'a, T, S> StringMap<'a, T, S> {
+    /// Walks the keys of the map to build the histogram of their lengths,
+    /// along with the number of keys per tier.
+    ///
+    /// This visits every entry, so it is linear in the length of the map.
+    /// The counts per tier alone are kept up to date by the tiers, see
+    /// [`len_for`](Self::len_for).
+    pub fn scan_key_stats(&self) -> KeyStats {
+        let mut stats = KeyStats::default();
+        for (key, _) in self.iter() {
+            stats.lengths[key.key().len().min(KEY_LENGTH_BUCKETS - 1)] += 1;
+        }
+        for tier in [
+            KeyLen::None,
+            KeyLen::S8,
+            KeyLen::S16,
+            KeyLen::S24,
+            KeyLen::Large,
+        ] {
+            stats.tiers[tier as usize] = self.len_for(tier);
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bumpalo::Bump;
+
+    use super::*;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_key_stats() {
+        let bump = Bump::new();
+        let mut map = StringMap::new(&bump);
+
+        let mut lengths = [0; KEY_LENGTH_BUCKETS];
+        for len in 0..100 {
+            for i in 0..=len % 3 {
+                let mut key = vec![b'x'; len];
+                if let Some(last) = key.last_mut() {
+                    *last = b'0' + i as u8;
+                }
+                if map.insert(KeyRef::from(&*key), ()).is_none() {
+                    lengths[len.min(KEY_LENGTH_BUCKETS - 1)] += 1;
+                }
+            }
+        }
+
+        let stats = map.scan_key_stats();
+        assert_eq!(stats.lengths, lengths);
+        assert_eq!(stats.total(), map.len());
+        assert_eq!(stats.tier(KeyLen::None), 1);
+        for tier in [KeyLen::S8, KeyLen::S16, KeyLen::S24, KeyLen::Large] {
+            let expected = map.iter().filter(|(key, _)| key.key_len() == tier).count();
+            assert_eq!(stats.tier(tier), expected);
+            assert_eq!(map.len_for(tier), expected);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::str::{self, Utf8Error};
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyRef, StringMap};
+
+/// A tiered map keyed by strings, stored as their UTF-8 bytes.
+///
+/// Only valid UTF-8 gets in, so keys are yielded back as `&str` without
+/// checking them again.
+pub struct StrMap<'a, T, S = RandomState> {
+    map: StringMap<'a, T, S>,
+}
+
+/// Converts a key inserted as a `str` back.
+#[inline]
+fn to_str(key: KeyRef<'_>) -> &str {
+    // SAFETY: Every key was inserted from a `str`.
+    unsafe { str::from_utf8_unchecked(key.key()) }
+}
+
+impl<'a, T, S> StrMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        StrMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> + '_ {
+        self.map.iter().map(|(key, value)| (to_str(key), value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut T)> + use<'_, 'a, T, S> {
+        self.map.iter_mut().map(|(key, value)| (to_str(key), value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, T, S> {
+        self.map
+    }
+}
+
+impl<'a, T> StrMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> StrMap<'a, T, S> {
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut T> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: &str, value: T) -> Option<T> {
+        self.map.insert(key, value)
+    }
+
+    /// Inserts a key read as bytes, e.g. from a file or a socket, once it is
+    /// checked to be valid UTF-8.
+    pub fn insert_utf8(&mut self, key: &[u8], value: T) -> Result<Option<T>, Utf8Error> {
+        Ok(self.insert(str::from_utf8(key)?, value))
+    }
+
+    pub fn get_or_insert_with(&mut self, key: &str, value: impl FnOnce() -> T) -> (&mut T, bool) {
+        self.map.get_or_insert_with(key, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        self.map.remove(key)
+    }
+
+    pub fn remove_entry(&mut self, key: &str) -> Option<(&'a str, T)> {
+        let (key, value) = self.map.remove_entry(key)?;
+        // SAFETY: See `to_str`.
+        Some((unsafe { str::from_utf8_unchecked(key) }, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::differential;
+
+    #[test]
+    fn test_str_map() {
+        let bump = Bump::new();
+        let key = |i: usize| ["ß", "a", "日本", ""][i % 4].repeat(rand::random::<usize>() % 12);
+        let (mut map, cmp) = differential(StrMap::new(&bump), 10000, key).into_parts();
+
+        for (key, value) in map.iter_mut() {
+            assert_eq!(cmp.get(key.as_bytes()), Some(&*value));
+            *value += 1;
+        }
+        for (key, value) in &cmp {
+            let key = str::from_utf8(key).unwrap();
+            assert_eq!(map.get(key), Some(&(value + 1)));
+        }
+
+        assert!(map.insert_utf8(b"\xff", 0).is_err());
+        assert_eq!(map.insert_utf8("é".as_bytes(), 1), Ok(None));
+        assert_eq!(map.remove_entry("é"), Some(("é", 1)));
+    }
+}
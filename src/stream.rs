@@ -0,0 +1,273 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::mem;
+use std::ptr::NonNull;
+
+use bumpalo::Bump;
+
+use crate::adaptive::StringMap;
+use crate::aggregate::AggregateState;
+
+const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// A batch of groups emitted by a [`GroupByStream`], with their keys copied
+/// out of the arena of the stream.
+pub struct GroupBatch<O> {
+    keys: Vec<u8>,
+    /// The end of every key in `keys`.
+    ends: Vec<usize>,
+    outputs: Vec<O>,
+}
+
+impl<O> GroupBatch<O> {
+    fn with_capacity(groups: usize) -> Self {
+        GroupBatch {
+            keys: Vec::new(),
+            ends: Vec::with_capacity(groups),
+            outputs: Vec::with_capacity(groups),
+        }
+    }
+
+    fn push(&mut self, key: &[u8], output: O) {
+        self.keys.extend_from_slice(key);
+        self.ends.push(self.keys.len());
+        self.outputs.push(output);
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn key(&self, index: usize) -> &[u8] {
+        let start = index.checked_sub(1).map_or(0, |prev| self.ends[prev]);
+        &self.keys[start..self.ends[index]]
+    }
+
+    pub fn outputs(&self) -> &[O] {
+        &self.outputs
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &O)> + '_ {
+        (0..self.len()).map(|index| (self.key(index), &self.outputs[index]))
+    }
+}
+
+/// A streaming aggregation operator, consuming batches of keys and inputs
+/// and producing batches of finalized groups.
+///
+/// The groups are kept in a [`StringMap`] over an arena owned by the stream.
+/// With a [memory limit](Self::memory_limit), all the groups are finalized
+/// and emitted as soon as the table and the arena outgrow it, which frees
+/// both. A group may then be emitted again by later batches, so that early
+/// emission suits partial aggregations, whose outputs are combined again
+/// downstream. Without a limit, every group is emitted once, when the stream
+/// is [finished](Self::finish).
+pub struct GroupByStream<A: AggregateState + 'static, S = RandomState> {
+    /// Only taken while the groups are emitted.
+    map: Option<StringMap<'static, A, S>>,
+    /// Leaked so that the map can borrow it while the stream moves, and freed
+    /// on drop after the map.
+    arena: NonNull<Bump>,
+    memory_limit: Option<usize>,
+    batch_size: usize,
+    ready: VecDeque<GroupBatch<A::Output>>,
+    emissions: usize,
+}
+
+impl<A: AggregateState + 'static> GroupByStream<A, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<A: AggregateState + 'static> Default for GroupByStream<A, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: AggregateState + 'static, S> GroupByStream<A, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        let arena = NonNull::from(Box::leak(Box::new(Bump::new())));
+        // SAFETY: The arena is only reset once the map is gone, and freed
+        // after it. The map is never handed out, and the keys emitted are
+        // copied.
+        let key_alloc = unsafe { arena.as_ref() };
+        GroupByStream {
+            map: Some(StringMap::with_hasher(hasher, key_alloc)),
+            arena,
+            memory_limit: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            ready: VecDeque::new(),
+            emissions: 0,
+        }
+    }
+
+    /// Sets the number of bytes of the table and the arena past which the
+    /// groups are emitted early.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum number of groups of an output batch.
+    pub fn batch_size(mut self, groups: usize) -> Self {
+        assert!(groups > 0, "Empty batches");
+        self.batch_size = groups;
+        self
+    }
+
+    fn map(&self) -> &StringMap<'static, A, S> {
+        self.map.as_ref().unwrap()
+    }
+
+    /// Returns the number of groups held by the stream.
+    pub fn groups(&self) -> usize {
+        self.map().len()
+    }
+
+    /// Returns the number of bytes held by the table and the arena.
+    pub fn memory_used(&self) -> usize {
+        self.map().bucket_bytes() + self.map().key_alloc().allocated_bytes()
+    }
+
+    /// Returns the number of times the groups were emitted early.
+    pub fn emissions(&self) -> usize {
+        self.emissions
+    }
+
+    /// Returns the next batch of groups ready to be emitted.
+    pub fn next_batch(&mut self) -> Option<GroupBatch<A::Output>> {
+        self.ready.pop_front()
+    }
+}
+
+impl<A: AggregateState + 'static, S: BuildHasher + Clone> GroupByStream<A, S> {
+    /// Updates the groups of `keys` with `inputs`, then emits all the groups
+    /// if the memory limit is exceeded.
+    pub fn push(&mut self, keys: &[&[u8]], inputs: &[A::Input]) {
+        self.map.as_mut().unwrap().aggregate(keys, inputs);
+        if self
+            .memory_limit
+            .is_some_and(|limit| self.memory_used() > limit)
+        {
+            self.emit();
+            self.emissions += 1;
+        }
+    }
+
+    /// Finalizes every group into the batches ready to be emitted, and starts
+    /// over with an empty table and arena.
+    fn emit(&mut self) {
+        let map = self.map.take().unwrap();
+        let hasher = map.hasher().clone();
+        for (key, output) in map.finalize() {
+            match self.ready.back_mut() {
+                Some(batch) if batch.len() < self.batch_size => batch.push(key, output),
+                _ => {
+                    let mut batch = GroupBatch::with_capacity(self.batch_size);
+                    batch.push(key, output);
+                    self.ready.push_back(batch);
+                }
+            }
+        }
+        // SAFETY: The map borrowing the arena is gone, and its keys were
+        // copied out.
+        unsafe { self.arena.as_mut() }.reset();
+        // SAFETY: See `with_hasher`.
+        let key_alloc = unsafe { self.arena.as_ref() };
+        self.map = Some(StringMap::with_hasher(hasher, key_alloc));
+    }
+
+    /// Consumes the stream, emitting the batches not yet taken along with the
+    /// remaining groups.
+    pub fn finish(mut self) -> impl Iterator<Item = GroupBatch<A::Output>> {
+        self.emit();
+        mem::take(&mut self.ready).into_iter()
+    }
+}
+
+impl<A: AggregateState + 'static, S> Drop for GroupByStream<A, S> {
+    fn drop(&mut self) {
+        drop(self.map.take());
+        // SAFETY: The arena was leaked by `with_hasher`, and the map borrowing
+        // it is gone.
+        drop(unsafe { Box::from_raw(self.arena.as_ptr()) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::aggregate::{Max, Sum};
+
+    #[test]
+    fn test_group_by_stream() {
+        let keys = (0..40000u32)
+            .map(|i| (i * 7919 % 5003).to_string().repeat(i as usize % 9))
+            .collect::<Vec<_>>();
+        let keys = keys.iter().map(|key| key.as_bytes()).collect::<Vec<_>>();
+        let inputs = (0..keys.len() as u64).collect::<Vec<_>>();
+        let mut expected = HashMap::<&[u8], u64>::new();
+        for (key, input) in keys.iter().zip(&inputs) {
+            *expected.entry(key).or_default() += input;
+        }
+
+        // Every group is emitted once, at the end.
+        let mut stream = GroupByStream::<Sum<u64>>::new().batch_size(1000);
+        for (keys, inputs) in keys.chunks(4096).zip(inputs.chunks(4096)) {
+            stream.push(keys, inputs);
+            assert!(stream.next_batch().is_none());
+        }
+        assert_eq!(stream.groups(), expected.len());
+        let mut groups = HashMap::new();
+        for batch in stream.finish() {
+            assert!(batch.len() <= 1000);
+            for (key, sum) in batch.iter() {
+                assert!(groups.insert(key.to_vec(), *sum).is_none());
+            }
+        }
+        assert_eq!(groups.len(), expected.len());
+        for (key, sum) in &expected {
+            assert_eq!(groups[*key], *sum);
+        }
+
+        // Partial sums emitted early add up to the same totals.
+        let mut stream = GroupByStream::<Sum<u64>>::new().memory_limit(64 << 10);
+        let mut groups = HashMap::<Vec<u8>, u64>::new();
+        let mut collect = |batch: GroupBatch<u64>| {
+            for (key, sum) in batch.iter() {
+                *groups.entry(key.to_vec()).or_default() += sum;
+            }
+        };
+        for (keys, inputs) in keys.chunks(1024).zip(inputs.chunks(1024)) {
+            stream.push(keys, inputs);
+            assert!(stream.memory_used() <= 64 << 10 || stream.groups() == 0);
+            while let Some(batch) = stream.next_batch() {
+                collect(batch);
+            }
+        }
+        assert!(stream.emissions() > 0);
+        stream.finish().for_each(&mut collect);
+        assert_eq!(groups.len(), expected.len());
+        for (key, sum) in &expected {
+            assert_eq!(groups[*key], *sum);
+        }
+
+        let mut stream = GroupByStream::<Max<u64>>::new();
+        stream.push(&[b"a", b"", b"a"], &[3, 1, 2]);
+        let batches = stream.finish().collect::<Vec<_>>();
+        assert_eq!(batches.len(), 1);
+        let mut groups = batches[0].iter().collect::<Vec<_>>();
+        groups.sort_unstable();
+        assert_eq!(groups, [(&b""[..], &1), (&b"a"[..], &3)]);
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering, Ordering::*};
+use std::sync::{Arc, Mutex};
+
+use bumpalo::Bump;
+
+const MIN_CAPACITY: usize = 16;
+
+/// An entry, never moved nor changed once published.
+struct Entry<T> {
+    hash: u64,
+    /// The key, in the arena of the map.
+    key: *const [u8],
+    value: T,
+}
+
+impl<T> Entry<T> {
+    fn key(&self) -> &[u8] {
+        // SAFETY: The arena outlives the entries.
+        unsafe { &*self.key }
+    }
+}
+
+struct Table<T> {
+    slots: Box<[AtomicPtr<Entry<T>>]>,
+}
+
+impl<T> Table<T> {
+    fn new(capacity: usize) -> Self {
+        let slots = (0..capacity).map(|_| AtomicPtr::new(ptr::null_mut()));
+        Table {
+            slots: slots.collect(),
+        }
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    /// Returns the index of the entry of `key`, or of the empty slot ending
+    /// its probe sequence, loading the slots with `order`.
+    fn find(&self, key: &[u8], hash: u64, order: Ordering) -> Result<usize, usize> {
+        let mut index = hash as usize & self.mask();
+        loop {
+            let entry = self.slots[index].load(order);
+            // SAFETY: Published entries are only freed once no reader is
+            // pinned, nor is the writer probing.
+            match unsafe { entry.as_ref() } {
+                None => return Err(index),
+                Some(entry) if entry.hash == hash && entry.key() == key => return Ok(index),
+                Some(_) => index = (index + 1) & self.mask(),
+            }
+        }
+    }
+}
+
+/// The tables and entries unlinked by the writer, waiting for the readers
+/// that may still see them.
+struct Retired<T> {
+    // Boxed since readers may still point at the tables themselves.
+    #[allow(clippy::vec_box)]
+    tables: Vec<Box<Table<T>>>,
+    entries: Vec<Box<Entry<T>>>,
+    /// The memory held by the tables and entries above.
+    bytes: usize,
+}
+
+struct Shared<T, S> {
+    table: AtomicPtr<Table<T>>,
+    len: AtomicUsize,
+    /// The number of readers pinned.
+    pinned: AtomicUsize,
+    retired: Mutex<Retired<T>>,
+    hasher: S,
+    key_alloc: Bump,
+}
+
+// SAFETY: The arena is only allocated into by the writer, and readers only
+// read the keys already published. Values are shared with the readers and
+// may be dropped by any of them.
+unsafe impl<T: Send + Sync, S: Send + Sync> Send for Shared<T, S> {}
+unsafe impl<T: Send + Sync, S: Send + Sync> Sync for Shared<T, S> {}
+
+impl<T, S> Drop for Shared<T, S> {
+    fn drop(&mut self) {
+        // SAFETY: The map is no longer shared, and each entry is in the
+        // current table or retired, but not both.
+        let table = unsafe { Box::from_raw(*self.table.get_mut()) };
+        for slot in table.slots.iter() {
+            let entry = slot.load(Relaxed);
+            if !entry.is_null() {
+                drop(unsafe { Box::from_raw(entry) });
+            }
+        }
+    }
+}
+
+/// The read side of a map that a single [`SwmrWriter`] inserts into while
+/// any number of threads look it up without locking, e.g. to probe the hash
+/// table of a join while it is still being built.
+///
+/// Entries are published by atomically storing a pointer to them into their
+/// slot, and are never changed afterwards: inserting an existing key
+/// publishes a new entry in place of the old one. Growing the table builds a
+/// new one next to it and swaps it in, so that the readers in the resize
+/// window keep reading the old table, missing at most the entries inserted
+/// in the meantime.
+///
+/// The unlinked tables and entries are reclaimed by the writer once no
+/// reader is pinned, as in epoch-based reclamation with a single epoch. They
+/// pile up as long as any reader stays pinned, which
+/// [`SwmrWriter::retired_bytes`] reports.
+pub struct SwmrStringMap<T, S = RandomState> {
+    shared: Arc<Shared<T, S>>,
+}
+
+impl<T, S> Clone for SwmrStringMap<T, S> {
+    fn clone(&self) -> Self {
+        SwmrStringMap {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T, S> SwmrStringMap<T, S> {
+    pub fn hasher(&self) -> &S {
+        &self.shared.hasher
+    }
+
+    /// Returns the number of entries published so far.
+    pub fn len(&self) -> usize {
+        self.shared.len.load(Acquire)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pins the current entries, which stay valid until the guard is
+    /// dropped. Pinned guards delay the reclamation of replaced entries and
+    /// old tables, so they are meant to be short-lived.
+    pub fn pin(&self) -> ReadGuard<'_, T, S> {
+        self.shared.pinned.fetch_add(1, SeqCst);
+        ReadGuard {
+            shared: &self.shared,
+        }
+    }
+}
+
+impl<T, S: BuildHasher> SwmrStringMap<T, S> {
+    /// Looks up `key` and passes its value, if any, to `f`.
+    pub fn get_with<R>(&self, key: &[u8], f: impl FnOnce(Option<&T>) -> R) -> R {
+        f(self.pin().get(key))
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.pin().get(key).is_some()
+    }
+}
+
+/// A reader pinning the entries it looks up, see [`SwmrStringMap::pin`].
+pub struct ReadGuard<'m, T, S> {
+    shared: &'m Shared<T, S>,
+}
+
+impl<'m, T, S: BuildHasher> ReadGuard<'m, T, S> {
+    pub fn get(&self, key: &[u8]) -> Option<&T> {
+        let hash = self.shared.hasher.hash_one(key);
+        // SAFETY: The table was loaded while pinned, so the writer doesn't
+        // free it until the guard is dropped.
+        let table = unsafe { &*self.shared.table.load(SeqCst) };
+        // The slots are loaded sequentially too: an acquire load could still
+        // return an entry the writer unlinked before it saw no reader pinned.
+        let index = table.find(key, hash, SeqCst).ok()?;
+        let entry = unsafe { &*table.slots[index].load(SeqCst) };
+        Some(&entry.value)
+    }
+}
+
+impl<T, S> Drop for ReadGuard<'_, T, S> {
+    fn drop(&mut self) {
+        self.shared.pinned.fetch_sub(1, SeqCst);
+    }
+}
+
+/// The write side of a [`SwmrStringMap`], of which there is only one.
+pub struct SwmrWriter<T, S = RandomState> {
+    shared: Arc<Shared<T, S>>,
+}
+
+impl<T> SwmrWriter<T, RandomState> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<T> Default for SwmrWriter<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S> SwmrWriter<T, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        let table = Box::into_raw(Box::new(Table::new(MIN_CAPACITY)));
+        SwmrWriter {
+            shared: Arc::new(Shared {
+                table: AtomicPtr::new(table),
+                len: AtomicUsize::new(0),
+                pinned: AtomicUsize::new(0),
+                retired: Mutex::new(Retired {
+                    tables: Vec::new(),
+                    entries: Vec::new(),
+                    bytes: 0,
+                }),
+                hasher,
+                key_alloc: Bump::new(),
+            }),
+        }
+    }
+
+    /// Returns a reader of the map, which can be cloned and sent to other
+    /// threads.
+    pub fn reader(&self) -> SwmrStringMap<T, S> {
+        SwmrStringMap {
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len.load(Relaxed)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn table(&self) -> &Table<T> {
+        // SAFETY: Only the writer swaps the table, and frees it later on.
+        unsafe { &*self.shared.table.load(Relaxed) }
+    }
+
+    /// Frees the tables and entries unlinked so far if no reader is pinned.
+    ///
+    /// A reader pinned after the check loads what the writer published
+    /// before, since both sides order these accesses sequentially.
+    fn reclaim(&mut self) {
+        let mut retired = self.shared.retired.lock().unwrap();
+        if self.shared.pinned.load(SeqCst) == 0 {
+            retired.tables.clear();
+            retired.entries.clear();
+            retired.bytes = 0;
+        }
+    }
+
+    /// Returns the number of unlinked tables and entries not reclaimed yet.
+    pub fn retired(&self) -> usize {
+        let retired = self.shared.retired.lock().unwrap();
+        retired.tables.len() + retired.entries.len()
+    }
+
+    /// Returns the memory held by the unlinked tables and entries not
+    /// reclaimed yet, excluding what the values own on the heap.
+    ///
+    /// Nothing is reclaimed while any reader stays pinned, so a guard held
+    /// across many writes makes this grow without bound.
+    pub fn retired_bytes(&self) -> usize {
+        self.shared.retired.lock().unwrap().bytes
+    }
+}
+
+impl<T, S: BuildHasher> SwmrWriter<T, S> {
+    pub fn get(&self, key: &[u8]) -> Option<&T> {
+        let table = self.table();
+        let hash = self.shared.hasher.hash_one(key);
+        let index = table.find(key, hash, Relaxed).ok()?;
+        // SAFETY: Only the writer unlinks entries.
+        Some(&unsafe { &*table.slots[index].load(Relaxed) }.value)
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Publishes `value` under `key`, returning whether the key is new. The
+    /// entry of an existing key is replaced, and reclaimed once no reader
+    /// may still see it.
+    pub fn insert(&mut self, key: &[u8], value: T) -> bool {
+        let hash = self.shared.hasher.hash_one(key);
+        if (self.len() + 1) * 8 > self.table().slots.len() * 7 {
+            self.grow();
+        }
+
+        let table = self.table();
+        let (index, key, old) = match table.find(key, hash, Relaxed) {
+            Ok(index) => {
+                let old = table.slots[index].load(Relaxed);
+                // SAFETY: Only the writer unlinks entries.
+                (index, unsafe { &*old }.key, Some(old))
+            }
+            Err(index) => {
+                let key = self.shared.key_alloc.alloc_slice_copy(key) as *const [u8];
+                (index, key, None)
+            }
+        };
+        let entry = Box::into_raw(Box::new(Entry { hash, key, value }));
+        table.slots[index].store(entry, SeqCst);
+
+        match old {
+            Some(old) => {
+                // SAFETY: The entry was just unlinked, and is freed by
+                // `reclaim` once no reader may still see it.
+                let old = unsafe { Box::from_raw(old) };
+                let mut retired = self.shared.retired.lock().unwrap();
+                retired.bytes += mem::size_of::<Entry<T>>();
+                retired.entries.push(old);
+                drop(retired);
+                self.reclaim();
+                false
+            }
+            None => {
+                self.shared.len.fetch_add(1, Release);
+                true
+            }
+        }
+    }
+
+    /// Builds a table twice as large and swaps it in, keeping the old one
+    /// until no reader may still probe it.
+    #[cold]
+    fn grow(&mut self) {
+        let old = self.table();
+        let new = Table::new(old.slots.len() * 2);
+        for slot in old.slots.iter() {
+            let entry = slot.load(Relaxed);
+            // SAFETY: Only the writer unlinks entries.
+            if let Some(data) = unsafe { entry.as_ref() } {
+                let mut index = data.hash as usize & new.mask();
+                while !new.slots[index].load(Relaxed).is_null() {
+                    index = (index + 1) & new.mask();
+                }
+                new.slots[index].store(entry, Relaxed);
+            }
+        }
+
+        let old = self.shared.table.swap(Box::into_raw(Box::new(new)), SeqCst);
+        // SAFETY: The table was just unlinked, and is freed by `reclaim` once
+        // no reader may still probe it.
+        let old = unsafe { Box::from_raw(old) };
+        let mut retired = self.shared.retired.lock().unwrap();
+        retired.bytes += mem::size_of::<Table<T>>() + mem::size_of_val(&*old.slots);
+        retired.tables.push(old);
+        drop(retired);
+        self.reclaim();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("{i};").repeat(i % 7 + 1).into_bytes()
+    }
+
+    #[test]
+    fn test_swmr() {
+        let mut writer = SwmrWriter::new();
+        let reader = writer.reader();
+        let published = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let reader = reader.clone();
+                let published = &published;
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        let n = published.load(Acquire);
+                        let guard = reader.pin();
+                        for i in (0..n).rev().step_by(97) {
+                            let value = guard.get(&key(i)).copied();
+                            assert!(value == Some(i) || value == Some(i + 1), "{i}: {value:?}");
+                        }
+                        assert_eq!(guard.get(b"absent"), None);
+                    }
+                });
+            }
+
+            for i in 0..100000 {
+                assert!(writer.insert(&key(i), i));
+                if i.is_multiple_of(10) && i > 0 {
+                    assert!(!writer.insert(&key(i - 10), i - 9));
+                }
+                published.store(i + 1, Release);
+            }
+        });
+
+        assert_eq!(reader.len(), 100000);
+        for i in 0..100000usize {
+            let expected = if i.is_multiple_of(10) && i < 99990 {
+                i + 1
+            } else {
+                i
+            };
+            reader.get_with(&key(i), |value| assert_eq!(value, Some(&expected)));
+        }
+        let guard = reader.pin();
+        let retired = writer.retired_bytes();
+        assert!(!writer.insert(&key(1), 1));
+        assert!(writer.insert(b"one more", 0));
+        assert_eq!(
+            writer.retired_bytes(),
+            retired + mem::size_of::<Entry<usize>>()
+        );
+        drop(guard);
+        writer.reclaim();
+        assert_eq!(writer.retired(), 0);
+        assert_eq!(writer.retired_bytes(), 0);
+        drop(writer);
+        assert!(reader.contains_key(&key(0)));
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+
+use crate::shadow::{ShadowMap, Shadowed};
+use crate::Reseed;
+
+/// A hasher giving every key the same hash, so that collisions can be forced.
+#[derive(Default)]
+pub struct ConstHasher;
+
+impl Hasher for ConstHasher {
+    fn finish(&self) -> u64 {
+        42
+    }
+
+    fn write(&mut self, _: &[u8]) {}
+}
+
+pub type ConstState = BuildHasherDefault<ConstHasher>;
+
+/// Hashes every key to 0 until reseeded, like a hasher whose seed was guessed
+/// by an attacker.
+#[derive(Default)]
+pub struct WeakState(u64);
+
+impl BuildHasher for WeakState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.0);
+        hasher
+    }
+
+    fn hash_one<H: Hash>(&self, x: H) -> u64 {
+        match self.0 {
+            0 => 0,
+            _ => {
+                let mut hasher = self.build_hasher();
+                x.hash(&mut hasher);
+                Hasher::finish(&hasher)
+            }
+        }
+    }
+}
+
+impl Reseed for WeakState {
+    fn reseed(&mut self) {
+        self.0 = rand::random::<u64>() | 1;
+    }
+}
+
+/// Runs `ops` random operations on `map` through a [`ShadowMap`], on keys
+/// drawn by `key` from the index of the operation, checking the whole
+/// contents every 5000 operations and at the end.
+pub fn differential<M, K>(map: M, ops: usize, mut key: impl FnMut(usize) -> K) -> ShadowMap<M>
+where
+    M: Shadowed<Value = usize>,
+    K: AsRef<[u8]>,
+{
+    let mut map = ShadowMap::new(map);
+    for i in 0..ops {
+        let key = key(i);
+        let key = key.as_ref();
+        match rand::random::<u8>() % 6 {
+            0 => drop(map.remove(key)),
+            1 => drop(map.get(key)),
+            2 => drop(map.update(key, |value| *value = value.wrapping_add(i))),
+            3 => drop(map.get_or_insert_with(key, || i)),
+            _ => drop(map.insert(key, i)),
+        }
+        if i % 5000 == 0 {
+            map.check();
+        }
+    }
+    map.check();
+    map
+}
+
+/// Draws one of `keys` distinct numbers, repeated up to 10 times depending on
+/// the index of the operation.
+pub fn numbered(keys: u16) -> impl FnMut(usize) -> String {
+    move |i| (rand::random::<u16>() % keys).to_string().repeat(i % 11)
+}
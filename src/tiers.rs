@@ -0,0 +1,420 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::iter::Chain;
+use std::marker::PhantomData;
+
+use bumpalo::Bump;
+
+use crate::adaptive::AsKeyBytes;
+use crate::common::{Iter, ShrinkPolicy};
+use crate::{large, small};
+
+/// A list of tiers, each taking the keys that fit it and passing the others on
+/// to the next, e.g. `Small<0, Small<8, Small<64>>>` for a workload of empty,
+/// short and medium keys.
+pub trait Tiers {
+    type Storage<'a, T: 'a>: TierStorage<'a, T>;
+}
+
+/// A tier storing keys of up to `N` bytes inline, followed by the tiers `R`.
+///
+/// `N` may not exceed 255, and a tier after one at least as wide never gets
+/// any key.
+pub struct Small<const N: usize, R = Large>(PhantomData<R>);
+
+/// The last tier, copying keys of any length into the arena.
+pub struct Large;
+
+/// The tiers of [`StringMap`](crate::StringMap).
+pub type DefaultTiers = Small<8, Small<16, Small<24>>>;
+
+impl<const N: usize, R: Tiers> Tiers for Small<N, R> {
+    type Storage<'a, T: 'a> = SmallTier<'a, T, N, R::Storage<'a, T>>;
+}
+
+impl Tiers for Large {
+    type Storage<'a, T: 'a> = LargeTier<'a, T>;
+}
+
+/// The tables of a list of tiers, routing every key to the first tier it fits.
+pub trait TierStorage<'a, T>: Sized {
+    type Iter<'b>: Iterator<Item = (&'b [u8], &'b T)>
+    where
+        Self: 'b,
+        T: 'b;
+
+    fn new(key_alloc: &'a Bump) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the slots of every tier.
+    fn capacity(&self) -> usize;
+
+    fn get(&self, key: &[u8], hash: u64) -> Option<&T>;
+
+    fn get_mut(&mut self, key: &[u8], hash: u64) -> Option<&mut T>;
+
+    fn insert<S: BuildHasher>(&mut self, key: &[u8], hash: u64, value: T, hasher: &S) -> Option<T>;
+
+    fn get_or_insert_with<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: impl FnOnce() -> T,
+        hasher: &S,
+    ) -> (&mut T, bool);
+
+    fn remove<S: BuildHasher>(&mut self, key: &[u8], hash: u64, hasher: &S) -> Option<T>;
+
+    fn clear(&mut self);
+
+    /// Reserves room for `additional` keys in every tier.
+    fn reserve<S: BuildHasher>(&mut self, additional: usize, hasher: &S);
+
+    /// Sets the load from which the tiers grow and their shrink policy, where
+    /// they support them.
+    fn configure(&mut self, max_load_percent: Option<u8>, shrink: ShrinkPolicy);
+
+    fn iter(&self) -> Self::Iter<'_>;
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    fn validate<S: BuildHasher>(&self, hasher: &S);
+}
+
+pub struct SmallTier<'a, T, const N: usize, R> {
+    map: small::StringMap<'a, T, N>,
+    rest: R,
+}
+
+pub struct LargeTier<'a, T> {
+    map: large::StringMap<'a, T>,
+}
+
+impl<'a, T: 'a, const N: usize, R: TierStorage<'a, T>> TierStorage<'a, T>
+    for SmallTier<'a, T, N, R>
+{
+    type Iter<'b>
+        = Chain<Iter<'b, small::SlotData<T, N>>, R::Iter<'b>>
+    where
+        Self: 'b,
+        T: 'b;
+
+    fn new(key_alloc: &'a Bump) -> Self {
+        SmallTier {
+            map: small::StringMap::new(key_alloc),
+            rest: R::new(key_alloc),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map.len() + self.rest.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.map.capacity() + self.rest.capacity()
+    }
+
+    #[inline]
+    fn get(&self, key: &[u8], hash: u64) -> Option<&T> {
+        match key.len() <= N {
+            true => self.map.get(key, hash),
+            false => self.rest.get(key, hash),
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &[u8], hash: u64) -> Option<&mut T> {
+        match key.len() <= N {
+            true => self.map.get_mut(key, hash),
+            false => self.rest.get_mut(key, hash),
+        }
+    }
+
+    #[inline]
+    fn insert<S: BuildHasher>(&mut self, key: &[u8], hash: u64, value: T, hasher: &S) -> Option<T> {
+        match key.len() <= N {
+            true => self.map.insert(key, hash, value, hasher),
+            false => self.rest.insert(key, hash, value, hasher),
+        }
+    }
+
+    #[inline]
+    fn get_or_insert_with<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: impl FnOnce() -> T,
+        hasher: &S,
+    ) -> (&mut T, bool) {
+        match key.len() <= N {
+            true => self.map.get_or_insert_with(key, hash, value, hasher),
+            false => self.rest.get_or_insert_with(key, hash, value, hasher),
+        }
+    }
+
+    #[inline]
+    fn remove<S: BuildHasher>(&mut self, key: &[u8], hash: u64, hasher: &S) -> Option<T> {
+        match key.len() <= N {
+            true => self.map.remove(key, hash, hasher),
+            false => self.rest.remove(key, hash, hasher),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.rest.clear();
+    }
+
+    fn reserve<S: BuildHasher>(&mut self, additional: usize, hasher: &S) {
+        self.map.reserve(additional, hasher);
+        self.rest.reserve(additional, hasher);
+    }
+
+    fn configure(&mut self, max_load_percent: Option<u8>, shrink: ShrinkPolicy) {
+        if let Some(percent) = max_load_percent {
+            self.map.set_max_load_percent(percent);
+        }
+        self.map.set_shrink_policy(shrink);
+        self.rest.configure(max_load_percent, shrink);
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.map.iter().chain(self.rest.iter())
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    fn validate<S: BuildHasher>(&self, hasher: &S) {
+        self.map.validate(hasher);
+        for (key, _) in self.map.iter() {
+            assert!(key.len() <= N, "Key of {} bytes in tier {N}", key.len());
+        }
+        self.rest.validate(hasher);
+    }
+}
+
+impl<'a, T: 'a> TierStorage<'a, T> for LargeTier<'a, T> {
+    type Iter<'b>
+        = Iter<'b, large::SlotData<'a, T>>
+    where
+        Self: 'b,
+        T: 'b;
+
+    fn new(key_alloc: &'a Bump) -> Self {
+        LargeTier {
+            map: large::StringMap::new(key_alloc),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    #[inline]
+    fn get(&self, key: &[u8], hash: u64) -> Option<&T> {
+        self.map.get(key, hash)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, key: &[u8], hash: u64) -> Option<&mut T> {
+        self.map.get_mut(key, hash)
+    }
+
+    #[inline]
+    fn insert<S: BuildHasher>(&mut self, key: &[u8], hash: u64, value: T, hasher: &S) -> Option<T> {
+        self.map.insert(key, hash, value, hasher)
+    }
+
+    #[inline]
+    fn get_or_insert_with<S: BuildHasher>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        value: impl FnOnce() -> T,
+        hasher: &S,
+    ) -> (&mut T, bool) {
+        self.map.get_or_insert_with(key, hash, value, hasher)
+    }
+
+    #[inline]
+    fn remove<S: BuildHasher>(&mut self, key: &[u8], hash: u64, hasher: &S) -> Option<T> {
+        self.map.remove(key, hash, hasher)
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    fn reserve<S: BuildHasher>(&mut self, additional: usize, hasher: &S) {
+        self.map.reserve(additional, hasher);
+    }
+
+    fn configure(&mut self, max_load_percent: Option<u8>, shrink: ShrinkPolicy) {
+        if let Some(percent) = max_load_percent {
+            self.map.set_max_load_percent(percent);
+        }
+        self.map.set_shrink_policy(shrink);
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        self.map.iter()
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    fn validate<S: BuildHasher>(&self, hasher: &S) {
+        self.map.validate(hasher);
+    }
+}
+
+/// A map whose tiers are chosen by the embedder, e.g. to add a tier for the
+/// widest keys of a workload, rather than fixed like those of
+/// [`StringMap`](crate::StringMap).
+///
+/// Keys are routed to the first of the tiers `L` they fit, and hashed as byte
+/// slices with `S`. It is built with
+/// [`StringMapBuilder::build_tiered`](crate::StringMapBuilder::build_tiered).
+///
+/// [`StringMap`](crate::StringMap) itself keeps its fixed tiers, as its API
+/// names them: callers build [`KeyRef`](crate::KeyRef)s of the tier of their
+/// keys, reserve room with [`KeyLen`](crate::KeyLen), and get handles and
+/// metrics per tier. This map offers the core operations over any list of
+/// tiers instead.
+pub struct TieredStringMap<'a, T: 'a, L: Tiers = DefaultTiers, S = RandomState> {
+    tiers: L::Storage<'a, T>,
+    hasher: S,
+}
+
+impl<'a, T: 'a, L: Tiers, S> TieredStringMap<'a, T, L, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        TieredStringMap {
+            tiers: TierStorage::new(key_alloc),
+            hasher,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiers.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// Returns the number of slots of all the tiers.
+    pub fn capacity(&self) -> usize {
+        self.tiers.capacity()
+    }
+
+    /// Iterates over the entries, tier after tier.
+    pub fn iter(&self) -> <L::Storage<'a, T> as TierStorage<'a, T>>::Iter<'_> {
+        self.tiers.iter()
+    }
+
+    /// Drops all the entries, keeping the buckets allocated.
+    pub fn clear(&mut self) {
+        self.tiers.clear();
+    }
+}
+
+impl<'a, T: 'a, L: Tiers> TieredStringMap<'a, T, L, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T: 'a, L: Tiers, S: BuildHasher> TieredStringMap<'a, T, L, S> {
+    pub fn get<K: AsKeyBytes>(&self, key: K) -> Option<&T> {
+        let key = key.as_key_bytes();
+        self.tiers.get(key, self.hasher.hash_one(key))
+    }
+
+    pub fn get_mut<K: AsKeyBytes>(&mut self, key: K) -> Option<&mut T> {
+        let key = key.as_key_bytes();
+        self.tiers.get_mut(key, self.hasher.hash_one(key))
+    }
+
+    pub fn contains_key<K: AsKeyBytes>(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert<K: AsKeyBytes>(&mut self, key: K, value: T) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        self.tiers.insert(key, hash, value, &self.hasher)
+    }
+
+    pub fn get_or_insert_with<K: AsKeyBytes>(
+        &mut self,
+        key: K,
+        value: impl FnOnce() -> T,
+    ) -> (&mut T, bool) {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        self.tiers
+            .get_or_insert_with(key, hash, value, &self.hasher)
+    }
+
+    pub fn remove<K: AsKeyBytes>(&mut self, key: K) -> Option<T> {
+        let key = key.as_key_bytes();
+        let hash = self.hasher.hash_one(key);
+        self.tiers.remove(key, hash, &self.hasher)
+    }
+
+    /// Applies the knobs of a [`StringMapBuilder`](crate::StringMapBuilder).
+    pub(crate) fn configure(
+        &mut self,
+        capacity: usize,
+        max_load_percent: Option<u8>,
+        shrink: ShrinkPolicy,
+    ) {
+        self.tiers.configure(max_load_percent, shrink);
+        if capacity > 0 {
+            self.tiers.reserve(capacity, &self.hasher);
+        }
+    }
+
+    /// Walks every tier and panics if any internal invariant is broken, or if
+    /// a key lies in a tier it doesn't fit.
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    pub fn validate(&self) {
+        self.tiers.validate(&self.hasher);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::differential;
+
+    fn exercise<L: Tiers>(max_len: usize) {
+        let bump = Bump::new();
+        let map = TieredStringMap::<usize, L>::new(&bump);
+
+        let key = |_| vec![rand::random::<u8>() % 4; rand::random::<usize>() % max_len];
+        let mut map = differential(map, 20000, key);
+        map.clear();
+        map.check();
+        assert!(map.inner().is_empty());
+    }
+
+    #[test]
+    fn test_tiers() {
+        exercise::<DefaultTiers>(40);
+        exercise::<Small<0, Small<8, Small<64>>>>(100);
+        exercise::<Large>(20);
+        #[cfg(feature = "hashbrown")]
+        exercise::<Small<8, Small<16, Small<24, crate::HashbrownLarge>>>>(40);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{KeyRef, StringMap};
+
+/// A key monitored by [`TopKStrings`].
+struct Counter {
+    count: u64,
+    /// The most the count may overestimate the occurrences of the key, i.e.
+    /// the count of the key it replaced.
+    error: u64,
+    hash: u64,
+    /// Leaked so that the map can borrow it while the counter moves around
+    /// the heap, and freed on drop.
+    key: NonNull<[u8]>,
+}
+
+impl Counter {
+    fn new(key: &[u8], hash: u64, count: u64, error: u64) -> Self {
+        Counter {
+            count,
+            error,
+            hash,
+            key: NonNull::from(Box::leak(Box::<[u8]>::from(key))),
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        // SAFETY: The key is only freed on drop.
+        unsafe { self.key.as_ref() }
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        // SAFETY: The key was leaked by `new`, and the map no longer borrows
+        // it, as a counter is removed from the map before being dropped.
+        drop(unsafe { Box::from_raw(self.key.as_ptr()) });
+    }
+}
+
+/// The heavy hitters of a stream of keys, approximated by the Space-Saving
+/// algorithm in memory bounded by the number of counters.
+///
+/// Up to `capacity` keys are monitored at once. A key that isn't replaces
+/// the one with the lowest count, and inherits that count as its error, so
+/// that a count never underestimates the occurrences of its key, and
+/// overestimates them by at most its error. Every key occurring more than
+/// `total / capacity` times is guaranteed to be monitored, so more counters
+/// than the number of heavy hitters wanted make for more accurate counts.
+///
+/// The counters are kept in a min-heap by count, and found by key through a
+/// [`StringMap`] borrowing the keys they own, so that replaced keys leave
+/// nothing behind.
+pub struct TopKStrings<S = RandomState> {
+    /// Dropped before the keys and the arena it borrows.
+    map: ManuallyDrop<StringMap<'static, usize, S>>,
+    heap: Vec<Counter>,
+    capacity: usize,
+    total: u64,
+    /// Leaked so that the map can borrow it while the tracker moves, and freed
+    /// on drop after the map.
+    arena: NonNull<Bump>,
+}
+
+impl TopKStrings<RandomState> {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<S> TopKStrings<S> {
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
+        assert!(capacity > 0, "No counters");
+        // Keys are borrowed rather than copied, so the arena stays empty.
+        let arena = NonNull::from(Box::leak(Box::new(Bump::new())));
+        // SAFETY: The arena is freed after the map, on drop.
+        let key_alloc = unsafe { arena.as_ref() };
+        TopKStrings {
+            map: ManuallyDrop::new(StringMap::with_hasher(hasher, key_alloc)),
+            heap: Vec::with_capacity(capacity),
+            capacity,
+            total: 0,
+            arena,
+        }
+    }
+
+    /// Returns the number of counters.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of keys monitored.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Returns the number of occurrences added, of all the keys.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Iterates over the keys monitored, along with their counts and errors,
+    /// in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], u64, u64)> + '_ {
+        (self.heap.iter()).map(|counter| (counter.key(), counter.count, counter.error))
+    }
+
+    /// Returns the `n` keys with the highest counts, along with their counts
+    /// and errors, by decreasing count.
+    pub fn top(&self, n: usize) -> Vec<(&[u8], u64, u64)> {
+        let mut top = self.iter().collect::<Vec<_>>();
+        top.sort_unstable_by(|(k1, c1, _), (k2, c2, _)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+        top.truncate(n);
+        top
+    }
+}
+
+impl<S: BuildHasher> TopKStrings<S> {
+    /// Returns the count and the error of `key`, if monitored.
+    pub fn get(&self, key: &[u8]) -> Option<(u64, u64)> {
+        let &index = self.map.get(KeyRef::from(key))?;
+        let counter = &self.heap[index];
+        Some((counter.count, counter.error))
+    }
+
+    pub fn add(&mut self, key: &[u8]) {
+        self.add_n(key, 1)
+    }
+
+    /// Adds `n` occurrences of `key`.
+    pub fn add_n(&mut self, key: &[u8], n: u64) {
+        self.total += n;
+        let hash = self.map.hasher().hash_one(KeyRef::from(key));
+        if let Some(&index) = self.map.get_hashed(KeyRef::from(key), hash) {
+            self.heap[index].count += n;
+            self.sift_down(index);
+            return;
+        }
+
+        let index = match self.heap.len() < self.capacity {
+            true => {
+                self.heap.push(Counter::new(key, hash, n, 0));
+                self.heap.len() - 1
+            }
+            false => {
+                let min = &self.heap[0];
+                self.map.remove_hashed(KeyRef::from(min.key()), min.hash);
+                let count = min.count;
+                self.heap[0] = Counter::new(key, hash, count + n, count);
+                0
+            }
+        };
+        // SAFETY: The key is only freed when its counter is dropped, after it
+        // was removed from the map.
+        let key = unsafe { self.heap[index].key.as_ref() };
+        self.map.insert_borrowed_hashed(key, hash, index);
+        self.sift_up(index);
+        self.sift_down(index);
+    }
+
+    /// Swaps two counters of the heap, updating their indices in the map.
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        for index in [i, j] {
+            let counter = &self.heap[index];
+            let key = KeyRef::from(counter.key());
+            *self.map.get_mut_hashed(key, counter.hash).unwrap() = index;
+        }
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.heap[parent].count <= self.heap[index].count {
+                break;
+            }
+            self.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let children = 2 * index + 1..(2 * index + 3).min(self.heap.len());
+            let Some(child) = children.min_by_key(|&child| self.heap[child].count) else {
+                break;
+            };
+            if self.heap[index].count <= self.heap[child].count {
+                break;
+            }
+            self.swap(index, child);
+            index = child;
+        }
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate"))]
+    pub fn validate(&self) {
+        assert_eq!(self.map.len(), self.heap.len());
+        for (index, counter) in self.heap.iter().enumerate() {
+            let key = KeyRef::from(counter.key());
+            assert_eq!(self.map.get_hashed(key, counter.hash), Some(&index));
+            if index > 0 {
+                assert!(self.heap[(index - 1) / 2].count <= counter.count);
+            }
+        }
+    }
+}
+
+impl<S> Drop for TopKStrings<S> {
+    fn drop(&mut self) {
+        // SAFETY: The map is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.map) };
+        // SAFETY: The arena was leaked by `with_hasher`, and the map borrowing
+        // it is gone.
+        drop(unsafe { Box::from_raw(self.arena.as_ptr()) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_top_k() {
+        let mut topk = TopKStrings::new(100);
+        let mut counts = HashMap::<Vec<u8>, u64>::new();
+        let key = |rank: u64| format!("key-{rank}").repeat(rank as usize % 4 + 1);
+
+        // A Zipf-like stream over 5000 keys, shuffled.
+        let mut stream = (1..=5000u64)
+            .flat_map(|rank| std::iter::repeat_n(rank, (20000 / rank).max(1) as usize))
+            .collect::<Vec<_>>();
+        for i in (1..stream.len()).rev() {
+            stream.swap(i, rand::random::<usize>() % (i + 1));
+        }
+        for (i, &rank) in stream.iter().enumerate() {
+            let key = key(rank);
+            match i % 10 {
+                0 => {
+                    topk.add_n(key.as_bytes(), 2);
+                    *counts.entry(key.into_bytes()).or_default() += 2;
+                }
+                _ => {
+                    topk.add(key.as_bytes());
+                    *counts.entry(key.into_bytes()).or_default() += 1;
+                }
+            }
+        }
+        #[cfg(any(debug_assertions, feature = "validate"))]
+        topk.validate();
+
+        assert_eq!(topk.len(), 100);
+        assert_eq!(topk.total(), counts.values().sum::<u64>());
+        for (key, count, error) in topk.iter() {
+            let actual = counts[key];
+            assert!(count - error <= actual && actual <= count);
+        }
+        for (key, &actual) in &counts {
+            if actual > topk.total() / topk.capacity() as u64 {
+                assert!(topk.get(key).is_some());
+            }
+        }
+
+        let top = topk.top(5);
+        let expected = (1..=5).map(key).collect::<Vec<_>>();
+        assert_eq!(
+            top.iter().map(|(key, ..)| *key).collect::<Vec<_>>(),
+            expected.iter().map(String::as_bytes).collect::<Vec<_>>(),
+        );
+    }
+}
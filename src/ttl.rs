@@ -0,0 +1,200 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, StringMap};
+
+/// A tiered map whose entries expire, e.g. a session or deduplication table
+/// in a streaming pipeline.
+///
+/// Time is whatever monotonic `u64` clock the caller passes in, such as
+/// milliseconds or event sequence numbers; an entry expires once `now`
+/// reaches its deadline. Expired entries are hidden from lookups right away,
+/// but only freed when looked up mutably or by [`evict_expired`], so
+/// [`len`](Self::len) counts them until then.
+///
+/// [`evict_expired`]: Self::evict_expired
+pub struct TtlStringMap<'a, T, S = RandomState> {
+    /// The values along with their deadlines.
+    map: StringMap<'a, (u64, T), S>,
+    ttl: u64,
+}
+
+impl<'a, T, S> TtlStringMap<'a, T, S> {
+    /// Creates a map whose entries expire `ttl` ticks after their insertion.
+    pub fn with_hasher(ttl: u64, hasher: S, key_alloc: &'a Bump) -> Self {
+        TtlStringMap {
+            map: StringMap::with_hasher(hasher, key_alloc),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    /// Returns the number of entries, including the expired ones not evicted
+    /// yet.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the entries not expired at `now`, along with their
+    /// deadlines.
+    pub fn iter(&self, now: u64) -> impl Iterator<Item = (&[u8], u64, &T)> + '_ {
+        self.map
+            .iter()
+            .filter(move |(_, (deadline, _))| now < *deadline)
+            .map(|(key, (deadline, value))| (key.key(), *deadline, value))
+    }
+
+    /// Removes the entries expired at `now`, returning how many were removed.
+    pub fn evict_expired(&mut self, now: u64) -> usize {
+        let len = self.map.len();
+        self.map.retain(|_, (deadline, _)| now < *deadline);
+        len - self.map.len()
+    }
+
+    pub fn into_inner(self) -> StringMap<'a, (u64, T), S> {
+        self.map
+    }
+}
+
+impl<'a, T> TtlStringMap<'a, T, RandomState> {
+    pub fn new(ttl: u64, key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(ttl, RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher> TtlStringMap<'a, T, S> {
+    pub fn get<Q: Equivalent>(&self, key: Q, now: u64) -> Option<&T> {
+        match self.map.get(key)? {
+            (deadline, value) if now < *deadline => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of `key` if it hasn't expired at `now`, removing it
+    /// otherwise.
+    pub fn get_mut<Q: Equivalent>(&mut self, key: Q, now: u64) -> Option<&mut T> {
+        self.get_mut_entry(key, now).map(|(_, value)| value)
+    }
+
+    pub fn contains_key<Q: Equivalent>(&self, key: Q, now: u64) -> bool {
+        self.get(key, now).is_some()
+    }
+
+    /// Returns the deadline of `key` if it hasn't expired at `now`.
+    pub fn deadline<Q: Equivalent>(&self, key: Q, now: u64) -> Option<u64> {
+        let deadline = self.map.get(key)?.0;
+        (now < deadline).then_some(deadline)
+    }
+
+    /// Inserts `key` to expire `ttl` ticks after `now`, returning the value
+    /// it replaced if that one hadn't expired.
+    pub fn insert<Q: Equivalent>(&mut self, key: Q, value: T, now: u64) -> Option<T> {
+        self.insert_until(key, value, now.saturating_add(self.ttl), now)
+    }
+
+    /// Inserts `key` to expire at `deadline` instead of after the default
+    /// TTL.
+    pub fn insert_until<Q: Equivalent>(
+        &mut self,
+        key: Q,
+        value: T,
+        deadline: u64,
+        now: u64,
+    ) -> Option<T> {
+        let (old_deadline, old) = self.map.insert(key, (deadline, value))?;
+        (now < old_deadline).then_some(old)
+    }
+
+    /// Pushes back the deadline of `key` to `ttl` ticks after `now`,
+    /// returning whether the key was present and not expired.
+    pub fn touch<Q: Equivalent>(&mut self, key: Q, now: u64) -> bool {
+        let ttl = self.ttl;
+        match self.get_mut_entry(key, now) {
+            Some((deadline, _)) => {
+                *deadline = now.saturating_add(ttl);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get_mut_entry<Q: Equivalent>(&mut self, key: Q, now: u64) -> Option<&mut (u64, T)> {
+        let key = key.as_key_ref();
+        let hash = self.map.hasher().hash_one(key);
+        if self.map.get_hashed(key, hash)?.0 <= now {
+            self.map.remove_hashed(key, hash);
+            return None;
+        }
+        self.map.get_mut_hashed(key, hash)
+    }
+
+    /// Removes `key`, returning its value if it hadn't expired at `now`.
+    pub fn remove<Q: Equivalent>(&mut self, key: Q, now: u64) -> Option<T> {
+        let (deadline, value) = self.map.remove(key)?;
+        (now < deadline).then_some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_ttl_map() {
+        let bump = Bump::new();
+        let mut map = TtlStringMap::new(100, &bump);
+        let mut cmp = HashMap::<String, (u64, u32)>::new();
+
+        for now in 0..20000u64 {
+            let key = (rand::random::<u16>() % 500)
+                .to_string()
+                .repeat(now as usize % 5);
+            let live = cmp
+                .get(&key)
+                .filter(|(deadline, _)| now < *deadline)
+                .copied();
+            let k = KeyRef::from(key.as_bytes());
+            match rand::random::<u8>() % 8 {
+                0 => {
+                    assert_eq!(map.remove(k, now), live.map(|(_, value)| value));
+                    cmp.remove(&key);
+                }
+                1 => {
+                    assert_eq!(map.touch(k, now), live.is_some());
+                    if let Some(entry) = cmp.get_mut(&key).filter(|_| live.is_some()) {
+                        entry.0 = now + 100;
+                    }
+                }
+                2 => assert_eq!(map.get_mut(k, now).copied(), live.map(|(_, value)| value)),
+                3 => assert_eq!(map.deadline(k, now), live.map(|(deadline, _)| deadline)),
+                _ => {
+                    let value = now as u32;
+                    assert_eq!(map.insert(k, value, now), live.map(|(_, value)| value));
+                    cmp.insert(key, (now + 100, value));
+                }
+            }
+
+            if now % 1000 == 999 {
+                let evicted = map.evict_expired(now);
+                let len = cmp.len();
+                cmp.retain(|_, (deadline, _)| now < *deadline);
+                assert!(evicted <= len - cmp.len());
+                assert_eq!(map.len(), cmp.len());
+                assert_eq!(map.iter(now).count(), cmp.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::mem;
+
+use bumpalo::Bump;
+
+use crate::adaptive::{Equivalent, StringMap};
+use crate::aggregate::AggregateState;
+
+/// A map of entries keyed by window and key, e.g. the groups of a tumbling
+/// window aggregation over a stream.
+///
+/// Windows are whatever `u64` epochs the caller assigns to its rows, and
+/// every window has a map of its own, so that evicting the windows before a
+/// watermark moves their maps out whole rather than scanning the entries.
+/// Once a watermark has been passed, rows of earlier windows are late: they
+/// are dropped and counted rather than reopening an evicted window.
+///
+/// The keys of all the windows are copied into the same arena, and stay
+/// there after their windows are evicted.
+pub struct WindowedStringMap<'a, T, S = RandomState> {
+    windows: BTreeMap<u64, StringMap<'a, T, S>>,
+    watermark: u64,
+    late_rows: usize,
+    hasher: S,
+    key_alloc: &'a Bump,
+}
+
+impl<'a, T, S> WindowedStringMap<'a, T, S> {
+    pub fn with_hasher(hasher: S, key_alloc: &'a Bump) -> Self {
+        WindowedStringMap {
+            windows: BTreeMap::new(),
+            watermark: 0,
+            late_rows: 0,
+            hasher,
+            key_alloc,
+        }
+    }
+
+    /// Returns the number of entries of all the windows.
+    pub fn len(&self) -> usize {
+        self.windows.values().map(StringMap::len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.windows.values().all(StringMap::is_empty)
+    }
+
+    /// Returns the number of windows held.
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// Returns the watermark, before which windows are evicted and rows are
+    /// late.
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    /// Returns the number of rows dropped for being late.
+    pub fn late_rows(&self) -> usize {
+        self.late_rows
+    }
+
+    /// Returns the map of `window`.
+    pub fn window(&self, window: u64) -> Option<&StringMap<'a, T, S>> {
+        self.windows.get(&window)
+    }
+
+    /// Iterates over the windows held and their maps, in window order.
+    pub fn windows(&self) -> impl Iterator<Item = (u64, &StringMap<'a, T, S>)> + '_ {
+        self.windows.iter().map(|(window, map)| (*window, map))
+    }
+
+    /// Removes the windows before `watermark`, yielding them in window order,
+    /// and makes later rows of these windows late.
+    ///
+    /// The watermark never moves back: a lower one evicts nothing.
+    pub fn evict_before(
+        &mut self,
+        watermark: u64,
+    ) -> impl Iterator<Item = (u64, StringMap<'a, T, S>)> + use<'a, T, S> {
+        self.watermark = self.watermark.max(watermark);
+        let kept = self.windows.split_off(&self.watermark);
+        mem::replace(&mut self.windows, kept).into_iter()
+    }
+}
+
+impl<'a, T> WindowedStringMap<'a, T, RandomState> {
+    pub fn new(key_alloc: &'a Bump) -> Self {
+        Self::with_hasher(RandomState::new(), key_alloc)
+    }
+}
+
+impl<'a, T, S: BuildHasher + Clone> WindowedStringMap<'a, T, S> {
+    /// Returns the map of `window`, creating it if needed, or `None` if the
+    /// window is late.
+    fn window_mut(&mut self, window: u64) -> Option<&mut StringMap<'a, T, S>> {
+        if window < self.watermark {
+            return None;
+        }
+        let map = (self.windows.entry(window))
+            .or_insert_with(|| StringMap::with_hasher(self.hasher.clone(), self.key_alloc));
+        Some(map)
+    }
+
+    pub fn get<Q: Equivalent>(&self, window: u64, key: Q) -> Option<&T> {
+        self.windows.get(&window)?.get(key)
+    }
+
+    pub fn get_mut<Q: Equivalent>(&mut self, window: u64, key: Q) -> Option<&mut T> {
+        self.windows.get_mut(&window)?.get_mut(key)
+    }
+
+    /// Returns the value of `key` in `window`, inserting the result of
+    /// `value` if absent, along with whether it was inserted; or `None` if the
+    /// window is late.
+    pub fn get_or_insert_with<Q: Equivalent>(
+        &mut self,
+        window: u64,
+        key: Q,
+        value: impl FnOnce() -> T,
+    ) -> Option<(&mut T, bool)> {
+        if window < self.watermark {
+            self.late_rows += 1;
+            return None;
+        }
+        Some(self.window_mut(window)?.get_or_insert_with(key, value))
+    }
+
+    /// Groups `inputs` by `keys` in `window`, as in
+    /// [`StringMap::accumulate`], returning `false` if the window is late.
+    pub fn accumulate<I>(
+        &mut self,
+        window: u64,
+        keys: &[&[u8]],
+        inputs: &[I],
+        init: impl FnMut(&I) -> T,
+        fold: impl FnMut(&mut T, &I),
+    ) -> bool {
+        match self.window_mut(window) {
+            Some(map) => {
+                map.accumulate(keys, inputs, init, fold);
+                true
+            }
+            None => {
+                self.late_rows += keys.len();
+                false
+            }
+        }
+    }
+}
+
+impl<'a, A: AggregateState, S: BuildHasher + Clone> WindowedStringMap<'a, A, S> {
+    /// Updates the states of the groups of `keys` in `window` with `inputs`,
+    /// returning `false` if the window is late.
+    pub fn aggregate(&mut self, window: u64, keys: &[&[u8]], inputs: &[A::Input]) -> bool {
+        self.accumulate(window, keys, inputs, A::init, A::update)
+    }
+
+    /// Evicts the windows before `watermark` like
+    /// [`evict_before`](Self::evict_before), yielding the final result of
+    /// every group along with its window.
+    pub fn evict_finalized_before(
+        &mut self,
+        watermark: u64,
+    ) -> impl Iterator<Item = (u64, &'a [u8], A::Output)> + use<'a, A, S> {
+        self.evict_before(watermark).flat_map(|(window, map)| {
+            map.finalize()
+                .map(move |(key, output)| (window, key, output))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::aggregate::Sum;
+    use crate::KeyRef;
+
+    #[test]
+    fn test_windowed_map() {
+        let bump = Bump::new();
+        let mut map = WindowedStringMap::<Sum<u64>>::new(&bump);
+        let mut cmp = HashMap::<(u64, Vec<u8>), u64>::new();
+        let mut watermark = 0;
+        let mut late = 0;
+
+        for time in 0..20000u64 {
+            // Rows arrive up to 150 ticks out of order, in windows of 100.
+            let window = time.saturating_sub(rand::random::<u64>() % 150) / 100;
+            let key = (rand::random::<u16>() % 50)
+                .to_string()
+                .repeat(time as usize % 4);
+            if map.aggregate(window, &[key.as_bytes()], &[time]) {
+                assert!(window >= watermark);
+                *cmp.entry((window, key.into_bytes())).or_default() += time;
+            } else {
+                assert!(window < watermark);
+                late += 1;
+            }
+
+            if time % 500 == 499 {
+                watermark = time / 100;
+                let mut evicted = 0;
+                for (window, key, sum) in map.evict_finalized_before(watermark) {
+                    assert!(window < watermark);
+                    assert_eq!(cmp.remove(&(window, key.to_vec())), Some(sum));
+                    evicted += 1;
+                }
+                assert!(evicted > 0);
+                assert!(cmp.keys().all(|(window, _)| *window >= watermark));
+                assert_eq!(map.len(), cmp.len());
+            }
+        }
+        assert_eq!(map.late_rows(), late);
+        assert!(late > 0);
+
+        for ((window, key), sum) in &cmp {
+            let state = map.get(*window, KeyRef::from(&key[..])).unwrap();
+            assert_eq!(state.finalize(), *sum);
+        }
+        // The watermark never moves back.
+        assert_eq!(map.evict_before(0).count(), 0);
+        let windows = map.window_count();
+        assert_eq!(map.evict_before(u64::MAX).count(), windows);
+        assert!(map.is_empty());
+        assert!(map
+            .get_or_insert_with(0, "late", || Sum::init(&0))
+            .is_none());
+    }
+}